@@ -4,73 +4,113 @@ use fs_extra::dir::CopyOptions;
 use glob::glob;
 use rayon::prelude::*;
 use std::env;
-use std::fs::{read_to_string, write};
-use std::path::PathBuf;
+use std::fmt::Write as _;
+use std::fs::{create_dir_all, write};
+use std::path::{Path, PathBuf};
 
-struct ShaderData {
-    src: String,
+#[path = "src/shader_compiler.rs"]
+#[allow(dead_code)]
+mod shader_compiler;
+
+struct ShaderJob {
     src_path: PathBuf,
+    name: String,
+    permutation: String,
+    defines: Vec<(String, Option<String>)>,
     spv_path: PathBuf,
-    kind: shaderc::ShaderKind,
-}
-
-impl ShaderData {
-    pub fn load(src_path: PathBuf) -> Result<Self> {
-        let extension = src_path
-            .extension()
-            .context("File has no extension")?
-            .to_str()
-            .context("Extension cannot be converted to &str")?;
-        let kind = match extension {
-            "vert" => shaderc::ShaderKind::Vertex,
-            "frag" => shaderc::ShaderKind::Fragment,
-            "comp" => shaderc::ShaderKind::Compute,
-            _ => bail!("Unsupported shader: {}", src_path.display()),
-        };
-
-        let src = read_to_string(src_path.clone())?;
-        let spv_path = src_path.with_extension(format!("{}.spv", extension));
-
-        Ok(Self {
-            src,
-            src_path,
-            spv_path,
-            kind,
-        })
-    }
 }
 
 fn main() -> Result<()> {
+    let shader_dir = Path::new("shaders");
+    // This tells cargo to rerun this script if something in /shaders/ changes,
+    // includes and the permutation manifest as well.
+    println!("cargo:rerun-if-changed=shaders");
+
     let mut shader_paths = Vec::new();
     shader_paths.extend(glob("./shaders/**/*.vert")?);
     shader_paths.extend(glob("./shaders/**/*.frag")?);
     shader_paths.extend(glob("./shaders/**/*.comp")?);
+    let mut names = Vec::new();
+    for path in shader_paths {
+        let path = path?;
+        let name = path
+            .strip_prefix(shader_dir)?
+            .to_string_lossy()
+            .replace('\\', "/");
+        names.push((path, name));
+    }
+    names.sort_by(|a, b| a.1.cmp(&b.1));
 
-    let shaders = shader_paths
-        .into_par_iter()
-        .map(|glob_result| ShaderData::load(glob_result?))
-        .collect::<Result<Vec<_>>>()?;
+    let permutations = shader_compiler::read_manifest(shader_dir)?;
+    for permutation in &permutations {
+        if !names.iter().any(|(_, name)| *name == permutation.shader) {
+            bail!(
+                "{} declares a permutation of the unknown shader {}",
+                shader_compiler::MANIFEST,
+                permutation.shader
+            );
+        }
+    }
 
-    let mut compiler = shaderc::Compiler::new().context("Unable to create shader compiler")?;
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+    let spirv_dir = out_dir.join("spirv");
+    let mut jobs = Vec::new();
+    for (src_path, name) in &names {
+        jobs.push(ShaderJob {
+            src_path: src_path.clone(),
+            name: name.clone(),
+            permutation: String::new(),
+            defines: vec![],
+            spv_path: spirv_dir.join(format!("{}.spv", name)),
+        });
+        for permutation in permutations.iter().filter(|p| p.shader == *name) {
+            jobs.push(ShaderJob {
+                src_path: src_path.clone(),
+                name: name.clone(),
+                permutation: permutation.name.clone(),
+                defines: permutation.defines.clone(),
+                spv_path: spirv_dir.join(format!("{}.{}.spv", name, permutation.name)),
+            });
+        }
+    }
 
-    for shader in shaders {
-        // This tells cargo to rerun this script if something in /shaders/ changes.
-        println!("cargo:rerun-if-changed={}", shader.src_path.display());
+    // Release builds get optimised SPIR-V, debug builds keep debug info.
+    let optimise = env::var("PROFILE")? == "release";
+    jobs.par_iter()
+        .map_init(
+            || shaderc::Compiler::new().expect("Unable to create shader compiler"),
+            |compiler, job| {
+                let compiled_spirv = shader_compiler::compile(
+                    compiler,
+                    shader_dir,
+                    &job.src_path,
+                    &job.defines,
+                    optimise,
+                )
+                .with_context(|| format!("Failed to compile {} {}", job.name, job.permutation))?;
+                create_dir_all(job.spv_path.parent().context("SPIR-V path has no parent")?)?;
+                write(&job.spv_path, compiled_spirv.as_binary_u8())?;
+                Ok(())
+            },
+        )
+        .collect::<Result<Vec<_>>>()?;
 
-        let compiled_spirv = compiler.compile_into_spirv(
-            &shader.src,
-            shader.kind,
-            &shader.src_path.to_str().unwrap(),
-            "main",
-            None,
+    let mut table = String::from("pub static SHADER_TABLE: &[ShaderEntry] = &[\n");
+    for job in &jobs {
+        writeln!(
+            table,
+            "    ShaderEntry {{ shader: {:?}, permutation: {:?}, spirv: include_bytes!({:?}) }},",
+            job.name,
+            job.permutation,
+            job.spv_path.to_string_lossy()
         )?;
-        write(shader.spv_path, compiled_spirv.as_binary_u8())?;
     }
+    table.push_str("];\n");
+    write(out_dir.join("shader_table.rs"), table)?;
 
     // This tells cargo to rerun this script if something in /res/ changes.
     println!("cargo:rerun-if-changed=res/*");
 
-    let out_dir = env::var("OUT_DIR")?;
     let mut copy_options = CopyOptions::new();
     copy_options.overwrite = true;
     let mut paths_to_copy = Vec::new();
@@ -0,0 +1,78 @@
+#version 450
+
+layout (location = 0) in vec2 uv;
+
+layout (location = 0) out vec4 out_color;
+
+layout (set = 0, binding = 0) uniform sampler2D source;
+
+layout (push_constant) uniform BloomParameters {
+	vec2 texel_size;
+	float threshold;
+	float knee;
+	float radius;
+	uint prefilter;
+} parameters;
+
+float luminance(vec3 colour) {
+  return dot(colour, vec3(0.2126, 0.7152, 0.0722));
+}
+
+// Weights each 2x2 block by inverse luminance so single very bright pixels do
+// not turn into flickering squares (Karis average).
+float karis_weight(vec3 colour) {
+  return 1.0 / (1.0 + luminance(colour));
+}
+
+// Soft knee around the threshold; a threshold of zero keeps all energy.
+vec3 apply_threshold(vec3 colour) {
+  float brightness = max(colour.r, max(colour.g, colour.b));
+  float soft = parameters.threshold * parameters.knee;
+  float curve = clamp(brightness - parameters.threshold + soft, 0.0, 2.0 * soft);
+  curve = curve * curve / (4.0 * soft + 1e-5);
+  float contribution = max(curve, brightness - parameters.threshold) / max(brightness, 1e-5);
+  return colour * contribution;
+}
+
+void main() {
+  vec2 t = parameters.texel_size;
+  // 13 taps from Jimenez, "Next Generation Post Processing in Call of Duty".
+  vec3 a = texture(source, uv + t * vec2(-2, -2)).rgb;
+  vec3 b = texture(source, uv + t * vec2( 0, -2)).rgb;
+  vec3 c = texture(source, uv + t * vec2( 2, -2)).rgb;
+  vec3 d = texture(source, uv + t * vec2(-2,  0)).rgb;
+  vec3 e = texture(source, uv).rgb;
+  vec3 f = texture(source, uv + t * vec2( 2,  0)).rgb;
+  vec3 g = texture(source, uv + t * vec2(-2,  2)).rgb;
+  vec3 h = texture(source, uv + t * vec2( 0,  2)).rgb;
+  vec3 i = texture(source, uv + t * vec2( 2,  2)).rgb;
+  vec3 j = texture(source, uv + t * vec2(-1, -1)).rgb;
+  vec3 k = texture(source, uv + t * vec2( 1, -1)).rgb;
+  vec3 l = texture(source, uv + t * vec2(-1,  1)).rgb;
+  vec3 m = texture(source, uv + t * vec2( 1,  1)).rgb;
+
+  vec3 blocks[5] = vec3[](
+    (j + k + l + m) * 0.25,
+    (a + b + d + e) * 0.25,
+    (b + c + e + f) * 0.25,
+    (d + e + g + h) * 0.25,
+    (e + f + h + i) * 0.25
+  );
+  const float weights[5] = float[](0.5, 0.125, 0.125, 0.125, 0.125);
+
+  vec3 colour = vec3(0.0);
+  if (parameters.prefilter != 0) {
+    float total = 0.0;
+    for (int n = 0; n < 5; n++) {
+      float w = weights[n] * karis_weight(blocks[n]);
+      colour += blocks[n] * w;
+      total += w;
+    }
+    colour = apply_threshold(colour / total);
+  } else {
+    for (int n = 0; n < 5; n++) {
+      colour += blocks[n] * weights[n];
+    }
+  }
+  out_color = vec4(colour, 1.0);
+}
@@ -0,0 +1,30 @@
+#version 450
+
+layout (location = 0) in vec2 uv;
+
+layout (location = 0) out vec4 out_color;
+
+layout (set = 0, binding = 0) uniform sampler2D source;
+
+layout (push_constant) uniform BloomParameters {
+	vec2 texel_size;
+	float threshold;
+	float knee;
+	float radius;
+	uint prefilter;
+} parameters;
+
+void main() {
+  // 3x3 tent filter; the result is blended additively onto the next larger level.
+  vec2 t = parameters.texel_size * parameters.radius;
+  vec3 colour = texture(source, uv).rgb * 4.0;
+  colour += (texture(source, uv + t * vec2( 0, -1)).rgb +
+             texture(source, uv + t * vec2(-1,  0)).rgb +
+             texture(source, uv + t * vec2( 1,  0)).rgb +
+             texture(source, uv + t * vec2( 0,  1)).rgb) * 2.0;
+  colour += texture(source, uv + t * vec2(-1, -1)).rgb +
+            texture(source, uv + t * vec2( 1, -1)).rgb +
+            texture(source, uv + t * vec2(-1,  1)).rgb +
+            texture(source, uv + t * vec2( 1,  1)).rgb;
+  out_color = vec4(colour / 16.0, 1.0);
+}
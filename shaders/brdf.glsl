@@ -0,0 +1,23 @@
+// Microfacet terms shared by the lit shaders.
+#ifndef BRDF_GLSL
+#define BRDF_GLSL
+
+const float PI = 3.14159265358979323846264;
+
+float distribution(vec3 normal,vec3 halfvector,float roughness2){
+	float NdotH=dot(halfvector,normal);
+	if (NdotH>0){
+		float r=roughness2*roughness2;
+		return r / (PI* (1 + NdotH*NdotH*(r-1))*(1 + NdotH*NdotH*(r-1)));
+	}else{
+		return 0.0;
+	}
+}
+
+float geometry(vec3 light, vec3 normal, vec3 view, float roughness2) {
+  float NdotL = abs(dot(normal, light));
+  float NdotV = abs(dot(normal, view));
+  return 0.5 / max(0.01, mix(2 * NdotL * NdotV, NdotL + NdotV, roughness2));
+}
+
+#endif
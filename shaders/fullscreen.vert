@@ -0,0 +1,9 @@
+#version 450
+
+layout (location = 0) out vec2 uv;
+
+void main() {
+  // One triangle covering the whole screen; uv spans 0..1 over the viewport.
+  uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+  gl_Position = vec4(uv * 2.0 - 1.0, 0.0, 1.0);
+}
@@ -0,0 +1,13 @@
+#version 450
+
+layout (push_constant) uniform IdParameters {
+	uint model;
+} parameters;
+
+layout (location = 0) in flat uint instance;
+
+layout (location = 0) out uvec2 id;
+
+void main() {
+  id = uvec2(parameters.model + 1, instance);
+}
@@ -0,0 +1,17 @@
+#version 450
+
+layout (location = 0) in vec3 position;
+layout (location = 2) in mat4 model_matrix;
+
+layout (set = 0, binding = 0) uniform UniformBufferObject {
+	mat4 view_matrix;
+	mat4 projection_matrix;
+} ubo;
+
+layout (location = 0) out flat uint instance;
+
+void main() {
+  gl_Position = ubo.projection_matrix * ubo.view_matrix * model_matrix *
+                vec4(position, 1.0);
+  instance = gl_InstanceIndex;
+}
@@ -0,0 +1,28 @@
+#version 450
+
+layout (location = 0) in vec2 corner;
+layout (location = 1) in vec4 colour;
+
+layout (location = 0) out vec4 out_color;
+
+layout (push_constant) uniform DrawParameters {
+	vec4 colours[4];
+	vec4 sizes;
+	uint shape;
+} parameters;
+
+void main() {
+  float r2 = dot(corner, corner);
+  if (r2 > 1.0) {
+    discard;
+  }
+  if (parameters.shape == 0) {
+    // Soft additive sprite.
+    float falloff = 1.0 - r2;
+    out_color = vec4(colour.rgb * colour.a * falloff * falloff, 1.0);
+  } else {
+    // Sphere impostor lit from the viewer.
+    float n_dot_v = sqrt(1.0 - r2);
+    out_color = vec4(colour.rgb * (0.2 + 0.8 * n_dot_v), 1.0);
+  }
+}
@@ -0,0 +1,58 @@
+#version 450
+
+struct Particle {
+  vec4 position_age;
+  vec4 velocity_lifetime;
+};
+
+layout (set = 0, binding = 0) uniform UniformBufferObject {
+	mat4 view_matrix;
+	mat4 projection_matrix;
+} ubo;
+
+layout (set = 1, binding = 0) readonly buffer Particles {
+  Particle particles[];
+};
+
+layout (push_constant) uniform DrawParameters {
+	vec4 colours[4];  // colour keys over the lifetime, rgb and alpha
+	vec4 sizes;       // size keys over the lifetime
+	uint shape;
+} parameters;
+
+layout (location = 0) out vec2 corner;
+layout (location = 1) out vec4 colour;
+
+vec4 colour_curve(float t) {
+  float x = clamp(t, 0.0, 1.0) * 3.0;
+  int i = min(int(x), 2);
+  return mix(parameters.colours[i], parameters.colours[i + 1], x - float(i));
+}
+
+float size_curve(float t) {
+  float x = clamp(t, 0.0, 1.0) * 3.0;
+  int i = min(int(x), 2);
+  return mix(parameters.sizes[i], parameters.sizes[i + 1], x - float(i));
+}
+
+const vec2 corners[6] = vec2[](vec2(-1, -1), vec2(1, -1), vec2(1, 1),
+                               vec2(-1, -1), vec2(1, 1), vec2(-1, 1));
+
+void main() {
+  Particle p = particles[gl_InstanceIndex];
+  float age = p.position_age.w;
+  float lifetime = p.velocity_lifetime.w;
+  if (age >= lifetime) {
+    // Dead particles collapse outside the clip volume.
+    gl_Position = vec4(0.0, 0.0, 2.0, 1.0);
+    return;
+  }
+  float t = age / lifetime;
+  corner = corners[gl_VertexIndex];
+  colour = colour_curve(t);
+
+  // Expand the quad in view space so it always faces the camera.
+  vec4 centre = ubo.view_matrix * vec4(p.position_age.xyz, 1.0);
+  centre.xy += corner * 0.5 * size_curve(t);
+  gl_Position = ubo.projection_matrix * centre;
+}
@@ -16,7 +16,7 @@ readonly layout (set=1, binding=0) buffer StorageBufferObject {
 } sbo;
 
 
-const float PI = 3.14159265358979323846264;
+#include "brdf.glsl"
 
 struct DirectionalLight {
   vec3 direction_to_light;
@@ -28,22 +28,6 @@ struct PointLight{
 	vec3 luminous_flux;
 };
 
-float distribution(vec3 normal,vec3 halfvector,float roughness2){
-	float NdotH=dot(halfvector,normal);
-	if (NdotH>0){
-		float r=roughness2*roughness2;
-		return r / (PI* (1 + NdotH*NdotH*(r-1))*(1 + NdotH*NdotH*(r-1)));
-	}else{
-		return 0.0;
-	}
-}
-
-float geometry(vec3 light, vec3 normal, vec3 view, float roughness2) {
-  float NdotL = abs(dot(normal, light));
-  float NdotV = abs(dot(normal, view));
-  return 0.5 / max(0.01, mix(2 * NdotL * NdotV, NdotL + NdotV, roughness2));
-}
-
 vec3 compute_radiance(vec3 irradiance, vec3 light_direction, vec3 normal,
                       vec3 camera_direction, vec3 surface_colour) {
   float NdotL = max(dot(normal, light_direction), 0);
@@ -103,5 +87,9 @@ void main() {
                           direction_to_camera, colour_in);
   }
 
-  out_color = vec4(L / (1 + L), 1.0);
+#ifdef DEBUG_NORMALS
+  out_color = vec4(0.5 * normal + 0.5, 1.0);
+#else
+  out_color = vec4(L, 1.0);
+#endif
 }
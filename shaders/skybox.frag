@@ -0,0 +1,86 @@
+#version 450
+
+layout (location = 0) in vec3 direction_in;
+
+layout (location = 0) out vec4 out_color;
+
+layout (set = 1, binding = 0) uniform sampler2D equirectangular;
+layout (set = 1, binding = 1) uniform samplerCube cubemap;
+
+layout (push_constant) uniform SkyboxParameters {
+	vec4 colours[3];
+	vec4 sun;
+	uint mode;
+	float far_depth;
+} parameters;
+
+const float PI = 3.14159265358979323846264;
+const vec3 UP = vec3(0.0, -1.0, 0.0);
+
+vec3 gradient(vec3 direction) {
+  float elevation = dot(direction, UP);
+  vec3 zenith = parameters.colours[0].rgb;
+  vec3 horizon = parameters.colours[1].rgb;
+  vec3 ground = parameters.colours[2].rgb;
+  if (elevation >= 0) {
+    return mix(horizon, zenith, sqrt(elevation));
+  } else {
+    return mix(horizon, ground, sqrt(-elevation));
+  }
+}
+
+vec3 analytic_sky(vec3 direction) {
+  vec3 direction_to_sun = normalize(parameters.sun.xyz);
+  float sun_intensity = parameters.sun.w;
+  float elevation = dot(direction, UP);
+  float sun_elevation = dot(direction_to_sun, UP);
+  float cos_theta = dot(direction, direction_to_sun);
+
+  // Longer optical path near the horizon shifts the sky from blue to white.
+  float optical_depth = 1.0 / max(elevation + 0.15, 0.05);
+  vec3 rayleigh = vec3(5.8, 13.5, 33.1) * 0.01;
+  vec3 extinction = exp(-rayleigh * optical_depth);
+  float rayleigh_phase = 0.75 * (1.0 + cos_theta * cos_theta);
+  float mie_phase = pow(max(cos_theta, 0.0), 32.0);
+  float daylight = clamp(sun_elevation * 4.0 + 0.2, 0.0, 1.0);
+
+  vec3 sky = (rayleigh_phase * (1.0 - extinction) + 0.2 * mie_phase) * daylight;
+  float sun_disk = smoothstep(0.9995, 0.9999, cos_theta);
+  sky += sun_disk * extinction * 20.0;
+  sky *= sun_intensity;
+
+  if (elevation < 0) {
+    vec3 ground = vec3(0.1, 0.09, 0.08) * daylight * sun_intensity;
+    sky = mix(sky, ground, clamp(-elevation * 10.0, 0.0, 1.0));
+  }
+  return sky;
+}
+
+vec3 equirectangular_lookup(vec3 direction) {
+  float phi = atan(direction.z, direction.x);
+  float theta = acos(clamp(dot(direction, UP), -1.0, 1.0));
+  vec2 uv = vec2(phi / (2 * PI) + 0.5, theta / PI);
+  return texture(equirectangular, uv).rgb;
+}
+
+void main() {
+  vec3 direction = normalize(direction_in);
+  vec3 colour;
+  switch (parameters.mode) {
+  case 1:
+    colour = gradient(direction);
+    break;
+  case 2:
+    colour = analytic_sky(direction);
+    break;
+  case 3:
+    colour = equirectangular_lookup(direction);
+    break;
+  case 4:
+    colour = texture(cubemap, direction).rgb;
+    break;
+  default:
+    colour = parameters.colours[0].rgb;
+  }
+  out_color = vec4(colour, 1.0);
+}
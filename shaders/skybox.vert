@@ -0,0 +1,35 @@
+#version 450
+
+layout (set = 0, binding = 0) uniform UniformBufferObject {
+	mat4 view_matrix;
+	mat4 projection_matrix;
+} ubo;
+
+layout (push_constant) uniform SkyboxParameters {
+	vec4 colours[3];
+	vec4 sun;
+	uint mode;
+	float far_depth;
+} parameters;
+
+layout (location = 0) out vec3 direction;
+
+void main() {
+  // One triangle covering the whole screen.
+  vec2 uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+  vec2 ndc = uv * 2.0 - 1.0;
+
+  // Two depths strictly inside the range stay finite whichever end is near
+  // and even with the far plane at infinity.
+  mat4 inverse_projection = inverse(ubo.projection_matrix);
+  vec4 a = inverse_projection * vec4(ndc, 0.25, 1.0);
+  vec4 b = inverse_projection * vec4(ndc, 0.75, 1.0);
+  vec3 ray = b.xyz / b.w - a.xyz / a.w;
+  // The camera looks along +z in view space.
+  if (ray.z < 0.0) {
+    ray = -ray;
+  }
+  direction = transpose(mat3(ubo.view_matrix)) * ray;
+
+  gl_Position = vec4(ndc, parameters.far_depth, 1.0);
+}
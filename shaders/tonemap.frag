@@ -0,0 +1,85 @@
+#version 450
+
+layout (location = 0) in vec2 uv;
+
+layout (location = 0) out vec4 out_color;
+
+layout (set = 0, binding = 0) uniform sampler2D hdr;
+layout (set = 0, binding = 1) uniform sampler2D bloom;
+
+layout (push_constant) uniform TonemapParameters {
+	float exposure;
+	uint operator;
+	uint encode_srgb;
+	float bloom_intensity;
+	float bloom_scale;
+} parameters;
+
+vec3 reinhard(vec3 L) {
+  return L / (1 + L);
+}
+
+// Narkowicz's fit of the ACES filmic curve.
+vec3 aces(vec3 x) {
+  return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+// Minimal AgX with the default contrast curve.
+vec3 agx_contrast(vec3 x) {
+  vec3 x2 = x * x;
+  vec3 x4 = x2 * x2;
+  return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x +
+         0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+vec3 agx(vec3 colour) {
+  const mat3 agx_inset = mat3(0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+                              0.0784335999999992, 0.878468636469772, 0.0784336,
+                              0.0792237451477643, 0.0791661274605434, 0.879142973793104);
+  const mat3 agx_outset = mat3(1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+                               -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+                               -0.0990297440797205, -0.0989611768448433, 1.15107367264116);
+  const float min_ev = -12.47393;
+  const float max_ev = 4.026069;
+
+  colour = agx_inset * colour;
+  colour = clamp(log2(max(colour, vec3(1e-10))), min_ev, max_ev);
+  colour = (colour - min_ev) / (max_ev - min_ev);
+  colour = agx_contrast(colour);
+  colour = agx_outset * colour;
+  // The curve produces display-encoded values; go back to linear for the output.
+  return pow(max(colour, vec3(0.0)), vec3(2.2));
+}
+
+vec3 linear_to_srgb(vec3 colour) {
+  vec3 low = colour * 12.92;
+  vec3 high = 1.055 * pow(colour, vec3(1.0 / 2.4)) - 0.055;
+  return mix(high, low, lessThanEqual(colour, vec3(0.0031308)));
+}
+
+void main() {
+  vec3 L = texture(hdr, uv).rgb;
+  // Bloom stands in for the lens point spread function, so it redistributes
+  // energy instead of adding to it.
+  if (parameters.bloom_intensity > 0) {
+    vec3 scattered = texture(bloom, uv).rgb * parameters.bloom_scale;
+    L = mix(L, scattered, parameters.bloom_intensity);
+  }
+  L *= parameters.exposure;
+  vec3 colour;
+  switch (parameters.operator) {
+  case 1:
+    colour = aces(L);
+    break;
+  case 2:
+    colour = agx(L);
+    break;
+  default:
+    colour = reinhard(L);
+  }
+  colour = clamp(colour, 0.0, 1.0);
+  if (parameters.encode_srgb != 0) {
+    colour = linear_to_srgb(colour);
+  }
+  out_color = vec4(colour, 1.0);
+}
@@ -0,0 +1,21 @@
+#version 450
+
+layout (location = 0) in vec2 uv;
+
+layout (location = 0) out vec4 out_color;
+
+layout (set = 0, binding = 0) uniform sampler2D scene;
+
+layout (push_constant) uniform VignetteParameters {
+	float strength;
+	float falloff;
+} parameters;
+
+void main() {
+  vec3 colour = texture(scene, uv).rgb;
+  // Natural cos^4 style light falloff towards the corners.
+  vec2 offset = (uv - 0.5) * parameters.falloff;
+  float r2 = dot(offset, offset);
+  float attenuation = 1.0 / ((1.0 + r2) * (1.0 + r2));
+  out_color = vec4(colour * mix(1.0, attenuation, parameters.strength), 1.0);
+}
@@ -1,23 +1,46 @@
 use crate::{
+    bloom::Bloom,
     buffers::Buffer,
+    camera::{Camera, CameraHandle},
+    compute::{record_dispatches, ComputeDispatch, DispatchOrder},
+    culling::{Culling, CullingStats},
     debug::DebugDongXi,
+    gpu_culling::GpuCulling,
+    id_buffer::{IdBuffer, PickQuery, PickResult},
     instance_device_queues::{
-        init_device_and_queues, init_instance, init_physical_device_and_properties, QueueFamilies,
-        Queues,
+        init_device_and_queues, init_instance, init_physical_device_and_properties,
+        supports_device_extension, QueueFamilies, Queues,
     },
+    light::LightManager,
+    lod::{LodHandle, LodModel},
     model::Model,
+    particles::{Emitter, EmitterHandle, ParticleSystem},
+    picking::{self, Hit, InstanceTransform, VertexPosition},
+    pipeline_cache::PipelineCache,
     pool_and_commandbuffer::{create_commandbuffers, Pools},
-    renderpass_and_pipeline::{init_renderpass, Pipeline},
+    postprocess::{Effect, PostProcessChain},
+    render_graph::{Access, RenderGraph},
+    renderpass_and_pipeline::{
+        init_present_renderpass, init_renderpass, set_viewport, DepthConvention, Pipeline,
+    },
+    scene::SceneGraph,
+    shaders,
+    skybox::{Background, Skybox},
     surface::SurfaceDongXi,
-    swapchain::SwapchainDongXi,
+    swapchain::{SwapchainConfig, SwapchainDongXi},
+    tonemap::Tonemapping,
 };
 use ash::{
+    extensions::khr::DrawIndirectCount,
     version::{DeviceV1_0, InstanceV1_0},
     vk,
 };
 use eyre::*;
 use nalgebra as na;
 
+#[cfg(feature = "hot-reload")]
+use crate::hot_reload::{self, ShaderWatcher};
+
 // TODO(#3): Rethink about the order of poles in the struct for 'right' drop order
 // to remove ManualDrop
 pub struct Aetna<V, I> {
@@ -32,22 +55,53 @@ pub struct Aetna<V, I> {
     pub queue_families: QueueFamilies,
     pub queues: Queues,
     pub device: ash::Device,
+    /// Lets indirect draws skip models without surviving instances, if the
+    /// device has `VK_KHR_draw_indirect_count`.
+    draw_indirect_count: Option<DrawIndirectCount>,
     pub swapchain: SwapchainDongXi,
+    swapchain_config: SwapchainConfig,
+    minimised: bool,
+    pipeline_cache: PipelineCache,
     renderpass: vk::RenderPass,
+    present_renderpass: vk::RenderPass,
     pipeline: Pipeline,
+    scene_permutation: String,
+    depth: DepthConvention,
+    skybox: Skybox,
+    id_buffer: Option<IdBuffer>,
+    postprocess: PostProcessChain,
+    pub particles: ParticleSystem,
+    pub bloom: Bloom,
+    pub tonemapping: Tonemapping,
     pub pools: Pools,
     pub commandbuffers: Vec<vk::CommandBuffer>,
     pub allocator: vk_mem::Allocator,
     pub models: Vec<Model<V, I>>,
+    lod_models: Vec<LodModel>,
+    culling: Culling,
+    culling_stats: CullingStats,
+    gpu_culling: Option<GpuCulling>,
+    cameras: Vec<Camera>,
+    compute_dispatches: Vec<ComputeDispatch>,
     pub uniformbuffer: Buffer,
     pub lightbuffer: Buffer,
     descriptor_pool: vk::DescriptorPool,
     pub descriptor_sets_camera: Vec<vk::DescriptorSet>,
     pub descriptor_sets_light: Vec<vk::DescriptorSet>,
+    #[cfg(feature = "hot-reload")]
+    shader_watcher: ShaderWatcher,
+    #[cfg(feature = "hot-reload")]
+    shader_errors: bool,
 }
 
 impl<V, I> Aetna<V, I> {
     pub fn init(window: winit::window::Window) -> Result<Self> {
+        Self::init_with_swapchain_config(window, SwapchainConfig::default())
+    }
+    pub fn init_with_swapchain_config(
+        window: winit::window::Window,
+        mut swapchain_config: SwapchainConfig,
+    ) -> Result<Self> {
         let entry = ash::Entry::new()?;
         let extension_names = ash_window::enumerate_required_extensions(&window)?;
 
@@ -61,8 +115,25 @@ impl<V, I> Aetna<V, I> {
 
         let queue_families = QueueFamilies::init(&instance, physical_device, &surfaces)?;
 
-        let (logical_device, queues) =
-            init_device_and_queues(&instance, physical_device, &queue_families, &layer_names)?;
+        let indirect_count_supported =
+            supports_device_extension(&instance, physical_device, DrawIndirectCount::name())?;
+        let device_extension_names = if indirect_count_supported {
+            vec![DrawIndirectCount::name()]
+        } else {
+            vec![]
+        };
+        let (logical_device, queues) = init_device_and_queues(
+            &instance,
+            physical_device,
+            &queue_families,
+            &layer_names,
+            &device_extension_names,
+        )?;
+        let draw_indirect_count = if indirect_count_supported {
+            Some(DrawIndirectCount::new(&instance, &logical_device))
+        } else {
+            None
+        };
 
         let allocator_create_info = vk_mem::AllocatorCreateInfo {
             physical_device,
@@ -74,7 +145,9 @@ impl<V, I> Aetna<V, I> {
             flags: vk_mem::AllocatorCreateFlags::NONE,
         };
         let allocator = vk_mem::Allocator::new(&allocator_create_info)?;
+        let pipeline_cache = PipelineCache::init(&instance, physical_device, &logical_device)?;
 
+        swapchain_config.fallback_extent = window_extent(&window);
         let mut swapchain = SwapchainDongXi::init(
             &instance,
             physical_device,
@@ -82,11 +155,52 @@ impl<V, I> Aetna<V, I> {
             &surfaces,
             &queue_families,
             &allocator,
+            &swapchain_config,
+            vk::SwapchainKHR::null(),
+        )?;
+        let renderpass = init_renderpass(&logical_device)?;
+        let present_renderpass =
+            init_present_renderpass(&logical_device, swapchain.surface_format.format)?;
+        swapchain.create_framebuffers(&logical_device, renderpass, present_renderpass)?;
+        let depth = DepthConvention::Standard;
+        let pipeline = Pipeline::init(
+            &logical_device,
+            pipeline_cache.cache,
+            &renderpass,
+            depth,
+            "",
         )?;
-        let renderpass = init_renderpass(&logical_device, swapchain.surface_format.format)?;
-        swapchain.create_framebuffers(&logical_device, renderpass)?;
-        let pipeline = Pipeline::init(&logical_device, &swapchain, &renderpass)?;
         let pools = Pools::init(&logical_device, &queue_families)?;
+        let postprocess = PostProcessChain::init(&logical_device)?;
+        let bloom = Bloom::init(
+            &logical_device,
+            pipeline_cache.cache,
+            &allocator,
+            &pools,
+            queues.graphics_queue,
+            &swapchain,
+            postprocess.output(&swapchain),
+        )?;
+        let tonemapping = Tonemapping::init(
+            &logical_device,
+            pipeline_cache.cache,
+            &swapchain,
+            &present_renderpass,
+            postprocess.output(&swapchain),
+            &bloom,
+        )?;
+        let skybox = Skybox::init(
+            &logical_device,
+            pipeline_cache.cache,
+            &allocator,
+            &pools,
+            queues.graphics_queue,
+            &renderpass,
+            depth,
+        )?;
+
+        let particles =
+            ParticleSystem::init(&logical_device, pipeline_cache.cache, &renderpass, depth)?;
 
         let commandbuffers =
             create_commandbuffers(&logical_device, &pools, swapchain.amount_of_images)?;
@@ -111,68 +225,14 @@ impl<V, I> Aetna<V, I> {
         )?;
         lightbuffer.fill(&allocator, &[0., 0.])?;
 
-        let pool_sizes = [
-            vk::DescriptorPoolSize {
-                ty: vk::DescriptorType::UNIFORM_BUFFER,
-                descriptor_count: swapchain.amount_of_images,
-            },
-            vk::DescriptorPoolSize {
-                ty: vk::DescriptorType::STORAGE_BUFFER,
-                descriptor_count: swapchain.amount_of_images,
-            },
-        ];
-        let descriptor_pool_info = vk::DescriptorPoolCreateInfo::builder()
-            .max_sets(2 * swapchain.amount_of_images)
-            .pool_sizes(&pool_sizes);
-        let descriptor_pool =
-            unsafe { logical_device.create_descriptor_pool(&descriptor_pool_info, None) }?;
-
-        let desc_layouts_camera =
-            vec![pipeline.descriptor_set_layouts[0]; swapchain.amount_of_images as usize];
-        let descriptor_set_allocate_info_camera = vk::DescriptorSetAllocateInfo::builder()
-            .descriptor_pool(descriptor_pool)
-            .set_layouts(&desc_layouts_camera);
-        let descriptor_sets_camera = unsafe {
-            logical_device.allocate_descriptor_sets(&descriptor_set_allocate_info_camera)
-        }?;
-
-        for descset in &descriptor_sets_camera {
-            let buffer_infos = [vk::DescriptorBufferInfo {
-                buffer: uniformbuffer.buffer,
-                offset: 0,
-                range: 128,
-            }];
-            let desc_sets_write = [vk::WriteDescriptorSet::builder()
-                .dst_set(*descset)
-                .dst_binding(0)
-                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                .buffer_info(&buffer_infos)
-                .build()];
-            unsafe { logical_device.update_descriptor_sets(&desc_sets_write, &[]) };
-        }
-        let desc_layouts_light =
-            vec![pipeline.descriptor_set_layouts[1]; swapchain.amount_of_images as usize];
-        let descriptor_set_allocate_info_light = vk::DescriptorSetAllocateInfo::builder()
-            .descriptor_pool(descriptor_pool)
-            .set_layouts(&desc_layouts_light);
-        let descriptor_sets_light = unsafe {
-            logical_device.allocate_descriptor_sets(&descriptor_set_allocate_info_light)
-        }?;
-
-        for descset in &descriptor_sets_light {
-            let buffer_infos = [vk::DescriptorBufferInfo {
-                buffer: lightbuffer.buffer,
-                offset: 0,
-                range: 8,
-            }];
-            let desc_sets_write = [vk::WriteDescriptorSet::builder()
-                .dst_set(*descset)
-                .dst_binding(0)
-                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-                .buffer_info(&buffer_infos)
-                .build()];
-            unsafe { logical_device.update_descriptor_sets(&desc_sets_write, &[]) };
-        }
+        let (descriptor_pool, descriptor_sets_camera, descriptor_sets_light) =
+            create_descriptor_sets(
+                &logical_device,
+                &pipeline,
+                swapchain.amount_of_images,
+                &uniformbuffer,
+                &lightbuffer,
+            )?;
 
         Ok(Aetna {
             window,
@@ -186,103 +246,915 @@ impl<V, I> Aetna<V, I> {
             queue_families,
             queues,
             device: logical_device,
+            draw_indirect_count,
             swapchain,
+            swapchain_config,
+            minimised: false,
+            pipeline_cache,
             renderpass,
+            present_renderpass,
             pipeline,
+            scene_permutation: String::new(),
+            depth,
+            skybox,
+            id_buffer: None,
+            postprocess,
+            particles,
+            bloom,
+            tonemapping,
             pools,
             commandbuffers,
             allocator,
             models: vec![],
+            lod_models: vec![],
+            culling: Culling::Cpu,
+            culling_stats: CullingStats::default(),
+            gpu_culling: None,
+            cameras: vec![],
+            compute_dispatches: vec![],
             uniformbuffer,
             lightbuffer,
             descriptor_pool,
             descriptor_sets_camera,
             descriptor_sets_light,
+            #[cfg(feature = "hot-reload")]
+            shader_watcher: ShaderWatcher::new(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders"))?,
+            #[cfg(feature = "hot-reload")]
+            shader_errors: false,
         })
     }
-    // TODO(#4): Still have validation errors on validation.
+    /// Rebuilds the swapchain and everything sized after it for a window of
+    /// `width` by `height`. A window without area, e.g. a minimised one, keeps
+    /// the old swapchain and frames are skipped until the next resize.
     pub fn recreate_swapchain(&mut self, width: u32, height: u32) -> Result<()> {
-        let _extent2d = vk::Extent2D { width, height };
+        let capabilities = self.surfaces.get_capabilities(self.physical_device)?;
+        self.minimised = width == 0
+            || height == 0
+            || capabilities.current_extent.width == 0
+            || capabilities.current_extent.height == 0;
+        if self.minimised {
+            return Ok(());
+        }
         unsafe {
             self.device
                 .device_wait_idle()
                 .expect("something wrong while waiting");
         }
-        unsafe {
-            self.swapchain.cleanup(&self.device, &self.allocator);
-        }
-        self.swapchain = SwapchainDongXi::init(
+        self.swapchain_config.fallback_extent = vk::Extent2D { width, height };
+        let swapchain = SwapchainDongXi::init(
             &self.instance,
             self.physical_device,
             &self.device,
             &self.surfaces,
             &self.queue_families,
             &self.allocator,
+            &self.swapchain_config,
+            self.swapchain.swapchain,
+        )?;
+        // The old swapchain is retired by handing it over, and only destroyed
+        // once the new one exists.
+        let mut old_swapchain = std::mem::replace(&mut self.swapchain, swapchain);
+        unsafe {
+            old_swapchain.cleanup(&self.device, &self.allocator);
+        }
+        if self.swapchain.surface_format.format != old_swapchain.surface_format.format {
+            unsafe {
+                self.device
+                    .destroy_render_pass(self.present_renderpass, None)
+            };
+            self.present_renderpass =
+                init_present_renderpass(&self.device, self.swapchain.surface_format.format)?;
+        }
+        self.swapchain.create_framebuffers(
+            &self.device,
+            self.renderpass,
+            self.present_renderpass,
+        )?;
+        // A new present mode may come with a different number of images, each
+        // recorded with its own command buffer and descriptor sets.
+        if self.swapchain.amount_of_images != old_swapchain.amount_of_images {
+            unsafe {
+                self.device
+                    .free_command_buffers(self.pools.commandpool_graphics, &self.commandbuffers);
+                self.device
+                    .destroy_descriptor_pool(self.descriptor_pool, None);
+            }
+            self.commandbuffers =
+                create_commandbuffers(&self.device, &self.pools, self.swapchain.amount_of_images)?;
+            let (descriptor_pool, descriptor_sets_camera, descriptor_sets_light) =
+                create_descriptor_sets(
+                    &self.device,
+                    &self.pipeline,
+                    self.swapchain.amount_of_images,
+                    &self.uniformbuffer,
+                    &self.lightbuffer,
+                )?;
+            self.descriptor_pool = descriptor_pool;
+            self.descriptor_sets_camera = descriptor_sets_camera;
+            self.descriptor_sets_light = descriptor_sets_light;
+        }
+        self.postprocess
+            .recreate(&self.device, &self.allocator, &self.swapchain)?;
+        let scene = self.postprocess.output(&self.swapchain);
+        self.bloom.recreate(
+            &self.device,
+            &self.allocator,
+            &self.pools,
+            self.queues.graphics_queue,
+            &self.swapchain,
+            scene,
+        )?;
+        self.tonemapping.recreate(
+            &self.device,
+            self.pipeline_cache.cache,
+            &self.swapchain,
+            &self.present_renderpass,
+            scene,
+            &self.bloom,
+        )?;
+        if let Some(id_buffer) = &mut self.id_buffer {
+            id_buffer.collect_all(&self.allocator)?;
+            id_buffer.resize(
+                &self.device,
+                &self.allocator,
+                self.swapchain.extent,
+                self.swapchain.amount_of_images as usize,
+            )?;
+        }
+        if let Some(gpu_culling) = &mut self.gpu_culling {
+            gpu_culling.resize(
+                &self.device,
+                &self.allocator,
+                &self.pools,
+                self.queues.graphics_queue,
+                self.swapchain.extent,
+                self.swapchain.depth.imageview,
+            )?;
+        }
+        let aspect = self.aspect();
+        for camera in &mut self.cameras {
+            camera.set_aspect(aspect);
+        }
+        Ok(())
+    }
+    /// Whether frames are skipped because the window has no area.
+    pub fn is_minimised(&self) -> bool {
+        self.minimised
+    }
+    fn aspect(&self) -> f32 {
+        self.swapchain.extent.width as f32 / self.swapchain.extent.height as f32
+    }
+    /// Hands `camera` to the renderer, which keeps its aspect ratio in line with
+    /// the swapchain from now on.
+    pub fn register_camera(&mut self, mut camera: Camera) -> CameraHandle {
+        camera.set_aspect(self.aspect());
+        self.cameras.push(camera);
+        CameraHandle(self.cameras.len() - 1)
+    }
+    pub fn camera(&self, handle: CameraHandle) -> &Camera {
+        &self.cameras[handle.0]
+    }
+    pub fn camera_mut(&mut self, handle: CameraHandle) -> &mut Camera {
+        &mut self.cameras[handle.0]
+    }
+    /// Uploads the view and projection of `handle` for the next frame.
+    /// Switches the depth test to match the projection of the camera first.
+    pub fn update_camera_buffer(&mut self, handle: CameraHandle) -> Result<()> {
+        let depth = self.cameras[handle.0].projection().depth_convention();
+        if depth != self.depth {
+            self.set_depth_convention(depth)?;
+        }
+        self.cameras[handle.0].update_buffer(&self.allocator, &mut self.uniformbuffer)?;
+        Ok(())
+    }
+    #[allow(dead_code)]
+    pub fn depth_convention(&self) -> DepthConvention {
+        self.depth
+    }
+    /// Rebuilds the pipelines that test depth for `depth`; the scene pass
+    /// clears to its far plane from the next recorded frame on.
+    pub fn set_depth_convention(&mut self, depth: DepthConvention) -> Result<()> {
+        unsafe {
+            self.device
+                .device_wait_idle()
+                .expect("something wrong while waiting");
+        }
+        let pipeline = Pipeline::init(
+            &self.device,
+            self.pipeline_cache.cache,
+            &self.renderpass,
+            depth,
+            &self.scene_permutation,
+        )?;
+        self.pipeline.cleanup(&self.device);
+        self.pipeline = pipeline;
+        self.skybox.recreate_pipeline(
+            &self.device,
+            self.pipeline_cache.cache,
+            &self.renderpass,
+            depth,
+        )?;
+        self.particles.recreate_pipelines(
+            &self.device,
+            self.pipeline_cache.cache,
+            &self.renderpass,
+            depth,
+        )?;
+        if let Some(id_buffer) = &mut self.id_buffer {
+            id_buffer.recreate_pipeline(&self.device, self.pipeline_cache.cache, depth)?;
+        }
+        if let Some(gpu_culling) = &mut self.gpu_culling {
+            gpu_culling.invalidate_pyramid();
+        }
+        self.depth = depth;
+        Ok(())
+    }
+    pub fn culling(&self) -> Culling {
+        self.culling
+    }
+    /// Switches how instances outside the view are left out, from the next
+    /// `update_instancebuffers` on.
+    pub fn set_culling(&mut self, culling: Culling) -> Result<()> {
+        match culling {
+            Culling::Gpu { occlusion } => match &mut self.gpu_culling {
+                Some(gpu_culling) => {
+                    // The pyramid was not kept up to date without occlusion.
+                    if occlusion && self.culling != culling {
+                        gpu_culling.invalidate_pyramid();
+                    }
+                }
+                None => {
+                    self.gpu_culling = Some(GpuCulling::init(
+                        &self.device,
+                        &self.allocator,
+                        self.pipeline_cache.cache,
+                        &self.pools,
+                        self.queues.graphics_queue,
+                        self.swapchain.extent,
+                        self.swapchain.depth.imageview,
+                    )?)
+                }
+            },
+            Culling::Off | Culling::Cpu => {
+                if let Some(gpu_culling) = self.gpu_culling.take() {
+                    unsafe {
+                        self.device
+                            .device_wait_idle()
+                            .expect("something wrong while waiting");
+                    }
+                    gpu_culling.cleanup(&self.device, &self.allocator);
+                }
+            }
+        }
+        self.culling = culling;
+        Ok(())
+    }
+    /// Appends `levels`, with their vertex and index buffers uploaded, to
+    /// `models` as the levels of detail of one object, most detailed first.
+    /// `thresholds` are the screen sizes down to which each level but the last
+    /// is drawn, see `LodModel`.
+    pub fn add_lod_model(
+        &mut self,
+        levels: Vec<Model<V, I>>,
+        thresholds: Vec<f32>,
+    ) -> Result<LodHandle> {
+        let indices = (self.models.len()..self.models.len() + levels.len()).collect();
+        let lod_model = LodModel::new(indices, thresholds)?;
+        self.models.extend(levels);
+        self.lod_models.push(lod_model);
+        Ok(LodHandle(self.lod_models.len() - 1))
+    }
+    pub fn lod_model(&self, handle: LodHandle) -> &LodModel {
+        &self.lod_models[handle.0]
+    }
+    pub fn lod_model_mut(&mut self, handle: LodHandle) -> &mut LodModel {
+        &mut self.lod_models[handle.0]
+    }
+    /// Adds a visible instance to a level of detail model, which picks its
+    /// level in `update_instancebuffers`.
+    pub fn insert_lod_instance(&mut self, handle: LodHandle, instance: I) -> usize {
+        self.lod_models[handle.0].insert_visibly(&mut self.models, instance)
+    }
+    /// Turns the ID buffer used by `request_pick` on or off.
+    pub fn enable_id_buffer(&mut self, enabled: bool) -> Result<()> {
+        if enabled == self.id_buffer.is_some() {
+            return Ok(());
+        }
+        unsafe {
+            self.device
+                .device_wait_idle()
+                .expect("something wrong while waiting");
+        }
+        match self.id_buffer.take() {
+            Some(id_buffer) => id_buffer.cleanup(&self.device, &self.allocator),
+            None => {
+                self.id_buffer = Some(IdBuffer::init(
+                    &self.device,
+                    &self.allocator,
+                    self.pipeline_cache.cache,
+                    self.swapchain.extent,
+                    self.depth,
+                    self.swapchain.amount_of_images as usize,
+                )?)
+            }
+        }
+        Ok(())
+    }
+    /// Asks the ID buffer which instances cover `area` of the window; a single
+    /// pixel for clicks or a rectangle for marquee selection. The answer comes
+    /// from `pick_results` a few frames later.
+    pub fn request_pick(&mut self, area: vk::Rect2D) -> Result<PickQuery> {
+        match &mut self.id_buffer {
+            Some(id_buffer) => Ok(id_buffer.request(area)),
+            None => bail!("Picking needs the ID buffer, see enable_id_buffer"),
+        }
+    }
+    /// Answers to `request_pick` from frames that have finished, without
+    /// waiting for the others.
+    pub fn pick_results(&mut self) -> Result<Vec<PickResult>> {
+        let id_buffer = match &mut self.id_buffer {
+            Some(id_buffer) => id_buffer,
+            None => return Ok(vec![]),
+        };
+        for (frame, &fence) in self.swapchain.may_begin_drawing.iter().enumerate() {
+            if id_buffer.is_waiting(frame) && unsafe { self.device.get_fence_status(fence) }? {
+                id_buffer.collect(&self.allocator, frame)?;
+            }
+        }
+        Ok(id_buffer.take_results())
+    }
+    /// Cache to create further pipelines with, e.g. the
+    /// [`ComputePipeline`](crate::compute::ComputePipeline)s of compute dispatches.
+    #[allow(dead_code)]
+    pub fn pipeline_cache(&self) -> vk::PipelineCache {
+        self.pipeline_cache.cache
+    }
+    pub fn swapchain_config(&self) -> &SwapchainConfig {
+        &self.swapchain_config
+    }
+    pub fn set_swapchain_config(&mut self, config: SwapchainConfig) -> Result<()> {
+        self.swapchain_config = config;
+        let size = self.window.inner_size();
+        self.recreate_swapchain(size.width, size.height)
+    }
+    pub fn scene_permutation(&self) -> &str {
+        &self.scene_permutation
+    }
+    /// Switches the models to another permutation of `shader.frag`, as listed in
+    /// `shaders/permutations.manifest`. `""` is the base variant.
+    pub fn set_scene_permutation(&mut self, permutation: &str) -> Result<()> {
+        if !shaders::permutations("shader.frag").any(|p| p == permutation) {
+            bail!("shader.frag has no permutation {:?}", permutation);
+        }
+        unsafe {
+            self.device
+                .device_wait_idle()
+                .expect("something wrong while waiting");
+        }
+        let pipeline = Pipeline::init(
+            &self.device,
+            self.pipeline_cache.cache,
+            &self.renderpass,
+            self.depth,
+            permutation,
         )?;
-        self.swapchain
-            .create_framebuffers(&self.device, self.renderpass)?;
         self.pipeline.cleanup(&self.device);
-        self.pipeline = Pipeline::init(&self.device, &self.swapchain, &self.renderpass)?;
+        self.pipeline = pipeline;
+        self.scene_permutation = permutation.to_string();
+        Ok(())
+    }
+    pub fn background(&self) -> &Background {
+        self.skybox.background()
+    }
+    pub fn set_background(&mut self, background: Background) -> Result<()> {
+        unsafe {
+            self.device
+                .device_wait_idle()
+                .expect("something wrong while waiting");
+        }
+        self.skybox.set_background(
+            &self.device,
+            &self.allocator,
+            &self.pools,
+            self.queues.graphics_queue,
+            background,
+        )
+    }
+    /// Appends a full-screen effect to the post-processing chain.
+    pub fn add_effect(&mut self, effect: Effect) -> Result<()> {
+        unsafe {
+            self.device
+                .device_wait_idle()
+                .expect("something wrong while waiting");
+        }
+        self.postprocess.push(
+            &self.device,
+            self.pipeline_cache.cache,
+            &self.allocator,
+            &self.swapchain,
+            effect,
+        )?;
+        self.rewire_postprocess()
+    }
+    #[allow(dead_code)]
+    pub fn remove_effect(&mut self, name: &str) -> Result<()> {
+        unsafe {
+            self.device
+                .device_wait_idle()
+                .expect("something wrong while waiting");
+        }
+        self.postprocess
+            .remove(&self.device, &self.allocator, &self.swapchain, name)?;
+        self.rewire_postprocess()
+    }
+    pub fn set_effect_parameters<T: bytemuck::Pod>(
+        &mut self,
+        name: &str,
+        parameters: &T,
+    ) -> Result<()> {
+        self.postprocess.set_parameters(name, parameters)
+    }
+    fn rewire_postprocess(&mut self) -> Result<()> {
+        let scene = self.postprocess.output(&self.swapchain);
+        self.bloom.set_input(&self.device, scene)?;
+        self.tonemapping
+            .set_inputs(&self.device, &self.swapchain, scene, &self.bloom)
+    }
+    /// Records `dispatch` into every frame from now on. The device must not be
+    /// using the buffers it writes.
+    #[allow(dead_code)]
+    pub fn add_compute_dispatch(&mut self, dispatch: ComputeDispatch) {
+        self.compute_dispatches.push(dispatch);
+    }
+    /// Lets `dt` seconds of simulated time pass for the particles, which catch
+    /// up in the next `update_commandbuffer`. Meant to be called from the
+    /// steps of a `FixedTimestep`.
+    pub fn advance(&mut self, dt: f32) {
+        self.particles.advance(dt);
+    }
+    /// Adds a particle emitter drawn in the scene pass from the next frame on.
+    pub fn add_emitter(&mut self, emitter: Emitter) -> Result<EmitterHandle> {
+        self.particles.add_emitter(
+            &self.device,
+            &self.allocator,
+            &self.pools,
+            self.queues.graphics_queue,
+            emitter,
+        )
+    }
+    /// Recompiles the shaders changed on disk and rebuilds the pipelines using
+    /// them. Whatever fails to compile or link is logged, the title bar shows
+    /// it, and the last good pipeline stays in use. Effects of the
+    /// post-processing chain are given as SPIR-V and are not reloaded.
+    #[cfg(feature = "hot-reload")]
+    pub fn reload_shaders(&mut self) -> Result<()> {
+        let reload = self.shader_watcher.poll();
+        if reload.compiled.is_empty() && reload.failed.is_empty() {
+            return Ok(());
+        }
+        unsafe {
+            self.device
+                .device_wait_idle()
+                .expect("something wrong while waiting");
+        }
+        let mut failed = reload.failed;
+        let mut names: Vec<&str> = vec![];
+        for shader in &reload.compiled {
+            if !names.contains(&shader.name.as_str()) {
+                names.push(&shader.name);
+            }
+        }
+        for name in names {
+            let previous: Vec<(&str, Option<Vec<u32>>)> = reload
+                .compiled
+                .iter()
+                .filter(|shader| shader.name == name)
+                .map(|shader| {
+                    let spirv = Some(shader.spirv.clone());
+                    let previous = hot_reload::install(name, &shader.permutation, spirv);
+                    (shader.permutation.as_str(), previous)
+                })
+                .collect();
+            match self.reload_pipelines(name) {
+                Ok(()) => log::info!("Reloaded {}", name),
+                Err(e) => {
+                    log::error!("Keeping the previous {}: {:?}", name, e);
+                    for (permutation, spirv) in previous {
+                        hot_reload::install(name, permutation, spirv);
+                    }
+                    failed.push(name.to_string());
+                }
+            }
+        }
+        if !failed.is_empty() {
+            self.window
+                .set_title(&format!("Shader errors in {}", failed.join(", ")));
+            self.shader_errors = true;
+        } else if self.shader_errors {
+            self.window.set_title("ashy");
+            self.shader_errors = false;
+        }
         Ok(())
     }
-    pub fn update_commandbuffer(&mut self, index: usize) -> Result<(), vk::Result> {
+    #[cfg(feature = "hot-reload")]
+    fn reload_pipelines(&mut self, shader: &str) -> Result<()> {
+        let scene = self.postprocess.output(&self.swapchain);
+        match shader {
+            "shader.vert" | "shader.frag" => {
+                let pipeline = Pipeline::init(
+                    &self.device,
+                    self.pipeline_cache.cache,
+                    &self.renderpass,
+                    self.depth,
+                    &self.scene_permutation,
+                )?;
+                self.pipeline.cleanup(&self.device);
+                self.pipeline = pipeline;
+            }
+            "skybox.vert" | "skybox.frag" => {
+                self.skybox.recreate_pipeline(
+                    &self.device,
+                    self.pipeline_cache.cache,
+                    &self.renderpass,
+                    self.depth,
+                )?;
+            }
+            "fullscreen.vert" | "tonemap.frag" => {
+                if shader == "fullscreen.vert" {
+                    self.bloom.recreate_pipelines(
+                        &self.device,
+                        self.pipeline_cache.cache,
+                        scene,
+                    )?;
+                }
+                self.tonemapping.recreate(
+                    &self.device,
+                    self.pipeline_cache.cache,
+                    &self.swapchain,
+                    &self.present_renderpass,
+                    scene,
+                    &self.bloom,
+                )?;
+            }
+            "bloom_downsample.frag" | "bloom_upsample.frag" => {
+                self.bloom
+                    .recreate_pipelines(&self.device, self.pipeline_cache.cache, scene)?;
+                self.tonemapping
+                    .set_inputs(&self.device, &self.swapchain, scene, &self.bloom)?;
+            }
+            "cull.comp" | "depth_pyramid.comp" => {
+                if let Some(gpu_culling) = &mut self.gpu_culling {
+                    gpu_culling.recreate_pipelines(&self.device, self.pipeline_cache.cache)?;
+                }
+            }
+            "id.vert" | "id.frag" => {
+                if let Some(id_buffer) = &mut self.id_buffer {
+                    id_buffer.recreate_pipeline(
+                        &self.device,
+                        self.pipeline_cache.cache,
+                        self.depth,
+                    )?;
+                }
+            }
+            "particles.vert" | "particles.frag" | "particles_simulate.comp" => {
+                self.particles.recreate_pipelines(
+                    &self.device,
+                    self.pipeline_cache.cache,
+                    &self.renderpass,
+                    self.depth,
+                )?;
+            }
+            _ => log::warn!("{} is not used by a built-in pipeline", shader),
+        }
+        Ok(())
+    }
+    pub fn update_commandbuffer(&mut self, index: usize) -> Result<()> {
         let commandbuffer = self.commandbuffers[index];
         let commandbuffer_begininfo = vk::CommandBufferBeginInfo::builder();
         unsafe {
             self.device
                 .begin_command_buffer(commandbuffer, &commandbuffer_begininfo)?;
         }
+        record_dispatches(
+            &self.device,
+            &self.allocator,
+            commandbuffer,
+            &self.compute_dispatches,
+            DispatchOrder::BeforeGraphics,
+        )?;
+        self.particles
+            .simulate(&self.device, &self.allocator, commandbuffer)?;
+        if let (Some(gpu_culling), Culling::Gpu { occlusion }) =
+            (&mut self.gpu_culling, self.culling)
+        {
+            gpu_culling.record_cull(
+                &self.device,
+                &self.allocator,
+                commandbuffer,
+                &self.models,
+                self.uniformbuffer.buffer,
+                self.depth,
+                occlusion,
+            )?;
+        }
         let clearvalues = [
             vk::ClearValue {
                 color: vk::ClearColorValue {
-                    float32: [0.0, 0.0, 0.08, 1.0],
+                    float32: self.skybox.background().clear_colour(),
                 },
             },
             vk::ClearValue {
                 depth_stencil: vk::ClearDepthStencilValue {
-                    depth: 1.0,
+                    depth: self.depth.far(),
                     stencil: 0,
                 },
             },
         ];
-        let renderpass_begininfo = vk::RenderPassBeginInfo::builder()
-            .render_pass(self.renderpass)
-            .framebuffer(self.swapchain.framebuffers[index])
-            .render_area(vk::Rect2D {
-                offset: vk::Offset2D { x: 0, y: 0 },
-                extent: self.swapchain.extent,
-            })
-            .clear_values(&clearvalues);
-        unsafe {
-            self.device.cmd_begin_render_pass(
-                commandbuffer,
-                &renderpass_begininfo,
-                vk::SubpassContents::INLINE,
-            );
-            self.device.cmd_bind_pipeline(
-                commandbuffer,
-                vk::PipelineBindPoint::GRAPHICS,
-                self.pipeline.pipeline,
-            );
-            self.device.cmd_bind_descriptor_sets(
+        let extent = self.swapchain.extent;
+        let scene_framebuffer = self.swapchain.scene_framebuffer;
+        let present_framebuffer = self.swapchain.framebuffers[index];
+        let camera_set = self.descriptor_sets_camera[index];
+        let light_set = self.descriptor_sets_light[index];
+        let (device, renderpass, present_renderpass) =
+            (&self.device, self.renderpass, self.present_renderpass);
+        let (pipeline, models, skybox, particles) =
+            (&self.pipeline, &self.models, &self.skybox, &self.particles);
+        let (tonemapping, bloom) = (&self.tonemapping, &self.bloom);
+        let draw_indirect_count = self.draw_indirect_count.as_ref();
+        let culled: Option<Vec<(vk::Buffer, vk::Buffer)>> =
+            self.gpu_culling.as_ref().map(|gpu_culling| {
+                (0..models.len())
+                    .map(|i| (gpu_culling.instances(i), gpu_culling.commands(i)))
+                    .collect()
+            });
+        let depth = self.depth;
+
+        // The targets are shared between frames in flight, so their first use
+        // waits for the last one of the frame before.
+        let mut graph = RenderGraph::new();
+        let hdr = graph.import_discarded(
+            self.swapchain.hdr.image,
+            vk::ImageAspectFlags::COLOR,
+            Access::Sampled,
+            Some(Access::Sampled),
+        );
+        let depth_target = graph.import_discarded(
+            self.swapchain.depth.image,
+            vk::ImageAspectFlags::DEPTH,
+            Access::DepthAttachmentWrite,
+            Some(Access::DepthAttachmentWrite),
+        );
+        graph
+            .add_pass("scene")
+            .write(hdr, Access::ColourAttachmentWrite)
+            .write(depth_target, Access::DepthAttachmentWrite)
+            .record(move |device, commandbuffer, _| {
+                let renderpass_begininfo = vk::RenderPassBeginInfo::builder()
+                    .render_pass(renderpass)
+                    .framebuffer(scene_framebuffer)
+                    .render_area(vk::Rect2D {
+                        offset: vk::Offset2D { x: 0, y: 0 },
+                        extent,
+                    })
+                    .clear_values(&clearvalues);
+                unsafe {
+                    device.cmd_begin_render_pass(
+                        commandbuffer,
+                        &renderpass_begininfo,
+                        vk::SubpassContents::INLINE,
+                    );
+                    set_viewport(device, commandbuffer, extent);
+                    device.cmd_bind_pipeline(
+                        commandbuffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        pipeline.pipeline,
+                    );
+                    device.cmd_bind_descriptor_sets(
+                        commandbuffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        pipeline.layout,
+                        0,
+                        &[camera_set, light_set],
+                        &[],
+                    );
+                    match culled {
+                        Some(culled) => {
+                            for (m, (instances, commands)) in models.iter().zip(culled) {
+                                m.draw_indirect(
+                                    device,
+                                    draw_indirect_count,
+                                    commandbuffer,
+                                    instances,
+                                    commands,
+                                );
+                            }
+                        }
+                        None => {
+                            for m in models {
+                                m.draw(device, commandbuffer);
+                            }
+                        }
+                    }
+                    skybox.draw(device, commandbuffer, camera_set);
+                    particles.draw(device, commandbuffer, camera_set, extent);
+                    device.cmd_end_render_pass(commandbuffer);
+                }
+            });
+        if let (Some(gpu_culling), Culling::Gpu { occlusion: true }) =
+            (&mut self.gpu_culling, self.culling)
+        {
+            graph
+                .add_pass("depth pyramid")
+                .read(depth_target, Access::ComputeSampled)
+                .record(move |device, commandbuffer, _| {
+                    gpu_culling.record_pyramid(device, commandbuffer, depth)
+                });
+        }
+        let scene = self.postprocess.add_passes(&mut graph, hdr);
+        let bloom_output = bloom.add_passes(&mut graph, scene);
+        graph
+            .add_pass("tonemap")
+            .read(scene, Access::FragmentSampled)
+            .read(bloom_output, Access::FragmentSampled)
+            .record(move |device, commandbuffer, _| {
+                let present_begininfo = vk::RenderPassBeginInfo::builder()
+                    .render_pass(present_renderpass)
+                    .framebuffer(present_framebuffer)
+                    .render_area(vk::Rect2D {
+                        offset: vk::Offset2D { x: 0, y: 0 },
+                        extent,
+                    });
+                unsafe {
+                    device.cmd_begin_render_pass(
+                        commandbuffer,
+                        &present_begininfo,
+                        vk::SubpassContents::INLINE,
+                    );
+                    tonemapping.draw(device, commandbuffer, bloom);
+                    device.cmd_end_render_pass(commandbuffer);
+                }
+            });
+        graph.execute(device, &self.allocator, commandbuffer)?;
+
+        if let Some(id_buffer) = &mut self.id_buffer {
+            // The caller waited for this frame's fence, so its earlier copies
+            // are complete.
+            let frame = self.swapchain.current_image;
+            id_buffer.collect(&self.allocator, frame)?;
+            id_buffer.record(
+                &self.device,
+                &self.allocator,
                 commandbuffer,
-                vk::PipelineBindPoint::GRAPHICS,
-                self.pipeline.layout,
-                0,
-                &[
-                    self.descriptor_sets_camera[index],
-                    self.descriptor_sets_light[index],
-                ],
-                &[],
-            );
-            for m in &self.models {
-                m.draw(&self.device, commandbuffer);
+                frame,
+                &self.models,
+                camera_set,
+            )?;
+        }
+        record_dispatches(
+            &self.device,
+            &self.allocator,
+            commandbuffer,
+            &self.compute_dispatches,
+            DispatchOrder::AfterGraphics,
+        )?;
+        unsafe { self.device.end_command_buffer(commandbuffer) }?;
+        Ok(())
+    }
+}
+
+/// Camera and light descriptor sets for each swapchain image, from a pool
+/// sized for exactly these.
+fn create_descriptor_sets(
+    logical_device: &ash::Device,
+    pipeline: &Pipeline,
+    amount_of_images: u32,
+    uniformbuffer: &Buffer,
+    lightbuffer: &Buffer,
+) -> Result<(
+    vk::DescriptorPool,
+    Vec<vk::DescriptorSet>,
+    Vec<vk::DescriptorSet>,
+)> {
+    let pool_sizes = [
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::UNIFORM_BUFFER,
+            descriptor_count: amount_of_images,
+        },
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: amount_of_images,
+        },
+    ];
+    let descriptor_pool_info = vk::DescriptorPoolCreateInfo::builder()
+        .max_sets(2 * amount_of_images)
+        .pool_sizes(&pool_sizes);
+    let descriptor_pool =
+        unsafe { logical_device.create_descriptor_pool(&descriptor_pool_info, None) }?;
+
+    let desc_layouts_camera = vec![pipeline.descriptor_set_layouts[0]; amount_of_images as usize];
+    let descriptor_set_allocate_info_camera = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(descriptor_pool)
+        .set_layouts(&desc_layouts_camera);
+    let descriptor_sets_camera =
+        unsafe { logical_device.allocate_descriptor_sets(&descriptor_set_allocate_info_camera) }?;
+
+    for descset in &descriptor_sets_camera {
+        let buffer_infos = [vk::DescriptorBufferInfo {
+            buffer: uniformbuffer.buffer,
+            offset: 0,
+            range: 128,
+        }];
+        let desc_sets_write = [vk::WriteDescriptorSet::builder()
+            .dst_set(*descset)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .buffer_info(&buffer_infos)
+            .build()];
+        unsafe { logical_device.update_descriptor_sets(&desc_sets_write, &[]) };
+    }
+    let desc_layouts_light = vec![pipeline.descriptor_set_layouts[1]; amount_of_images as usize];
+    let descriptor_set_allocate_info_light = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(descriptor_pool)
+        .set_layouts(&desc_layouts_light);
+    let descriptor_sets_light =
+        unsafe { logical_device.allocate_descriptor_sets(&descriptor_set_allocate_info_light) }?;
+
+    for descset in &descriptor_sets_light {
+        let buffer_infos = [vk::DescriptorBufferInfo {
+            buffer: lightbuffer.buffer,
+            offset: 0,
+            range: lightbuffer.size_in_bytes,
+        }];
+        let desc_sets_write = [vk::WriteDescriptorSet::builder()
+            .dst_set(*descset)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&buffer_infos)
+            .build()];
+        unsafe { logical_device.update_descriptor_sets(&desc_sets_write, &[]) };
+    }
+    Ok((
+        descriptor_pool,
+        descriptor_sets_camera,
+        descriptor_sets_light,
+    ))
+}
+
+fn window_extent(window: &winit::window::Window) -> vk::Extent2D {
+    let size = window.inner_size();
+    vk::Extent2D {
+        width: size.width,
+        height: size.height,
+    }
+}
+
+impl<V: VertexPosition, I: InstanceTransform> Aetna<V, I> {
+    /// Places what the nodes of `scene` changed since the last call hold, see
+    /// `SceneGraph::update`. Returns whether `lights` need their buffer
+    /// refilled.
+    pub fn update_scene(
+        &mut self,
+        scene: &mut SceneGraph,
+        lights: &mut LightManager,
+    ) -> Result<bool> {
+        scene.update(&mut self.models, &mut self.cameras, lights)
+    }
+    /// Closest visible model instance under the window position `x`, `y`, as
+    /// seen by `camera`.
+    pub fn pick(&self, camera: CameraHandle, x: f32, y: f32) -> Option<Hit> {
+        let ray = self.cameras[camera.0].ray_from_screen(x, y, self.swapchain.extent);
+        picking::pick(&self.models, &ray)
+    }
+    /// Uploads the instances of every model for the next frame, culled to the
+    /// view of `camera` with `Culling::Cpu`. The GPU culls the uploaded
+    /// instances itself with `Culling::Gpu`. Instances of level of detail
+    /// models get the level for their size as seen by `camera` first.
+    pub fn update_instancebuffers(&mut self, camera: CameraHandle) -> Result<()> {
+        for lod_model in &mut self.lod_models {
+            lod_model.select(&mut self.models, &self.cameras[camera.0])?;
+        }
+        let frustum = self.cameras[camera.0].frustum();
+        let mut stats = CullingStats::default();
+        for model in &mut self.models {
+            match self.culling {
+                Culling::Off => model.update_instancebuffer(&self.allocator)?,
+                Culling::Cpu => {
+                    stats += model.update_instancebuffer_culled(&self.allocator, &frustum)?
+                }
+                Culling::Gpu { .. } => {
+                    // Cached for the compute pass, which reads it without the vertex type.
+                    model.bounding_sphere();
+                    model.update_instancebuffer(&self.allocator)?;
+                }
             }
-            self.device.cmd_end_render_pass(commandbuffer);
-            self.device.end_command_buffer(commandbuffer)?;
         }
+        self.culling_stats = stats;
         Ok(())
     }
+    /// What CPU culling did in the last `update_instancebuffers`.
+    pub fn culling_stats(&self) -> CullingStats {
+        self.culling_stats
+    }
 }
 
 impl<V, I> Drop for Aetna<V, I> {
@@ -316,8 +1188,26 @@ impl<V, I> Drop for Aetna<V, I> {
                         .expect("Failed destroy index buffer.")
                 }
             }
+            for dispatch in &self.compute_dispatches {
+                dispatch.pipeline.cleanup(&self.device);
+            }
+            self.skybox.cleanup(&self.device, &self.allocator);
+            if let Some(id_buffer) = &self.id_buffer {
+                id_buffer.cleanup(&self.device, &self.allocator);
+            }
+            if let Some(gpu_culling) = &self.gpu_culling {
+                gpu_culling.cleanup(&self.device, &self.allocator);
+            }
+            self.particles.cleanup(&self.device, &self.allocator);
+            self.bloom.cleanup(&self.device, &self.allocator);
+            self.postprocess.cleanup(&self.device, &self.allocator);
             self.pools.cleanup(&self.device);
+            self.tonemapping.cleanup(&self.device);
             self.pipeline.cleanup(&self.device);
+            self.pipeline_cache.save(&self.device);
+            self.pipeline_cache.cleanup(&self.device);
+            self.device
+                .destroy_render_pass(self.present_renderpass, None);
             self.device.destroy_render_pass(self.renderpass, None);
             self.swapchain.cleanup(&self.device, &self.allocator);
             self.allocator.destroy();
@@ -0,0 +1,464 @@
+use crate::{
+    pool_and_commandbuffer::{one_time_submit, Pools},
+    render_graph::{Access, ImageHandle, RenderGraph},
+    render_target::{create_sampler, RenderTarget, HDR_FORMAT},
+    renderpass_and_pipeline::{init_offscreen_renderpass, set_viewport, Pipeline},
+    shaders,
+    swapchain::SwapchainDongXi,
+};
+use ash::{version::DeviceV1_0, vk};
+use eyre::*;
+
+const MAX_LEVELS: usize = 6;
+
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+struct BloomParameters {
+    texel_size: [f32; 2],
+    threshold: f32,
+    knee: f32,
+    radius: f32,
+    prefilter: u32,
+}
+
+unsafe impl bytemuck::Zeroable for BloomParameters {}
+unsafe impl bytemuck::Pod for BloomParameters {}
+
+struct BloomLevel {
+    target: RenderTarget,
+    extent: vk::Extent2D,
+    framebuffer: vk::Framebuffer,
+}
+
+/// Downsample/upsample chain over the HDR scene colour. Level 0 is half the
+/// scene resolution and ends up holding the sum of all levels, which the
+/// tonemapping pass blends with the scene.
+pub struct Bloom {
+    pub enabled: bool,
+    /// Brightness where bloom starts. Zero lets every pixel scatter, which is
+    /// what a real lens does.
+    pub threshold: f32,
+    /// Width of the soft transition around the threshold, relative to it.
+    pub knee: f32,
+    /// Fraction of the scene colour replaced by the scattered light.
+    pub intensity: f32,
+    /// Upsample filter radius in texels of the smaller level.
+    pub radius: f32,
+    levels: Vec<BloomLevel>,
+    scene_extent: vk::Extent2D,
+    downsample_renderpass: vk::RenderPass,
+    upsample_renderpass: vk::RenderPass,
+    downsample_pipeline: Pipeline,
+    upsample_pipeline: Pipeline,
+    sampler: vk::Sampler,
+    descriptor_pool: vk::DescriptorPool,
+    downsample_sets: Vec<vk::DescriptorSet>,
+    upsample_sets: Vec<vk::DescriptorSet>,
+}
+
+impl Bloom {
+    pub fn init(
+        logical_device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
+        allocator: &vk_mem::Allocator,
+        pools: &Pools,
+        queue: vk::Queue,
+        swapchain: &SwapchainDongXi,
+        scene: vk::ImageView,
+    ) -> Result<Bloom> {
+        let downsample_renderpass = init_offscreen_renderpass(logical_device, HDR_FORMAT, false)?;
+        let upsample_renderpass = init_offscreen_renderpass(logical_device, HDR_FORMAT, true)?;
+        let (downsample_pipeline, upsample_pipeline) = Bloom::create_pipelines(
+            logical_device,
+            pipeline_cache,
+            &downsample_renderpass,
+            &upsample_renderpass,
+        )?;
+        let sampler = create_sampler(logical_device)?;
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 2 * MAX_LEVELS as u32,
+        }];
+        let descriptor_pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(2 * MAX_LEVELS as u32)
+            .pool_sizes(&pool_sizes);
+        let descriptor_pool =
+            unsafe { logical_device.create_descriptor_pool(&descriptor_pool_info, None) }?;
+        let mut bloom = Bloom {
+            enabled: true,
+            threshold: 0.0,
+            knee: 0.5,
+            intensity: 0.04,
+            radius: 1.0,
+            levels: vec![],
+            scene_extent: swapchain.extent,
+            downsample_renderpass,
+            upsample_renderpass,
+            downsample_pipeline,
+            upsample_pipeline,
+            sampler,
+            descriptor_pool,
+            downsample_sets: vec![],
+            upsample_sets: vec![],
+        };
+        bloom.create_levels(logical_device, allocator, pools, queue, swapchain, scene)?;
+        Ok(bloom)
+    }
+
+    fn create_pipelines(
+        logical_device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
+        downsample_renderpass: &vk::RenderPass,
+        upsample_renderpass: &vk::RenderPass,
+    ) -> Result<(Pipeline, Pipeline)> {
+        let size = std::mem::size_of::<BloomParameters>() as u32;
+        let ds_src = shaders::spirv("bloom_downsample.frag", "");
+        let downsample_pipeline = Pipeline::init_fullscreen(
+            logical_device,
+            pipeline_cache,
+            downsample_renderpass,
+            &ds_src,
+            1,
+            size,
+            false,
+        )?;
+        let us_src = shaders::spirv("bloom_upsample.frag", "");
+        let upsample_pipeline = match Pipeline::init_fullscreen(
+            logical_device,
+            pipeline_cache,
+            upsample_renderpass,
+            &us_src,
+            1,
+            size,
+            true,
+        ) {
+            Ok(pipeline) => pipeline,
+            Err(e) => {
+                downsample_pipeline.cleanup(logical_device);
+                return Err(e);
+            }
+        };
+        Ok((downsample_pipeline, upsample_pipeline))
+    }
+
+    /// Rebuilds both pipelines from the current shader code, keeping the old
+    /// ones if that fails. The device must be idle.
+    #[allow(dead_code)]
+    pub fn recreate_pipelines(
+        &mut self,
+        logical_device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
+        scene: vk::ImageView,
+    ) -> Result<()> {
+        let (downsample_pipeline, upsample_pipeline) = Bloom::create_pipelines(
+            logical_device,
+            pipeline_cache,
+            &self.downsample_renderpass,
+            &self.upsample_renderpass,
+        )?;
+        self.downsample_pipeline.cleanup(logical_device);
+        self.upsample_pipeline.cleanup(logical_device);
+        self.downsample_pipeline = downsample_pipeline;
+        self.upsample_pipeline = upsample_pipeline;
+        self.set_input(logical_device, scene)
+    }
+
+    fn create_levels(
+        &mut self,
+        logical_device: &ash::Device,
+        allocator: &vk_mem::Allocator,
+        pools: &Pools,
+        queue: vk::Queue,
+        swapchain: &SwapchainDongXi,
+        scene: vk::ImageView,
+    ) -> Result<()> {
+        self.scene_extent = swapchain.extent;
+        let mut extent = swapchain.extent;
+        while self.levels.len() < MAX_LEVELS && extent.width >= 2 && extent.height >= 2 {
+            extent = vk::Extent2D {
+                width: extent.width / 2,
+                height: extent.height / 2,
+            };
+            let target = RenderTarget::new(
+                logical_device,
+                allocator,
+                extent,
+                HDR_FORMAT,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            )?;
+            let iview = [target.imageview];
+            // Both render passes are compatible, so one framebuffer serves both.
+            let framebuffer_info = vk::FramebufferCreateInfo::builder()
+                .render_pass(self.downsample_renderpass)
+                .attachments(&iview)
+                .width(extent.width)
+                .height(extent.height)
+                .layers(1);
+            let framebuffer =
+                unsafe { logical_device.create_framebuffer(&framebuffer_info, None) }?;
+            self.levels.push(BloomLevel {
+                target,
+                extent,
+                framebuffer,
+            });
+        }
+        if self.levels.is_empty() {
+            bail!("Swapchain is too small for bloom");
+        }
+
+        // The tonemapping pass samples level 0 even when bloom is off, so every
+        // level has to start out in the layout it is sampled in.
+        let mut graph = RenderGraph::new();
+        for level in &self.levels {
+            graph.import_image(
+                level.target.image,
+                vk::ImageAspectFlags::COLOR,
+                None,
+                Some(Access::Sampled),
+            );
+        }
+        let mut recorded = Ok(());
+        one_time_submit(logical_device, pools, queue, |commandbuffer| {
+            recorded = graph.execute(logical_device, allocator, commandbuffer);
+        })?;
+        recorded?;
+
+        self.set_input(logical_device, scene)
+    }
+
+    /// Points the first downsample at a different scene colour image.
+    pub fn set_input(&mut self, logical_device: &ash::Device, scene: vk::ImageView) -> Result<()> {
+        unsafe {
+            logical_device
+                .reset_descriptor_pool(self.descriptor_pool, vk::DescriptorPoolResetFlags::empty())
+        }?;
+        let count = self.levels.len();
+        let desc_layouts = vec![self.downsample_pipeline.descriptor_set_layouts[0]; count];
+        let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.descriptor_pool)
+            .set_layouts(&desc_layouts);
+        self.downsample_sets =
+            unsafe { logical_device.allocate_descriptor_sets(&descriptor_set_allocate_info) }?;
+        let desc_layouts = vec![self.upsample_pipeline.descriptor_set_layouts[0]; count - 1];
+        self.upsample_sets = if desc_layouts.is_empty() {
+            vec![]
+        } else {
+            let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(self.descriptor_pool)
+                .set_layouts(&desc_layouts);
+            unsafe { logical_device.allocate_descriptor_sets(&descriptor_set_allocate_info) }?
+        };
+
+        // Level i is downsampled from the level above it (the scene for level 0)
+        // and upsampled from the level below it.
+        let mut sources = Vec::with_capacity(2 * count - 1);
+        for (i, set) in self.downsample_sets.iter().enumerate() {
+            let view = if i == 0 {
+                scene
+            } else {
+                self.levels[i - 1].target.imageview
+            };
+            sources.push((*set, view));
+        }
+        for (i, set) in self.upsample_sets.iter().enumerate() {
+            sources.push((*set, self.levels[i + 1].target.imageview));
+        }
+        for (set, view) in sources {
+            let image_infos = [vk::DescriptorImageInfo {
+                sampler: self.sampler,
+                image_view: view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            }];
+            let desc_sets_write = [vk::WriteDescriptorSet::builder()
+                .dst_set(set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&image_infos)
+                .build()];
+            unsafe { logical_device.update_descriptor_sets(&desc_sets_write, &[]) };
+        }
+        Ok(())
+    }
+
+    /// Rebuilds the chain for a new swapchain. The device must be idle.
+    pub fn recreate(
+        &mut self,
+        logical_device: &ash::Device,
+        allocator: &vk_mem::Allocator,
+        pools: &Pools,
+        queue: vk::Queue,
+        swapchain: &SwapchainDongXi,
+        scene: vk::ImageView,
+    ) -> Result<()> {
+        self.destroy_levels(logical_device, allocator);
+        self.create_levels(logical_device, allocator, pools, queue, swapchain, scene)
+    }
+
+    /// View holding the composited bloom, sampled by the tonemapping pass.
+    pub fn output(&self) -> vk::ImageView {
+        self.levels[0].target.imageview
+    }
+
+    /// Blend factor and normalisation for the bloom output. The chain sums
+    /// every level, so it is divided by their number.
+    pub fn composite_weights(&self) -> (f32, f32) {
+        if self.enabled {
+            (self.intensity, 1.0 / self.levels.len() as f32)
+        } else {
+            (0.0, 0.0)
+        }
+    }
+
+    /// Adds the chain over `scene` to `graph` and returns the level the
+    /// tonemapping pass samples, which is left untouched while bloom is off.
+    pub fn add_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        scene: ImageHandle,
+    ) -> ImageHandle {
+        let levels: Vec<ImageHandle> = self
+            .levels
+            .iter()
+            .map(|level| {
+                graph.import_image(
+                    level.target.image,
+                    vk::ImageAspectFlags::COLOR,
+                    Some(Access::Sampled),
+                    Some(Access::Sampled),
+                )
+            })
+            .collect();
+        if !self.enabled {
+            return levels[0];
+        }
+        let mut source = scene;
+        let mut source_extent = self.scene_extent;
+        for (i, level) in self.levels.iter().enumerate() {
+            let parameters = BloomParameters {
+                texel_size: texel_size(source_extent),
+                threshold: self.threshold,
+                knee: self.knee,
+                radius: self.radius,
+                prefilter: (i == 0) as u32,
+            };
+            graph
+                .add_pass("bloom downsample")
+                .read(source, Access::FragmentSampled)
+                .write(levels[i], Access::ColourAttachmentWrite)
+                .record(move |device, commandbuffer, _| {
+                    self.draw_level(
+                        device,
+                        commandbuffer,
+                        self.downsample_renderpass,
+                        &self.downsample_pipeline,
+                        self.downsample_sets[i],
+                        level,
+                        &parameters,
+                    )
+                });
+            source = levels[i];
+            source_extent = level.extent;
+        }
+        for i in (0..self.levels.len() - 1).rev() {
+            let parameters = BloomParameters {
+                texel_size: texel_size(self.levels[i + 1].extent),
+                threshold: self.threshold,
+                knee: self.knee,
+                radius: self.radius,
+                prefilter: 0,
+            };
+            // Blends onto the downsampled contents of the level.
+            graph
+                .add_pass("bloom upsample")
+                .read(levels[i + 1], Access::FragmentSampled)
+                .write(levels[i], Access::ColourAttachmentWrite)
+                .record(move |device, commandbuffer, _| {
+                    self.draw_level(
+                        device,
+                        commandbuffer,
+                        self.upsample_renderpass,
+                        &self.upsample_pipeline,
+                        self.upsample_sets[i],
+                        &self.levels[i],
+                        &parameters,
+                    )
+                });
+        }
+        levels[0]
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn draw_level(
+        &self,
+        logical_device: &ash::Device,
+        commandbuffer: vk::CommandBuffer,
+        renderpass: vk::RenderPass,
+        pipeline: &Pipeline,
+        descriptor_set: vk::DescriptorSet,
+        level: &BloomLevel,
+        parameters: &BloomParameters,
+    ) {
+        let renderpass_begininfo = vk::RenderPassBeginInfo::builder()
+            .render_pass(renderpass)
+            .framebuffer(level.framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: level.extent,
+            });
+        unsafe {
+            logical_device.cmd_begin_render_pass(
+                commandbuffer,
+                &renderpass_begininfo,
+                vk::SubpassContents::INLINE,
+            );
+        }
+        set_viewport(logical_device, commandbuffer, level.extent);
+        unsafe {
+            logical_device.cmd_bind_pipeline(
+                commandbuffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline.pipeline,
+            );
+            logical_device.cmd_bind_descriptor_sets(
+                commandbuffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline.layout,
+                0,
+                &[descriptor_set],
+                &[],
+            );
+            logical_device.cmd_push_constants(
+                commandbuffer,
+                pipeline.layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                bytemuck::bytes_of(parameters),
+            );
+            logical_device.cmd_draw(commandbuffer, 3, 1, 0, 0);
+            logical_device.cmd_end_render_pass(commandbuffer);
+        }
+    }
+
+    fn destroy_levels(&mut self, logical_device: &ash::Device, allocator: &vk_mem::Allocator) {
+        for level in self.levels.drain(..) {
+            unsafe { logical_device.destroy_framebuffer(level.framebuffer, None) };
+            level.target.cleanup(logical_device, allocator);
+        }
+    }
+
+    pub fn cleanup(&mut self, logical_device: &ash::Device, allocator: &vk_mem::Allocator) {
+        self.destroy_levels(logical_device, allocator);
+        unsafe {
+            logical_device.destroy_descriptor_pool(self.descriptor_pool, None);
+            logical_device.destroy_sampler(self.sampler, None);
+            logical_device.destroy_render_pass(self.downsample_renderpass, None);
+            logical_device.destroy_render_pass(self.upsample_renderpass, None);
+        }
+        self.downsample_pipeline.cleanup(logical_device);
+        self.upsample_pipeline.cleanup(logical_device);
+    }
+}
+
+fn texel_size(extent: vk::Extent2D) -> [f32; 2] {
+    [1.0 / extent.width as f32, 1.0 / extent.height as f32]
+}
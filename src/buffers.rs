@@ -57,4 +57,45 @@ impl Buffer {
         allocator.unmap_memory(&self.allocation)?;
         Ok(())
     }
+    /// Like `fill`, with the elements of `data` at `selection`, in that order.
+    pub fn fill_selection<T: Sized>(
+        &mut self,
+        allocator: &vk_mem::Allocator,
+        data: &[T],
+        selection: &[usize],
+    ) -> Result<(), vk_mem::error::Error> {
+        let bytes_to_write = (selection.len() * std::mem::size_of::<T>()) as u64;
+        if bytes_to_write > self.size_in_bytes {
+            allocator.destroy_buffer(self.buffer, &self.allocation)?;
+            let newbuffer = Buffer::new(
+                allocator,
+                bytes_to_write,
+                self.buffer_usage,
+                self.memory_usage,
+            )?;
+            *self = newbuffer;
+        }
+        let data_ptr = allocator.map_memory(&self.allocation)? as *mut T;
+        for (i, &index) in selection.iter().enumerate() {
+            unsafe { data_ptr.add(i).copy_from_nonoverlapping(&data[index], 1) };
+        }
+        allocator.unmap_memory(&self.allocation)?;
+        Ok(())
+    }
+    /// Copies the first `count` elements out of a host visible buffer.
+    pub fn read<T: Copy>(
+        &self,
+        allocator: &vk_mem::Allocator,
+        count: usize,
+    ) -> Result<Vec<T>, vk_mem::error::Error> {
+        assert!((count * std::mem::size_of::<T>()) as u64 <= self.size_in_bytes);
+        let mut data = Vec::with_capacity(count);
+        let data_ptr = allocator.map_memory(&self.allocation)? as *const T;
+        unsafe {
+            data_ptr.copy_to_nonoverlapping(data.as_mut_ptr(), count);
+            data.set_len(count);
+        }
+        allocator.unmap_memory(&self.allocation)?;
+        Ok(data)
+    }
 }
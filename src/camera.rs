@@ -1,37 +1,231 @@
-use crate::buffers::Buffer;
+use crate::{
+    buffers::Buffer,
+    culling::{BoundingSphere, Frustum},
+    picking::Ray,
+    renderpass_and_pipeline::DepthConvention,
+};
+use ash::vk;
 use nalgebra as na;
 
+/// How view space is mapped to clip space. View space has x to the right,
+/// y down and z forward.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection {
+    Perspective {
+        fovy: f32,
+        near: f32,
+        far: f32,
+    },
+    /// Perspective without a far plane.
+    InfinitePerspective {
+        fovy: f32,
+        near: f32,
+    },
+    /// Perspective with near at depth 1 and far at depth 0, without a far plane
+    /// if `far` is `None`.
+    ReverseZ {
+        fovy: f32,
+        near: f32,
+        far: Option<f32>,
+    },
+    /// Parallel projection of a box `height` units high.
+    Orthographic {
+        height: f32,
+        near: f32,
+        far: f32,
+    },
+}
+
+impl Projection {
+    pub fn depth_convention(&self) -> DepthConvention {
+        match self {
+            Projection::ReverseZ { .. } => DepthConvention::Reversed,
+            _ => DepthConvention::Standard,
+        }
+    }
+
+    pub fn near(&self) -> f32 {
+        match *self {
+            Projection::Perspective { near, .. }
+            | Projection::InfinitePerspective { near, .. }
+            | Projection::ReverseZ { near, .. }
+            | Projection::Orthographic { near, .. } => near,
+        }
+    }
+
+    pub fn far(&self) -> Option<f32> {
+        match *self {
+            Projection::Perspective { far, .. } | Projection::Orthographic { far, .. } => Some(far),
+            Projection::InfinitePerspective { .. } => None,
+            Projection::ReverseZ { far, .. } => far,
+        }
+    }
+
+    fn set_near(&mut self, value: f32) {
+        match self {
+            Projection::Perspective { near, .. }
+            | Projection::InfinitePerspective { near, .. }
+            | Projection::ReverseZ { near, .. }
+            | Projection::Orthographic { near, .. } => *near = value,
+        }
+    }
+
+    /// Does nothing for projections without a far plane.
+    fn set_far(&mut self, value: f32) {
+        match self {
+            Projection::Perspective { far, .. } | Projection::Orthographic { far, .. } => {
+                *far = value
+            }
+            Projection::ReverseZ { far: Some(far), .. } => *far = value,
+            _ => {}
+        }
+    }
+
+    /// `None` for orthographic projections.
+    pub fn fovy(&self) -> Option<f32> {
+        match *self {
+            Projection::Perspective { fovy, .. }
+            | Projection::InfinitePerspective { fovy, .. }
+            | Projection::ReverseZ { fovy, .. } => Some(fovy),
+            Projection::Orthographic { .. } => None,
+        }
+    }
+
+    /// Does nothing for orthographic projections.
+    fn set_fovy(&mut self, value: f32) {
+        match self {
+            Projection::Perspective { fovy, .. }
+            | Projection::InfinitePerspective { fovy, .. }
+            | Projection::ReverseZ { fovy, .. } => *fovy = value,
+            Projection::Orthographic { .. } => {}
+        }
+    }
+
+    pub fn matrix(&self, aspect: f32) -> na::Matrix4<f32> {
+        let perspective = |fovy: f32, z_scale: f32, z_offset: f32| {
+            let d = 1.0 / (0.5 * fovy).tan();
+            na::Matrix4::new(
+                d / aspect,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                d,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                z_scale,
+                z_offset,
+                0.0,
+                0.0,
+                1.0,
+                0.0,
+            )
+        };
+        match *self {
+            Projection::Perspective { fovy, near, far } => {
+                perspective(fovy, far / (far - near), -near * far / (far - near))
+            }
+            Projection::InfinitePerspective { fovy, near } => perspective(fovy, 1.0, -near),
+            Projection::ReverseZ {
+                fovy,
+                near,
+                far: Some(far),
+            } => perspective(fovy, -near / (far - near), near * far / (far - near)),
+            Projection::ReverseZ {
+                fovy,
+                near,
+                far: None,
+            } => perspective(fovy, 0.0, near),
+            Projection::Orthographic { height, near, far } => na::Matrix4::new(
+                2.0 / (height * aspect),
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                2.0 / height,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                1.0 / (far - near),
+                -near / (far - near),
+                0.0,
+                0.0,
+                0.0,
+                1.0,
+            ),
+        }
+    }
+}
+
+/// Axis-aligned views for inspecting a scene, see `Camera::look_along_axis`.
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AxisView {
+    /// Looking along +z.
+    Front,
+    /// Looking along -z.
+    Back,
+    /// Looking along -x.
+    Right,
+    /// Looking along +x.
+    Left,
+    /// Looking down along +y, as up is -y.
+    Top,
+    /// Looking up along -y.
+    Bottom,
+}
+
+impl AxisView {
+    /// View and down direction of the view.
+    pub fn directions(self) -> (na::Vector3<f32>, na::Vector3<f32>) {
+        match self {
+            AxisView::Front => (na::Vector3::z(), na::Vector3::y()),
+            AxisView::Back => (-na::Vector3::z(), na::Vector3::y()),
+            AxisView::Right => (-na::Vector3::x(), na::Vector3::y()),
+            AxisView::Left => (na::Vector3::x(), na::Vector3::y()),
+            AxisView::Top => (na::Vector3::y(), -na::Vector3::z()),
+            AxisView::Bottom => (-na::Vector3::y(), na::Vector3::z()),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Camera {
     viewmatrix: na::Matrix4<f32>,
     position: na::Vector3<f32>,
     view_direction: na::Unit<na::Vector3<f32>>,
     down_direction: na::Unit<na::Vector3<f32>>,
-    fovy: f32,
+    projection: Projection,
     aspect: f32,
-    near: f32,
-    far: f32,
     projectionmatrix: na::Matrix4<f32>,
 }
 
+/// Camera registered with the renderer, see `Aetna::register_camera`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CameraHandle(pub(crate) usize);
+
 pub struct CameraBuilder {
     position: na::Vector3<f32>,
     view_direction: na::Unit<na::Vector3<f32>>,
     down_direction: na::Unit<na::Vector3<f32>>,
-    fovy: f32,
+    projection: Projection,
     aspect: f32,
-    near: f32,
-    far: f32,
 }
 
 #[allow(dead_code)]
 impl CameraBuilder {
     pub fn build(self) -> Camera {
-        if self.far < self.near {
-            println!(
-                "far plane (at {}) closer than near plane (at {}) — is that right?",
-                self.far, self.near
-            );
+        if let Some(far) = self.projection.far() {
+            if far < self.projection.near() {
+                println!(
+                    "far plane (at {}) closer than near plane (at {}) — is that right?",
+                    far,
+                    self.projection.near()
+                );
+            }
         }
         let mut cam = Camera {
             position: self.position,
@@ -44,10 +238,8 @@ impl CameraBuilder {
                         .dot(self.view_direction.as_ref())
                         * self.view_direction.as_ref(),
             ),
-            fovy: self.fovy,
+            projection: self.projection,
             aspect: self.aspect,
-            near: self.near,
-            far: self.far,
             viewmatrix: na::Matrix4::identity(),
             projectionmatrix: na::Matrix4::identity(),
         };
@@ -56,78 +248,70 @@ impl CameraBuilder {
         cam
     }
 
-    fn position(mut self, pos: na::Vector3<f32>) -> CameraBuilder {
+    pub fn position(mut self, pos: na::Vector3<f32>) -> CameraBuilder {
         self.position = pos;
         self
     }
 
-    fn fovy(mut self, fovy: f32) -> CameraBuilder {
-        self.fovy = fovy.max(0.01).min(std::f32::consts::PI - 0.01);
+    pub fn fovy(mut self, fovy: f32) -> CameraBuilder {
+        self.projection
+            .set_fovy(fovy.clamp(0.01, std::f32::consts::PI - 0.01));
         self
     }
 
-    fn aspect(mut self, aspect: f32) -> CameraBuilder {
+    /// Replaces the field of view and planes set so far.
+    pub fn projection(mut self, projection: Projection) -> CameraBuilder {
+        self.projection = projection;
+        self
+    }
+
+    pub fn aspect(mut self, aspect: f32) -> CameraBuilder {
         self.aspect = aspect;
         self
     }
 
-    fn near(mut self, near: f32) -> CameraBuilder {
+    pub fn near(mut self, near: f32) -> CameraBuilder {
         if near <= 0.0 {
             println!("setting near plane to negative value: {} — you sure?", near);
         }
-        self.near = near;
+        self.projection.set_near(near);
         self
     }
-    fn far(mut self, far: f32) -> CameraBuilder {
+    pub fn far(mut self, far: f32) -> CameraBuilder {
         if far <= 0.0 {
             println!("setting far plane to negative value: {} — you sure?", far);
         }
-        self.far = far;
+        self.projection.set_far(far);
         self
     }
     // TODO(#2): Do nothing if vector is already normalized
-    fn view_direction(mut self, direction: na::Vector3<f32>) -> CameraBuilder {
+    pub fn view_direction(mut self, direction: na::Vector3<f32>) -> CameraBuilder {
         self.view_direction = na::Unit::new_normalize(direction);
         self
     }
-    fn down_direction(mut self, direction: na::Vector3<f32>) -> CameraBuilder {
+    pub fn down_direction(mut self, direction: na::Vector3<f32>) -> CameraBuilder {
         self.down_direction = na::Unit::new_normalize(direction);
         self
     }
 }
 
+#[allow(dead_code)]
 impl Camera {
     pub fn builder() -> CameraBuilder {
         CameraBuilder {
             position: na::Vector3::new(0.0, -3.0, -3.0),
             view_direction: na::Unit::new_normalize(na::Vector3::new(0.0, 1.0, 1.0)),
             down_direction: na::Unit::new_normalize(na::Vector3::new(0.0, 1.0, -1.0)),
-            fovy: std::f32::consts::FRAC_PI_3,
+            projection: Projection::Perspective {
+                fovy: std::f32::consts::FRAC_PI_3,
+                near: 0.1,
+                far: 100.0,
+            },
             aspect: 800.0 / 600.0,
-            near: 0.1,
-            far: 100.0,
         }
     }
     fn update_projectionmatrix(&mut self) {
-        let d = 1.0 / (0.5 * self.fovy).tan();
-        self.projectionmatrix = na::Matrix4::new(
-            d / self.aspect,
-            0.0,
-            0.0,
-            0.0,
-            0.0,
-            d,
-            0.0,
-            0.0,
-            0.0,
-            0.0,
-            self.far / (self.far - self.near),
-            -self.near * self.far / (self.far - self.near),
-            0.0,
-            0.0,
-            1.0,
-            0.0,
-        );
+        self.projectionmatrix = self.projection.matrix(self.aspect);
     }
     pub fn update_buffer(
         &self,
@@ -159,7 +343,7 @@ impl Camera {
             0.0,
             1.0,
         );
-        self.viewmatrix = self.projectionmatrix * m;
+        self.viewmatrix = m;
     }
 
     pub fn move_forward(&mut self, distance: f32) {
@@ -197,4 +381,98 @@ impl Camera {
         self.aspect = aspect;
         self.update_projectionmatrix();
     }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+        self.update_projectionmatrix();
+    }
+
+    /// Vertical field of view in radians, kept within the same limits as by
+    /// the builder. Does nothing for orthographic projections.
+    pub fn set_fovy(&mut self, fovy: f32) {
+        self.projection
+            .set_fovy(fovy.clamp(0.01, std::f32::consts::PI - 0.01));
+        self.update_projectionmatrix();
+    }
+    pub fn position(&self) -> na::Vector3<f32> {
+        self.position
+    }
+
+    pub fn view_direction(&self) -> na::Unit<na::Vector3<f32>> {
+        self.view_direction
+    }
+
+    pub fn down_direction(&self) -> na::Unit<na::Vector3<f32>> {
+        self.down_direction
+    }
+
+    /// Places the camera at `position` looking along `view_direction`. The down
+    /// direction is made perpendicular to the view.
+    pub fn set_pose(
+        &mut self,
+        position: na::Vector3<f32>,
+        view_direction: na::Unit<na::Vector3<f32>>,
+        down_direction: na::Unit<na::Vector3<f32>>,
+    ) {
+        self.position = position;
+        self.view_direction = view_direction;
+        self.down_direction = na::Unit::new_normalize(
+            down_direction.as_ref()
+                - down_direction.dot(view_direction.as_ref()) * view_direction.as_ref(),
+        );
+        self.update_viewmatrix();
+    }
+
+    /// What the camera sees, for culling.
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_matrix(&(self.projectionmatrix * self.viewmatrix))
+    }
+
+    /// Diameter of `sphere` on screen as a fraction of the viewport height,
+    /// e.g. to pick a level of detail. Spheres around the camera are
+    /// unbounded.
+    pub fn screen_size(&self, sphere: &BoundingSphere) -> f32 {
+        let centre = self.viewmatrix.transform_point(&sphere.centre);
+        // Clip w is the view depth for perspective projections and one for
+        // orthographic ones.
+        let w = (self.projectionmatrix.row(3) * centre.to_homogeneous())[0];
+        if w <= sphere.radius * self.projectionmatrix[(3, 2)].abs() {
+            return f32::INFINITY;
+        }
+        sphere.radius * self.projectionmatrix[(1, 1)].abs() / w
+    }
+
+    /// Ray through the pixel position `x`, `y` of an image of size `extent`,
+    /// e.g. the cursor position in the window. It starts on the near plane.
+    pub fn ray_from_screen(&self, x: f32, y: f32, extent: vk::Extent2D) -> Ray {
+        let ndc_x = 2.0 * x / extent.width as f32 - 1.0;
+        let ndc_y = 2.0 * y / extent.height as f32 - 1.0;
+        let inverse = (self.projectionmatrix * self.viewmatrix)
+            .try_inverse()
+            .expect("camera matrices are invertible");
+        // The second point lies halfway into the depth range, which is at a
+        // finite distance even for projections without a far plane.
+        let near_depth = 1.0 - self.projection.depth_convention().far();
+        let origin = inverse.transform_point(&na::Point3::new(ndc_x, ndc_y, near_depth));
+        let further = inverse.transform_point(&na::Point3::new(ndc_x, ndc_y, 0.5));
+        Ray {
+            origin,
+            direction: na::Unit::new_normalize(further - origin),
+        }
+    }
+
+    /// Places the camera `distance` away from `target`, looking at it along
+    /// the axis of `view`.
+    pub fn look_along_axis(&mut self, view: AxisView, target: na::Vector3<f32>, distance: f32) {
+        let (view_direction, down_direction) = view.directions();
+        self.set_pose(
+            target - distance * view_direction,
+            na::Unit::new_unchecked(view_direction),
+            na::Unit::new_unchecked(down_direction),
+        );
+    }
 }
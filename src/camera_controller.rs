@@ -0,0 +1,446 @@
+use crate::{camera::Camera, input::Input};
+use nalgebra as na;
+use winit::{
+    dpi::PhysicalSize,
+    event::{MouseScrollDelta, WindowEvent},
+};
+
+/// Keeps pitch away from the poles, where yaw stops meaning anything.
+const PITCH_LIMIT: f32 = std::f32::consts::FRAC_PI_2 - 0.01;
+
+/// Moves a [`Camera`] from window input. Cursor and wheel events are collected
+/// as they arrive and applied once per frame by [`CameraController::update`],
+/// buttons are read as the actions of an [`Input`] that has already seen the
+/// event.
+pub trait CameraController {
+    /// Returns whether the controller made use of `event`.
+    fn handle_event(&mut self, event: &WindowEvent, input: &Input) -> bool;
+    /// Advances by `dt` seconds and places `camera` accordingly.
+    fn update(&mut self, camera: &mut Camera, input: &Input, dt: f32);
+}
+
+/// Fraction of the way to a goal covered in `dt` seconds by exponential
+/// smoothing with `time_constant`. A time constant of zero jumps right there.
+fn smoothing_factor(time_constant: f32, dt: f32) -> f32 {
+    if time_constant <= 0.0 {
+        1.0
+    } else {
+        1.0 - (-dt / time_constant).exp()
+    }
+}
+
+fn scroll_lines(delta: &MouseScrollDelta) -> f32 {
+    match delta {
+        MouseScrollDelta::LineDelta(_, y) => *y,
+        MouseScrollDelta::PixelDelta(position) => position.y as f32 / 50.0,
+    }
+}
+
+/// Reference frame for yaw around `up` and pitch above the horizontal plane.
+/// Zero yaw looks along `forward`, positive yaw turns towards `right`.
+#[derive(Copy, Clone, Debug)]
+struct Horizon {
+    up: na::Unit<na::Vector3<f32>>,
+    forward: na::Unit<na::Vector3<f32>>,
+    right: na::Unit<na::Vector3<f32>>,
+}
+
+impl Horizon {
+    /// `forward` is projected onto the horizontal plane.
+    fn new(up: na::Vector3<f32>, forward: na::Vector3<f32>) -> Horizon {
+        let up = na::Unit::new_normalize(up);
+        let forward = na::Unit::try_new(forward - forward.dot(&up) * up.as_ref(), 1e-6)
+            .unwrap_or_else(|| {
+                // Looking straight up or down, any horizontal direction will do.
+                let other = if up.x.abs() < 0.9 {
+                    na::Vector3::x()
+                } else {
+                    na::Vector3::z()
+                };
+                na::Unit::new_normalize(other - other.dot(&up) * up.as_ref())
+            });
+        let right = na::Unit::new_normalize(forward.cross(&up));
+        Horizon { up, forward, right }
+    }
+
+    fn direction(&self, yaw: f32, pitch: f32) -> na::Unit<na::Vector3<f32>> {
+        na::Unit::new_normalize(
+            pitch.cos() * (yaw.cos() * self.forward.as_ref() + yaw.sin() * self.right.as_ref())
+                + pitch.sin() * self.up.as_ref(),
+        )
+    }
+
+    /// Yaw and pitch of `direction`.
+    fn angles(&self, direction: &na::Vector3<f32>) -> (f32, f32) {
+        let direction = direction.normalize();
+        let pitch = direction.dot(&self.up).clamp(-1.0, 1.0).asin();
+        let yaw = direction
+            .dot(&self.right)
+            .atan2(direction.dot(&self.forward));
+        (yaw, pitch.clamp(-PITCH_LIMIT, PITCH_LIMIT))
+    }
+
+    fn down(&self) -> na::Unit<na::Vector3<f32>> {
+        na::Unit::new_unchecked(-self.up.into_inner())
+    }
+}
+
+/// First-person flight: `move_forward`, `move_backward`, `move_left` and
+/// `move_right` move and strafe in the horizontal plane, `move_up` and
+/// `move_down` rise and sink, `boost` speeds up, and dragging while `look` is
+/// held looks around.
+#[derive(Clone, Debug)]
+pub struct FpsController {
+    /// Units per second.
+    pub speed: f32,
+    /// Speed multiplier while `boost` is held.
+    pub boost: f32,
+    /// Radians per pixel of mouse movement.
+    pub sensitivity: f32,
+    /// Time constant in seconds with which the view follows the mouse.
+    pub smoothing: f32,
+    /// Rate per second at which the velocity approaches the keys' demand, so
+    /// the camera accelerates and coasts. Zero starts and stops instantly.
+    pub damping: f32,
+    horizon: Horizon,
+    position: na::Vector3<f32>,
+    velocity: na::Vector3<f32>,
+    yaw: f32,
+    pitch: f32,
+    goal_yaw: f32,
+    goal_pitch: f32,
+    cursor: Option<(f64, f64)>,
+}
+
+impl FpsController {
+    /// Takes over from wherever `camera` is, with `up` as the vertical.
+    pub fn from_camera(camera: &Camera, up: na::Vector3<f32>) -> FpsController {
+        let horizon = Horizon::new(up, camera.view_direction().into_inner());
+        let (yaw, pitch) = horizon.angles(&camera.view_direction());
+        FpsController {
+            speed: 3.0,
+            boost: 4.0,
+            sensitivity: 0.003,
+            smoothing: 0.03,
+            damping: 12.0,
+            horizon,
+            position: camera.position(),
+            velocity: na::Vector3::zeros(),
+            yaw,
+            pitch,
+            goal_yaw: yaw,
+            goal_pitch: pitch,
+            cursor: None,
+        }
+    }
+}
+
+impl CameraController for FpsController {
+    fn handle_event(&mut self, event: &WindowEvent, input: &Input) -> bool {
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                let looking = input.is_held("look");
+                if let (true, Some((x, y))) = (looking, self.cursor) {
+                    self.goal_yaw += (position.x - x) as f32 * self.sensitivity;
+                    self.goal_pitch = (self.goal_pitch
+                        - (position.y - y) as f32 * self.sensitivity)
+                        .clamp(-PITCH_LIMIT, PITCH_LIMIT);
+                }
+                self.cursor = Some((position.x, position.y));
+                looking
+            }
+            _ => false,
+        }
+    }
+
+    fn update(&mut self, camera: &mut Camera, input: &Input, dt: f32) {
+        let t = smoothing_factor(self.smoothing, dt);
+        self.yaw += (self.goal_yaw - self.yaw) * t;
+        self.pitch += (self.goal_pitch - self.pitch) * t;
+        let view = self.horizon.direction(self.yaw, self.pitch);
+        let forward = self.horizon.direction(self.yaw, 0.0).into_inner();
+        let right = forward.cross(&self.horizon.up);
+        let up = self.horizon.up.into_inner();
+        let mut demand = input.axis("move_backward", "move_forward") * forward
+            + input.axis("move_left", "move_right") * right
+            + input.axis("move_down", "move_up") * up;
+        if demand.norm_squared() > 0.0 {
+            let speed = if input.is_held("boost") {
+                self.speed * self.boost
+            } else {
+                self.speed
+            };
+            demand = demand.normalize() * speed;
+        }
+        let a = if self.damping <= 0.0 {
+            1.0
+        } else {
+            1.0 - (-self.damping * dt).exp()
+        };
+        self.velocity += (demand - self.velocity) * a;
+        self.position += self.velocity * dt;
+        camera.set_pose(self.position, view, self.horizon.down());
+    }
+}
+
+/// Circles a target point: dragging while `orbit` is held orbits, while `pan`
+/// is held pans the target, and scrolling zooms.
+#[derive(Clone, Debug)]
+pub struct OrbitController {
+    /// Radians per pixel of mouse movement.
+    pub sensitivity: f32,
+    /// Fraction of the distance covered by one line of scrolling.
+    pub zoom_speed: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    /// Time constant in seconds with which the camera follows the input.
+    pub smoothing: f32,
+    /// Rate per second at which the spin left by a released drag dies down.
+    /// Zero stops as soon as the button is released.
+    pub damping: f32,
+    horizon: Horizon,
+    target: na::Vector3<f32>,
+    goal_target: na::Vector3<f32>,
+    yaw: f32,
+    pitch: f32,
+    distance: f32,
+    goal_yaw: f32,
+    goal_pitch: f32,
+    goal_distance: f32,
+    spin: (f32, f32),
+    dragged: (f32, f32),
+    cursor: Option<(f64, f64)>,
+}
+
+impl OrbitController {
+    /// Orbits `target` from wherever `camera` is, with `up` as the vertical.
+    pub fn from_camera(
+        camera: &Camera,
+        target: na::Vector3<f32>,
+        up: na::Vector3<f32>,
+    ) -> OrbitController {
+        let offset = target - camera.position();
+        let horizon = Horizon::new(up, offset);
+        let (yaw, pitch) = horizon.angles(&offset);
+        let distance = offset.norm().max(1e-3);
+        OrbitController {
+            sensitivity: 0.005,
+            zoom_speed: 0.1,
+            min_distance: 0.1,
+            max_distance: 1000.0,
+            smoothing: 0.05,
+            damping: 4.0,
+            horizon,
+            target,
+            goal_target: target,
+            yaw,
+            pitch,
+            distance,
+            goal_yaw: yaw,
+            goal_pitch: pitch,
+            goal_distance: distance,
+            spin: (0.0, 0.0),
+            dragged: (0.0, 0.0),
+            cursor: None,
+        }
+    }
+}
+
+impl CameraController for OrbitController {
+    fn handle_event(&mut self, event: &WindowEvent, input: &Input) -> bool {
+        let rotating = input.is_held("orbit");
+        let panning = input.is_held("pan");
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                if let Some((x, y)) = self.cursor {
+                    let dx = (position.x - x) as f32;
+                    let dy = (position.y - y) as f32;
+                    if rotating {
+                        let (yaw, pitch) = (dx * self.sensitivity, -dy * self.sensitivity);
+                        self.goal_yaw += yaw;
+                        self.goal_pitch =
+                            (self.goal_pitch + pitch).clamp(-PITCH_LIMIT, PITCH_LIMIT);
+                        self.dragged.0 += yaw;
+                        self.dragged.1 += pitch;
+                    }
+                    if panning {
+                        // Drags the scene along, so the target moves against the cursor.
+                        let view = self.horizon.direction(self.goal_yaw, self.goal_pitch);
+                        let right = na::Unit::new_normalize(view.cross(&self.horizon.up));
+                        let down = view.cross(&right);
+                        let scale = self.goal_distance * self.sensitivity * 0.2;
+                        self.goal_target -= (dx * right.into_inner() + dy * down) * scale;
+                    }
+                }
+                self.cursor = Some((position.x, position.y));
+                rotating || panning
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.goal_distance = (self.goal_distance
+                    * (1.0 - self.zoom_speed).powf(scroll_lines(delta)))
+                .max(self.min_distance)
+                .min(self.max_distance);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn update(&mut self, camera: &mut Camera, input: &Input, dt: f32) {
+        if input.is_held("orbit") {
+            if dt > 0.0 {
+                self.spin = (self.dragged.0 / dt, self.dragged.1 / dt);
+            }
+        } else if self.damping > 0.0 {
+            self.goal_yaw += self.spin.0 * dt;
+            self.goal_pitch = (self.goal_pitch + self.spin.1 * dt).clamp(-PITCH_LIMIT, PITCH_LIMIT);
+            let decay = (-self.damping * dt).exp();
+            self.spin = (self.spin.0 * decay, self.spin.1 * decay);
+        } else {
+            self.spin = (0.0, 0.0);
+        }
+        self.dragged = (0.0, 0.0);
+        let t = smoothing_factor(self.smoothing, dt);
+        self.yaw += (self.goal_yaw - self.yaw) * t;
+        self.pitch += (self.goal_pitch - self.pitch) * t;
+        self.distance += (self.goal_distance - self.distance) * t;
+        self.target += (self.goal_target - self.target) * t;
+        let view = self.horizon.direction(self.yaw, self.pitch);
+        let position = self.target - self.distance * view.as_ref();
+        camera.set_pose(position, view, self.horizon.down());
+    }
+}
+
+/// Turns the view around a target as if rolling a ball under the cursor, with
+/// no preferred vertical. Drag while `rotate` is held, scroll to zoom.
+#[derive(Clone, Debug)]
+pub struct ArcballController {
+    /// Fraction of the distance covered by one line of scrolling.
+    pub zoom_speed: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    /// Time constant in seconds with which the camera follows the input.
+    pub smoothing: f32,
+    /// Rate per second at which the spin left by a released drag dies down.
+    /// Zero stops as soon as the button is released.
+    pub damping: f32,
+    target: na::Vector3<f32>,
+    /// Takes the camera's right, down and view axes to world space.
+    orientation: na::UnitQuaternion<f32>,
+    goal_orientation: na::UnitQuaternion<f32>,
+    distance: f32,
+    goal_distance: f32,
+    /// Angular velocity about camera axes, as axis times radians per second.
+    spin: na::Vector3<f32>,
+    dragged: na::UnitQuaternion<f32>,
+    window_size: PhysicalSize<u32>,
+    cursor: Option<(f64, f64)>,
+}
+
+impl ArcballController {
+    /// Rolls around `target` from wherever `camera` is. The ball fills the
+    /// smaller side of a window of `window_size`.
+    pub fn from_camera(
+        camera: &Camera,
+        target: na::Vector3<f32>,
+        window_size: PhysicalSize<u32>,
+    ) -> ArcballController {
+        let view = camera.view_direction().into_inner();
+        let down = camera.down_direction().into_inner();
+        let right = down.cross(&view);
+        let orientation = na::UnitQuaternion::from_rotation_matrix(
+            &na::Rotation3::from_matrix_unchecked(na::Matrix3::from_columns(&[right, down, view])),
+        );
+        let distance = (target - camera.position()).norm().max(1e-3);
+        ArcballController {
+            zoom_speed: 0.1,
+            min_distance: 0.1,
+            max_distance: 1000.0,
+            smoothing: 0.05,
+            damping: 4.0,
+            target,
+            orientation,
+            goal_orientation: orientation,
+            distance,
+            goal_distance: distance,
+            spin: na::Vector3::zeros(),
+            dragged: na::UnitQuaternion::identity(),
+            window_size,
+            cursor: None,
+        }
+    }
+
+    /// Point on the ball under the cursor, in camera axes. Outside the ball the
+    /// cursor slides along its rim.
+    fn ball_point(&self, x: f64, y: f64) -> na::Vector3<f32> {
+        let width = self.window_size.width.max(1) as f64;
+        let height = self.window_size.height.max(1) as f64;
+        let radius = 0.5 * width.min(height);
+        let px = ((x - 0.5 * width) / radius) as f32;
+        let py = ((y - 0.5 * height) / radius) as f32;
+        let d2 = px * px + py * py;
+        if d2 <= 1.0 {
+            // The visible half of the ball faces the camera, against the view axis.
+            na::Vector3::new(px, py, -(1.0 - d2).sqrt())
+        } else {
+            na::Vector3::new(px, py, 0.0).normalize()
+        }
+    }
+}
+
+impl CameraController for ArcballController {
+    fn handle_event(&mut self, event: &WindowEvent, input: &Input) -> bool {
+        let rotating = input.is_held("rotate");
+        match event {
+            WindowEvent::Resized(size) => {
+                self.window_size = *size;
+                false
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                if let (true, Some((x, y))) = (rotating, self.cursor) {
+                    let from = self.ball_point(x, y);
+                    let to = self.ball_point(position.x, position.y);
+                    // Rolling the scene one way turns the camera the other.
+                    if let Some(roll) = na::UnitQuaternion::rotation_between(&to, &from) {
+                        self.goal_orientation *= roll;
+                        self.dragged *= roll;
+                    }
+                }
+                self.cursor = Some((position.x, position.y));
+                rotating
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.goal_distance = (self.goal_distance
+                    * (1.0 - self.zoom_speed).powf(scroll_lines(delta)))
+                .max(self.min_distance)
+                .min(self.max_distance);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn update(&mut self, camera: &mut Camera, input: &Input, dt: f32) {
+        if input.is_held("rotate") {
+            if dt > 0.0 {
+                self.spin = self.dragged.scaled_axis() / dt;
+            }
+        } else if self.damping > 0.0 {
+            self.goal_orientation *= na::UnitQuaternion::from_scaled_axis(self.spin * dt);
+            self.spin *= (-self.damping * dt).exp();
+        } else {
+            self.spin = na::Vector3::zeros();
+        }
+        self.dragged = na::UnitQuaternion::identity();
+        let t = smoothing_factor(self.smoothing, dt);
+        self.orientation = self
+            .orientation
+            .try_slerp(&self.goal_orientation, t, 1e-6)
+            .unwrap_or(self.goal_orientation);
+        self.distance += (self.goal_distance - self.distance) * t;
+        let view = self.orientation * na::Vector3::z_axis();
+        let down = self.orientation * na::Vector3::y_axis();
+        let position = self.target - self.distance * view.as_ref();
+        camera.set_pose(position, view, down);
+    }
+}
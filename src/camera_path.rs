@@ -0,0 +1,437 @@
+use crate::camera::Camera;
+use eyre::*;
+use nalgebra as na;
+use std::path::Path;
+
+/// Placement of a [`Camera`] that can be restored exactly.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CameraPose {
+    pub position: na::Vector3<f32>,
+    pub view_direction: na::Unit<na::Vector3<f32>>,
+    pub down_direction: na::Unit<na::Vector3<f32>>,
+    /// `None` for orthographic projections, whose height is left alone.
+    pub fovy: Option<f32>,
+}
+
+impl CameraPose {
+    pub fn of(camera: &Camera) -> CameraPose {
+        CameraPose {
+            position: camera.position(),
+            view_direction: camera.view_direction(),
+            down_direction: camera.down_direction(),
+            fovy: camera.projection().fovy(),
+        }
+    }
+
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.set_pose(self.position, self.view_direction, self.down_direction);
+        if let Some(fovy) = self.fovy {
+            camera.set_fovy(fovy);
+        }
+    }
+
+    /// Rotation taking view space, x right, y down and z forward, to world
+    /// space.
+    fn orientation(&self) -> na::UnitQuaternion<f32> {
+        let view = self.view_direction.into_inner();
+        let down = self.down_direction.into_inner();
+        let right = down.cross(&view).normalize();
+        // Down may not be quite perpendicular to view after parsing.
+        let down = view.cross(&right);
+        na::UnitQuaternion::from_rotation_matrix(&na::Rotation3::from_matrix_unchecked(
+            na::Matrix3::from_columns(&[right, down, view]),
+        ))
+    }
+
+    fn from_orientation(
+        position: na::Vector3<f32>,
+        orientation: na::UnitQuaternion<f32>,
+        fovy: Option<f32>,
+    ) -> CameraPose {
+        CameraPose {
+            position,
+            view_direction: orientation * na::Vector3::z_axis(),
+            down_direction: orientation * na::Vector3::y_axis(),
+            fovy,
+        }
+    }
+
+    /// Ten numbers: position, view and down direction, then the field of view
+    /// in radians or `-`.
+    fn parse<'a>(words: &mut impl Iterator<Item = &'a str>) -> Result<CameraPose> {
+        let mut numbers = [0.0f32; 9];
+        for number in &mut numbers {
+            *number = words
+                .next()
+                .context("Too few numbers for a pose")?
+                .parse()?;
+        }
+        let fovy = match words.next().context("Pose without a field of view")? {
+            "-" => None,
+            fovy => Some(fovy.parse()?),
+        };
+        if words.next().is_some() {
+            bail!("Too many numbers for a pose");
+        }
+        let vector = |i: usize| na::Vector3::new(numbers[i], numbers[i + 1], numbers[i + 2]);
+        let direction = |i: usize| {
+            na::Unit::try_new(vector(i), f32::EPSILON).context("Pose with a zero direction")
+        };
+        Ok(CameraPose {
+            position: vector(0),
+            view_direction: direction(3)?,
+            down_direction: direction(6)?,
+            fovy,
+        })
+    }
+
+    fn to_text(self) -> String {
+        let fovy = self
+            .fovy
+            .map_or_else(|| "-".to_string(), |fovy| fovy.to_string());
+        format!(
+            "{} {} {}  {} {} {}  {} {} {}  {}",
+            self.position.x,
+            self.position.y,
+            self.position.z,
+            self.view_direction.x,
+            self.view_direction.y,
+            self.view_direction.z,
+            self.down_direction.x,
+            self.down_direction.y,
+            self.down_direction.z,
+            fovy
+        )
+    }
+}
+
+/// Lines of `text` without comments and blank lines, numbered from one.
+fn content_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(number, line)| (number + 1, line.split('#').next().unwrap_or("").trim()))
+        .filter(|(_, line)| !line.is_empty())
+}
+
+/// Named camera poses, kept in a text file with one bookmark per line.
+#[derive(Clone, Debug, Default)]
+pub struct Bookmarks {
+    poses: Vec<(String, CameraPose)>,
+}
+
+#[allow(dead_code)]
+impl Bookmarks {
+    pub fn get(&self, name: &str) -> Option<&CameraPose> {
+        self.poses
+            .iter()
+            .find(|(known, _)| known == name)
+            .map(|(_, pose)| pose)
+    }
+
+    /// Adds the bookmark `name` or moves it to `pose`.
+    pub fn set(&mut self, name: &str, pose: CameraPose) {
+        match self.poses.iter_mut().find(|(known, _)| known == name) {
+            Some((_, known)) => *known = pose,
+            None => self.poses.push((name.to_string(), pose)),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<CameraPose> {
+        let index = self.poses.iter().position(|(known, _)| known == name)?;
+        Some(self.poses.remove(index).1)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.poses.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Each line holds a name without whitespace followed by a pose, see
+    /// [`Bookmarks::to_text`]. `#` starts a comment.
+    pub fn parse(text: &str) -> Result<Bookmarks> {
+        let mut bookmarks = Bookmarks::default();
+        for (number, line) in content_lines(text) {
+            let mut words = line.split_whitespace();
+            let name = words.next().unwrap_or_default();
+            let pose = CameraPose::parse(&mut words)
+                .with_context(|| format!("line {}: bookmark {}", number, name))?;
+            bookmarks.set(name, pose);
+        }
+        Ok(bookmarks)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::from(
+            "# name  position  view direction  down direction  fovy in radians or -\n",
+        );
+        for (name, pose) in &self.poses {
+            text += &format!("{}  {}\n", name, pose.to_text());
+        }
+        text
+    }
+
+    /// Bookmarks saved at `path`, none if there is no such file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Bookmarks> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Bookmarks::default());
+        }
+        Bookmarks::parse(&std::fs::read_to_string(path)?)
+            .with_context(|| format!("Failed to load {}", path.display()))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, self.to_text())?;
+        Ok(())
+    }
+}
+
+/// How a [`CameraPath`] moves between keyframes. Orientations are always
+/// interpolated by quaternion slerp.
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Interpolation {
+    /// Positions and field of view follow a Catmull-Rom spline through the
+    /// keyframes.
+    CatmullRom,
+    /// Positions and field of view move in straight lines.
+    Slerp,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum PathState {
+    Idle,
+    /// Seconds from the last keyframe to the previous frame, and the pose
+    /// then, if there was one.
+    Recording(f32, Option<CameraPose>),
+    /// Seconds since the first keyframe.
+    Playing(f32),
+}
+
+/// Camera keyframes taken every `step` seconds, recorded from a camera while
+/// it moves and played back onto one.
+#[derive(Clone, Debug)]
+pub struct CameraPath {
+    keyframes: Vec<CameraPose>,
+    step: f32,
+    pub interpolation: Interpolation,
+    pub looping: bool,
+    /// Playback advances by this many seconds per `update` instead of the
+    /// frame time, so that every run shows the same frames.
+    pub fixed_step: Option<f32>,
+    state: PathState,
+}
+
+#[allow(dead_code)]
+impl CameraPath {
+    pub fn new(step: f32) -> CameraPath {
+        CameraPath {
+            keyframes: vec![],
+            step: step.max(f32::EPSILON),
+            interpolation: Interpolation::CatmullRom,
+            looping: false,
+            fixed_step: None,
+            state: PathState::Idle,
+        }
+    }
+
+    pub fn keyframes(&self) -> &[CameraPose] {
+        &self.keyframes
+    }
+
+    /// Appends a keyframe `step` seconds after the last one.
+    pub fn push(&mut self, pose: CameraPose) {
+        self.keyframes.push(pose);
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.len().saturating_sub(1) as f32 * self.step
+    }
+
+    /// Drops the keyframes and takes new ones from the camera passed to
+    /// `update`.
+    pub fn record(&mut self) {
+        self.keyframes.clear();
+        self.state = PathState::Recording(0.0, None);
+    }
+
+    /// Starts placing the camera passed to `update` from the first keyframe.
+    pub fn play(&mut self) {
+        if !self.keyframes.is_empty() {
+            self.state = PathState::Playing(0.0);
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.state = PathState::Idle;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        matches!(self.state, PathState::Recording(..))
+    }
+
+    pub fn is_playing(&self) -> bool {
+        matches!(self.state, PathState::Playing(_))
+    }
+
+    /// Advances by `dt` seconds, taking keyframes from `camera` while
+    /// recording and placing it while playing. Playback stops at the end
+    /// unless it loops.
+    pub fn update(&mut self, camera: &mut Camera, dt: f32) {
+        match self.state {
+            PathState::Idle => {}
+            PathState::Recording(since, previous) => {
+                let current = CameraPose::of(camera);
+                let previous = match previous {
+                    Some(previous) => previous,
+                    None => {
+                        self.keyframes.push(current);
+                        self.state = PathState::Recording(0.0, Some(current));
+                        return;
+                    }
+                };
+                // Keyframes due since the previous frame lie on the straight
+                // line the camera moved along as far as they can tell.
+                let mut since = since;
+                while since + dt >= self.step {
+                    let t = (self.step - since) / dt;
+                    self.keyframes
+                        .push(interpolate_linear(&previous, &current, t));
+                    since -= self.step;
+                }
+                self.state = PathState::Recording(since + dt, Some(current));
+            }
+            PathState::Playing(time) => {
+                let mut time = time + self.fixed_step.unwrap_or(dt);
+                let duration = self.duration();
+                if time > duration {
+                    if self.looping && duration > 0.0 {
+                        time %= duration;
+                    } else {
+                        time = duration;
+                        self.state = PathState::Idle;
+                    }
+                }
+                if let Some(pose) = self.sample(time) {
+                    pose.apply(camera);
+                }
+                if self.is_playing() {
+                    self.state = PathState::Playing(time);
+                }
+            }
+        }
+    }
+
+    /// Pose `time` seconds after the first keyframe, clamped to the path.
+    pub fn sample(&self, time: f32) -> Option<CameraPose> {
+        let last = self.keyframes.len().checked_sub(1)?;
+        let position = (time / self.step).clamp(0.0, last as f32);
+        let index = (position.floor() as usize).min(last.saturating_sub(1));
+        let t = position - index as f32;
+        let at = |i: isize| self.keyframes[i.clamp(0, last as isize) as usize];
+        let (p1, p2) = (at(index as isize), at(index as isize + 1));
+        Some(match self.interpolation {
+            Interpolation::Slerp => interpolate_linear(&p1, &p2, t),
+            Interpolation::CatmullRom => {
+                let (p0, p3) = (at(index as isize - 1), at(index as isize + 2));
+                let position = catmull_rom(p0.position, p1.position, p2.position, p3.position, t);
+                let fovy = match (p0.fovy, p1.fovy, p2.fovy, p3.fovy) {
+                    (Some(f0), Some(f1), Some(f2), Some(f3)) => {
+                        Some(catmull_rom(f0, f1, f2, f3, t))
+                    }
+                    _ => interpolate_linear(&p1, &p2, t).fovy,
+                };
+                CameraPose::from_orientation(position, slerp(&p1, &p2, t), fovy)
+            }
+        })
+    }
+
+    /// The first non-comment line is `step` and the seconds between
+    /// keyframes, every further line a keyframe as in [`Bookmarks`] without
+    /// the name.
+    pub fn parse(text: &str) -> Result<CameraPath> {
+        let mut lines = content_lines(text);
+        let (number, line) = lines.next().context("Camera path without a step")?;
+        let step = match line.split_whitespace().collect::<Vec<_>>()[..] {
+            ["step", step] => step
+                .parse()
+                .with_context(|| format!("line {}: bad step", number))?,
+            _ => bail!(
+                "line {}: expected step and the seconds between keyframes",
+                number
+            ),
+        };
+        let mut path = CameraPath::new(step);
+        for (number, line) in lines {
+            let pose = CameraPose::parse(&mut line.split_whitespace())
+                .with_context(|| format!("line {}: keyframe", number))?;
+            path.push(pose);
+        }
+        Ok(path)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "step {}\n# position  view direction  down direction  fovy in radians or -\n",
+            self.step
+        );
+        for pose in &self.keyframes {
+            text += &pose.to_text();
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Camera path saved at `path`, an empty one taking keyframes every 0.1 s
+    /// if there is no such file.
+    pub fn load(path: impl AsRef<Path>) -> Result<CameraPath> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(CameraPath::new(0.1));
+        }
+        CameraPath::parse(&std::fs::read_to_string(path)?)
+            .with_context(|| format!("Failed to load {}", path.display()))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, self.to_text())?;
+        Ok(())
+    }
+}
+
+fn slerp(a: &CameraPose, b: &CameraPose, t: f32) -> na::UnitQuaternion<f32> {
+    let (a, b) = (a.orientation(), b.orientation());
+    // Opposite orientations have no shortest arc; either end will do.
+    a.try_slerp(&b, t, 1.0e-6)
+        .unwrap_or(if t < 0.5 { a } else { b })
+}
+
+fn interpolate_linear(a: &CameraPose, b: &CameraPose, t: f32) -> CameraPose {
+    let fovy = match (a.fovy, b.fovy) {
+        (Some(fa), Some(fb)) => Some(fa + (fb - fa) * t),
+        _ => {
+            if t < 0.5 {
+                a.fovy
+            } else {
+                b.fovy
+            }
+        }
+    };
+    CameraPose::from_orientation(a.position.lerp(&b.position, t), slerp(a, b, t), fovy)
+}
+
+/// Uniform Catmull-Rom spline through `p1` at `t` = 0 and `p2` at `t` = 1.
+fn catmull_rom<T>(p0: T, p1: T, p2: T, p3: T, t: f32) -> T
+where
+    T: Copy
+        + std::ops::Add<Output = T>
+        + std::ops::Sub<Output = T>
+        + std::ops::Mul<f32, Output = T>,
+{
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
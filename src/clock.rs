@@ -0,0 +1,260 @@
+use nalgebra as na;
+use std::collections::VecDeque;
+use std::time::Instant;
+
+/// Frame timing, ticked once per frame: the time since the last frame, the
+/// time since the first and a history of recent frame times.
+pub struct Clock {
+    last_tick: Option<Instant>,
+    delta: f32,
+    time: f64,
+    frame: u64,
+    /// Longest frame time passed on, so that long stalls, e.g. while the
+    /// window was dragged, are not caught up on.
+    pub max_delta: f32,
+    /// Weight of the newest frame in the smoothed frame time.
+    pub smoothing: f32,
+    smoothed: f32,
+    history: VecDeque<f32>,
+    history_length: usize,
+}
+
+impl Default for Clock {
+    fn default() -> Clock {
+        Clock::new(120)
+    }
+}
+
+#[allow(dead_code)]
+impl Clock {
+    /// Keeps the times of the last `history_length` frames.
+    pub fn new(history_length: usize) -> Clock {
+        Clock {
+            last_tick: None,
+            delta: 0.0,
+            time: 0.0,
+            frame: 0,
+            max_delta: 0.1,
+            smoothing: 0.05,
+            smoothed: 0.0,
+            history: VecDeque::with_capacity(history_length),
+            history_length,
+        }
+    }
+
+    /// Starts a frame and returns its delta. The first tick has a delta of
+    /// zero.
+    pub fn tick(&mut self) -> f32 {
+        let now = Instant::now();
+        let frame_time = self
+            .last_tick
+            .map_or(0.0, |last| (now - last).as_secs_f32());
+        self.last_tick = Some(now);
+        self.delta = frame_time.min(self.max_delta);
+        self.time += self.delta as f64;
+        if self.frame > 0 {
+            self.smoothed = if self.history.is_empty() {
+                frame_time
+            } else {
+                self.smoothed + (frame_time - self.smoothed) * self.smoothing
+            };
+            if self.history.len() == self.history_length {
+                self.history.pop_front();
+            }
+            self.history.push_back(frame_time);
+        }
+        self.frame += 1;
+        self.delta
+    }
+
+    /// Seconds between the last two ticks, at most `max_delta`.
+    pub fn delta(&self) -> f32 {
+        self.delta
+    }
+
+    /// Sum of the deltas so far, which falls behind the wall clock by the
+    /// stalls that were cut short.
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// Number of ticks so far.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Frames per second, from the smoothed frame time.
+    pub fn fps(&self) -> f32 {
+        if self.smoothed > 0.0 {
+            1.0 / self.smoothed
+        } else {
+            0.0
+        }
+    }
+
+    /// Recent frame times in seconds, oldest first, unclamped.
+    pub fn frame_times(&self) -> impl Iterator<Item = f32> + '_ {
+        self.history.iter().copied()
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        let count = self.history.len().max(1) as f32;
+        FrameStats {
+            fps: self.fps(),
+            mean: self.history.iter().sum::<f32>() / count,
+            max: self.history.iter().copied().fold(0.0, f32::max),
+        }
+    }
+}
+
+/// Frame times over a `Clock`'s history.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FrameStats {
+    pub fps: f32,
+    /// Seconds.
+    pub mean: f32,
+    /// Seconds.
+    pub max: f32,
+}
+
+impl std::fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.1} fps, {:.2} ms mean, {:.2} ms worst frame",
+            self.fps,
+            self.mean * 1000.0,
+            self.max * 1000.0
+        )
+    }
+}
+
+/// Runs a simulation in steps of constant length however long frames take.
+/// Frames pass their delta to `advance`, which takes as many steps as fit and
+/// carries the rest over, so rendering is a fraction of a step behind the
+/// simulation; see [`Interpolated`] for drawing in between steps.
+#[derive(Clone, Debug)]
+pub struct FixedTimestep {
+    /// Seconds per step.
+    pub step: f32,
+    /// Most steps taken in one `advance`. Time beyond that is dropped, so a
+    /// simulation slower than real time falls behind instead of taking ever
+    /// more steps per frame.
+    pub max_steps: u32,
+    accumulator: f32,
+    steps: u64,
+}
+
+#[allow(dead_code)]
+impl FixedTimestep {
+    pub fn new(step: f32) -> FixedTimestep {
+        FixedTimestep {
+            step,
+            max_steps: 8,
+            accumulator: 0.0,
+            steps: 0,
+        }
+    }
+
+    /// Calls `update` with the step length once for every step that is due
+    /// after `dt` more seconds, and returns [`FixedTimestep::alpha`].
+    pub fn advance(&mut self, dt: f32, mut update: impl FnMut(f32)) -> f32 {
+        self.accumulator += dt;
+        let mut taken = 0;
+        while self.accumulator >= self.step {
+            if taken == self.max_steps {
+                self.accumulator %= self.step;
+                break;
+            }
+            update(self.step);
+            self.accumulator -= self.step;
+            self.steps += 1;
+            taken += 1;
+        }
+        self.alpha()
+    }
+
+    /// Fraction of a step carried over, with which to interpolate between the
+    /// states after the last two steps.
+    pub fn alpha(&self) -> f32 {
+        self.accumulator / self.step
+    }
+
+    /// Steps taken so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Simulated seconds so far.
+    pub fn time(&self) -> f64 {
+        self.steps as f64 * self.step as f64
+    }
+}
+
+/// Values that can be blended, `t` going from zero at `self` to one at `other`.
+pub trait Interpolate {
+    fn interpolate(&self, other: &Self, t: f32) -> Self;
+}
+
+impl Interpolate for f32 {
+    fn interpolate(&self, other: &f32, t: f32) -> f32 {
+        self + (other - self) * t
+    }
+}
+
+impl Interpolate for na::Vector3<f32> {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        self.lerp(other, t)
+    }
+}
+
+impl Interpolate for na::Point3<f32> {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Interpolate for na::UnitQuaternion<f32> {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        // Opposite rotations have no shortest path between them.
+        self.try_slerp(other, t, 1e-6).unwrap_or(*other)
+    }
+}
+
+/// State after the last two simulation steps, to be drawn in between with the
+/// alpha of a [`FixedTimestep`].
+#[derive(Clone, Debug)]
+pub struct Interpolated<T> {
+    previous: T,
+    current: T,
+}
+
+#[allow(dead_code)]
+impl<T: Interpolate + Clone> Interpolated<T> {
+    pub fn new(value: T) -> Interpolated<T> {
+        Interpolated {
+            previous: value.clone(),
+            current: value,
+        }
+    }
+
+    /// Records the state after a step.
+    pub fn push(&mut self, value: T) {
+        self.previous = std::mem::replace(&mut self.current, value);
+    }
+
+    /// Jumps to `value` without blending from the previous state.
+    pub fn reset(&mut self, value: T) {
+        self.previous = value.clone();
+        self.current = value;
+    }
+
+    pub fn current(&self) -> &T {
+        &self.current
+    }
+
+    /// State `alpha` of a step after the previous one.
+    pub fn get(&self, alpha: f32) -> T {
+        self.previous.interpolate(&self.current, alpha)
+    }
+}
@@ -0,0 +1,339 @@
+use crate::{
+    pool_and_commandbuffer::{one_time_submit, Pools},
+    render_graph::{Access, BufferHandle, RenderGraph},
+    renderpass_and_pipeline::Pipeline,
+};
+use ash::{version::DeviceV1_0, vk};
+use eyre::*;
+
+/// Resource bound to one binding of a compute pipeline's set 0.
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug)]
+pub enum ComputeResource {
+    StorageBuffer(vk::Buffer),
+    UniformBuffer(vk::Buffer),
+    SampledImage(vk::ImageView, vk::Sampler),
+    StorageImage(vk::ImageView),
+}
+
+impl ComputeResource {
+    fn descriptor_type(&self) -> vk::DescriptorType {
+        match self {
+            ComputeResource::StorageBuffer(_) => vk::DescriptorType::STORAGE_BUFFER,
+            ComputeResource::UniformBuffer(_) => vk::DescriptorType::UNIFORM_BUFFER,
+            ComputeResource::SampledImage(..) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            ComputeResource::StorageImage(_) => vk::DescriptorType::STORAGE_IMAGE,
+        }
+    }
+}
+
+/// Compute shader together with the descriptor sets it is dispatched with.
+pub struct ComputePipeline {
+    pub pipeline: Pipeline,
+    bindings: Vec<vk::DescriptorType>,
+    push_constant_size: u32,
+    descriptor_pool: vk::DescriptorPool,
+}
+
+#[allow(dead_code)]
+impl ComputePipeline {
+    /// Set 0 has one descriptor of each of `bindings`. Up to `max_sets`
+    /// descriptor sets can be created with [`ComputePipeline::create_descriptor_set`].
+    pub fn init(
+        logical_device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
+        compute_shader: &[u32],
+        bindings: &[vk::DescriptorType],
+        push_constant_size: u32,
+        max_sets: u32,
+    ) -> Result<ComputePipeline> {
+        let pipeline = Pipeline::init_compute(
+            logical_device,
+            pipeline_cache,
+            compute_shader,
+            bindings,
+            push_constant_size,
+        )?;
+        let mut pool_sizes: Vec<vk::DescriptorPoolSize> = vec![];
+        for ty in bindings {
+            match pool_sizes.iter_mut().find(|size| size.ty == *ty) {
+                Some(size) => size.descriptor_count += max_sets,
+                None => pool_sizes.push(vk::DescriptorPoolSize {
+                    ty: *ty,
+                    descriptor_count: max_sets,
+                }),
+            }
+        }
+        let descriptor_pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(max_sets)
+            .pool_sizes(&pool_sizes);
+        let descriptor_pool =
+            unsafe { logical_device.create_descriptor_pool(&descriptor_pool_info, None) }?;
+        Ok(ComputePipeline {
+            pipeline,
+            bindings: bindings.to_vec(),
+            push_constant_size,
+            descriptor_pool,
+        })
+    }
+
+    /// Rebuilds the pipeline from `compute_shader` with the same bindings. Sets
+    /// created before stay valid, since the new layout is identical.
+    pub fn recreate(
+        &mut self,
+        logical_device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
+        compute_shader: &[u32],
+    ) -> Result<()> {
+        let pipeline = Pipeline::init_compute(
+            logical_device,
+            pipeline_cache,
+            compute_shader,
+            &self.bindings,
+            self.push_constant_size,
+        )?;
+        self.pipeline.cleanup(logical_device);
+        self.pipeline = pipeline;
+        Ok(())
+    }
+
+    /// `resources` are bound in order and have to match the pipeline's bindings.
+    pub fn create_descriptor_set(
+        &self,
+        logical_device: &ash::Device,
+        resources: &[ComputeResource],
+    ) -> Result<vk::DescriptorSet> {
+        if resources.len() != self.bindings.len()
+            || resources
+                .iter()
+                .zip(&self.bindings)
+                .any(|(resource, ty)| resource.descriptor_type() != *ty)
+        {
+            bail!("Compute resources do not match the pipeline bindings");
+        }
+        let desc_layouts = [self.pipeline.descriptor_set_layouts[0]];
+        let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.descriptor_pool)
+            .set_layouts(&desc_layouts);
+        let descriptor_set =
+            unsafe { logical_device.allocate_descriptor_sets(&descriptor_set_allocate_info) }?[0];
+        let buffer_infos: Vec<[vk::DescriptorBufferInfo; 1]> = resources
+            .iter()
+            .map(|resource| match resource {
+                ComputeResource::StorageBuffer(buffer) | ComputeResource::UniformBuffer(buffer) => {
+                    [vk::DescriptorBufferInfo {
+                        buffer: *buffer,
+                        offset: 0,
+                        range: vk::WHOLE_SIZE,
+                    }]
+                }
+                _ => [vk::DescriptorBufferInfo::default()],
+            })
+            .collect();
+        let image_infos: Vec<[vk::DescriptorImageInfo; 1]> = resources
+            .iter()
+            .map(|resource| match resource {
+                ComputeResource::SampledImage(view, sampler) => [vk::DescriptorImageInfo {
+                    sampler: *sampler,
+                    image_view: *view,
+                    image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                }],
+                ComputeResource::StorageImage(view) => [vk::DescriptorImageInfo {
+                    sampler: vk::Sampler::null(),
+                    image_view: *view,
+                    image_layout: vk::ImageLayout::GENERAL,
+                }],
+                _ => [vk::DescriptorImageInfo::default()],
+            })
+            .collect();
+        let desc_sets_write: Vec<vk::WriteDescriptorSet> = resources
+            .iter()
+            .enumerate()
+            .map(|(binding, resource)| {
+                let write = vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(binding as u32)
+                    .descriptor_type(resource.descriptor_type());
+                match resource {
+                    ComputeResource::StorageBuffer(_) | ComputeResource::UniformBuffer(_) => {
+                        write.buffer_info(&buffer_infos[binding]).build()
+                    }
+                    _ => write.image_info(&image_infos[binding]).build(),
+                }
+            })
+            .collect();
+        unsafe { logical_device.update_descriptor_sets(&desc_sets_write, &[]) };
+        Ok(descriptor_set)
+    }
+
+    /// Frees every descriptor set created so far.
+    pub fn reset_descriptor_sets(&self, logical_device: &ash::Device) -> Result<(), vk::Result> {
+        unsafe {
+            logical_device
+                .reset_descriptor_pool(self.descriptor_pool, vk::DescriptorPoolResetFlags::empty())
+        }
+    }
+
+    /// Binds the pipeline and records one dispatch of `groups` workgroups.
+    pub fn record(
+        &self,
+        logical_device: &ash::Device,
+        commandbuffer: vk::CommandBuffer,
+        descriptor_set: vk::DescriptorSet,
+        push_constants: &[u8],
+        groups: [u32; 3],
+    ) {
+        debug_assert_eq!(push_constants.len() as u32, self.push_constant_size);
+        unsafe {
+            logical_device.cmd_bind_pipeline(
+                commandbuffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline.pipeline,
+            );
+            logical_device.cmd_bind_descriptor_sets(
+                commandbuffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline.layout,
+                0,
+                &[descriptor_set],
+                &[],
+            );
+            if !push_constants.is_empty() {
+                logical_device.cmd_push_constants(
+                    commandbuffer,
+                    self.pipeline.layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    push_constants,
+                );
+            }
+            logical_device.cmd_dispatch(commandbuffer, groups[0], groups[1], groups[2]);
+        }
+    }
+
+    /// Submits a single dispatch and waits for it, with every write made visible
+    /// to the host. Meant for tools and tests that read the results back.
+    pub fn dispatch_blocking(
+        &self,
+        logical_device: &ash::Device,
+        pools: &Pools,
+        queue: vk::Queue,
+        descriptor_set: vk::DescriptorSet,
+        push_constants: &[u8],
+        groups: [u32; 3],
+    ) -> Result<(), vk::Result> {
+        one_time_submit(logical_device, pools, queue, |commandbuffer| {
+            self.record(
+                logical_device,
+                commandbuffer,
+                descriptor_set,
+                push_constants,
+                groups,
+            );
+            let barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::HOST_READ)
+                .build();
+            unsafe {
+                logical_device.cmd_pipeline_barrier(
+                    commandbuffer,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::PipelineStageFlags::HOST,
+                    vk::DependencyFlags::empty(),
+                    &[barrier],
+                    &[],
+                    &[],
+                )
+            };
+        })
+    }
+
+    pub fn cleanup(&self, logical_device: &ash::Device) {
+        unsafe { logical_device.destroy_descriptor_pool(self.descriptor_pool, None) };
+        self.pipeline.cleanup(logical_device);
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DispatchOrder {
+    BeforeGraphics,
+    AfterGraphics,
+}
+
+/// Buffer a dispatch touches. `kernel` is how the shader uses it and `graphics`
+/// how the draw calls do; the kernel waits for the draws and vice versa.
+#[derive(Copy, Clone, Debug)]
+pub struct DispatchBuffer {
+    pub buffer: vk::Buffer,
+    pub kernel: Access,
+    pub graphics: Access,
+}
+
+/// Dispatch recorded into every frame's command buffer.
+pub struct ComputeDispatch {
+    pub name: String,
+    pub order: DispatchOrder,
+    pub pipeline: ComputePipeline,
+    pub descriptor_set: vk::DescriptorSet,
+    pub push_constants: Vec<u8>,
+    pub groups: [u32; 3],
+    pub buffers: Vec<DispatchBuffer>,
+}
+
+/// Records the dispatches scheduled at `order`, with the buffer barriers
+/// between them and the graphics work derived by a [`RenderGraph`].
+pub fn record_dispatches(
+    logical_device: &ash::Device,
+    allocator: &vk_mem::Allocator,
+    commandbuffer: vk::CommandBuffer,
+    dispatches: &[ComputeDispatch],
+    order: DispatchOrder,
+) -> Result<()> {
+    let scheduled: Vec<&ComputeDispatch> = dispatches
+        .iter()
+        .filter(|dispatch| dispatch.order == order)
+        .collect();
+    if scheduled.is_empty() {
+        return Ok(());
+    }
+    let mut graph = RenderGraph::new();
+    let mut imported: Vec<(vk::Buffer, BufferHandle)> = vec![];
+    for dispatch in &scheduled {
+        for buffer in &dispatch.buffers {
+            if !imported.iter().any(|(known, _)| *known == buffer.buffer) {
+                let handle = graph.import_buffer(
+                    buffer.buffer,
+                    Some(buffer.graphics),
+                    Some(buffer.graphics),
+                );
+                imported.push((buffer.buffer, handle));
+            }
+        }
+    }
+    for dispatch in scheduled {
+        let mut pass = graph.add_pass(&dispatch.name);
+        for buffer in &dispatch.buffers {
+            let (_, handle) = imported
+                .iter()
+                .find(|(known, _)| *known == buffer.buffer)
+                .expect("Dispatch buffer was not imported");
+            pass = if buffer.kernel.writes() {
+                pass.write_buffer(*handle, buffer.kernel)
+            } else {
+                pass.read_buffer(*handle, buffer.kernel)
+            };
+        }
+        pass.record(move |device, commandbuffer, _| {
+            dispatch.pipeline.record(
+                device,
+                commandbuffer,
+                dispatch.descriptor_set,
+                &dispatch.push_constants,
+                dispatch.groups,
+            );
+        });
+    }
+    graph.execute(logical_device, allocator, commandbuffer)?;
+    graph.cleanup(allocator)
+}
@@ -0,0 +1,163 @@
+use crate::{
+    model::Model,
+    picking::{Bounds, InstanceTransform, VertexPosition},
+};
+use nalgebra as na;
+
+/// Sphere around a model, or around one of its instances.
+#[derive(Copy, Clone, Debug)]
+pub struct BoundingSphere {
+    pub centre: na::Point3<f32>,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// Sphere around the box of `points`, `None` if there are none. Not the
+    /// smallest, but close for the meshes this renderer draws.
+    pub fn from_points(points: &[na::Point3<f32>]) -> Option<BoundingSphere> {
+        let bounds = Bounds::from_points(points.iter().copied())?;
+        let centre = na::center(&bounds.min, &bounds.max);
+        let radius = points
+            .iter()
+            .map(|p| na::distance(&centre, p))
+            .fold(0.0, f32::max);
+        Some(BoundingSphere { centre, radius })
+    }
+
+    /// Sphere around the transformed sphere, which grows by the largest scale
+    /// of `matrix`.
+    pub fn transformed(&self, matrix: &na::Matrix4<f32>) -> BoundingSphere {
+        let scale = (0..3)
+            .map(|i| matrix.fixed_slice::<na::U3, na::U1>(0, i).norm())
+            .fold(0.0, f32::max);
+        BoundingSphere {
+            centre: matrix.transform_point(&self.centre),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+/// The six planes bounding what a camera sees, facing inwards.
+#[derive(Copy, Clone, Debug)]
+pub struct Frustum {
+    planes: [na::Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Planes of the clip volume of `matrix`, projection times view, with
+    /// depth from 0 to 1. A plane at infinity comes out with a zero normal and
+    /// never rejects anything.
+    pub fn from_matrix(matrix: &na::Matrix4<f32>) -> Frustum {
+        let row = |i: usize| matrix.row(i).transpose();
+        let mut planes = [
+            row(3) + row(0),
+            row(3) - row(0),
+            row(3) + row(1),
+            row(3) - row(1),
+            row(2),
+            row(3) - row(2),
+        ];
+        for plane in &mut planes {
+            let length = plane.xyz().norm();
+            if length > f32::EPSILON {
+                *plane /= length;
+            }
+        }
+        Frustum { planes }
+    }
+
+    /// Whether any part of `sphere` may be visible.
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.xyz().dot(&sphere.centre.coords) + plane.w >= -sphere.radius)
+    }
+}
+
+/// Where instances outside the view are left out, if at all.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Culling {
+    Off,
+    /// Per instance on the CPU before upload, with `CullingStats`.
+    Cpu,
+    /// In a compute pass that feeds indirect draws. With `occlusion`, also
+    /// against the depth of the previous frame.
+    Gpu {
+        occlusion: bool,
+    },
+}
+
+impl Culling {
+    pub fn next(self) -> Culling {
+        match self {
+            Culling::Off => Culling::Cpu,
+            Culling::Cpu => Culling::Gpu { occlusion: false },
+            Culling::Gpu { occlusion: false } => Culling::Gpu { occlusion: true },
+            Culling::Gpu { occlusion: true } => Culling::Off,
+        }
+    }
+}
+
+/// Instances looked at and kept by culling.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CullingStats {
+    pub tested: usize,
+    pub drawn: usize,
+}
+
+impl CullingStats {
+    pub fn culled(&self) -> usize {
+        self.tested - self.drawn
+    }
+}
+
+impl std::ops::AddAssign for CullingStats {
+    fn add_assign(&mut self, other: CullingStats) {
+        self.tested += other.tested;
+        self.drawn += other.drawn;
+    }
+}
+
+impl std::fmt::Display for CullingStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} of {} instances drawn, {} culled",
+            self.drawn,
+            self.tested,
+            self.culled()
+        )
+    }
+}
+
+impl<V: VertexPosition, I: InstanceTransform> Model<V, I> {
+    /// Like `update_instancebuffer`, but only uploads the visible instances
+    /// whose bounding sphere reaches into `frustum`.
+    pub fn update_instancebuffer_culled(
+        &mut self,
+        allocator: &vk_mem::Allocator,
+        frustum: &Frustum,
+    ) -> Result<CullingStats, vk_mem::error::Error> {
+        let sphere = match self.bounding_sphere() {
+            Some(sphere) => sphere,
+            None => {
+                self.update_instancebuffer(allocator)?;
+                return Ok(CullingStats::default());
+            }
+        };
+        let tested = self.visible_instances().count();
+        let kept: Vec<usize> = self
+            .visible_instances()
+            .enumerate()
+            .filter(|(_, (_, instance))| {
+                frustum.intersects_sphere(&sphere.transformed(&instance.modelmatrix()))
+            })
+            .map(|(index, _)| index)
+            .collect();
+        self.update_instancebuffer_selection(allocator, &kept)?;
+        Ok(CullingStats {
+            tested,
+            drawn: kept.len(),
+        })
+    }
+}
@@ -0,0 +1,650 @@
+use crate::{
+    buffers::Buffer,
+    compute::{ComputePipeline, ComputeResource},
+    model::Model,
+    pool_and_commandbuffer::{one_time_submit, Pools},
+    render_graph::{Access, RenderGraph},
+    renderpass_and_pipeline::DepthConvention,
+    shaders,
+};
+use ash::{version::DeviceV1_0, vk};
+use eyre::*;
+
+const WORKGROUP_SIZE: u32 = 64;
+const PYRAMID_WORKGROUP_SIZE: u32 = 8;
+const PYRAMID_FORMAT: vk::Format = vk::Format::R32_SFLOAT;
+/// Enough levels for a 65536 texel wide window.
+const MAX_PYRAMID_LEVELS: u32 = 17;
+/// `vk::DrawIndexedIndirectCommand` followed by the draw count.
+const COMMANDS_SIZE: u64 = 24;
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+struct CullParameters {
+    sphere: [f32; 4],
+    count: u32,
+    occlusion: u32,
+    levels: u32,
+    stride: u32,
+    extent: [f32; 2],
+}
+
+unsafe impl bytemuck::Zeroable for CullParameters {}
+unsafe impl bytemuck::Pod for CullParameters {}
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+struct PyramidParameters {
+    first_level: u32,
+    reversed: u32,
+}
+
+unsafe impl bytemuck::Zeroable for PyramidParameters {}
+unsafe impl bytemuck::Pod for PyramidParameters {}
+
+/// What the compute pass of one model reads and writes.
+struct CulledModel {
+    /// Instance buffer of the model the set was created for.
+    source: vk::Buffer,
+    /// Surviving instances, compacted.
+    instances: Buffer,
+    commands: Buffer,
+    descriptor_set: vk::DescriptorSet,
+}
+
+/// Farthest depth of each 2^level by 2^level block of the previous frame. The
+/// last row and column of a level also cover what halving an odd size drops.
+struct DepthPyramid {
+    image: vk::Image,
+    allocation: vk_mem::Allocation,
+    imageview: vk::ImageView,
+    level_views: Vec<vk::ImageView>,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    extent: vk::Extent2D,
+}
+
+/// Culls the instances of every model in a compute pass, against the camera
+/// frustum and optionally against a depth pyramid of the previous frame, and
+/// leaves the survivors and the parameters of an indirect draw on the GPU.
+pub struct GpuCulling {
+    cull: ComputePipeline,
+    reduce: ComputePipeline,
+    sampler: vk::Sampler,
+    pyramid: DepthPyramid,
+    /// The pyramid holds the depth of a previous frame in the current
+    /// convention.
+    pyramid_valid: bool,
+    models: Vec<CulledModel>,
+    /// Descriptor sets the culling pipeline has room for.
+    capacity: usize,
+    camera: vk::Buffer,
+    pipeline_cache: vk::PipelineCache,
+}
+
+impl GpuCulling {
+    pub fn init(
+        logical_device: &ash::Device,
+        allocator: &vk_mem::Allocator,
+        pipeline_cache: vk::PipelineCache,
+        pools: &Pools,
+        queue: vk::Queue,
+        extent: vk::Extent2D,
+        depth: vk::ImageView,
+    ) -> Result<GpuCulling> {
+        let capacity = 16;
+        let cull = init_cull_pipeline(logical_device, pipeline_cache, capacity)?;
+        let reduce = ComputePipeline::init(
+            logical_device,
+            pipeline_cache,
+            &shaders::spirv("depth_pyramid.comp", ""),
+            &[
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                vk::DescriptorType::STORAGE_IMAGE,
+                vk::DescriptorType::STORAGE_IMAGE,
+            ],
+            std::mem::size_of::<PyramidParameters>() as u32,
+            MAX_PYRAMID_LEVELS,
+        )?;
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::NEAREST)
+            .min_filter(vk::Filter::NEAREST)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .max_lod(vk::LOD_CLAMP_NONE);
+        let sampler = unsafe { logical_device.create_sampler(&sampler_info, None) }?;
+        let pyramid = DepthPyramid::new(
+            logical_device,
+            allocator,
+            pools,
+            queue,
+            &reduce,
+            sampler,
+            extent,
+            depth,
+        )?;
+        Ok(GpuCulling {
+            cull,
+            reduce,
+            sampler,
+            pyramid,
+            pyramid_valid: false,
+            models: vec![],
+            capacity,
+            camera: vk::Buffer::null(),
+            pipeline_cache,
+        })
+    }
+
+    /// Follows a resized swapchain. The device must be idle.
+    pub fn resize(
+        &mut self,
+        logical_device: &ash::Device,
+        allocator: &vk_mem::Allocator,
+        pools: &Pools,
+        queue: vk::Queue,
+        extent: vk::Extent2D,
+        depth: vk::ImageView,
+    ) -> Result<()> {
+        self.reduce.reset_descriptor_sets(logical_device)?;
+        self.pyramid.cleanup(logical_device, allocator);
+        self.pyramid = DepthPyramid::new(
+            logical_device,
+            allocator,
+            pools,
+            queue,
+            &self.reduce,
+            self.sampler,
+            extent,
+            depth,
+        )?;
+        self.invalidate_pyramid();
+        // The culling sets sample the old pyramid.
+        self.camera = vk::Buffer::null();
+        Ok(())
+    }
+
+    /// Rebuilds both pipelines from the current SPIR-V. The device must be idle.
+    #[cfg(feature = "hot-reload")]
+    pub fn recreate_pipelines(
+        &mut self,
+        logical_device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
+    ) -> Result<()> {
+        self.cull.recreate(
+            logical_device,
+            pipeline_cache,
+            &shaders::spirv("cull.comp", ""),
+        )?;
+        self.reduce.recreate(
+            logical_device,
+            pipeline_cache,
+            &shaders::spirv("depth_pyramid.comp", ""),
+        )
+    }
+
+    /// Stops occlusion culling until the pyramid is built again, e.g. after
+    /// the depth convention changed.
+    pub fn invalidate_pyramid(&mut self) {
+        self.pyramid_valid = false;
+    }
+
+    /// Compacted instances of model `index`, as of the last `record_cull`.
+    pub fn instances(&self, index: usize) -> vk::Buffer {
+        self.models[index].instances.buffer
+    }
+
+    /// Indirect draw parameters of model `index`, see `Model::draw_indirect`.
+    pub fn commands(&self, index: usize) -> vk::Buffer {
+        self.models[index].commands.buffer
+    }
+
+    /// Creates what the models added or reallocated since the last call need,
+    /// after waiting for the frames in flight to let go of the old buffers.
+    fn prepare<V, I>(
+        &mut self,
+        logical_device: &ash::Device,
+        allocator: &vk_mem::Allocator,
+        models: &[Model<V, I>],
+        camera: vk::Buffer,
+    ) -> Result<()> {
+        let stale = camera != self.camera
+            || models.len() != self.models.len()
+            || models.iter().zip(&self.models).any(|(model, culled)| {
+                model
+                    .instancebuffer
+                    .as_ref()
+                    .map_or(vk::Buffer::null(), |buffer| buffer.buffer)
+                    != culled.source
+            });
+        if !stale {
+            return Ok(());
+        }
+        unsafe { logical_device.device_wait_idle() }?;
+        if models.len() > self.capacity {
+            self.capacity = models.len().next_power_of_two();
+            let cull = init_cull_pipeline(logical_device, self.pipeline_cache, self.capacity)?;
+            self.cull.cleanup(logical_device);
+            self.cull = cull;
+        } else {
+            self.cull.reset_descriptor_sets(logical_device)?;
+        }
+        let mut previous = std::mem::take(&mut self.models).into_iter();
+        for model in models {
+            let (source, size) = match &model.instancebuffer {
+                Some(buffer) => (buffer.buffer, buffer.size_in_bytes.max(4)),
+                None => (vk::Buffer::null(), 4),
+            };
+            let new_instances = || {
+                Buffer::new(
+                    allocator,
+                    size,
+                    vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::VERTEX_BUFFER,
+                    vk_mem::MemoryUsage::GpuOnly,
+                )
+            };
+            let (instances, commands) = match previous.next() {
+                Some(old) if old.instances.size_in_bytes >= size => (old.instances, old.commands),
+                Some(old) => {
+                    allocator.destroy_buffer(old.instances.buffer, &old.instances.allocation)?;
+                    (new_instances()?, old.commands)
+                }
+                None => (
+                    new_instances()?,
+                    Buffer::new(
+                        allocator,
+                        COMMANDS_SIZE,
+                        vk::BufferUsageFlags::STORAGE_BUFFER
+                            | vk::BufferUsageFlags::INDIRECT_BUFFER
+                            | vk::BufferUsageFlags::TRANSFER_DST,
+                        vk_mem::MemoryUsage::GpuOnly,
+                    )?,
+                ),
+            };
+            let descriptor_set = if source == vk::Buffer::null() {
+                vk::DescriptorSet::null()
+            } else {
+                self.cull.create_descriptor_set(
+                    logical_device,
+                    &[
+                        ComputeResource::UniformBuffer(camera),
+                        ComputeResource::StorageBuffer(source),
+                        ComputeResource::StorageBuffer(instances.buffer),
+                        ComputeResource::StorageBuffer(commands.buffer),
+                        ComputeResource::SampledImage(self.pyramid.imageview, self.sampler),
+                    ],
+                )?
+            };
+            self.models.push(CulledModel {
+                source,
+                instances,
+                commands,
+                descriptor_set,
+            });
+        }
+        for model in previous {
+            model.cleanup(allocator);
+        }
+        self.camera = camera;
+        Ok(())
+    }
+
+    /// Records the culling of every model, to be drawn with
+    /// `Model::draw_indirect` from `instances` and `commands`. `camera` is the
+    /// uniform buffer with view and projection. Models are tested with their
+    /// cached bounding sphere and drawn whole without one.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record_cull<V, I>(
+        &mut self,
+        logical_device: &ash::Device,
+        allocator: &vk_mem::Allocator,
+        commandbuffer: vk::CommandBuffer,
+        models: &[Model<V, I>],
+        camera: vk::Buffer,
+        depth: DepthConvention,
+        occlusion: bool,
+    ) -> Result<()> {
+        self.prepare(logical_device, allocator, models, camera)?;
+        let occlusion = match (occlusion && self.pyramid_valid, depth) {
+            (false, _) => 0,
+            (true, DepthConvention::Standard) => 1,
+            (true, DepthConvention::Reversed) => 2,
+        };
+        let stride = (std::mem::size_of::<I>() / std::mem::size_of::<f32>()) as u32;
+        let mut graph = RenderGraph::new();
+        for (model, culled) in models.iter().zip(&self.models) {
+            let count = model.drawn_handles().len() as u32;
+            let sphere = match model.cached_bounding_sphere() {
+                Some(sphere) => [
+                    sphere.centre.x,
+                    sphere.centre.y,
+                    sphere.centre.z,
+                    sphere.radius,
+                ],
+                None => [0.0, 0.0, 0.0, -1.0],
+            };
+            let parameters = CullParameters {
+                sphere,
+                count,
+                occlusion,
+                levels: self.pyramid.level_views.len() as u32,
+                stride,
+                extent: [
+                    self.pyramid.extent.width as f32,
+                    self.pyramid.extent.height as f32,
+                ],
+            };
+            let commands = graph.import_buffer(
+                culled.commands.buffer,
+                Some(Access::IndirectBuffer),
+                Some(Access::IndirectBuffer),
+            );
+            let index_count = model.indices().len() as u32;
+            graph
+                .add_pass("reset draw")
+                .write_buffer(commands, Access::TransferWrite)
+                .record(move |device, commandbuffer, resources| unsafe {
+                    let draw = [index_count, 0, 0, 0, 0, 0];
+                    device.cmd_update_buffer(
+                        commandbuffer,
+                        resources.buffer(commands),
+                        0,
+                        bytemuck::cast_slice(&draw),
+                    );
+                });
+            if count == 0 {
+                continue;
+            }
+            let source = graph.import_buffer(culled.source, Some(Access::VertexBuffer), None);
+            let instances = graph.import_buffer(
+                culled.instances.buffer,
+                Some(Access::VertexBuffer),
+                Some(Access::VertexBuffer),
+            );
+            let cull = &self.cull;
+            let descriptor_set = culled.descriptor_set;
+            let groups = count.div_ceil(WORKGROUP_SIZE);
+            // Dispatches are limited to 65535 workgroups along each axis.
+            let groups_x = groups.min(65535);
+            let groups_y = groups.div_ceil(groups_x);
+            graph
+                .add_pass("cull instances")
+                .read_buffer(source, Access::ComputeStorageRead)
+                .write_buffer(instances, Access::ComputeStorageWrite)
+                .write_buffer(commands, Access::ComputeStorageWrite)
+                .record(move |device, commandbuffer, _| {
+                    cull.record(
+                        device,
+                        commandbuffer,
+                        descriptor_set,
+                        bytemuck::bytes_of(&parameters),
+                        [groups_x, groups_y, 1],
+                    );
+                });
+        }
+        graph.execute(logical_device, allocator, commandbuffer)?;
+        graph.cleanup(allocator)
+    }
+
+    /// Records the reduction of the scene depth, which the render graph has
+    /// made ready for sampling, into the pyramid the next frame culls against.
+    pub fn record_pyramid(
+        &mut self,
+        logical_device: &ash::Device,
+        commandbuffer: vk::CommandBuffer,
+        depth: DepthConvention,
+    ) {
+        let pyramid = &self.pyramid;
+        let levels = pyramid.level_views.len() as u32;
+        let range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: levels,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let to_general = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_READ)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+            .old_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(pyramid.image)
+            .subresource_range(range)
+            .build();
+        unsafe {
+            logical_device.cmd_pipeline_barrier(
+                commandbuffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_general],
+            );
+        }
+        for (level, &descriptor_set) in pyramid.descriptor_sets.iter().enumerate() {
+            let extent = mip_extent(pyramid.extent, level as u32);
+            let parameters = PyramidParameters {
+                first_level: (level == 0) as u32,
+                reversed: (depth == DepthConvention::Reversed) as u32,
+            };
+            self.reduce.record(
+                logical_device,
+                commandbuffer,
+                descriptor_set,
+                bytemuck::bytes_of(&parameters),
+                [
+                    extent.width.div_ceil(PYRAMID_WORKGROUP_SIZE),
+                    extent.height.div_ceil(PYRAMID_WORKGROUP_SIZE),
+                    1,
+                ],
+            );
+            let written = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build();
+            unsafe {
+                logical_device.cmd_pipeline_barrier(
+                    commandbuffer,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[written],
+                    &[],
+                    &[],
+                );
+            }
+        }
+        let to_sampled = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .old_layout(vk::ImageLayout::GENERAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(pyramid.image)
+            .subresource_range(range)
+            .build();
+        unsafe {
+            logical_device.cmd_pipeline_barrier(
+                commandbuffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_sampled],
+            );
+        }
+        self.pyramid_valid = true;
+    }
+
+    pub fn cleanup(&self, logical_device: &ash::Device, allocator: &vk_mem::Allocator) {
+        for model in &self.models {
+            model.cleanup(allocator);
+        }
+        self.pyramid.cleanup(logical_device, allocator);
+        unsafe { logical_device.destroy_sampler(self.sampler, None) };
+        self.reduce.cleanup(logical_device);
+        self.cull.cleanup(logical_device);
+    }
+}
+
+impl CulledModel {
+    fn cleanup(&self, allocator: &vk_mem::Allocator) {
+        allocator
+            .destroy_buffer(self.instances.buffer, &self.instances.allocation)
+            .expect("Failed destroy culled instance buffer");
+        allocator
+            .destroy_buffer(self.commands.buffer, &self.commands.allocation)
+            .expect("Failed destroy indirect command buffer");
+    }
+}
+
+impl DepthPyramid {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        logical_device: &ash::Device,
+        allocator: &vk_mem::Allocator,
+        pools: &Pools,
+        queue: vk::Queue,
+        reduce: &ComputePipeline,
+        sampler: vk::Sampler,
+        extent: vk::Extent2D,
+        depth: vk::ImageView,
+    ) -> Result<DepthPyramid> {
+        let levels = 32 - extent.width.max(extent.height).max(1).leading_zeros();
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(PYRAMID_FORMAT)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(levels)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        let allocation_info = vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::GpuOnly,
+            ..Default::default()
+        };
+        let (image, allocation, _) = allocator.create_image(&image_info, &allocation_info)?;
+        let create_view = |base_mip_level, level_count| {
+            let subresource_range = vk::ImageSubresourceRange::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .base_mip_level(base_mip_level)
+                .level_count(level_count)
+                .base_array_layer(0)
+                .layer_count(1);
+            let imageview_create_info = vk::ImageViewCreateInfo::builder()
+                .image(image)
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(PYRAMID_FORMAT)
+                .subresource_range(*subresource_range);
+            unsafe { logical_device.create_image_view(&imageview_create_info, None) }
+        };
+        let imageview = create_view(0, levels)?;
+        let level_views = (0..levels)
+            .map(|level| create_view(level, 1))
+            .collect::<Result<Vec<_>, _>>()?;
+        let descriptor_sets = (0..levels as usize)
+            .map(|level| {
+                reduce.create_descriptor_set(
+                    logical_device,
+                    &[
+                        ComputeResource::SampledImage(depth, sampler),
+                        ComputeResource::StorageImage(level_views[level.saturating_sub(1)]),
+                        ComputeResource::StorageImage(level_views[level]),
+                    ],
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // Culling samples the pyramid before it is first built, with occlusion
+        // off, so it starts out in the layout it is sampled in.
+        one_time_submit(logical_device, pools, queue, |commandbuffer| {
+            let barrier = vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(image)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: levels,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .build();
+            unsafe {
+                logical_device.cmd_pipeline_barrier(
+                    commandbuffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[barrier],
+                )
+            };
+        })?;
+        Ok(DepthPyramid {
+            image,
+            allocation,
+            imageview,
+            level_views,
+            descriptor_sets,
+            extent,
+        })
+    }
+
+    fn cleanup(&self, logical_device: &ash::Device, allocator: &vk_mem::Allocator) {
+        unsafe {
+            for &view in &self.level_views {
+                logical_device.destroy_image_view(view, None);
+            }
+            logical_device.destroy_image_view(self.imageview, None);
+        }
+        allocator
+            .destroy_image(self.image, &self.allocation)
+            .expect("Failed destroy depth pyramid");
+    }
+}
+
+fn init_cull_pipeline(
+    logical_device: &ash::Device,
+    pipeline_cache: vk::PipelineCache,
+    max_sets: usize,
+) -> Result<ComputePipeline> {
+    ComputePipeline::init(
+        logical_device,
+        pipeline_cache,
+        &shaders::spirv("cull.comp", ""),
+        &[
+            vk::DescriptorType::UNIFORM_BUFFER,
+            vk::DescriptorType::STORAGE_BUFFER,
+            vk::DescriptorType::STORAGE_BUFFER,
+            vk::DescriptorType::STORAGE_BUFFER,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        ],
+        std::mem::size_of::<CullParameters>() as u32,
+        max_sets as u32,
+    )
+}
+
+fn mip_extent(extent: vk::Extent2D, level: u32) -> vk::Extent2D {
+    vk::Extent2D {
+        width: (extent.width >> level).max(1),
+        height: (extent.height >> level).max(1),
+    }
+}
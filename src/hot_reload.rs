@@ -0,0 +1,180 @@
+use crate::shader_compiler::{self, Permutation};
+use eyre::*;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// SPIR-V compiled at runtime by shader and permutation, as in the shader
+/// table. Takes precedence over the code embedded at build time.
+#[allow(clippy::type_complexity)]
+static RELOADED: Mutex<Vec<(String, String, Vec<u32>)>> = Mutex::new(Vec::new());
+
+pub fn reloaded(shader: &str, permutation: &str) -> Option<Vec<u32>> {
+    let reloaded = RELOADED.lock().expect("poisoned shader table");
+    reloaded
+        .iter()
+        .find(|(s, p, _)| s == shader && p == permutation)
+        .map(|(_, _, spirv)| spirv.clone())
+}
+
+/// Replaces the code for `shader` and `permutation`, `None` going back to the
+/// embedded one. Returns what was used before.
+pub fn install(shader: &str, permutation: &str, spirv: Option<Vec<u32>>) -> Option<Vec<u32>> {
+    let mut reloaded = RELOADED.lock().expect("poisoned shader table");
+    let previous = reloaded
+        .iter()
+        .position(|(s, p, _)| s == shader && p == permutation)
+        .map(|index| reloaded.remove(index).2);
+    if let Some(spirv) = spirv {
+        reloaded.push((shader.to_string(), permutation.to_string(), spirv));
+    }
+    previous
+}
+
+pub struct CompiledShader {
+    /// Path relative to the shader directory, e.g. `shader.frag`.
+    pub name: String,
+    pub permutation: String,
+    pub spirv: Vec<u32>,
+}
+
+#[derive(Default)]
+pub struct Reload {
+    pub compiled: Vec<CompiledShader>,
+    /// Names of the shaders that failed to compile. The messages are logged.
+    pub failed: Vec<String>,
+}
+
+/// Polls a shader directory for sources modified since the last poll and
+/// compiles every permutation of them. A changed include or manifest
+/// recompiles every shader.
+pub struct ShaderWatcher {
+    directory: PathBuf,
+    compiler: shaderc::Compiler,
+    modified: HashMap<PathBuf, SystemTime>,
+    last_poll: Instant,
+}
+
+impl ShaderWatcher {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Result<Self> {
+        let directory = directory.into();
+        let compiler = shaderc::Compiler::new().context("Unable to create shader compiler")?;
+        let mut modified = HashMap::new();
+        for (path, time) in files(&directory)? {
+            modified.insert(path, time);
+        }
+        Ok(ShaderWatcher {
+            directory,
+            compiler,
+            modified,
+            last_poll: Instant::now(),
+        })
+    }
+
+    pub fn poll(&mut self) -> Reload {
+        let mut reload = Reload::default();
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return reload;
+        }
+        self.last_poll = Instant::now();
+        let files = match files(&self.directory) {
+            Ok(files) => files,
+            Err(e) => {
+                log::error!("Cannot watch {}: {:?}", self.directory.display(), e);
+                return reload;
+            }
+        };
+        let mut changed = vec![];
+        let mut everything = false;
+        for (path, time) in &files {
+            if self.modified.insert(path.clone(), *time) != Some(*time) {
+                everything |= shader_compiler::shader_kind(path).is_none();
+                changed.push(path.clone());
+            }
+        }
+        if everything {
+            changed = files
+                .into_iter()
+                .map(|(path, _)| path)
+                .filter(|path| shader_compiler::shader_kind(path).is_some())
+                .collect();
+        } else {
+            changed.retain(|path| shader_compiler::shader_kind(path).is_some());
+        }
+        if changed.is_empty() {
+            return reload;
+        }
+        let permutations = match shader_compiler::read_manifest(&self.directory) {
+            Ok(permutations) => permutations,
+            Err(e) => {
+                log::error!("{:?}", e);
+                reload.failed.push(shader_compiler::MANIFEST.to_string());
+                return reload;
+            }
+        };
+        for path in changed {
+            let name = path
+                .strip_prefix(&self.directory)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .replace('\\', "/");
+            let base = Permutation {
+                shader: name.clone(),
+                name: String::new(),
+                defines: vec![],
+            };
+            let variants =
+                std::iter::once(&base).chain(permutations.iter().filter(|p| p.shader == name));
+            for permutation in variants {
+                match shader_compiler::compile(
+                    &mut self.compiler,
+                    &self.directory,
+                    &path,
+                    &permutation.defines,
+                    false,
+                ) {
+                    Ok(artifact) => {
+                        if artifact.get_num_warnings() > 0 {
+                            log::warn!("{}", artifact.get_warning_messages());
+                        }
+                        reload.compiled.push(CompiledShader {
+                            name: name.clone(),
+                            permutation: permutation.name.clone(),
+                            spirv: artifact.as_binary().to_vec(),
+                        });
+                    }
+                    Err(e) => {
+                        log::error!("{} {}: {:?}", name, permutation.name, e);
+                        if !reload.failed.contains(&name) {
+                            reload.failed.push(name.clone());
+                        }
+                    }
+                }
+            }
+        }
+        reload
+    }
+}
+
+/// Every file below `directory` with its modification time.
+fn files(directory: &Path) -> Result<Vec<(PathBuf, SystemTime)>> {
+    let mut files = vec![];
+    let mut directories = vec![directory.to_path_buf()];
+    while let Some(directory) = directories.pop() {
+        for entry in std::fs::read_dir(&directory)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                directories.push(path);
+            } else if path.extension().is_some_and(|e| e != "spv") {
+                let time = std::fs::metadata(&path)?.modified()?;
+                files.push((path, time));
+            }
+        }
+    }
+    Ok(files)
+}
@@ -0,0 +1,432 @@
+use crate::{
+    buffers::Buffer,
+    model::Model,
+    render_target::{RenderTarget, DEPTH_FORMAT, ID_FORMAT},
+    renderpass_and_pipeline::{init_id_renderpass, set_viewport, DepthConvention, Pipeline},
+};
+use ash::{version::DeviceV1_0, vk};
+use eyre::*;
+
+/// Bytes per texel of the ID target.
+const ID_SIZE: u64 = 8;
+
+/// Ticket for a pick requested with `IdBuffer::request`, matching it to its
+/// `PickResult`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PickQuery(usize);
+
+/// Instance seen in the ID buffer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PickedInstance {
+    /// Index into `Aetna::models`.
+    pub model: usize,
+    /// Handle returned by `Model::insert_visibly`.
+    pub instance: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct PickResult {
+    pub query: PickQuery,
+    /// Pixels that were looked at, the requested ones clipped to the window.
+    pub area: vk::Rect2D,
+    /// Every instance covering a pixel of the area, each once, in row order of
+    /// the first pixel they cover.
+    pub instances: Vec<PickedInstance>,
+}
+
+/// Copies recorded into one frame's command buffer, readable once its fence
+/// has signalled.
+#[derive(Default)]
+struct Readback {
+    buffer: Option<Buffer>,
+    queries: Vec<(PickQuery, vk::Rect2D, u64)>,
+    /// Handles of the drawn instances of each model when the IDs were drawn,
+    /// which `gl_InstanceIndex` counts.
+    handles: Vec<Vec<usize>>,
+}
+
+/// Optional render target holding, per pixel, the model and instance drawn
+/// there. It is only drawn in frames with pending queries, and results arrive
+/// a few frames later instead of stalling the frame that asked.
+pub struct IdBuffer {
+    renderpass: vk::RenderPass,
+    pipeline: Pipeline,
+    target: RenderTarget,
+    depth_target: RenderTarget,
+    framebuffer: vk::Framebuffer,
+    extent: vk::Extent2D,
+    depth: DepthConvention,
+    pending: Vec<(PickQuery, vk::Rect2D)>,
+    readbacks: Vec<Readback>,
+    results: Vec<PickResult>,
+    next_query: usize,
+}
+
+impl IdBuffer {
+    /// `frames` is the number of frames in flight, each of which gets its own
+    /// readback buffer.
+    pub fn init(
+        logical_device: &ash::Device,
+        allocator: &vk_mem::Allocator,
+        pipeline_cache: vk::PipelineCache,
+        extent: vk::Extent2D,
+        depth: DepthConvention,
+        frames: usize,
+    ) -> Result<IdBuffer> {
+        let renderpass = init_id_renderpass(logical_device)?;
+        let pipeline = Pipeline::init_id(logical_device, pipeline_cache, &renderpass, depth)?;
+        let (target, depth_target, framebuffer) =
+            create_targets(logical_device, allocator, renderpass, extent)?;
+        Ok(IdBuffer {
+            renderpass,
+            pipeline,
+            target,
+            depth_target,
+            framebuffer,
+            extent,
+            depth,
+            pending: vec![],
+            readbacks: (0..frames).map(|_| Readback::default()).collect(),
+            results: vec![],
+            next_query: 0,
+        })
+    }
+
+    /// Follows a resized swapchain. Every readback must have been collected,
+    /// see `collect_all`.
+    pub fn resize(
+        &mut self,
+        logical_device: &ash::Device,
+        allocator: &vk_mem::Allocator,
+        extent: vk::Extent2D,
+        frames: usize,
+    ) -> Result<()> {
+        let (target, depth_target, framebuffer) =
+            create_targets(logical_device, allocator, self.renderpass, extent)?;
+        self.cleanup_targets(logical_device, allocator);
+        self.target = target;
+        self.depth_target = depth_target;
+        self.framebuffer = framebuffer;
+        self.extent = extent;
+        for readback in self.readbacks.drain(..) {
+            if let Some(buffer) = readback.buffer {
+                allocator.destroy_buffer(buffer.buffer, &buffer.allocation)?;
+            }
+        }
+        self.readbacks = (0..frames).map(|_| Readback::default()).collect();
+        Ok(())
+    }
+
+    /// Rebuilds the pipeline for another depth convention. The device must be
+    /// idle.
+    pub fn recreate_pipeline(
+        &mut self,
+        logical_device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
+        depth: DepthConvention,
+    ) -> Result<()> {
+        let pipeline = Pipeline::init_id(logical_device, pipeline_cache, &self.renderpass, depth)?;
+        self.pipeline.cleanup(logical_device);
+        self.pipeline = pipeline;
+        self.depth = depth;
+        Ok(())
+    }
+
+    /// Asks for the instances in `area`, answered by `take_results` once a
+    /// frame drawn after this has finished.
+    pub fn request(&mut self, area: vk::Rect2D) -> PickQuery {
+        let query = PickQuery(self.next_query);
+        self.next_query += 1;
+        self.pending.push((query, area));
+        query
+    }
+
+    /// Draws the IDs and copies the pending areas out, if any, in the command
+    /// buffer of frame `frame`, whose earlier copies must have been collected.
+    /// `camera_set` is the scene's camera set.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record<V, I>(
+        &mut self,
+        logical_device: &ash::Device,
+        allocator: &vk_mem::Allocator,
+        commandbuffer: vk::CommandBuffer,
+        frame: usize,
+        models: &[Model<V, I>],
+        camera_set: vk::DescriptorSet,
+    ) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let readback = &mut self.readbacks[frame];
+        let mut size = 0;
+        for (query, area) in self.pending.drain(..) {
+            let area = clip(area, self.extent);
+            readback.queries.push((query, area, size));
+            size += area.extent.width as u64 * area.extent.height as u64 * ID_SIZE;
+        }
+        // `Option::is_none_or` is too recent for the toolchains this builds on.
+        #[allow(unknown_lints, clippy::unnecessary_map_or)]
+        let too_small = readback
+            .buffer
+            .as_ref()
+            .map_or(true, |buffer| buffer.size_in_bytes < size.max(ID_SIZE));
+        if too_small {
+            if let Some(buffer) = readback.buffer.take() {
+                allocator.destroy_buffer(buffer.buffer, &buffer.allocation)?;
+            }
+            readback.buffer = Some(Buffer::new(
+                allocator,
+                size.max(ID_SIZE),
+                vk::BufferUsageFlags::TRANSFER_DST,
+                vk_mem::MemoryUsage::GpuToCpu,
+            )?);
+        }
+        readback.handles = models
+            .iter()
+            .map(|model| model.drawn_handles().to_vec())
+            .collect();
+
+        let clearvalues = [
+            vk::ClearValue {
+                color: vk::ClearColorValue { uint32: [0; 4] },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: self.depth.far(),
+                    stencil: 0,
+                },
+            },
+        ];
+        let renderpass_begininfo = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.renderpass)
+            .framebuffer(self.framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            })
+            .clear_values(&clearvalues);
+        let buffer = readback.buffer.as_ref().expect("readback buffer").buffer;
+        let regions: Vec<_> = readback
+            .queries
+            .iter()
+            .filter(|(_, area, _)| area.extent.width > 0 && area.extent.height > 0)
+            .map(|&(_, area, offset)| {
+                vk::BufferImageCopy::builder()
+                    .buffer_offset(offset)
+                    .image_subresource(vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level: 0,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    })
+                    .image_offset(vk::Offset3D {
+                        x: area.offset.x,
+                        y: area.offset.y,
+                        z: 0,
+                    })
+                    .image_extent(vk::Extent3D {
+                        width: area.extent.width,
+                        height: area.extent.height,
+                        depth: 1,
+                    })
+                    .build()
+            })
+            .collect();
+        let host_barrier = vk::BufferMemoryBarrier::builder()
+            .buffer(buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .build();
+        unsafe {
+            logical_device.cmd_begin_render_pass(
+                commandbuffer,
+                &renderpass_begininfo,
+                vk::SubpassContents::INLINE,
+            );
+            set_viewport(logical_device, commandbuffer, self.extent);
+            logical_device.cmd_bind_pipeline(
+                commandbuffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.pipeline,
+            );
+            logical_device.cmd_bind_descriptor_sets(
+                commandbuffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.layout,
+                0,
+                &[camera_set],
+                &[],
+            );
+            for (index, model) in models.iter().enumerate() {
+                logical_device.cmd_push_constants(
+                    commandbuffer,
+                    self.pipeline.layout,
+                    vk::ShaderStageFlags::FRAGMENT,
+                    0,
+                    bytemuck::bytes_of(&(index as u32)),
+                );
+                model.draw(logical_device, commandbuffer);
+            }
+            logical_device.cmd_end_render_pass(commandbuffer);
+            if !regions.is_empty() {
+                logical_device.cmd_copy_image_to_buffer(
+                    commandbuffer,
+                    self.target.image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    buffer,
+                    &regions,
+                );
+            }
+            logical_device.cmd_pipeline_barrier(
+                commandbuffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[host_barrier],
+                &[],
+            );
+        }
+        Ok(())
+    }
+
+    /// Turns the copies of frame `frame` into results. Its fence must have
+    /// signalled since they were recorded.
+    pub fn collect(&mut self, allocator: &vk_mem::Allocator, frame: usize) -> Result<()> {
+        let readback = &mut self.readbacks[frame];
+        if readback.queries.is_empty() {
+            return Ok(());
+        }
+        let buffer = readback.buffer.as_ref().expect("readback buffer");
+        let texels = readback
+            .queries
+            .iter()
+            .map(|(_, area, offset)| {
+                (offset / ID_SIZE) as usize
+                    + area.extent.width as usize * area.extent.height as usize
+            })
+            .max()
+            .unwrap_or(0);
+        let ids: Vec<[u32; 2]> = buffer.read(allocator, texels)?;
+        for (query, area, offset) in readback.queries.drain(..) {
+            let start = (offset / ID_SIZE) as usize;
+            let count = area.extent.width as usize * area.extent.height as usize;
+            let mut instances = vec![];
+            for &[model, index] in &ids[start..start + count] {
+                if model == 0 {
+                    continue;
+                }
+                let model = model as usize - 1;
+                let handle = readback
+                    .handles
+                    .get(model)
+                    .and_then(|handles| handles.get(index as usize));
+                if let Some(&instance) = handle {
+                    let picked = PickedInstance { model, instance };
+                    if !instances.contains(&picked) {
+                        instances.push(picked);
+                    }
+                }
+            }
+            self.results.push(PickResult {
+                query,
+                area,
+                instances,
+            });
+        }
+        Ok(())
+    }
+
+    /// Collects every frame, for when the device is idle.
+    pub fn collect_all(&mut self, allocator: &vk_mem::Allocator) -> Result<()> {
+        for frame in 0..self.readbacks.len() {
+            self.collect(allocator, frame)?;
+        }
+        Ok(())
+    }
+
+    /// Whether frame `frame` has copies waiting to be collected.
+    pub fn is_waiting(&self, frame: usize) -> bool {
+        !self.readbacks[frame].queries.is_empty()
+    }
+
+    /// Results collected so far, in the order they arrived.
+    pub fn take_results(&mut self) -> Vec<PickResult> {
+        std::mem::take(&mut self.results)
+    }
+
+    fn cleanup_targets(&self, logical_device: &ash::Device, allocator: &vk_mem::Allocator) {
+        unsafe {
+            logical_device.destroy_framebuffer(self.framebuffer, None);
+        }
+        self.target.cleanup(logical_device, allocator);
+        self.depth_target.cleanup(logical_device, allocator);
+    }
+
+    pub fn cleanup(&self, logical_device: &ash::Device, allocator: &vk_mem::Allocator) {
+        self.cleanup_targets(logical_device, allocator);
+        for readback in &self.readbacks {
+            if let Some(buffer) = &readback.buffer {
+                allocator
+                    .destroy_buffer(buffer.buffer, &buffer.allocation)
+                    .expect("Failed destroy readback buffer");
+            }
+        }
+        self.pipeline.cleanup(logical_device);
+        unsafe {
+            logical_device.destroy_render_pass(self.renderpass, None);
+        }
+    }
+}
+
+fn create_targets(
+    logical_device: &ash::Device,
+    allocator: &vk_mem::Allocator,
+    renderpass: vk::RenderPass,
+    extent: vk::Extent2D,
+) -> Result<(RenderTarget, RenderTarget, vk::Framebuffer)> {
+    let target = RenderTarget::new(
+        logical_device,
+        allocator,
+        extent,
+        ID_FORMAT,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+    )?;
+    let depth_target = RenderTarget::new(
+        logical_device,
+        allocator,
+        extent,
+        DEPTH_FORMAT,
+        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+    )?;
+    let iview = [target.imageview, depth_target.imageview];
+    let framebuffer_info = vk::FramebufferCreateInfo::builder()
+        .render_pass(renderpass)
+        .attachments(&iview)
+        .width(extent.width)
+        .height(extent.height)
+        .layers(1);
+    let framebuffer = unsafe { logical_device.create_framebuffer(&framebuffer_info, None) }?;
+    Ok((target, depth_target, framebuffer))
+}
+
+/// Part of `area` inside `extent`, possibly empty.
+fn clip(area: vk::Rect2D, extent: vk::Extent2D) -> vk::Rect2D {
+    let x0 = area.offset.x.clamp(0, extent.width as i32);
+    let y0 = area.offset.y.clamp(0, extent.height as i32);
+    let x1 =
+        (area.offset.x as i64 + area.extent.width as i64).clamp(x0 as i64, extent.width as i64);
+    let y1 =
+        (area.offset.y as i64 + area.extent.height as i64).clamp(y0 as i64, extent.height as i64);
+    vk::Rect2D {
+        offset: vk::Offset2D { x: x0, y: y0 },
+        extent: vk::Extent2D {
+            width: (x1 - x0 as i64) as u32,
+            height: (y1 - y0 as i64) as u32,
+        },
+    }
+}
@@ -0,0 +1,392 @@
+use eyre::*;
+use std::path::Path;
+use winit::event::{
+    ElementState, KeyboardInput, ModifiersState, MouseButton, VirtualKeyCode, WindowEvent,
+};
+
+/// Bindings used when there is no input config, see `input.cfg` for the
+/// format.
+pub const DEFAULT_CONFIG: &str = include_str!("../input.cfg");
+
+/// Key or mouse button an action can be bound to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Button {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+}
+
+/// Button together with the modifiers that have to be held with it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Binding {
+    pub button: Button,
+    pub modifiers: ModifiersState,
+}
+
+/// Keys that can be named in a config, by their `VirtualKeyCode` name.
+const KEYS: &[VirtualKeyCode] = {
+    use VirtualKeyCode::*;
+    &[
+        Key1,
+        Key2,
+        Key3,
+        Key4,
+        Key5,
+        Key6,
+        Key7,
+        Key8,
+        Key9,
+        Key0,
+        A,
+        B,
+        C,
+        D,
+        E,
+        F,
+        G,
+        H,
+        I,
+        J,
+        K,
+        L,
+        M,
+        N,
+        O,
+        P,
+        Q,
+        R,
+        S,
+        T,
+        U,
+        V,
+        W,
+        X,
+        Y,
+        Z,
+        Escape,
+        F1,
+        F2,
+        F3,
+        F4,
+        F5,
+        F6,
+        F7,
+        F8,
+        F9,
+        F10,
+        F11,
+        F12,
+        F13,
+        F14,
+        F15,
+        F16,
+        F17,
+        F18,
+        F19,
+        F20,
+        F21,
+        F22,
+        F23,
+        F24,
+        Snapshot,
+        Scroll,
+        Pause,
+        Insert,
+        Home,
+        Delete,
+        End,
+        PageDown,
+        PageUp,
+        Left,
+        Up,
+        Right,
+        Down,
+        Back,
+        Return,
+        Space,
+        Tab,
+        Numpad0,
+        Numpad1,
+        Numpad2,
+        Numpad3,
+        Numpad4,
+        Numpad5,
+        Numpad6,
+        Numpad7,
+        Numpad8,
+        Numpad9,
+        NumpadAdd,
+        NumpadSubtract,
+        NumpadMultiply,
+        NumpadDivide,
+        NumpadDecimal,
+        NumpadEnter,
+        Apostrophe,
+        Backslash,
+        Comma,
+        Equals,
+        Grave,
+        LBracket,
+        Minus,
+        Period,
+        RBracket,
+        Semicolon,
+        Slash,
+        LAlt,
+        LControl,
+        LShift,
+        LWin,
+        RAlt,
+        RControl,
+        RShift,
+        RWin,
+    ]
+};
+
+impl Binding {
+    /// `[Shift+][Ctrl+][Alt+][Logo+]button`, where the button is a
+    /// `VirtualKeyCode` name such as `W`, `Key1` or `PageUp`, or one of
+    /// `MouseLeft`, `MouseRight`, `MouseMiddle` and `Mouse` with a number.
+    pub fn parse(text: &str) -> Result<Binding> {
+        let mut parts: Vec<&str> = text.split('+').collect();
+        let button = parts.pop().unwrap_or_default();
+        let mut modifiers = ModifiersState::empty();
+        for modifier in parts {
+            modifiers |= match modifier {
+                "Shift" => ModifiersState::SHIFT,
+                "Ctrl" => ModifiersState::CTRL,
+                "Alt" => ModifiersState::ALT,
+                "Logo" => ModifiersState::LOGO,
+                _ => bail!("Unknown modifier {} in {}", modifier, text),
+            };
+        }
+        let button = match button {
+            "MouseLeft" => Button::Mouse(MouseButton::Left),
+            "MouseRight" => Button::Mouse(MouseButton::Right),
+            "MouseMiddle" => Button::Mouse(MouseButton::Middle),
+            _ => match button.strip_prefix("Mouse").map(str::parse) {
+                Some(Ok(number)) => Button::Mouse(MouseButton::Other(number)),
+                _ => Button::Key(
+                    *KEYS
+                        .iter()
+                        .find(|key| format!("{:?}", key) == button)
+                        .with_context(|| format!("Unknown key {}", button))?,
+                ),
+            },
+        };
+        Ok(Binding { button, modifiers })
+    }
+
+    /// Whether pressing `button` with `modifiers` held triggers the binding.
+    /// Further modifiers are fine, so that e.g. a movement key keeps working
+    /// while Shift speeds it up.
+    fn matches(&self, button: Button, modifiers: ModifiersState) -> bool {
+        self.button == button && modifiers.contains(self.modifiers)
+    }
+}
+
+/// Named actions and the bindings that trigger them.
+#[derive(Clone, Debug)]
+pub struct InputMap {
+    actions: Vec<(String, Vec<Binding>)>,
+}
+
+impl Default for InputMap {
+    fn default() -> InputMap {
+        InputMap::parse(DEFAULT_CONFIG).expect("Default input config is valid")
+    }
+}
+
+#[allow(dead_code)]
+impl InputMap {
+    /// Each line names an action followed by its bindings, see
+    /// [`Binding::parse`]. `#` starts a comment, and an action listed twice
+    /// gets the bindings of both lines.
+    pub fn parse(text: &str) -> Result<InputMap> {
+        let mut map = InputMap { actions: vec![] };
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut words = line.split_whitespace();
+            let action = match words.next() {
+                Some(action) => action,
+                None => continue,
+            };
+            let bindings = words
+                .map(Binding::parse)
+                .collect::<Result<Vec<_>>>()
+                .with_context(|| format!("line {}: {}", number + 1, action))?;
+            if bindings.is_empty() {
+                bail!("line {}: {} has no bindings", number + 1, action);
+            }
+            for binding in bindings {
+                map.bind(action, binding);
+            }
+        }
+        Ok(map)
+    }
+
+    /// The config at `path`, or the default one if there is no such file.
+    pub fn load(path: impl AsRef<Path>) -> Result<InputMap> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(InputMap::default());
+        }
+        InputMap::parse(&std::fs::read_to_string(path)?)
+            .with_context(|| format!("Failed to load {}", path.display()))
+    }
+
+    pub fn bind(&mut self, action: &str, binding: Binding) {
+        match self.actions.iter_mut().find(|(name, _)| name == action) {
+            Some((_, bindings)) => bindings.push(binding),
+            None => self.actions.push((action.to_string(), vec![binding])),
+        }
+    }
+
+    /// Removes every binding of `action`.
+    pub fn unbind(&mut self, action: &str) {
+        self.actions.retain(|(name, _)| name != action);
+    }
+
+    pub fn bindings(&self, action: &str) -> &[Binding] {
+        self.index(action)
+            .map_or(&[], |index| &self.actions[index].1)
+    }
+
+    fn index(&self, action: &str) -> Option<usize> {
+        self.actions.iter().position(|(name, _)| name == action)
+    }
+
+    /// Actions triggered by pressing `button` with `modifiers`. Where several
+    /// bindings of the button match, only those with the most modifiers
+    /// count, so that Shift+F5 does not also trigger F5.
+    fn triggered(&self, button: Button, modifiers: ModifiersState) -> Vec<usize> {
+        let matching = |action: &(String, Vec<Binding>)| {
+            action
+                .1
+                .iter()
+                .filter(|binding| binding.matches(button, modifiers))
+                .map(|binding| binding.modifiers.bits().count_ones())
+                .max()
+        };
+        let most = self.actions.iter().filter_map(matching).max();
+        self.actions
+            .iter()
+            .enumerate()
+            .filter(|(_, action)| matching(action).is_some() && matching(action) == most)
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+/// Which actions are held, and which were pressed or released since the last
+/// [`Input::end_frame`], fed from window events. Key repeats are ignored, so
+/// held actions can drive continuous movement scaled by the frame time.
+pub struct Input {
+    map: InputMap,
+    modifiers: ModifiersState,
+    /// Buttons held down and the actions their press triggered.
+    held: Vec<(Button, Vec<usize>)>,
+    pressed: Vec<usize>,
+    released: Vec<usize>,
+}
+
+#[allow(dead_code)]
+impl Input {
+    pub fn new(map: InputMap) -> Input {
+        Input {
+            map,
+            modifiers: ModifiersState::empty(),
+            held: vec![],
+            pressed: vec![],
+            released: vec![],
+        }
+    }
+
+    pub fn map(&self) -> &InputMap {
+        &self.map
+    }
+
+    /// Replaces the bindings. Held actions are released.
+    pub fn set_map(&mut self, map: InputMap) {
+        self.release_all();
+        self.map = map;
+    }
+
+    pub fn modifiers(&self) -> ModifiersState {
+        self.modifiers
+    }
+
+    pub fn handle_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state,
+                        virtual_keycode: Some(keycode),
+                        ..
+                    },
+                ..
+            } => self.button(Button::Key(*keycode), *state),
+            WindowEvent::MouseInput { state, button, .. } => {
+                self.button(Button::Mouse(*button), *state)
+            }
+            WindowEvent::ModifiersChanged(modifiers) => self.modifiers = *modifiers,
+            // Releases are not reported to unfocused windows.
+            WindowEvent::Focused(false) => self.release_all(),
+            _ => {}
+        }
+    }
+
+    fn button(&mut self, button: Button, state: ElementState) {
+        let held = self.held.iter().position(|(known, _)| *known == button);
+        match (state, held) {
+            (ElementState::Pressed, None) => {
+                let actions = self.map.triggered(button, self.modifiers);
+                self.pressed.extend(&actions);
+                self.held.push((button, actions));
+            }
+            (ElementState::Released, Some(index)) => {
+                let (_, actions) = self.held.remove(index);
+                self.released.extend(actions);
+            }
+            // Key repeats and releases of buttons pressed elsewhere.
+            _ => {}
+        }
+    }
+
+    fn release_all(&mut self) {
+        for (_, actions) in self.held.drain(..) {
+            self.released.extend(actions);
+        }
+    }
+
+    pub fn is_held(&self, action: &str) -> bool {
+        self.map.index(action).is_some_and(|index| {
+            self.held
+                .iter()
+                .any(|(_, actions)| actions.contains(&index))
+        })
+    }
+
+    pub fn was_pressed(&self, action: &str) -> bool {
+        self.map
+            .index(action)
+            .is_some_and(|index| self.pressed.contains(&index))
+    }
+
+    pub fn was_released(&self, action: &str) -> bool {
+        self.map
+            .index(action)
+            .is_some_and(|index| self.released.contains(&index))
+    }
+
+    /// One if only `positive` is held, minus one if only `negative` is.
+    pub fn axis(&self, negative: &str, positive: &str) -> f32 {
+        self.is_held(positive) as i32 as f32 - self.is_held(negative) as i32 as f32
+    }
+
+    /// Forgets what was pressed and released; held actions stay held.
+    pub fn end_frame(&mut self) {
+        self.pressed.clear();
+        self.released.clear();
+    }
+}
@@ -82,6 +82,18 @@ pub fn init_physical_device_and_properties(
     chosen
 }
 
+/// Whether `physical_device` offers the device extension `name`.
+pub fn supports_device_extension(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    name: &CStr,
+) -> Result<bool, vk::Result> {
+    let properties = unsafe { instance.enumerate_device_extension_properties(physical_device)? };
+    Ok(properties
+        .iter()
+        .any(|extension| unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) } == name))
+}
+
 pub struct QueueFamilies {
     pub graphics_q_index: Option<u32>,
     pub transfer_q_index: Option<u32>,
@@ -128,6 +140,7 @@ pub fn init_device_and_queues(
     physical_device: vk::PhysicalDevice,
     queue_families: &QueueFamilies,
     layer_names: &[&str],
+    extension_names: &[&CStr],
 ) -> Result<(ash::Device, Queues)> {
     let layer_names_c: Vec<CString> = layer_names
         .iter()
@@ -150,7 +163,10 @@ pub fn init_device_and_queues(
             .build(),
     ];
     let device_extension_name_pointers: Vec<*const i8> =
-        vec![ash::extensions::khr::Swapchain::name().as_ptr()];
+        std::iter::once(ash::extensions::khr::Swapchain::name())
+            .chain(extension_names.iter().copied())
+            .map(CStr::as_ptr)
+            .collect();
     let features = vk::PhysicalDeviceFeatures::builder().fill_mode_non_solid(true);
     let device_create_info = vk::DeviceCreateInfo::builder()
         .queue_create_infos(&queue_infos)
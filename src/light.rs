@@ -2,11 +2,13 @@ use ash::version::DeviceV1_0;
 use ash::vk;
 use nalgebra as na;
 
+#[derive(Copy, Clone, Debug)]
 pub struct DirectionalLight {
     pub direction: na::Vector3<f32>,
     pub illuminance: [f32; 3], //in lx = lm/m^2
 }
 
+#[derive(Copy, Clone, Debug)]
 pub struct PointLight {
     pub position: na::Point3<f32>, //in m
     pub luminous_flux: [f32; 3],   //in lm
@@ -56,13 +58,23 @@ impl LightManager {
         }
     }
 
-    pub fn update_buffer(
-        &self,
-        logical_device: &ash::Device,
-        allocator: &vk_mem::Allocator,
-        buffer: &mut crate::buffers::Buffer,
-        descriptor_sets_light: &mut [vk::DescriptorSet],
-    ) -> Result<(), vk_mem::error::Error> {
+    pub fn directional_lights(&self) -> &[DirectionalLight] {
+        &self.directional_lights
+    }
+
+    pub fn point_lights(&self) -> &[PointLight] {
+        &self.point_lights
+    }
+
+    pub fn directional_light_mut(&mut self, index: usize) -> Option<&mut DirectionalLight> {
+        self.directional_lights.get_mut(index)
+    }
+
+    pub fn point_light_mut(&mut self, index: usize) -> Option<&mut PointLight> {
+        self.point_lights.get_mut(index)
+    }
+
+    fn data(&self) -> Vec<f32> {
         let mut data: Vec<f32> = vec![];
         data.push(self.directional_lights.len() as f32);
         data.push(self.point_lights.len() as f32);
@@ -88,6 +100,17 @@ impl LightManager {
             data.push(pl.luminous_flux[2]);
             data.push(0.0);
         }
+        data
+    }
+
+    pub fn update_buffer(
+        &self,
+        logical_device: &ash::Device,
+        allocator: &vk_mem::Allocator,
+        buffer: &mut crate::buffers::Buffer,
+        descriptor_sets_light: &mut [vk::DescriptorSet],
+    ) -> Result<(), vk_mem::error::Error> {
+        let data = self.data();
         buffer.fill(allocator, &data)?;
         for descset in descriptor_sets_light {
             let buffer_infos = [vk::DescriptorBufferInfo {
@@ -105,4 +128,14 @@ impl LightManager {
         }
         Ok(())
     }
+
+    /// Rewrites the buffer of the last `update_buffer`, for lights that moved
+    /// or changed colour. Adding lights needs `update_buffer` instead.
+    pub fn refill_buffer(
+        &self,
+        allocator: &vk_mem::Allocator,
+        buffer: &mut crate::buffers::Buffer,
+    ) -> Result<(), vk_mem::error::Error> {
+        buffer.fill(allocator, &self.data())
+    }
 }
@@ -0,0 +1,154 @@
+use crate::{
+    camera::Camera,
+    model::{InvalidHandle, Model},
+    picking::{InstanceTransform, VertexPosition},
+};
+use eyre::*;
+use std::collections::HashMap;
+
+/// Level of detail model registered with the renderer, see
+/// `Aetna::add_lod_model`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LodHandle(pub(crate) usize);
+
+/// Meshes of one object, most detailed first, of which every instance is drawn
+/// with the one that suits its size on screen. The meshes are models in
+/// `Aetna::models` with instance buffers of their own, and instances move
+/// between them as they are assigned another level.
+pub struct LodModel {
+    /// Indices into `Aetna::models`.
+    levels: Vec<usize>,
+    /// Screen size, see `Camera::screen_size`, down to which each level but
+    /// the last is used.
+    thresholds: Vec<f32>,
+    /// Fraction of a threshold by which an instance has to pass it before it
+    /// switches level, so that instances close to it do not pop back and forth.
+    pub hysteresis: f32,
+    /// Level and handle in that level's model of every instance.
+    placements: HashMap<usize, (usize, usize)>,
+    next_handle: usize,
+}
+
+impl LodModel {
+    /// `thresholds` has one decreasing entry fewer than there are `levels`.
+    pub fn new(levels: Vec<usize>, thresholds: Vec<f32>) -> Result<LodModel> {
+        if levels.is_empty() || thresholds.len() + 1 != levels.len() {
+            bail!(
+                "{} levels of detail need {} thresholds, not {}",
+                levels.len(),
+                levels.len().saturating_sub(1),
+                thresholds.len()
+            );
+        }
+        if thresholds.windows(2).any(|pair| pair[0] <= pair[1]) {
+            bail!("Level of detail thresholds must decrease");
+        }
+        Ok(LodModel {
+            levels,
+            thresholds,
+            hysteresis: 0.0,
+            placements: HashMap::new(),
+            next_handle: 0,
+        })
+    }
+
+    /// Indices of the level models in `Aetna::models`, most detailed first.
+    #[allow(dead_code)]
+    pub fn levels(&self) -> &[usize] {
+        &self.levels
+    }
+
+    /// Level `handle` was last assigned.
+    pub fn level(&self, handle: usize) -> Option<usize> {
+        self.placements.get(&handle).map(|&(level, _)| level)
+    }
+
+    /// Instance `handle`, wherever it is drawn.
+    pub fn get<'a, V, I>(&self, models: &'a [Model<V, I>], handle: usize) -> Option<&'a I> {
+        let &(level, inner) = self.placements.get(&handle)?;
+        models[self.levels[level]].get(inner)
+    }
+
+    /// Handle of the instance drawn as `instance` of `Aetna::models[model]`,
+    /// e.g. the model and instance of a pick.
+    pub fn find(&self, model: usize, instance: usize) -> Option<usize> {
+        let level = self.levels.iter().position(|&index| index == model)?;
+        self.placements
+            .iter()
+            .find(|(_, &placement)| placement == (level, instance))
+            .map(|(&handle, _)| handle)
+    }
+
+    /// Adds a visible instance, drawn with the most detailed level until the
+    /// next `select`.
+    pub fn insert_visibly<V, I>(&mut self, models: &mut [Model<V, I>], instance: I) -> usize {
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        let inner = models[self.levels[0]].insert_visibly(instance);
+        self.placements.insert(handle, (0, inner));
+        handle
+    }
+
+    /// Takes an instance out of whichever level draws it.
+    #[allow(dead_code)]
+    pub fn remove<V, I>(
+        &mut self,
+        models: &mut [Model<V, I>],
+        handle: usize,
+    ) -> Result<I, InvalidHandle> {
+        let (level, inner) = self.placements.remove(&handle).ok_or(InvalidHandle)?;
+        models[self.levels[level]].remove(inner)
+    }
+
+    /// Level for an instance of screen size `size` that is drawn with
+    /// `current`.
+    fn level_for(&self, size: f32, current: usize) -> usize {
+        let ideal = self
+            .thresholds
+            .iter()
+            .position(|&threshold| size >= threshold)
+            .unwrap_or(self.thresholds.len());
+        let keep = if ideal < current {
+            size < self.thresholds[current - 1] * (1.0 + self.hysteresis)
+        } else if ideal > current {
+            size >= self.thresholds[current] * (1.0 - self.hysteresis)
+        } else {
+            true
+        };
+        if keep {
+            current
+        } else {
+            ideal
+        }
+    }
+
+    /// Assigns every instance the level for its size as seen by
+    /// `camera`, moving those whose level changed to the other model.
+    pub fn select<V: VertexPosition, I: InstanceTransform>(
+        &mut self,
+        models: &mut [Model<V, I>],
+        camera: &Camera,
+    ) -> Result<(), InvalidHandle> {
+        let mut moves = vec![];
+        for (&handle, &(level, inner)) in &self.placements {
+            let model = &mut models[self.levels[level]];
+            let sphere = match model.bounding_sphere() {
+                Some(sphere) => sphere,
+                None => continue,
+            };
+            let instance = model.get(inner).ok_or(InvalidHandle)?;
+            let size = camera.screen_size(&sphere.transformed(&instance.modelmatrix()));
+            let new_level = self.level_for(size, level);
+            if new_level != level {
+                moves.push((handle, new_level));
+            }
+        }
+        for (handle, new_level) in moves {
+            let (level, inner) = self.placements[&handle];
+            let instance = models[self.levels[level]].remove(inner)?;
+            let inner = models[self.levels[new_level]].insert_visibly(instance);
+            self.placements.insert(handle, (new_level, inner));
+        }
+        Ok(())
+    }
+}
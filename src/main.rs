@@ -9,88 +9,155 @@ use nalgebra as na;
 
 mod aetna;
 mod angle;
+mod bloom;
 mod buffers;
 mod camera;
+mod camera_controller;
+mod camera_path;
+mod clock;
+mod compute;
+mod culling;
 mod debug;
+mod gpu_culling;
+#[cfg(feature = "hot-reload")]
+mod hot_reload;
+mod id_buffer;
+mod input;
 mod instance_device_queues;
 mod light;
+mod lod;
 mod math;
 mod model;
+mod particles;
+mod picking;
+mod pipeline_cache;
 mod pool_and_commandbuffer;
+mod postprocess;
+mod reflection;
+mod render_graph;
+mod render_target;
 mod renderpass_and_pipeline;
+mod scene;
+mod scene_file;
+#[cfg(feature = "hot-reload")]
+mod shader_compiler;
+mod shaders;
+mod skybox;
 mod surface;
 mod swapchain;
+mod texture;
+mod tonemap;
 mod utils;
-use crate::light::{DirectionalLight, LightManager, PointLight};
+use crate::camera::{AxisView, CameraHandle, Projection};
+use crate::camera_controller::{
+    ArcballController, CameraController, FpsController, OrbitController,
+};
+use crate::camera_path::{Bookmarks, CameraPath, CameraPose};
+use crate::clock::{Clock, FixedTimestep, Interpolated};
+use crate::input::{Input, InputMap};
+use crate::particles::{Curve, Emitter};
+use crate::picking::InstanceTransform;
+use crate::postprocess::{Effect, EffectInput};
+use crate::render_graph::{Access, RenderGraph};
+use crate::scene::{Attachment, SceneGraph, Transform};
+use crate::scene_file::{SceneFile, SceneInstance};
+use crate::swapchain::PresentPreference;
+
+const BOOKMARKS: &str = "camera_bookmarks.txt";
+/// Key and mouse bindings, the built-in ones from `input.cfg` are used without it.
+const INPUT_CONFIG: &str = "input.cfg";
+const CAMERA_PATH: &str = "camera_path.txt";
+/// Scene loaded at startup, the built-in one from `scene.toml` is used without it.
+const SCENE: &str = "scene.toml";
+/// Where save_scene writes the scene as it is, to be loaded as `SCENE`.
+const SAVED_SCENE: &str = "saved_scene.toml";
 
 fn main() -> Result<()> {
     color_eyre::install()?;
+    env_logger::init();
     let eventloop = EventLoop::new();
     let window = winit::window::Window::new(&eventloop)?;
     let mut aetna = aetna::Aetna::init(window)?;
-    let mut sphere = model::Model::<model::VertexData, model::InstanceData>::sphere(3);
-    sphere.insert_visibly(model::InstanceData::from_matrix_and_colour(
-        na::Matrix4::new_scaling(0.5),
-        [0.955, 0.638, 0.538],
-    ));
-    for i in 0..10 {
-        for j in 0..10 {
-            sphere.insert_visibly(
-                model::InstanceData::from_matrix_colour_metallic_and_roughness(
-                    na::Matrix4::new_translation(&na::Vector3::new(
-                        i as f32 - 5.,
-                        j as f32 + 5.,
-                        10.0,
-                    )) * na::Matrix4::new_scaling(0.5),
-                    [0., 0., 0.8],
-                    i as f32 * 0.1,
-                    j as f32 * 0.1,
-                ),
+    let mut scene = SceneFile::load(SCENE)?.build(&mut aetna)?;
+    // The copper sphere bobs up and down with the orbiting light circling it,
+    // as far as the scene has them. Both start out where the file put them.
+    let mut scene_graph = SceneGraph::new();
+    let pivot = scene_graph.add("pivot", Transform::default(), None);
+    match scene.instance("copper sphere") {
+        Some(SceneInstance::Model { model, handle }) => {
+            let instance = aetna.models[model]
+                .get(handle)
+                .context("Copper sphere without instance data")?;
+            scene_graph.add_attached(
+                "copper sphere",
+                Transform::from_matrix(&instance.modelmatrix()),
+                Some(pivot),
+                Attachment::Instance { model, handle },
             );
         }
+        _ => log::warn!("No copper sphere in {} to animate", SCENE),
+    }
+    match scene.point_light("orbiting light") {
+        Some(light) => {
+            scene_graph.add_attached(
+                "orbiting light",
+                Transform::from_translation(scene.lights.point_lights()[light].position.coords),
+                Some(pivot),
+                Attachment::PointLight(light),
+            );
+        }
+        None => log::warn!("No orbiting light in {} to animate", SCENE),
     }
-    sphere.update_vertexbuffer(&aetna.allocator)?;
-    sphere.update_indexbuffer(&aetna.allocator)?;
-    sphere.update_instancebuffer(&aetna.allocator)?;
-    aetna.models = vec![sphere];
 
-    let mut lights = LightManager::default();
-    lights.add_light(DirectionalLight {
-        direction: na::Vector3::new(-1., -1., 0.),
-        illuminance: [10.1, 10.1, 10.1],
-    });
-    lights.add_light(PointLight {
-        position: na::Point3::new(0.1, -3.0, -3.0),
-        luminous_flux: [100.0, 100.0, 100.0],
-    });
-    lights.add_light(PointLight {
-        position: na::Point3::new(1.5, 0.0, 0.0),
-        luminous_flux: [10.0, 10.0, 10.0],
-    });
-    lights.add_light(PointLight {
-        position: na::Point3::new(1.5, 0.2, 0.0),
-        luminous_flux: [5.0, 5.0, 5.0],
-    });
-    lights.add_light(PointLight {
-        position: na::Point3::new(0.1, -3.0, -3.0),
-        luminous_flux: [100.0, 100.0, 100.0],
-    });
-    lights.add_light(PointLight {
-        position: na::Point3::new(0.1, -3.0, -3.0),
-        luminous_flux: [100.0, 100.0, 100.0],
-    });
-    lights.update_buffer(
-        &aetna.device,
-        &aetna.allocator,
-        &mut aetna.lightbuffer,
-        &mut aetna.descriptor_sets_light,
+    let mut vignette = [0.6f32, 1.5];
+    aetna.add_effect(
+        Effect::fragment("vignette", shaders::spirv("vignette.frag", ""))
+            .input(EffectInput::Previous)
+            .parameters(&vignette),
     )?;
+    aetna.add_emitter(Emitter {
+        position: na::Point3::new(0.0, 1.0, 3.0),
+        spawn_radius: 0.05,
+        spawn_rate: 40_000.0,
+        lifetime: (3.0, 5.0),
+        velocity: na::Vector3::new(0.0, -4.0, 0.0),
+        spread: 0.25,
+        acceleration: na::Vector3::new(0.0, 2.0, 0.0),
+        speed: Curve::linear(1.0, 0.6),
+        colour: Curve([
+            [8.0, 4.0, 1.0, 1.0],
+            [4.0, 1.5, 0.3, 0.8],
+            [1.0, 0.2, 0.05, 0.4],
+            [0.2, 0.02, 0.0, 0.0],
+        ]),
+        size: Curve::linear(0.03, 0.08),
+        max_particles: 200_000,
+        ..Default::default()
+    })?;
 
-    let mut camera = camera::Camera::builder().build();
+    let camera = scene.camera;
+    let mut controller_index = 0;
+    let mut controller = camera_controller(controller_index, &aetna, camera);
+    let mut clock = Clock::default();
+    let mut next_report = 1.0;
+    // The scene and particles are animated at a fixed rate and drawn
+    // interpolated between the last two steps.
+    let mut timestep = FixedTimestep::new(1.0 / 120.0);
+    let mut animation_time = 0.0f32;
+    let mut pivot_transform = Interpolated::new(Transform::default());
+    let mut cursor = winit::dpi::PhysicalPosition::new(0.0, 0.0);
+    let mut input = Input::new(InputMap::load(INPUT_CONFIG)?);
+    let mut selection_start = None;
+    let mut gpu_picking = false;
+    let mut bookmarks = Bookmarks::load(BOOKMARKS)?;
+    let mut camera_path = CameraPath::load(CAMERA_PATH)?;
 
-    let mut shift_acceleration = 0.;
     eventloop.run(move |event, _, controlflow| {
         *controlflow = ControlFlow::Poll;
+        if let Event::WindowEvent { event, .. } = &event {
+            input.handle_event(event);
+            controller.handle_event(event, &input);
+        }
         match event {
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
@@ -99,53 +166,195 @@ fn main() -> Result<()> {
                 *controlflow = ControlFlow::Exit;
             }
             Event::WindowEvent {
-                event: WindowEvent::ModifiersChanged(state),
+                event: WindowEvent::CursorMoved { position, .. },
                 ..
             } => {
-                let shift_pressed = state.shift() as u32;
-                shift_acceleration = 0.2 * shift_pressed as f32;
+                cursor = position;
             }
-            Event::WindowEvent {
-                event: WindowEvent::KeyboardInput { input, .. },
-                ..
-            } => {
-                if let KeyboardInput {
-                    state: ElementState::Pressed,
-                    virtual_keycode: Some(keycode),
-                    ..
-                } = input
-                {
-                    match keycode {
-                        VirtualKeyCode::Right | VirtualKeyCode::D => {
-                            camera.turn_right(0.1 + shift_acceleration);
-                        }
-                        VirtualKeyCode::Left | VirtualKeyCode::A => {
-                            camera.turn_left(0.1 + shift_acceleration);
-                        }
-                        VirtualKeyCode::Up | VirtualKeyCode::W => {
-                            camera.move_forward(0.05 + shift_acceleration);
-                        }
-                        VirtualKeyCode::Down | VirtualKeyCode::S => {
-                            camera.move_backward(0.05 + shift_acceleration);
-                        }
-                        VirtualKeyCode::Space => {
-                            camera.turn_down(0.02);
-                        }
-                        VirtualKeyCode::Z => {
-                            camera.turn_up(0.02);
-                        }
-                        VirtualKeyCode::Escape => {
-                            *controlflow = ControlFlow::Exit;
+            Event::MainEventsCleared => {
+                #[cfg(feature = "hot-reload")]
+                aetna.reload_shaders().expect("Failed reload shaders.");
+                for result in aetna.pick_results().expect("Failed read picks.") {
+                    log::info!(
+                        "{:?} found {:?} in {:?}",
+                        result.query,
+                        result.instances,
+                        result.area
+                    );
+                }
+                // Pick reports the instance under the cursor, found on the CPU or,
+                // after toggle_id_buffer, in the ID buffer, where dragging selects
+                // a rectangle.
+                if input.was_pressed("pick") {
+                    selection_start = Some(cursor);
+                }
+                if input.was_released("pick") {
+                    if let Some(start) = selection_start.take() {
+                        if gpu_picking {
+                            let x = start.x.min(cursor.x) as i32;
+                            let y = start.y.min(cursor.y) as i32;
+                            let area = vk::Rect2D {
+                                offset: vk::Offset2D { x, y },
+                                extent: vk::Extent2D {
+                                    width: (start.x - cursor.x).abs() as u32 + 1,
+                                    height: (start.y - cursor.y).abs() as u32 + 1,
+                                },
+                            };
+                            aetna.request_pick(area).expect("Failed request pick.");
+                        } else {
+                            match aetna.pick(camera, cursor.x as f32, cursor.y as f32) {
+                                Some(hit) => {
+                                    log::info!(
+                                        "Picked instance {} of model {} at {:?}, normal {:?}",
+                                        hit.instance,
+                                        hit.model,
+                                        hit.point.coords.as_slice(),
+                                        hit.normal.as_slice()
+                                    );
+                                    if let Some(grid) = scene.lod("grid") {
+                                        let lod_model = aetna.lod_model(grid);
+                                        if let Some(handle) =
+                                            lod_model.find(hit.model, hit.instance)
+                                        {
+                                            log::info!(
+                                                "Grid sphere {} at level of detail {:?}",
+                                                handle,
+                                                lod_model.level(handle)
+                                            );
+                                        }
+                                    }
+                                }
+                                None => log::info!("Nothing picked"),
+                            }
                         }
-                        VirtualKeyCode::F12 => {
-                            screenshot(&aetna).expect("screenshot trouble");
+                    }
+                }
+                if input.was_pressed("next_controller") {
+                    controller_index = (controller_index + 1) % 3;
+                    controller = camera_controller(controller_index, &aetna, camera);
+                }
+                if input.was_pressed("next_projection") {
+                    let projection = next_projection(aetna.camera(camera).projection());
+                    aetna.camera_mut(camera).set_projection(projection);
+                    log::info!("Projection: {:?}", projection);
+                }
+                for (action, view) in &[
+                    ("view_front", AxisView::Front),
+                    ("view_right", AxisView::Right),
+                    ("view_top", AxisView::Top),
+                ] {
+                    if input.was_pressed(action) {
+                        let distance = aetna.camera(camera).position().norm().max(1.0);
+                        aetna.camera_mut(camera).look_along_axis(
+                            *view,
+                            na::Vector3::zeros(),
+                            distance,
+                        );
+                        controller = camera_controller(controller_index, &aetna, camera);
+                    }
+                }
+                for slot in 1..=4 {
+                    let name = slot.to_string();
+                    if input.was_pressed(&format!("store_bookmark_{}", slot)) {
+                        bookmarks.set(&name, CameraPose::of(aetna.camera(camera)));
+                        bookmarks.save(BOOKMARKS).expect("Failed save bookmarks.");
+                    } else if input.was_pressed(&format!("recall_bookmark_{}", slot)) {
+                        if let Some(pose) = bookmarks.get(&name) {
+                            pose.apply(aetna.camera_mut(camera));
+                            controller = camera_controller(controller_index, &aetna, camera);
                         }
-                        _ => {}
                     }
                 }
-            }
-            Event::MainEventsCleared => {
-                aetna.window.request_redraw();
+                if input.was_pressed("record_path") {
+                    if camera_path.is_recording() {
+                        camera_path.stop();
+                        camera_path
+                            .save(CAMERA_PATH)
+                            .expect("Failed save camera path.");
+                        log::info!("Recorded {:.1} s", camera_path.duration());
+                    } else {
+                        camera_path.record();
+                    }
+                }
+                if input.was_pressed("play_path") {
+                    if camera_path.is_playing() {
+                        camera_path.stop();
+                        controller = camera_controller(controller_index, &aetna, camera);
+                    } else {
+                        camera_path.play();
+                    }
+                }
+                if input.was_pressed("toggle_id_buffer") {
+                    gpu_picking = !gpu_picking;
+                    aetna
+                        .enable_id_buffer(gpu_picking)
+                        .expect("Failed switch ID buffer.");
+                }
+                if input.was_pressed("next_culling") {
+                    aetna
+                        .set_culling(aetna.culling().next())
+                        .expect("Failed switch culling.");
+                    log::info!("Culling: {:?}", aetna.culling());
+                }
+                if input.was_pressed("next_tonemapper") {
+                    aetna.tonemapping.operator = aetna.tonemapping.operator.next();
+                }
+                if input.was_pressed("debug_normals") {
+                    let permutation = if aetna.scene_permutation().is_empty() {
+                        "debug_normals"
+                    } else {
+                        ""
+                    };
+                    aetna
+                        .set_scene_permutation(permutation)
+                        .expect("Failed switch scene shader.");
+                }
+                if input.was_pressed("toggle_bloom") {
+                    aetna.bloom.enabled = !aetna.bloom.enabled;
+                }
+                if input.was_pressed("toggle_vignette") {
+                    vignette[0] = if vignette[0] > 0.0 { 0.0 } else { 0.6 };
+                    aetna
+                        .set_effect_parameters("vignette", &vignette)
+                        .expect("vignette parameters");
+                }
+                if input.was_pressed("exposure_up") {
+                    aetna.tonemapping.exposure *= 1.25;
+                }
+                if input.was_pressed("exposure_down") {
+                    aetna.tonemapping.exposure /= 1.25;
+                }
+                if input.was_pressed("next_present_mode") {
+                    let mut config = aetna.swapchain_config().clone();
+                    config.present = match config.present {
+                        PresentPreference::Vsync => PresentPreference::Mailbox,
+                        _ => PresentPreference::Vsync,
+                    };
+                    aetna
+                        .set_swapchain_config(config)
+                        .expect("Failed recreate swapchain.");
+                    log::info!("Present mode: {:?}", aetna.swapchain.present_mode);
+                }
+                if input.was_pressed("save_scene") {
+                    scene
+                        .capture(&aetna)
+                        .save(SAVED_SCENE)
+                        .expect("Failed save scene.");
+                    log::info!("Saved the scene to {}", SAVED_SCENE);
+                }
+                if input.was_pressed("screenshot") {
+                    screenshot(&aetna).expect("screenshot trouble");
+                }
+                if input.was_pressed("quit") {
+                    *controlflow = ControlFlow::Exit;
+                }
+                input.end_frame();
+                if aetna.is_minimised() {
+                    // Nothing to draw until the window is restored.
+                    *controlflow = ControlFlow::Wait;
+                } else {
+                    aetna.window.request_redraw();
+                }
             }
             Event::WindowEvent {
                 event: WindowEvent::Resized(new_size),
@@ -154,26 +363,56 @@ fn main() -> Result<()> {
                 aetna
                     .recreate_swapchain(new_size.width, new_size.height)
                     .expect("Failed recreate swapchain.");
-                // camera.set_aspect(
-                //     aetna.swapchain.extent.width as f32 / aetna.swapchain.extent.height as f32,
-                // );
-                // camera
-                //     .update_buffer(&aetna.allocator, &mut aetna.uniformbuffer)
-                //     .expect("camera buffer update");
             }
 
             Event::RedrawRequested(_) => {
-                let (image_index, _) = unsafe {
-                    aetna
-                        .swapchain
-                        .swapchain_loader
-                        .acquire_next_image(
-                            aetna.swapchain.swapchain,
-                            std::u64::MAX,
-                            aetna.swapchain.image_available[aetna.swapchain.current_image],
-                            vk::Fence::null(),
-                        )
-                        .expect("image acquisition trouble")
+                if aetna.is_minimised() {
+                    return;
+                }
+                let dt = clock.tick();
+                let alpha = timestep.advance(dt, |step| {
+                    animation_time += step;
+                    pivot_transform.push(Transform {
+                        translation: na::Vector3::new(
+                            0.0,
+                            -0.25 * (1.5 * animation_time).sin(),
+                            0.0,
+                        ),
+                        rotation: na::UnitQuaternion::from_axis_angle(
+                            &na::Vector3::y_axis(),
+                            0.8 * animation_time,
+                        ),
+                        ..Default::default()
+                    });
+                    aetna.advance(step);
+                });
+                if camera_path.is_playing() {
+                    camera_path.update(aetna.camera_mut(camera), dt);
+                    if !camera_path.is_playing() {
+                        controller = camera_controller(controller_index, &aetna, camera);
+                    }
+                } else {
+                    controller.update(aetna.camera_mut(camera), &input, dt);
+                    camera_path.update(aetna.camera_mut(camera), dt);
+                }
+                let acquired = unsafe {
+                    aetna.swapchain.swapchain_loader.acquire_next_image(
+                        aetna.swapchain.swapchain,
+                        std::u64::MAX,
+                        aetna.swapchain.image_available[aetna.swapchain.current_image],
+                        vk::Fence::null(),
+                    )
+                };
+                let image_index = match acquired {
+                    Ok((image_index, _)) => image_index,
+                    Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                        let size = aetna.window.inner_size();
+                        aetna
+                            .recreate_swapchain(size.width, size.height)
+                            .expect("Failed recreate swapchain.");
+                        return;
+                    }
+                    Err(e) => panic!("image acquisition trouble: {}", e),
                 };
                 unsafe {
                     aetna
@@ -191,12 +430,26 @@ fn main() -> Result<()> {
                         ])
                         .expect("resetting fences");
                 }
-                camera
-                    .update_buffer(&aetna.allocator, &mut aetna.uniformbuffer)
+                scene_graph.set_local(pivot, pivot_transform.get(alpha));
+                if aetna
+                    .update_scene(&mut scene_graph, &mut scene.lights)
+                    .expect("Failed update scene.")
+                {
+                    scene
+                        .lights
+                        .refill_buffer(&aetna.allocator, &mut aetna.lightbuffer)
+                        .expect("Failed update light buffer.");
+                }
+                aetna
+                    .update_camera_buffer(camera)
                     .expect("Failed update camera buffer.");
-                for m in &mut aetna.models {
-                    m.update_instancebuffer(&aetna.allocator)
-                        .expect("Failed update instance buffer");
+                aetna
+                    .update_instancebuffers(camera)
+                    .expect("Failed update instance buffer");
+                if clock.time() > next_report {
+                    log::info!("Frames: {}", clock.stats());
+                    log::info!("Culling: {}", aetna.culling_stats());
+                    next_report = clock.time() + 1.0;
                 }
                 aetna
                     .update_commandbuffer(image_index as usize)
@@ -235,18 +488,14 @@ fn main() -> Result<()> {
                         .swapchain_loader
                         .queue_present(aetna.queues.graphics_queue, &present_info)
                     {
-                        Ok(..) => {}
-                        Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                        Ok(false) => {}
+                        // Suboptimal or out of date, e.g. after a resize that
+                        // has not been reported yet.
+                        Ok(true) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                            let size = aetna.window.inner_size();
                             aetna
-                                .recreate_swapchain(0, 0)
+                                .recreate_swapchain(size.width, size.height)
                                 .expect("Failed recreate swapchain.");
-                            camera.set_aspect(
-                                aetna.swapchain.extent.width as f32
-                                    / aetna.swapchain.extent.height as f32,
-                            );
-                            camera
-                                .update_buffer(&aetna.allocator, &mut aetna.uniformbuffer)
-                                .expect("camera buffer update");
                         }
                         _ => panic!("Unhandled queue presentation error."),
                     }
@@ -259,6 +508,50 @@ fn main() -> Result<()> {
     });
 }
 
+/// The demo's camera controllers, cycled through with C. The scene is built
+/// with -y as up.
+fn camera_controller<V, I>(
+    index: usize,
+    aetna: &aetna::Aetna<V, I>,
+    camera: CameraHandle,
+) -> Box<dyn CameraController> {
+    let up = -na::Vector3::y();
+    let target = na::Vector3::zeros();
+    let camera = aetna.camera(camera);
+    match index {
+        0 => Box::new(FpsController::from_camera(camera, up)),
+        1 => Box::new(OrbitController::from_camera(camera, target, up)),
+        _ => Box::new(ArcballController::from_camera(
+            camera,
+            target,
+            aetna.window.inner_size(),
+        )),
+    }
+}
+
+/// The projections P cycles through.
+fn next_projection(projection: Projection) -> Projection {
+    let fovy = std::f32::consts::FRAC_PI_3;
+    match projection {
+        Projection::Perspective { .. } => Projection::InfinitePerspective { fovy, near: 0.1 },
+        Projection::InfinitePerspective { .. } => Projection::ReverseZ {
+            fovy,
+            near: 0.1,
+            far: None,
+        },
+        Projection::ReverseZ { .. } => Projection::Orthographic {
+            height: 5.0,
+            near: 0.0,
+            far: 100.0,
+        },
+        Projection::Orthographic { .. } => Projection::Perspective {
+            fovy,
+            near: 0.1,
+            far: 100.0,
+        },
+    }
+}
+
 // TODO(#6): Allocate commandbuffers beforehand.
 fn screenshot<V, I>(aetna: &aetna::Aetna<V, I>) -> Result<(), Box<dyn std::error::Error>> {
     let commandbuf_allocate_info = vk::CommandBufferAllocateInfo::builder()
@@ -296,144 +589,60 @@ fn screenshot<V, I>(aetna: &aetna::Aetna<V, I>) -> Result<(), Box<dyn std::error
     let (destination_image, dst_alloc, _allocinfo) =
         aetna.allocator.create_image(&ici, &allocinfo)?;
 
-    let barrier = vk::ImageMemoryBarrier::builder()
-        .image(destination_image)
-        .src_access_mask(vk::AccessFlags::empty())
-        .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-        .old_layout(vk::ImageLayout::UNDEFINED)
-        .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
-        .subresource_range(vk::ImageSubresourceRange {
-            aspect_mask: vk::ImageAspectFlags::COLOR,
-            base_mip_level: 0,
-            level_count: 1,
-            base_array_layer: 0,
-            layer_count: 1,
-        })
-        .build();
-    unsafe {
-        aetna.device.cmd_pipeline_barrier(
-            copybuffer,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::DependencyFlags::empty(),
-            &[],
-            &[],
-            &[barrier],
-        )
-    };
-
     let source_image = aetna.swapchain.images[aetna.swapchain.current_image];
-    let barrier = vk::ImageMemoryBarrier::builder()
-        .image(source_image)
-        .src_access_mask(vk::AccessFlags::MEMORY_READ)
-        .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
-        .old_layout(vk::ImageLayout::PRESENT_SRC_KHR)
-        .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
-        .subresource_range(vk::ImageSubresourceRange {
-            aspect_mask: vk::ImageAspectFlags::COLOR,
-            base_mip_level: 0,
-            level_count: 1,
-            base_array_layer: 0,
-            layer_count: 1,
-        })
-        .build();
-    unsafe {
-        aetna.device.cmd_pipeline_barrier(
-            copybuffer,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::DependencyFlags::empty(),
-            &[],
-            &[],
-            &[barrier],
-        )
-    };
-
-    let zero_offset = vk::Offset3D::default();
-    let copy_area = vk::ImageCopy::builder()
-        .src_subresource(vk::ImageSubresourceLayers {
-            aspect_mask: vk::ImageAspectFlags::COLOR,
-            mip_level: 0,
-            base_array_layer: 0,
-            layer_count: 1,
-        })
-        .src_offset(zero_offset)
-        .dst_subresource(vk::ImageSubresourceLayers {
-            aspect_mask: vk::ImageAspectFlags::COLOR,
-            mip_level: 0,
-            base_array_layer: 0,
-            layer_count: 1,
-        })
-        .dst_offset(zero_offset)
-        .extent(vk::Extent3D {
-            width: aetna.swapchain.extent.width,
-            height: aetna.swapchain.extent.height,
-            depth: 1,
-        })
-        .build();
-    unsafe {
-        aetna.device.cmd_copy_image(
-            copybuffer,
-            source_image,
-            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-            destination_image,
-            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            &[copy_area],
-        )
-    };
-
-    // TODO(#7): Think about unnecessary barriers.
-    let barrier = vk::ImageMemoryBarrier::builder()
-        .image(destination_image)
-        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-        .dst_access_mask(vk::AccessFlags::MEMORY_READ)
-        .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
-        .new_layout(vk::ImageLayout::GENERAL)
-        .subresource_range(vk::ImageSubresourceRange {
-            aspect_mask: vk::ImageAspectFlags::COLOR,
-            base_mip_level: 0,
-            level_count: 1,
-            base_array_layer: 0,
-            layer_count: 1,
-        })
-        .build();
-    unsafe {
-        aetna.device.cmd_pipeline_barrier(
-            copybuffer,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::DependencyFlags::empty(),
-            &[],
-            &[],
-            &[barrier],
-        )
-    };
-
-    let barrier = vk::ImageMemoryBarrier::builder()
-        .image(source_image)
-        .src_access_mask(vk::AccessFlags::TRANSFER_READ)
-        .dst_access_mask(vk::AccessFlags::MEMORY_READ)
-        .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
-        .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
-        .subresource_range(vk::ImageSubresourceRange {
-            aspect_mask: vk::ImageAspectFlags::COLOR,
-            base_mip_level: 0,
-            level_count: 1,
-            base_array_layer: 0,
-            layer_count: 1,
-        })
-        .build();
-    unsafe {
-        aetna.device.cmd_pipeline_barrier(
-            copybuffer,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::DependencyFlags::empty(),
-            &[],
-            &[],
-            &[barrier],
-        )
-    };
+    let extent = aetna.swapchain.extent;
+    let mut graph = RenderGraph::new();
+    let source = graph.import_image(
+        source_image,
+        vk::ImageAspectFlags::COLOR,
+        Some(Access::Present),
+        Some(Access::Present),
+    );
+    let destination = graph.import_image(
+        destination_image,
+        vk::ImageAspectFlags::COLOR,
+        None,
+        Some(Access::HostRead),
+    );
+    graph
+        .add_pass("screenshot copy")
+        .read(source, Access::TransferRead)
+        .write(destination, Access::TransferWrite)
+        .record(move |device, commandbuffer, resources| {
+            let zero_offset = vk::Offset3D::default();
+            let copy_area = vk::ImageCopy::builder()
+                .src_subresource(vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: 0,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .src_offset(zero_offset)
+                .dst_subresource(vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: 0,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .dst_offset(zero_offset)
+                .extent(vk::Extent3D {
+                    width: extent.width,
+                    height: extent.height,
+                    depth: 1,
+                })
+                .build();
+            unsafe {
+                device.cmd_copy_image(
+                    commandbuffer,
+                    resources.image(source),
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    resources.image(destination),
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[copy_area],
+                )
+            };
+        });
+    graph.execute(&aetna.device, &aetna.allocator, copybuffer)?;
 
     unsafe { aetna.device.end_command_buffer(copybuffer) }?;
     let submit_infos = [vk::SubmitInfo::builder()
@@ -478,17 +687,22 @@ fn screenshot<V, I>(aetna: &aetna::Aetna<V, I>) -> Result<(), Box<dyn std::error
         data.set_len(subresource_layout.size as usize);
     }
     aetna.allocator.unmap_memory(&dst_alloc)?;
+    graph.cleanup(&aetna.allocator)?;
     aetna
         .allocator
         .destroy_image(destination_image, &dst_alloc)?;
-    let screen: image::ImageBuffer<image::Bgra<u8>, _> = image::ImageBuffer::from_raw(
-        aetna.swapchain.extent.width,
-        aetna.swapchain.extent.height,
-        data,
-    )
-    .expect("ImageBuffer creation");
+    let (width, height) = (aetna.swapchain.extent.width, aetna.swapchain.extent.height);
+    // The copy keeps the bytes in the order of the surface format.
+    let screen = match aetna.swapchain.surface_format.format {
+        vk::Format::R8G8B8A8_SRGB | vk::Format::R8G8B8A8_UNORM => image::DynamicImage::ImageRgba8(
+            image::ImageBuffer::from_raw(width, height, data).expect("ImageBuffer creation"),
+        ),
+        _ => image::DynamicImage::ImageBgra8(
+            image::ImageBuffer::from_raw(width, height, data).expect("ImageBuffer creation"),
+        ),
+    };
 
-    let screen_image = image::DynamicImage::ImageBgra8(screen).to_rgba8();
+    let screen_image = screen.to_rgba8();
     screen_image.save("screenshot.jpg")?;
 
     Ok(())
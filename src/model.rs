@@ -1,4 +1,4 @@
-use crate::buffers::Buffer;
+use crate::{buffers::Buffer, culling::BoundingSphere, picking::VertexPosition};
 use ash::{version::DeviceV1_0, vk};
 use nalgebra as na;
 
@@ -53,6 +53,7 @@ impl InstanceData {
             roughness,
         }
     }
+    #[allow(dead_code)]
     pub fn from_matrix_and_colour(modelmatrix: na::Matrix4<f32>, colour: [f32; 3]) -> InstanceData {
         InstanceData {
             modelmatrix: modelmatrix.into(),
@@ -102,6 +103,9 @@ pub struct Model<V, I> {
     instances: Vec<I>,
     first_invisible: usize,
     next_handle: usize,
+    /// Handles of the instances in the instance buffer, in buffer order.
+    drawn_handles: Vec<usize>,
+    bounding_sphere: Option<BoundingSphere>,
     pub vertexbuffer: Option<Buffer>,
     pub indexbuffer: Option<Buffer>,
     pub instancebuffer: Option<Buffer>,
@@ -109,14 +113,14 @@ pub struct Model<V, I> {
 
 #[allow(dead_code)]
 impl<V, I> Model<V, I> {
-    fn get(&self, handle: usize) -> Option<&I> {
+    pub fn get(&self, handle: usize) -> Option<&I> {
         if let Some(&index) = self.handle_to_index.get(&handle) {
             self.instances.get(index)
         } else {
             None
         }
     }
-    fn get_mut(&mut self, handle: usize) -> Option<&mut I> {
+    pub fn get_mut(&mut self, handle: usize) -> Option<&mut I> {
         if let Some(&index) = self.handle_to_index.get(&handle) {
             self.instances.get_mut(index)
         } else {
@@ -167,12 +171,25 @@ impl<V, I> Model<V, I> {
         self.handle_to_index.insert(handle, index);
         handle
     }
+    pub fn vertices(&self) -> &[V] {
+        &self.vertexdata
+    }
+    pub fn indices(&self) -> &[u32] {
+        &self.indexdata
+    }
+    /// Handles and data of the instances that are drawn.
+    pub fn visible_instances(&self) -> impl Iterator<Item = (usize, &I)> {
+        self.handles[..self.first_invisible]
+            .iter()
+            .copied()
+            .zip(&self.instances[..self.first_invisible])
+    }
     pub fn insert_visibly(&mut self, element: I) -> usize {
         let new_handle = self.insert(element);
         self.make_visible(new_handle).ok();
         new_handle
     }
-    fn remove(&mut self, handle: usize) -> Result<I, InvalidHandle> {
+    pub fn remove(&mut self, handle: usize) -> Result<I, InvalidHandle> {
         if let Some(&index) = self.handle_to_index.get(&handle) {
             if index < self.first_invisible {
                 self.swap_by_index(index, self.first_invisible - 1);
@@ -196,8 +213,8 @@ impl<V, I> Model<V, I> {
         ) {
             self.handles.swap(index1, index2);
             self.instances.swap(index1, index2);
-            self.handle_to_index.insert(index1, handle2);
-            self.handle_to_index.insert(index2, handle1);
+            self.handle_to_index.insert(handle2, index1);
+            self.handle_to_index.insert(handle1, index2);
             Ok(())
         } else {
             Err(InvalidHandle)
@@ -211,13 +228,14 @@ impl<V, I> Model<V, I> {
         let handle2 = self.handles[index2];
         self.handles.swap(index1, index2);
         self.instances.swap(index1, index2);
-        self.handle_to_index.insert(index1, handle2);
-        self.handle_to_index.insert(index2, handle1);
+        self.handle_to_index.insert(handle2, index1);
+        self.handle_to_index.insert(handle1, index2);
     }
     pub fn update_vertexbuffer(
         &mut self,
         allocator: &vk_mem::Allocator,
     ) -> Result<(), vk_mem::error::Error> {
+        self.bounding_sphere = None;
         if let Some(buffer) = &mut self.vertexbuffer {
             buffer.fill(allocator, &self.vertexdata)?;
             Ok(())
@@ -258,15 +276,18 @@ impl<V, I> Model<V, I> {
         &mut self,
         allocator: &vk_mem::Allocator,
     ) -> Result<(), vk_mem::error::Error> {
+        self.drawn_handles.clear();
+        self.drawn_handles
+            .extend_from_slice(&self.handles[..self.first_invisible]);
         if let Some(buffer) = &mut self.instancebuffer {
             buffer.fill(allocator, &self.instances[0..self.first_invisible])?;
             Ok(())
         } else {
-            let bytes = (self.first_invisible * std::mem::size_of::<I>()) as u64;
+            let bytes = (self.first_invisible.max(1) * std::mem::size_of::<I>()) as u64;
             let mut buffer = Buffer::new(
                 &allocator,
                 bytes,
-                vk::BufferUsageFlags::VERTEX_BUFFER,
+                vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
                 vk_mem::MemoryUsage::CpuToGpu,
             )?;
             buffer.fill(allocator, &self.instances[0..self.first_invisible])?;
@@ -274,11 +295,54 @@ impl<V, I> Model<V, I> {
             Ok(())
         }
     }
+    /// Uploads only the visible instances at `selection`, indices as counted
+    /// by `visible_instances`, and draws just those.
+    pub fn update_instancebuffer_selection(
+        &mut self,
+        allocator: &vk_mem::Allocator,
+        selection: &[usize],
+    ) -> Result<(), vk_mem::error::Error> {
+        self.drawn_handles.clear();
+        let handles = &self.handles;
+        self.drawn_handles
+            .extend(selection.iter().map(|&index| handles[index]));
+        let visible = &self.instances[0..self.first_invisible];
+        // Room for every visible instance, so a selection that grows as the
+        // camera moves never reallocates a buffer frames in flight still read.
+        let bytes = (self.first_invisible.max(1) * std::mem::size_of::<I>()) as u64;
+        if let Some(buffer) = &mut self.instancebuffer {
+            if buffer.size_in_bytes < bytes {
+                // More visible instances than before: grow like update_instancebuffer.
+                buffer.fill(allocator, visible)?;
+            }
+            buffer.fill_selection(allocator, visible, selection)?;
+        } else {
+            let mut buffer = Buffer::new(
+                &allocator,
+                bytes,
+                vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk_mem::MemoryUsage::CpuToGpu,
+            )?;
+            buffer.fill_selection(allocator, visible, selection)?;
+            self.instancebuffer = Some(buffer);
+        }
+        Ok(())
+    }
+    /// Handles of the instances the last update put in the instance buffer;
+    /// `gl_InstanceIndex` counts these.
+    pub fn drawn_handles(&self) -> &[usize] {
+        &self.drawn_handles
+    }
+    /// The sphere of the last `bounding_sphere` call, unless the vertices
+    /// changed since.
+    pub fn cached_bounding_sphere(&self) -> Option<BoundingSphere> {
+        self.bounding_sphere
+    }
     pub fn draw(&self, logical_device: &ash::Device, commandbuffer: vk::CommandBuffer) {
         if let Some(vertexbuffer) = &self.vertexbuffer {
             if let Some(indexbuffer) = &self.indexbuffer {
                 if let Some(instancebuffer) = &self.instancebuffer {
-                    if self.first_invisible > 0 {
+                    if !self.drawn_handles.is_empty() {
                         unsafe {
                             logical_device.cmd_bind_vertex_buffers(
                                 commandbuffer,
@@ -301,7 +365,7 @@ impl<V, I> Model<V, I> {
                             logical_device.cmd_draw_indexed(
                                 commandbuffer,
                                 self.indexdata.len() as u32,
-                                self.first_invisible as u32,
+                                self.drawn_handles.len() as u32,
                                 0,
                                 0,
                                 0,
@@ -312,6 +376,65 @@ impl<V, I> Model<V, I> {
             }
         }
     }
+    /// Draws the instances in `instances` with the draw parameters in
+    /// `commands`, a `vk::DrawIndexedIndirectCommand` followed by the number of
+    /// draws, zero or one, which lets `draw_indirect_count` skip empty draws.
+    pub fn draw_indirect(
+        &self,
+        logical_device: &ash::Device,
+        draw_indirect_count: Option<&ash::extensions::khr::DrawIndirectCount>,
+        commandbuffer: vk::CommandBuffer,
+        instances: vk::Buffer,
+        commands: vk::Buffer,
+    ) {
+        let stride = std::mem::size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+        if let (Some(vertexbuffer), Some(indexbuffer)) = (&self.vertexbuffer, &self.indexbuffer) {
+            unsafe {
+                logical_device.cmd_bind_vertex_buffers(
+                    commandbuffer,
+                    0,
+                    &[vertexbuffer.buffer, instances],
+                    &[0, 0],
+                );
+                logical_device.cmd_bind_index_buffer(
+                    commandbuffer,
+                    indexbuffer.buffer,
+                    0,
+                    vk::IndexType::UINT32,
+                );
+                match draw_indirect_count {
+                    Some(loader) => loader.cmd_draw_indexed_indirect_count(
+                        commandbuffer,
+                        commands,
+                        0,
+                        commands,
+                        stride as u64,
+                        1,
+                        stride,
+                    ),
+                    None => logical_device.cmd_draw_indexed_indirect(
+                        commandbuffer,
+                        commands,
+                        0,
+                        1,
+                        stride,
+                    ),
+                }
+            }
+        }
+    }
+}
+
+impl<V: VertexPosition, I> Model<V, I> {
+    /// Model space sphere around the vertices, kept until the vertex buffer is
+    /// next updated.
+    pub fn bounding_sphere(&mut self) -> Option<BoundingSphere> {
+        if self.bounding_sphere.is_none() {
+            let points: Vec<_> = self.vertexdata.iter().map(|v| v.position()).collect();
+            self.bounding_sphere = BoundingSphere::from_points(&points);
+        }
+        self.bounding_sphere
+    }
 }
 
 impl<I> Model<[f32; 3], I> {
@@ -465,6 +588,8 @@ impl Model<VertexData, InstanceData> {
             handles: Vec::new(),
             instances: Vec::new(),
             first_invisible: 0,
+            drawn_handles: vec![],
+            bounding_sphere: None,
             next_handle: 0,
             vertexbuffer: None,
             indexbuffer: None,
@@ -481,6 +606,123 @@ impl Model<VertexData, InstanceData> {
         }
         model
     }
+    /// Triangle mesh from the vertices, normals and faces of a Wavefront OBJ
+    /// file, ignoring everything else in it. Polygons are split into fans, and
+    /// vertices without a normal get the average of their faces' normals.
+    pub fn load_obj(
+        path: impl AsRef<std::path::Path>,
+    ) -> eyre::Result<Model<VertexData, InstanceData>> {
+        use eyre::{bail, WrapErr};
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+        let mut positions: Vec<[f32; 3]> = vec![];
+        let mut normals: Vec<[f32; 3]> = vec![];
+        let mut vertices: Vec<VertexData> = vec![];
+        let mut indices: Vec<u32> = vec![];
+        // Vertex for each pair of position and normal index, where vertices
+        // without a normal share theirs with the other faces using the position.
+        let mut known = std::collections::HashMap::<(usize, Option<usize>), u32>::new();
+        let mut computed = vec![];
+        for (number, line) in text.lines().enumerate() {
+            let mut words = line.split('#').next().unwrap_or("").split_whitespace();
+            let keyword = words.next();
+            let context = || format!("{}:{}", path.display(), number + 1);
+            // OBJ indices start at one, negative ones count back from the end.
+            let index = |word: &str, count: usize| -> eyre::Result<usize> {
+                let index: i64 = word
+                    .parse()
+                    .wrap_err_with(|| format!("{}: bad index {}", context(), word))?;
+                let resolved = if index < 0 {
+                    count as i64 + index
+                } else {
+                    index - 1
+                };
+                if resolved < 0 || resolved >= count as i64 {
+                    bail!("{}: index {} out of range", context(), index);
+                }
+                Ok(resolved as usize)
+            };
+            match keyword {
+                Some("v") | Some("vn") => {
+                    let coordinates = words
+                        .take(3)
+                        .map(str::parse)
+                        .collect::<Result<Vec<f32>, _>>()
+                        .wrap_err_with(context)?;
+                    if coordinates.len() != 3 {
+                        bail!("{}: expected three coordinates", context());
+                    }
+                    let vector = [coordinates[0], coordinates[1], coordinates[2]];
+                    if keyword == Some("v") {
+                        positions.push(vector);
+                    } else {
+                        normals.push(vector);
+                    }
+                }
+                Some("f") => {
+                    let mut face = vec![];
+                    for corner in words {
+                        let mut parts = corner.split('/');
+                        let position = index(parts.next().unwrap_or(""), positions.len())?;
+                        let normal = match parts.nth(1) {
+                            Some(word) if !word.is_empty() => Some(index(word, normals.len())?),
+                            _ => None,
+                        };
+                        let vertex = *known.entry((position, normal)).or_insert_with(|| {
+                            vertices.push(VertexData {
+                                position: positions[position],
+                                normal: normal.map_or([0.0; 3], |normal| normals[normal]),
+                            });
+                            if normal.is_none() {
+                                computed.push(vertices.len() - 1);
+                            }
+                            vertices.len() as u32 - 1
+                        });
+                        face.push(vertex);
+                    }
+                    if face.len() < 3 {
+                        bail!("{}: face with fewer than three corners", context());
+                    }
+                    for i in 1..face.len() - 1 {
+                        indices.extend(&[face[0], face[i], face[i + 1]]);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if indices.is_empty() {
+            bail!("{} has no faces", path.display());
+        }
+        let mut sums = vec![na::Vector3::<f32>::zeros(); vertices.len()];
+        for triangle in indices.chunks(3) {
+            let corner = |i: usize| na::Vector3::from(vertices[triangle[i] as usize].position);
+            let normal = (corner(1) - corner(0)).cross(&(corner(2) - corner(0)));
+            for &vertex in triangle {
+                sums[vertex as usize] += normal;
+            }
+        }
+        for vertex in computed {
+            vertices[vertex].normal = sums[vertex]
+                .try_normalize(1e-12)
+                .unwrap_or_else(na::Vector3::y)
+                .into();
+        }
+        Ok(Model {
+            vertexdata: vertices,
+            indexdata: indices,
+            handle_to_index: std::collections::HashMap::new(),
+            handles: Vec::new(),
+            instances: Vec::new(),
+            first_invisible: 0,
+            drawn_handles: vec![],
+            bounding_sphere: None,
+            next_handle: 0,
+            vertexbuffer: None,
+            indexbuffer: None,
+            instancebuffer: None,
+        })
+    }
     pub fn refine(&mut self) {
         let mut new_indices = vec![];
         let mut midpoints = std::collections::HashMap::<(u32, u32), u32>::new();
@@ -550,6 +792,8 @@ fn cube<I>() -> Model<[f32; 3], I> {
         handles: Vec::new(),
         instances: Vec::new(),
         first_invisible: 0,
+        drawn_handles: vec![],
+        bounding_sphere: None,
         next_handle: 0,
         vertexbuffer: None,
         indexbuffer: None,
@@ -614,6 +858,8 @@ fn icosahedron<I>() -> Model<[f32; 3], I> {
         handles: Vec::new(),
         instances: Vec::new(),
         first_invisible: 0,
+        drawn_handles: vec![],
+        bounding_sphere: None,
         next_handle: 0,
         vertexbuffer: None,
         indexbuffer: None,
@@ -634,7 +880,7 @@ pub fn sphere<I>(refinements: u32) -> Model<[f32; 3], I> {
 }
 
 #[derive(Debug, Clone)]
-struct InvalidHandle;
+pub struct InvalidHandle;
 impl std::fmt::Display for InvalidHandle {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "invalid handle")
@@ -688,6 +934,8 @@ impl Model<TexturedVertexData, TexturedInstanceData> {
             handles: Vec::new(),
             instances: Vec::new(),
             first_invisible: 0,
+            drawn_handles: vec![],
+            bounding_sphere: None,
             next_handle: 0,
             vertexbuffer: None,
             indexbuffer: None,
@@ -0,0 +1,477 @@
+use crate::{
+    buffers::Buffer,
+    compute::{ComputePipeline, ComputeResource},
+    pool_and_commandbuffer::{one_time_submit, Pools},
+    render_graph::{Access, RenderGraph},
+    renderpass_and_pipeline::{set_viewport, DepthConvention, Pipeline},
+    shaders,
+};
+use ash::{version::DeviceV1_0, vk};
+use eyre::*;
+use nalgebra as na;
+
+const WORKGROUP_SIZE: u32 = 256;
+const MAX_EMITTERS: u32 = 32;
+/// Bytes per particle: position and age, velocity and lifetime.
+const PARTICLE_SIZE: u64 = 32;
+
+/// Value over a particle's lifetime, given by four evenly spaced keys at the
+/// normalised ages 0, 1/3, 2/3 and 1 and interpolated linearly in between.
+#[derive(Copy, Clone, Debug)]
+pub struct Curve<T>(pub [T; 4]);
+
+#[allow(dead_code)]
+impl<T: Copy + Lerp> Curve<T> {
+    pub fn constant(value: T) -> Self {
+        Curve([value; 4])
+    }
+    pub fn linear(start: T, end: T) -> Self {
+        Curve([
+            start,
+            start.lerp(end, 1.0 / 3.0),
+            start.lerp(end, 2.0 / 3.0),
+            end,
+        ])
+    }
+}
+
+pub trait Lerp {
+    fn lerp(self, other: Self, t: f32) -> Self;
+}
+
+impl Lerp for f32 {
+    fn lerp(self, other: Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Lerp for [f32; 4] {
+    fn lerp(self, other: Self, t: f32) -> Self {
+        [
+            self[0].lerp(other[0], t),
+            self[1].lerp(other[1], t),
+            self[2].lerp(other[2], t),
+            self[3].lerp(other[3], t),
+        ]
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ParticleShape {
+    /// Soft camera facing sprite, blended additively.
+    Billboard,
+    /// Shaded sphere impostor, written to the depth buffer.
+    Sphere,
+}
+
+/// Describes where particles appear and how they evolve. Colours are linear
+/// HDR values with alpha, sizes are diameters in metres.
+#[derive(Clone, Debug)]
+pub struct Emitter {
+    pub position: na::Point3<f32>,
+    /// Particles spawn uniformly within a cube of this half-extent.
+    pub spawn_radius: f32,
+    /// Particles per second.
+    pub spawn_rate: f32,
+    /// Minimum and maximum lifetime in seconds.
+    pub lifetime: (f32, f32),
+    pub velocity: na::Vector3<f32>,
+    /// Half-angle of the cone around `velocity`, in radians.
+    pub spread: f32,
+    pub acceleration: na::Vector3<f32>,
+    /// Multiplier of the speed over the lifetime.
+    pub speed: Curve<f32>,
+    pub colour: Curve<[f32; 4]>,
+    pub size: Curve<f32>,
+    pub shape: ParticleShape,
+    pub max_particles: u32,
+    pub enabled: bool,
+}
+
+impl Default for Emitter {
+    fn default() -> Self {
+        Emitter {
+            position: na::Point3::origin(),
+            spawn_radius: 0.0,
+            spawn_rate: 100.0,
+            lifetime: (1.0, 2.0),
+            velocity: na::Vector3::new(0.0, -1.0, 0.0),
+            spread: 0.3,
+            acceleration: na::Vector3::zeros(),
+            speed: Curve::constant(1.0),
+            colour: Curve::linear([1.0, 1.0, 1.0, 1.0], [1.0, 1.0, 1.0, 0.0]),
+            size: Curve::constant(0.05),
+            shape: ParticleShape::Billboard,
+            max_particles: 1024,
+            enabled: true,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+struct SimulationParameters {
+    position: [f32; 4],
+    velocity: [f32; 4],
+    acceleration: [f32; 4],
+    speed: [f32; 4],
+    lifetime: [f32; 2],
+    dt: f32,
+    spawn_count: u32,
+    seed: u32,
+    particle_count: u32,
+}
+
+unsafe impl bytemuck::Zeroable for SimulationParameters {}
+unsafe impl bytemuck::Pod for SimulationParameters {}
+
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+pub struct DrawParameters {
+    colours: [[f32; 4]; 4],
+    sizes: [f32; 4],
+    shape: u32,
+    _padding: [u32; 3],
+}
+
+unsafe impl bytemuck::Zeroable for DrawParameters {}
+unsafe impl bytemuck::Pod for DrawParameters {}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct EmitterHandle(usize);
+
+struct EmitterState {
+    emitter: Emitter,
+    particles: Buffer,
+    spawned: Buffer,
+    simulation_set: vk::DescriptorSet,
+    draw_set: vk::DescriptorSet,
+    /// Fractional particles carried over to the next frame.
+    spawn_accumulator: f32,
+}
+
+/// Emitters simulated by a compute shader over a storage buffer of particles
+/// and drawn as instanced quads using the camera uniform.
+pub struct ParticleSystem {
+    simulation: ComputePipeline,
+    billboard_pipeline: Pipeline,
+    sphere_pipeline: Pipeline,
+    descriptor_pool: vk::DescriptorPool,
+    emitters: Vec<EmitterState>,
+    /// Seconds passed to `advance` since the last simulation step.
+    pending: f32,
+    seed: u32,
+}
+
+impl ParticleSystem {
+    pub fn init(
+        logical_device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
+        renderpass: &vk::RenderPass,
+        depth: DepthConvention,
+    ) -> Result<Self> {
+        let simulation = ComputePipeline::init(
+            logical_device,
+            pipeline_cache,
+            &shaders::spirv("particles_simulate.comp", ""),
+            &[vk::DescriptorType::STORAGE_BUFFER; 2],
+            std::mem::size_of::<SimulationParameters>() as u32,
+            MAX_EMITTERS,
+        )?;
+        let (billboard_pipeline, sphere_pipeline) =
+            ParticleSystem::create_pipelines(logical_device, pipeline_cache, renderpass, depth)?;
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: MAX_EMITTERS,
+        }];
+        let descriptor_pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(MAX_EMITTERS)
+            .pool_sizes(&pool_sizes);
+        let descriptor_pool =
+            unsafe { logical_device.create_descriptor_pool(&descriptor_pool_info, None) }?;
+        Ok(ParticleSystem {
+            simulation,
+            billboard_pipeline,
+            sphere_pipeline,
+            descriptor_pool,
+            emitters: vec![],
+            pending: 0.0,
+            seed: 0,
+        })
+    }
+
+    fn create_pipelines(
+        logical_device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
+        renderpass: &vk::RenderPass,
+        depth: DepthConvention,
+    ) -> Result<(Pipeline, Pipeline)> {
+        let billboard_pipeline =
+            Pipeline::init_particles(logical_device, pipeline_cache, renderpass, depth, false)?;
+        let sphere_pipeline =
+            match Pipeline::init_particles(logical_device, pipeline_cache, renderpass, depth, true)
+            {
+                Ok(pipeline) => pipeline,
+                Err(e) => {
+                    billboard_pipeline.cleanup(logical_device);
+                    return Err(e);
+                }
+            };
+        Ok((billboard_pipeline, sphere_pipeline))
+    }
+
+    /// Rebuilds the simulation and draw pipelines from the current shader code,
+    /// keeping the old ones if that fails. The device must be idle.
+    pub fn recreate_pipelines(
+        &mut self,
+        logical_device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
+        renderpass: &vk::RenderPass,
+        depth: DepthConvention,
+    ) -> Result<()> {
+        let (billboard_pipeline, sphere_pipeline) =
+            ParticleSystem::create_pipelines(logical_device, pipeline_cache, renderpass, depth)?;
+        if let Err(e) = self.simulation.recreate(
+            logical_device,
+            pipeline_cache,
+            &shaders::spirv("particles_simulate.comp", ""),
+        ) {
+            billboard_pipeline.cleanup(logical_device);
+            sphere_pipeline.cleanup(logical_device);
+            return Err(e);
+        }
+        self.billboard_pipeline.cleanup(logical_device);
+        self.sphere_pipeline.cleanup(logical_device);
+        self.billboard_pipeline = billboard_pipeline;
+        self.sphere_pipeline = sphere_pipeline;
+        Ok(())
+    }
+
+    /// Allocates the emitter's particle buffer, with every particle dead.
+    pub fn add_emitter(
+        &mut self,
+        logical_device: &ash::Device,
+        allocator: &vk_mem::Allocator,
+        pools: &Pools,
+        queue: vk::Queue,
+        emitter: Emitter,
+    ) -> Result<EmitterHandle> {
+        if self.emitters.len() as u32 >= MAX_EMITTERS {
+            bail!("At most {} particle emitters are supported", MAX_EMITTERS);
+        }
+        if emitter.max_particles == 0 {
+            bail!("Particle emitter needs room for at least one particle");
+        }
+        let particles = Buffer::new(
+            allocator,
+            emitter.max_particles as u64 * PARTICLE_SIZE,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk_mem::MemoryUsage::GpuOnly,
+        )?;
+        let spawned = Buffer::new(
+            allocator,
+            4,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk_mem::MemoryUsage::GpuOnly,
+        )?;
+        one_time_submit(logical_device, pools, queue, |commandbuffer| unsafe {
+            logical_device.cmd_fill_buffer(commandbuffer, particles.buffer, 0, vk::WHOLE_SIZE, 0);
+        })?;
+        let simulation_set = self.simulation.create_descriptor_set(
+            logical_device,
+            &[
+                ComputeResource::StorageBuffer(particles.buffer),
+                ComputeResource::StorageBuffer(spawned.buffer),
+            ],
+        )?;
+
+        let desc_layouts = [self.billboard_pipeline.descriptor_set_layouts[1]];
+        let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.descriptor_pool)
+            .set_layouts(&desc_layouts);
+        let draw_set =
+            unsafe { logical_device.allocate_descriptor_sets(&descriptor_set_allocate_info) }?[0];
+        let buffer_infos = [vk::DescriptorBufferInfo {
+            buffer: particles.buffer,
+            offset: 0,
+            range: vk::WHOLE_SIZE,
+        }];
+        let desc_sets_write = [vk::WriteDescriptorSet::builder()
+            .dst_set(draw_set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&buffer_infos)
+            .build()];
+        unsafe { logical_device.update_descriptor_sets(&desc_sets_write, &[]) };
+
+        self.emitters.push(EmitterState {
+            emitter,
+            particles,
+            spawned,
+            simulation_set,
+            draw_set,
+            spawn_accumulator: 0.0,
+        });
+        Ok(EmitterHandle(self.emitters.len() - 1))
+    }
+
+    /// Changes to `max_particles` are ignored, the buffer keeps its size.
+    #[allow(dead_code)]
+    pub fn emitter_mut(&mut self, handle: EmitterHandle) -> Option<&mut Emitter> {
+        self.emitters
+            .get_mut(handle.0)
+            .map(|state| &mut state.emitter)
+    }
+
+    /// Lets `dt` seconds pass for the next `simulate`.
+    pub fn advance(&mut self, dt: f32) {
+        self.pending += dt;
+    }
+
+    /// Records one simulation step of every enabled emitter, covering the
+    /// time passed to `advance` since the previous call.
+    pub fn simulate(
+        &mut self,
+        logical_device: &ash::Device,
+        allocator: &vk_mem::Allocator,
+        commandbuffer: vk::CommandBuffer,
+    ) -> Result<()> {
+        let dt = std::mem::take(&mut self.pending);
+        self.seed = self.seed.wrapping_add(0x9e37_79b9);
+
+        let mut graph = RenderGraph::new();
+        for state in self.emitters.iter_mut().filter(|s| s.emitter.enabled) {
+            let emitter = &state.emitter;
+            state.spawn_accumulator += emitter.spawn_rate * dt;
+            let spawn_count = state.spawn_accumulator.floor();
+            state.spawn_accumulator -= spawn_count;
+            let parameters = SimulationParameters {
+                position: [
+                    emitter.position.x,
+                    emitter.position.y,
+                    emitter.position.z,
+                    emitter.spawn_radius,
+                ],
+                velocity: [
+                    emitter.velocity.x,
+                    emitter.velocity.y,
+                    emitter.velocity.z,
+                    emitter.spread,
+                ],
+                acceleration: [
+                    emitter.acceleration.x,
+                    emitter.acceleration.y,
+                    emitter.acceleration.z,
+                    0.0,
+                ],
+                speed: emitter.speed.0,
+                lifetime: [emitter.lifetime.0, emitter.lifetime.1],
+                dt,
+                spawn_count: spawn_count as u32,
+                seed: self.seed,
+                particle_count: emitter.max_particles,
+            };
+            let groups = emitter.max_particles.div_ceil(WORKGROUP_SIZE);
+
+            let particles = graph.import_buffer(
+                state.particles.buffer,
+                Some(Access::VertexStorageRead),
+                Some(Access::VertexStorageRead),
+            );
+            let spawned = graph.import_buffer(
+                state.spawned.buffer,
+                Some(Access::ComputeStorageWrite),
+                None,
+            );
+            graph
+                .add_pass("reset spawn counter")
+                .write_buffer(spawned, Access::TransferWrite)
+                .record(move |device, commandbuffer, resources| unsafe {
+                    device.cmd_fill_buffer(commandbuffer, resources.buffer(spawned), 0, 4, 0);
+                });
+            let simulation = &self.simulation;
+            let simulation_set = state.simulation_set;
+            graph
+                .add_pass("simulate particles")
+                .write_buffer(particles, Access::ComputeStorageWrite)
+                .write_buffer(spawned, Access::ComputeStorageWrite)
+                .record(move |device, commandbuffer, _| {
+                    simulation.record(
+                        device,
+                        commandbuffer,
+                        simulation_set,
+                        bytemuck::bytes_of(&parameters),
+                        [groups, 1, 1],
+                    );
+                });
+        }
+        graph.execute(logical_device, allocator, commandbuffer)?;
+        graph.cleanup(allocator)
+    }
+
+    /// Draws every enabled emitter inside the scene render pass.
+    pub fn draw(
+        &self,
+        logical_device: &ash::Device,
+        commandbuffer: vk::CommandBuffer,
+        descriptor_set_camera: vk::DescriptorSet,
+        extent: vk::Extent2D,
+    ) {
+        set_viewport(logical_device, commandbuffer, extent);
+        for state in self.emitters.iter().filter(|s| s.emitter.enabled) {
+            let emitter = &state.emitter;
+            let pipeline = match emitter.shape {
+                ParticleShape::Billboard => &self.billboard_pipeline,
+                ParticleShape::Sphere => &self.sphere_pipeline,
+            };
+            let parameters = DrawParameters {
+                colours: emitter.colour.0,
+                sizes: emitter.size.0,
+                shape: match emitter.shape {
+                    ParticleShape::Billboard => 0,
+                    ParticleShape::Sphere => 1,
+                },
+                _padding: [0; 3],
+            };
+            unsafe {
+                logical_device.cmd_bind_pipeline(
+                    commandbuffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline.pipeline,
+                );
+                logical_device.cmd_bind_descriptor_sets(
+                    commandbuffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline.layout,
+                    0,
+                    &[descriptor_set_camera, state.draw_set],
+                    &[],
+                );
+                logical_device.cmd_push_constants(
+                    commandbuffer,
+                    pipeline.layout,
+                    vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                    0,
+                    bytemuck::bytes_of(&parameters),
+                );
+                logical_device.cmd_draw(commandbuffer, 6, emitter.max_particles, 0, 0);
+            }
+        }
+    }
+
+    pub fn cleanup(&self, logical_device: &ash::Device, allocator: &vk_mem::Allocator) {
+        for state in &self.emitters {
+            allocator
+                .destroy_buffer(state.particles.buffer, &state.particles.allocation)
+                .expect("problem with buffer destruction");
+            allocator
+                .destroy_buffer(state.spawned.buffer, &state.spawned.allocation)
+                .expect("problem with buffer destruction");
+        }
+        unsafe { logical_device.destroy_descriptor_pool(self.descriptor_pool, None) };
+        self.simulation.cleanup(logical_device);
+        self.billboard_pipeline.cleanup(logical_device);
+        self.sphere_pipeline.cleanup(logical_device);
+    }
+}
@@ -0,0 +1,249 @@
+use crate::model::{InstanceData, Model, TexturedInstanceData, TexturedVertexData, VertexData};
+use nalgebra as na;
+
+/// Half-line in world space, e.g. from `Camera::ray_from_screen`.
+#[derive(Copy, Clone, Debug)]
+pub struct Ray {
+    pub origin: na::Point3<f32>,
+    pub direction: na::Unit<na::Vector3<f32>>,
+}
+
+/// Closest instance hit by a ray.
+#[derive(Copy, Clone, Debug)]
+pub struct Hit {
+    /// Index into `Aetna::models`.
+    pub model: usize,
+    /// Handle returned by `Model::insert_visibly`.
+    pub instance: usize,
+    /// Distance along the ray.
+    pub distance: f32,
+    pub point: na::Point3<f32>,
+    /// Face normal in world space, facing the ray.
+    pub normal: na::Unit<na::Vector3<f32>>,
+}
+
+/// Vertices that picking can read a position from.
+pub trait VertexPosition {
+    fn position(&self) -> na::Point3<f32>;
+}
+
+impl VertexPosition for [f32; 3] {
+    fn position(&self) -> na::Point3<f32> {
+        na::Point3::from(*self)
+    }
+}
+
+impl VertexPosition for VertexData {
+    fn position(&self) -> na::Point3<f32> {
+        na::Point3::from(self.position)
+    }
+}
+
+impl VertexPosition for TexturedVertexData {
+    fn position(&self) -> na::Point3<f32> {
+        na::Point3::from(self.position)
+    }
+}
+
+/// Instances that picking and culling can read a placement from, and the
+/// scene graph can write one to.
+pub trait InstanceTransform {
+    fn modelmatrix(&self) -> na::Matrix4<f32>;
+    fn inverse_modelmatrix(&self) -> na::Matrix4<f32>;
+    /// Sets the model matrix along with its inverse.
+    fn set_modelmatrix(&mut self, modelmatrix: na::Matrix4<f32>);
+}
+
+/// Inverse of a model matrix, zero for the degenerate ones that flatten an
+/// instance, e.g. scaled to nothing to hide it.
+fn inverse(modelmatrix: &na::Matrix4<f32>) -> [[f32; 4]; 4] {
+    modelmatrix
+        .try_inverse()
+        .unwrap_or_else(na::Matrix4::zeros)
+        .into()
+}
+
+impl InstanceTransform for InstanceData {
+    fn modelmatrix(&self) -> na::Matrix4<f32> {
+        self.modelmatrix.into()
+    }
+    fn inverse_modelmatrix(&self) -> na::Matrix4<f32> {
+        self.inverse_modelmatrix.into()
+    }
+    fn set_modelmatrix(&mut self, modelmatrix: na::Matrix4<f32>) {
+        self.modelmatrix = modelmatrix.into();
+        self.inverse_modelmatrix = inverse(&modelmatrix);
+    }
+}
+
+impl InstanceTransform for TexturedInstanceData {
+    fn modelmatrix(&self) -> na::Matrix4<f32> {
+        self.modelmatrix.into()
+    }
+    fn inverse_modelmatrix(&self) -> na::Matrix4<f32> {
+        self.inverse_modelmatrix.into()
+    }
+    fn set_modelmatrix(&mut self, modelmatrix: na::Matrix4<f32>) {
+        self.modelmatrix = modelmatrix.into();
+        self.inverse_modelmatrix = inverse(&modelmatrix);
+    }
+}
+
+/// Axis-aligned box in model space.
+#[derive(Copy, Clone, Debug)]
+pub struct Bounds {
+    pub min: na::Point3<f32>,
+    pub max: na::Point3<f32>,
+}
+
+impl Bounds {
+    /// Bounds of `points`, `None` if there are none.
+    pub fn from_points(points: impl IntoIterator<Item = na::Point3<f32>>) -> Option<Bounds> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        Some(points.fold(
+            Bounds {
+                min: first,
+                max: first,
+            },
+            |bounds, p| Bounds {
+                min: bounds.min.inf(&p),
+                max: bounds.max.sup(&p),
+            },
+        ))
+    }
+
+    /// Entry distance of a ray through the box (slab test), if it hits at or
+    /// after `origin` and before `max_distance`. The direction need not be
+    /// normalised; distances are in multiples of it.
+    pub fn intersect(
+        &self,
+        origin: &na::Point3<f32>,
+        direction: &na::Vector3<f32>,
+        max_distance: f32,
+    ) -> Option<f32> {
+        let mut near = 0.0_f32;
+        let mut far = max_distance;
+        for axis in 0..3 {
+            let inverse = 1.0 / direction[axis];
+            let t0 = (self.min[axis] - origin[axis]) * inverse;
+            let t1 = (self.max[axis] - origin[axis]) * inverse;
+            // NaN from 0 * inf, a ray in the slab's plane, must not shrink the
+            // interval, which `max`/`min` ensure by preferring the number.
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
+            if near > far {
+                return None;
+            }
+        }
+        Some(near)
+    }
+}
+
+/// Möller–Trumbore intersection, counting both sides of the triangle.
+fn intersect_triangle(
+    origin: &na::Point3<f32>,
+    direction: &na::Vector3<f32>,
+    [a, b, c]: [na::Point3<f32>; 3],
+) -> Option<f32> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = direction.cross(&edge2);
+    let determinant = edge1.dot(&p);
+    if determinant.abs() < f32::EPSILON {
+        return None;
+    }
+    let inverse = 1.0 / determinant;
+    let s = origin - a;
+    let u = s.dot(&p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(&edge1);
+    let v = direction.dot(&q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = edge2.dot(&q) * inverse;
+    if t >= 0.0 {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+impl<V: VertexPosition, I: InstanceTransform> Model<V, I> {
+    /// Model space bounds of the vertices.
+    pub fn bounds(&self) -> Option<Bounds> {
+        Bounds::from_points(self.vertices().iter().map(VertexPosition::position))
+    }
+
+    /// Closest visible instance hit before `max_distance`, with its handle,
+    /// the distance and the model space triangle.
+    #[allow(clippy::type_complexity)]
+    fn pick(&self, ray: &Ray, max_distance: f32) -> Option<(usize, &I, f32, [na::Point3<f32>; 3])> {
+        let bounds = self.bounds()?;
+        let mut closest = None;
+        let mut max_distance = max_distance;
+        for (handle, instance) in self.visible_instances() {
+            // The ray moved into model space keeps its parameter, so distances
+            // stay comparable across instances.
+            let inverse = instance.inverse_modelmatrix();
+            let origin = inverse.transform_point(&ray.origin);
+            let direction = inverse.transform_vector(&ray.direction);
+            if bounds
+                .intersect(&origin, &direction, max_distance)
+                .is_none()
+            {
+                continue;
+            }
+            for triangle in self.indices().chunks_exact(3) {
+                let corners = [
+                    self.vertices()[triangle[0] as usize].position(),
+                    self.vertices()[triangle[1] as usize].position(),
+                    self.vertices()[triangle[2] as usize].position(),
+                ];
+                if let Some(t) = intersect_triangle(&origin, &direction, corners) {
+                    if t < max_distance {
+                        max_distance = t;
+                        closest = Some((handle, instance, t, corners));
+                    }
+                }
+            }
+        }
+        closest
+    }
+}
+
+/// Closest visible instance of `models` along `ray`: each instance's bounds are
+/// tested first, then the triangles of those the ray passes through.
+pub fn pick<V: VertexPosition, I: InstanceTransform>(
+    models: &[Model<V, I>],
+    ray: &Ray,
+) -> Option<Hit> {
+    let mut closest: Option<Hit> = None;
+    for (index, model) in models.iter().enumerate() {
+        let max_distance = closest.map_or(f32::INFINITY, |hit| hit.distance);
+        if let Some((handle, instance, distance, [a, b, c])) = model.pick(ray, max_distance) {
+            // Normals go through the inverse transpose to stay perpendicular
+            // under non-uniform scaling.
+            let normal = instance
+                .inverse_modelmatrix()
+                .transpose()
+                .transform_vector(&(b - a).cross(&(c - a)));
+            let normal = if normal.dot(&ray.direction) > 0.0 {
+                -normal
+            } else {
+                normal
+            };
+            closest = Some(Hit {
+                model: index,
+                instance: handle,
+                distance,
+                point: ray.origin + distance * ray.direction.as_ref(),
+                normal: na::Unit::new_normalize(normal),
+            });
+        }
+    }
+    closest
+}
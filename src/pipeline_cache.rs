@@ -0,0 +1,119 @@
+use ash::{
+    version::{DeviceV1_0, InstanceV1_0},
+    vk,
+};
+use eyre::*;
+use std::path::PathBuf;
+
+/// Size of the `VK_PIPELINE_CACHE_HEADER_VERSION_ONE` header: length, version,
+/// vendor ID, device ID and the 16 byte cache UUID.
+const HEADER_SIZE: usize = 32;
+
+/// `vk::PipelineCache` kept on disk between runs, one file per device and
+/// driver so a cache is never handed to a driver that did not write it.
+pub struct PipelineCache {
+    pub cache: vk::PipelineCache,
+    path: Option<PathBuf>,
+}
+
+impl PipelineCache {
+    /// Loads the cache of `physical_device`, starting empty if there is none
+    /// or it was written by another device or driver version.
+    pub fn init(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        logical_device: &ash::Device,
+    ) -> Result<PipelineCache> {
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+        let path = cache_directory().map(|directory| {
+            let uuid: String = properties
+                .pipeline_cache_uuid
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            directory.join(format!(
+                "pipelines_{:04x}_{:04x}_{}.bin",
+                properties.vendor_id, properties.device_id, uuid
+            ))
+        });
+        let data = match &path {
+            Some(path) => match std::fs::read(path) {
+                Ok(data) if header_matches(&data, &properties) => data,
+                Ok(_) => {
+                    log::warn!("Discarding stale pipeline cache {}", path.display());
+                    vec![]
+                }
+                Err(_) => vec![],
+            },
+            None => vec![],
+        };
+        let cache_info = vk::PipelineCacheCreateInfo::builder().initial_data(&data);
+        let cache = match unsafe { logical_device.create_pipeline_cache(&cache_info, None) } {
+            Ok(cache) => cache,
+            Err(e) if !data.is_empty() => {
+                log::warn!("Pipeline cache rejected by the driver: {}", e);
+                let cache_info = vk::PipelineCacheCreateInfo::builder();
+                unsafe { logical_device.create_pipeline_cache(&cache_info, None) }?
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(PipelineCache { cache, path })
+    }
+
+    /// Writes the cache back to its file. Failing to do so only costs the next
+    /// start some time, so it is logged rather than returned.
+    pub fn save(&self, logical_device: &ash::Device) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let result = unsafe { logical_device.get_pipeline_cache_data(self.cache) }
+            .map_err(Report::from)
+            .and_then(|data| {
+                if let Some(directory) = path.parent() {
+                    std::fs::create_dir_all(directory)?;
+                }
+                // Written aside and renamed, so a crash never leaves half a cache.
+                let partial = path.with_extension("partial");
+                std::fs::write(&partial, data)?;
+                std::fs::rename(&partial, path)?;
+                Ok(())
+            });
+        if let Err(e) = result {
+            log::warn!("Cannot save pipeline cache {}: {:?}", path.display(), e);
+        }
+    }
+
+    pub fn cleanup(&self, logical_device: &ash::Device) {
+        unsafe { logical_device.destroy_pipeline_cache(self.cache, None) };
+    }
+}
+
+fn header_matches(data: &[u8], properties: &vk::PhysicalDeviceProperties) -> bool {
+    if data.len() < HEADER_SIZE {
+        return false;
+    }
+    let word = |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+    word(0) as usize >= HEADER_SIZE
+        && word(4) == vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32
+        && word(8) == properties.vendor_id
+        && word(12) == properties.device_id
+        && data[16..32] == properties.pipeline_cache_uuid
+}
+
+/// `$ASHY_CACHE_DIR`, else the platform's user cache directory.
+fn cache_directory() -> Option<PathBuf> {
+    if let Some(directory) = std::env::var_os("ASHY_CACHE_DIR") {
+        return Some(PathBuf::from(directory));
+    }
+    let base = if cfg!(windows) {
+        std::env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Caches"))
+    } else {
+        std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+    };
+    base.map(|base| base.join("ashy"))
+}
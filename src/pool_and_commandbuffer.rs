@@ -43,3 +43,35 @@ pub fn create_commandbuffers(
         .command_buffer_count(amount);
     unsafe { logical_device.allocate_command_buffers(&commandbuf_allocate_info) }
 }
+
+pub fn one_time_submit<F: FnOnce(vk::CommandBuffer)>(
+    logical_device: &ash::Device,
+    pools: &Pools,
+    queue: vk::Queue,
+    record: F,
+) -> Result<(), vk::Result> {
+    let commandbuf_allocate_info = vk::CommandBufferAllocateInfo::builder()
+        .command_pool(pools.commandpool_graphics)
+        .command_buffer_count(1);
+    let commandbuffer =
+        unsafe { logical_device.allocate_command_buffers(&commandbuf_allocate_info) }?[0];
+
+    let cmdbegininfo =
+        vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    unsafe { logical_device.begin_command_buffer(commandbuffer, &cmdbegininfo) }?;
+    record(commandbuffer);
+    unsafe { logical_device.end_command_buffer(commandbuffer) }?;
+
+    let commandbuffers = [commandbuffer];
+    let submit_infos = [vk::SubmitInfo::builder()
+        .command_buffers(&commandbuffers)
+        .build()];
+    let fence = unsafe { logical_device.create_fence(&vk::FenceCreateInfo::default(), None) }?;
+    unsafe {
+        logical_device.queue_submit(queue, &submit_infos, fence)?;
+        logical_device.wait_for_fences(&[fence], true, u64::MAX)?;
+        logical_device.destroy_fence(fence, None);
+        logical_device.free_command_buffers(pools.commandpool_graphics, &commandbuffers);
+    }
+    Ok(())
+}
@@ -0,0 +1,528 @@
+use crate::{
+    render_graph::{Access, ImageHandle, RenderGraph},
+    render_target::{create_sampler, RenderTarget, HDR_FORMAT},
+    renderpass_and_pipeline::{init_offscreen_renderpass, set_viewport, Pipeline},
+    swapchain::SwapchainDongXi,
+};
+use ash::{version::DeviceV1_0, vk};
+use eyre::*;
+
+/// Workgroup size compute effects have to declare, `local_size_x = 8, local_size_y = 8`.
+const COMPUTE_GROUP_SIZE: u32 = 8;
+/// Minimum push constant size every implementation supports.
+const MAX_PARAMETERS_SIZE: usize = 128;
+
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EffectKind {
+    /// Drawn as a full-screen triangle with `shaders/fullscreen.vert`, writing
+    /// `location = 0`.
+    Fragment,
+    /// Dispatched over the output, writing the `rgba16f` storage image bound
+    /// after the inputs.
+    Compute,
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum EffectInput {
+    /// HDR scene colour.
+    Scene,
+    /// Output of the effect right before this one, or the scene for the first.
+    Previous,
+    /// Output of an earlier effect with this name.
+    Output(String),
+}
+
+/// One step of the [`PostProcessChain`]. Inputs are bound in order as combined
+/// image samplers at set 0, bindings `0..inputs.len()`; `parameters` are pushed
+/// as constants. The output is an HDR image named after the effect.
+#[derive(Clone, Debug)]
+pub struct Effect {
+    pub name: String,
+    pub kind: EffectKind,
+    pub shader: Vec<u32>,
+    pub inputs: Vec<EffectInput>,
+    /// Output resolution relative to the swapchain.
+    pub scale: f32,
+    pub parameters: Vec<u8>,
+}
+
+#[allow(dead_code)]
+impl Effect {
+    pub fn fragment<S: Into<Vec<u32>>>(name: &str, shader: S) -> Effect {
+        Effect::new(name, EffectKind::Fragment, shader.into())
+    }
+    pub fn compute<S: Into<Vec<u32>>>(name: &str, shader: S) -> Effect {
+        Effect::new(name, EffectKind::Compute, shader.into())
+    }
+    fn new(name: &str, kind: EffectKind, shader: Vec<u32>) -> Effect {
+        Effect {
+            name: name.to_owned(),
+            kind,
+            shader,
+            inputs: vec![],
+            scale: 1.0,
+            parameters: vec![],
+        }
+    }
+    pub fn input(mut self, input: EffectInput) -> Effect {
+        self.inputs.push(input);
+        self
+    }
+    pub fn scale(mut self, scale: f32) -> Effect {
+        self.scale = scale;
+        self
+    }
+    pub fn parameters<T: bytemuck::Pod>(mut self, parameters: &T) -> Effect {
+        self.parameters = bytemuck::bytes_of(parameters).to_vec();
+        self
+    }
+}
+
+struct Stage {
+    effect: Effect,
+    pipeline: Pipeline,
+    target: RenderTarget,
+    extent: vk::Extent2D,
+    /// Null for compute effects.
+    framebuffer: vk::Framebuffer,
+    descriptor_set: vk::DescriptorSet,
+}
+
+/// Ordered list of full-screen effects applied to the HDR scene colour before
+/// bloom and tonemapping. Owns the intermediate targets and resizes them with
+/// the swapchain.
+pub struct PostProcessChain {
+    stages: Vec<Stage>,
+    renderpass: vk::RenderPass,
+    sampler: vk::Sampler,
+    descriptor_pool: vk::DescriptorPool,
+}
+
+impl PostProcessChain {
+    pub fn init(logical_device: &ash::Device) -> Result<PostProcessChain> {
+        Ok(PostProcessChain {
+            stages: vec![],
+            renderpass: init_offscreen_renderpass(logical_device, HDR_FORMAT, false)?,
+            sampler: create_sampler(logical_device)?,
+            descriptor_pool: vk::DescriptorPool::null(),
+        })
+    }
+
+    /// Appends an effect. Its inputs can only refer to effects already in the
+    /// chain. The device must be idle.
+    // `usize::is_multiple_of` is too recent for the toolchains this builds on.
+    #[allow(unknown_lints, clippy::manual_is_multiple_of)]
+    pub fn push(
+        &mut self,
+        logical_device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
+        allocator: &vk_mem::Allocator,
+        swapchain: &SwapchainDongXi,
+        effect: Effect,
+    ) -> Result<()> {
+        if self.position(&effect.name).is_some() {
+            bail!("Effect {} is already in the chain", effect.name);
+        }
+        for input in &effect.inputs {
+            if let EffectInput::Output(name) = input {
+                if self.position(name).is_none() {
+                    bail!("Effect {} reads unknown output {}", effect.name, name);
+                }
+            }
+        }
+        if effect.parameters.len() % 4 != 0 || effect.parameters.len() > MAX_PARAMETERS_SIZE {
+            bail!(
+                "Parameters of effect {} must be a multiple of 4 and at most {} bytes",
+                effect.name,
+                MAX_PARAMETERS_SIZE
+            );
+        }
+        if effect.scale <= 0.0 {
+            bail!("Effect {} has a non-positive scale", effect.name);
+        }
+        let inputs = effect.inputs.len() as u32;
+        let parameters_size = effect.parameters.len() as u32;
+        let pipeline = match effect.kind {
+            EffectKind::Fragment => Pipeline::init_fullscreen(
+                logical_device,
+                pipeline_cache,
+                &self.renderpass,
+                &effect.shader,
+                inputs,
+                parameters_size,
+                false,
+            )?,
+            EffectKind::Compute => {
+                let mut bindings =
+                    vec![vk::DescriptorType::COMBINED_IMAGE_SAMPLER; inputs as usize];
+                bindings.push(vk::DescriptorType::STORAGE_IMAGE);
+                Pipeline::init_compute(
+                    logical_device,
+                    pipeline_cache,
+                    &effect.shader,
+                    &bindings,
+                    parameters_size,
+                )?
+            }
+        };
+        let (target, extent, framebuffer) =
+            self.create_target(logical_device, allocator, swapchain, &effect)?;
+        self.stages.push(Stage {
+            effect,
+            pipeline,
+            target,
+            extent,
+            framebuffer,
+            descriptor_set: vk::DescriptorSet::null(),
+        });
+        self.update_descriptor_sets(logical_device, swapchain)
+    }
+
+    /// Removes an effect that no later effect reads from. The device must be idle.
+    pub fn remove(
+        &mut self,
+        logical_device: &ash::Device,
+        allocator: &vk_mem::Allocator,
+        swapchain: &SwapchainDongXi,
+        name: &str,
+    ) -> Result<()> {
+        let index = self
+            .position(name)
+            .with_context(|| format!("No effect named {}", name))?;
+        let output = EffectInput::Output(name.to_owned());
+        for (i, stage) in self.stages.iter().enumerate().skip(index + 1) {
+            if stage.effect.inputs.contains(&output)
+                || (i == index + 1 && stage.effect.inputs.contains(&EffectInput::Previous))
+            {
+                bail!("Effect {} still reads from {}", stage.effect.name, name);
+            }
+        }
+        let stage = self.stages.remove(index);
+        PostProcessChain::destroy_stage(logical_device, allocator, &stage);
+        self.update_descriptor_sets(logical_device, swapchain)
+    }
+
+    /// Replaces the push constants of an effect. The size cannot change.
+    pub fn set_parameters<T: bytemuck::Pod>(&mut self, name: &str, parameters: &T) -> Result<()> {
+        let stage = self
+            .stages
+            .iter_mut()
+            .find(|stage| stage.effect.name == name)
+            .with_context(|| format!("No effect named {}", name))?;
+        let bytes = bytemuck::bytes_of(parameters);
+        if bytes.len() != stage.effect.parameters.len() {
+            bail!("Parameters of effect {} changed size", name);
+        }
+        stage.effect.parameters = bytes.to_vec();
+        Ok(())
+    }
+
+    /// Resizes the intermediate targets for a new swapchain. The device must be idle.
+    pub fn recreate(
+        &mut self,
+        logical_device: &ash::Device,
+        allocator: &vk_mem::Allocator,
+        swapchain: &SwapchainDongXi,
+    ) -> Result<()> {
+        for i in 0..self.stages.len() {
+            let stage = &self.stages[i];
+            unsafe { logical_device.destroy_framebuffer(stage.framebuffer, None) };
+            stage.target.cleanup(logical_device, allocator);
+            let (target, extent, framebuffer) =
+                self.create_target(logical_device, allocator, swapchain, &stage.effect)?;
+            let stage = &mut self.stages[i];
+            stage.target = target;
+            stage.extent = extent;
+            stage.framebuffer = framebuffer;
+        }
+        self.update_descriptor_sets(logical_device, swapchain)
+    }
+
+    /// Image the rest of the frame should read as scene colour.
+    pub fn output(&self, swapchain: &SwapchainDongXi) -> vk::ImageView {
+        self.stages
+            .last()
+            .map_or(swapchain.hdr.imageview, |stage| stage.target.imageview)
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.stages
+            .iter()
+            .position(|stage| stage.effect.name == name)
+    }
+
+    fn create_target(
+        &self,
+        logical_device: &ash::Device,
+        allocator: &vk_mem::Allocator,
+        swapchain: &SwapchainDongXi,
+        effect: &Effect,
+    ) -> Result<(RenderTarget, vk::Extent2D, vk::Framebuffer)> {
+        let extent = vk::Extent2D {
+            width: ((swapchain.extent.width as f32 * effect.scale) as u32).max(1),
+            height: ((swapchain.extent.height as f32 * effect.scale) as u32).max(1),
+        };
+        let usage = match effect.kind {
+            EffectKind::Fragment => vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            EffectKind::Compute => vk::ImageUsageFlags::STORAGE,
+        };
+        let target = RenderTarget::new(
+            logical_device,
+            allocator,
+            extent,
+            HDR_FORMAT,
+            usage | vk::ImageUsageFlags::SAMPLED,
+        )?;
+        let framebuffer = match effect.kind {
+            EffectKind::Fragment => {
+                let iview = [target.imageview];
+                let framebuffer_info = vk::FramebufferCreateInfo::builder()
+                    .render_pass(self.renderpass)
+                    .attachments(&iview)
+                    .width(extent.width)
+                    .height(extent.height)
+                    .layers(1);
+                unsafe { logical_device.create_framebuffer(&framebuffer_info, None) }?
+            }
+            EffectKind::Compute => vk::Framebuffer::null(),
+        };
+        Ok((target, extent, framebuffer))
+    }
+
+    fn update_descriptor_sets(
+        &mut self,
+        logical_device: &ash::Device,
+        swapchain: &SwapchainDongXi,
+    ) -> Result<()> {
+        unsafe { logical_device.destroy_descriptor_pool(self.descriptor_pool, None) };
+        self.descriptor_pool = vk::DescriptorPool::null();
+        if self.stages.is_empty() {
+            return Ok(());
+        }
+        let sampled: u32 = self
+            .stages
+            .iter()
+            .map(|stage| stage.effect.inputs.len() as u32)
+            .sum();
+        let storage = self
+            .stages
+            .iter()
+            .filter(|stage| stage.effect.kind == EffectKind::Compute)
+            .count() as u32;
+        let mut pool_sizes = vec![];
+        if sampled > 0 {
+            pool_sizes.push(vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: sampled,
+            });
+        }
+        if storage > 0 {
+            pool_sizes.push(vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_IMAGE,
+                descriptor_count: storage,
+            });
+        }
+        let descriptor_pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(self.stages.len() as u32)
+            .pool_sizes(&pool_sizes);
+        self.descriptor_pool =
+            unsafe { logical_device.create_descriptor_pool(&descriptor_pool_info, None) }?;
+
+        for i in 0..self.stages.len() {
+            let desc_layouts = [self.stages[i].pipeline.descriptor_set_layouts[0]];
+            let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(self.descriptor_pool)
+                .set_layouts(&desc_layouts);
+            let descriptor_set =
+                unsafe { logical_device.allocate_descriptor_sets(&descriptor_set_allocate_info) }?
+                    [0];
+            let stage = &self.stages[i];
+            let image_infos: Vec<[vk::DescriptorImageInfo; 1]> = stage
+                .effect
+                .inputs
+                .iter()
+                .map(|input| {
+                    let source = match input {
+                        EffectInput::Scene => None,
+                        EffectInput::Previous => i.checked_sub(1),
+                        EffectInput::Output(name) => self.position(name),
+                    };
+                    [vk::DescriptorImageInfo {
+                        sampler: self.sampler,
+                        image_view: source.map_or(swapchain.hdr.imageview, |source| {
+                            self.stages[source].target.imageview
+                        }),
+                        image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    }]
+                })
+                .collect();
+            let storage_infos = [vk::DescriptorImageInfo {
+                sampler: vk::Sampler::null(),
+                image_view: stage.target.imageview,
+                image_layout: vk::ImageLayout::GENERAL,
+            }];
+            let mut desc_sets_write: Vec<vk::WriteDescriptorSet> = image_infos
+                .iter()
+                .enumerate()
+                .map(|(binding, info)| {
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(descriptor_set)
+                        .dst_binding(binding as u32)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .image_info(info)
+                        .build()
+                })
+                .collect();
+            if stage.effect.kind == EffectKind::Compute {
+                desc_sets_write.push(
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(descriptor_set)
+                        .dst_binding(image_infos.len() as u32)
+                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                        .image_info(&storage_infos)
+                        .build(),
+                );
+            }
+            unsafe { logical_device.update_descriptor_sets(&desc_sets_write, &[]) };
+            self.stages[i].descriptor_set = descriptor_set;
+        }
+        Ok(())
+    }
+
+    /// Adds every effect in order to `graph`, reading the HDR scene colour
+    /// `scene`, and returns the image the rest of the frame should read as
+    /// scene colour.
+    pub fn add_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        scene: ImageHandle,
+    ) -> ImageHandle {
+        // Every target is written before it is read within a frame.
+        let outputs: Vec<ImageHandle> = self
+            .stages
+            .iter()
+            .map(|stage| {
+                graph.import_discarded(
+                    stage.target.image,
+                    vk::ImageAspectFlags::COLOR,
+                    Access::Sampled,
+                    Some(Access::Sampled),
+                )
+            })
+            .collect();
+        for (i, stage) in self.stages.iter().enumerate() {
+            let (read, write) = match stage.effect.kind {
+                EffectKind::Fragment => (Access::FragmentSampled, Access::ColourAttachmentWrite),
+                EffectKind::Compute => (Access::ComputeSampled, Access::ComputeStorageWrite),
+            };
+            let mut pass = graph.add_pass(&stage.effect.name);
+            for input in &stage.effect.inputs {
+                let source = match input {
+                    EffectInput::Scene => None,
+                    EffectInput::Previous => i.checked_sub(1),
+                    EffectInput::Output(name) => self.position(name),
+                };
+                pass = pass.read(source.map_or(scene, |source| outputs[source]), read);
+            }
+            pass.write(outputs[i], write)
+                .record(move |device, commandbuffer, _| match stage.effect.kind {
+                    EffectKind::Fragment => self.draw(device, commandbuffer, stage),
+                    EffectKind::Compute => PostProcessChain::dispatch(device, commandbuffer, stage),
+                });
+        }
+        outputs.last().copied().unwrap_or(scene)
+    }
+
+    fn draw(&self, logical_device: &ash::Device, commandbuffer: vk::CommandBuffer, stage: &Stage) {
+        let renderpass_begininfo = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.renderpass)
+            .framebuffer(stage.framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: stage.extent,
+            });
+        unsafe {
+            logical_device.cmd_begin_render_pass(
+                commandbuffer,
+                &renderpass_begininfo,
+                vk::SubpassContents::INLINE,
+            );
+        }
+        set_viewport(logical_device, commandbuffer, stage.extent);
+        unsafe {
+            PostProcessChain::bind(
+                logical_device,
+                commandbuffer,
+                stage,
+                vk::PipelineBindPoint::GRAPHICS,
+                vk::ShaderStageFlags::FRAGMENT,
+            );
+            logical_device.cmd_draw(commandbuffer, 3, 1, 0, 0);
+            logical_device.cmd_end_render_pass(commandbuffer);
+        }
+    }
+
+    fn dispatch(logical_device: &ash::Device, commandbuffer: vk::CommandBuffer, stage: &Stage) {
+        unsafe {
+            PostProcessChain::bind(
+                logical_device,
+                commandbuffer,
+                stage,
+                vk::PipelineBindPoint::COMPUTE,
+                vk::ShaderStageFlags::COMPUTE,
+            );
+            logical_device.cmd_dispatch(
+                commandbuffer,
+                stage.extent.width.div_ceil(COMPUTE_GROUP_SIZE),
+                stage.extent.height.div_ceil(COMPUTE_GROUP_SIZE),
+                1,
+            );
+        }
+    }
+
+    unsafe fn bind(
+        logical_device: &ash::Device,
+        commandbuffer: vk::CommandBuffer,
+        stage: &Stage,
+        bind_point: vk::PipelineBindPoint,
+        shader_stage: vk::ShaderStageFlags,
+    ) {
+        logical_device.cmd_bind_pipeline(commandbuffer, bind_point, stage.pipeline.pipeline);
+        logical_device.cmd_bind_descriptor_sets(
+            commandbuffer,
+            bind_point,
+            stage.pipeline.layout,
+            0,
+            &[stage.descriptor_set],
+            &[],
+        );
+        if !stage.effect.parameters.is_empty() {
+            logical_device.cmd_push_constants(
+                commandbuffer,
+                stage.pipeline.layout,
+                shader_stage,
+                0,
+                &stage.effect.parameters,
+            );
+        }
+    }
+
+    fn destroy_stage(logical_device: &ash::Device, allocator: &vk_mem::Allocator, stage: &Stage) {
+        unsafe { logical_device.destroy_framebuffer(stage.framebuffer, None) };
+        stage.target.cleanup(logical_device, allocator);
+        stage.pipeline.cleanup(logical_device);
+    }
+
+    pub fn cleanup(&mut self, logical_device: &ash::Device, allocator: &vk_mem::Allocator) {
+        for stage in self.stages.drain(..) {
+            PostProcessChain::destroy_stage(logical_device, allocator, &stage);
+        }
+        unsafe {
+            logical_device.destroy_descriptor_pool(self.descriptor_pool, None);
+            logical_device.destroy_sampler(self.sampler, None);
+            logical_device.destroy_render_pass(self.renderpass, None);
+        }
+    }
+}
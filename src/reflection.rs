@@ -0,0 +1,707 @@
+use ash::{version::DeviceV1_0, vk};
+use eyre::*;
+use std::collections::HashMap;
+
+const MAGIC_NUMBER: u32 = 0x0723_0203;
+
+// Opcodes, decorations and storage classes from the SPIR-V specification.
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_BOOL: u32 = 20;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+const DECORATION_BLOCK: u32 = 2;
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BUILT_IN: u32 = 11;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_INPUT: u32 = 1;
+const STORAGE_UNIFORM: u32 = 2;
+const STORAGE_PUSH_CONSTANT: u32 = 9;
+const STORAGE_STORAGE_BUFFER: u32 = 12;
+
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+
+#[derive(Clone, Debug)]
+enum Type {
+    Bool,
+    Int { width: u32, signed: bool },
+    Float { width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Image { dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array { element: u32, length: u32 },
+    RuntimeArray,
+    Struct { members: Vec<u32> },
+    Pointer { pointee: u32 },
+}
+
+#[derive(Default)]
+struct Decorations {
+    block: bool,
+    buffer_block: bool,
+    built_in: bool,
+    location: Option<u32>,
+    set: Option<u32>,
+    binding: Option<u32>,
+    array_stride: Option<u32>,
+}
+
+#[derive(Default)]
+struct MemberDecorations {
+    offset: u32,
+    matrix_stride: Option<u32>,
+    built_in: bool,
+}
+
+/// Descriptor a shader declares. Bindings used by several stages are merged
+/// into one with the union of their stages.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ReflectedBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    pub count: u32,
+    pub stages: vk::ShaderStageFlags,
+}
+
+/// Vertex shader input. Matrices and arrays take one location per column or
+/// element.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ReflectedInput {
+    pub location: u32,
+    pub format: vk::Format,
+}
+
+/// What one compiled shader module expects from the pipeline.
+#[derive(Clone, Debug)]
+pub struct ShaderReflection {
+    pub stage: vk::ShaderStageFlags,
+    pub bindings: Vec<ReflectedBinding>,
+    /// Size in bytes of the push constant block, if there is one.
+    pub push_constant_size: Option<u32>,
+    pub inputs: Vec<ReflectedInput>,
+}
+
+impl ShaderReflection {
+    pub fn reflect(spirv: &[u32]) -> Result<ShaderReflection> {
+        if spirv.len() < 5 || spirv[0] != MAGIC_NUMBER {
+            bail!("Not a SPIR-V module");
+        }
+        let mut stage = None;
+        let mut types: HashMap<u32, Type> = HashMap::new();
+        let mut constants: HashMap<u32, u32> = HashMap::new();
+        let mut decorations: HashMap<u32, Decorations> = HashMap::new();
+        let mut member_decorations: HashMap<(u32, u32), MemberDecorations> = HashMap::new();
+        let mut variables: Vec<(u32, u32, u32)> = vec![];
+
+        let mut position = 5;
+        while position < spirv.len() {
+            let word_count = (spirv[position] >> 16) as usize;
+            let opcode = spirv[position] & 0xffff;
+            if word_count == 0 || position + word_count > spirv.len() {
+                bail!("Truncated SPIR-V instruction at word {}", position);
+            }
+            let operands = &spirv[position + 1..position + word_count];
+            position += word_count;
+            match opcode {
+                OP_ENTRY_POINT => {
+                    stage = Some(match operands[0] {
+                        0 => vk::ShaderStageFlags::VERTEX,
+                        1 => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+                        2 => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+                        3 => vk::ShaderStageFlags::GEOMETRY,
+                        4 => vk::ShaderStageFlags::FRAGMENT,
+                        5 => vk::ShaderStageFlags::COMPUTE,
+                        model => bail!("Unsupported execution model {}", model),
+                    });
+                }
+                OP_TYPE_BOOL => {
+                    types.insert(operands[0], Type::Bool);
+                }
+                OP_TYPE_INT => {
+                    types.insert(
+                        operands[0],
+                        Type::Int {
+                            width: operands[1],
+                            signed: operands[2] == 1,
+                        },
+                    );
+                }
+                OP_TYPE_FLOAT => {
+                    types.insert(operands[0], Type::Float { width: operands[1] });
+                }
+                OP_TYPE_VECTOR => {
+                    types.insert(
+                        operands[0],
+                        Type::Vector {
+                            component: operands[1],
+                            count: operands[2],
+                        },
+                    );
+                }
+                OP_TYPE_MATRIX => {
+                    types.insert(
+                        operands[0],
+                        Type::Matrix {
+                            column: operands[1],
+                            count: operands[2],
+                        },
+                    );
+                }
+                OP_TYPE_IMAGE => {
+                    types.insert(
+                        operands[0],
+                        Type::Image {
+                            dim: operands[2],
+                            sampled: operands[6],
+                        },
+                    );
+                }
+                OP_TYPE_SAMPLER => {
+                    types.insert(operands[0], Type::Sampler);
+                }
+                OP_TYPE_SAMPLED_IMAGE => {
+                    types.insert(operands[0], Type::SampledImage);
+                }
+                OP_TYPE_ARRAY => {
+                    let length = *constants
+                        .get(&operands[2])
+                        .context("Array length is not a constant")?;
+                    types.insert(
+                        operands[0],
+                        Type::Array {
+                            element: operands[1],
+                            length,
+                        },
+                    );
+                }
+                OP_TYPE_RUNTIME_ARRAY => {
+                    types.insert(operands[0], Type::RuntimeArray);
+                }
+                OP_TYPE_STRUCT => {
+                    types.insert(
+                        operands[0],
+                        Type::Struct {
+                            members: operands[1..].to_vec(),
+                        },
+                    );
+                }
+                OP_TYPE_POINTER => {
+                    types.insert(
+                        operands[0],
+                        Type::Pointer {
+                            pointee: operands[2],
+                        },
+                    );
+                }
+                OP_CONSTANT => {
+                    constants.insert(operands[1], operands[2]);
+                }
+                OP_VARIABLE => {
+                    variables.push((operands[1], operands[0], operands[2]));
+                }
+                OP_DECORATE => {
+                    let target = decorations.entry(operands[0]).or_default();
+                    match operands[1] {
+                        DECORATION_BLOCK => target.block = true,
+                        DECORATION_BUFFER_BLOCK => target.buffer_block = true,
+                        DECORATION_BUILT_IN => target.built_in = true,
+                        DECORATION_LOCATION => target.location = Some(operands[2]),
+                        DECORATION_DESCRIPTOR_SET => target.set = Some(operands[2]),
+                        DECORATION_BINDING => target.binding = Some(operands[2]),
+                        DECORATION_ARRAY_STRIDE => target.array_stride = Some(operands[2]),
+                        _ => {}
+                    }
+                }
+                OP_MEMBER_DECORATE => {
+                    let target = member_decorations
+                        .entry((operands[0], operands[1]))
+                        .or_default();
+                    match operands[2] {
+                        DECORATION_OFFSET => target.offset = operands[3],
+                        DECORATION_MATRIX_STRIDE => target.matrix_stride = Some(operands[3]),
+                        DECORATION_BUILT_IN => target.built_in = true,
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let module = Module {
+            types,
+            decorations,
+            member_decorations,
+        };
+        let stage = stage.context("SPIR-V module has no entry point")?;
+        let mut reflection = ShaderReflection {
+            stage,
+            bindings: vec![],
+            push_constant_size: None,
+            inputs: vec![],
+        };
+        for (id, pointer, storage_class) in variables {
+            let pointee = match module.types.get(&pointer) {
+                Some(Type::Pointer { pointee }) => *pointee,
+                _ => bail!("Variable {} is not a pointer", id),
+            };
+            let decoration = module.decorations.get(&id);
+            match storage_class {
+                STORAGE_UNIFORM_CONSTANT | STORAGE_UNIFORM | STORAGE_STORAGE_BUFFER => {
+                    let decoration = decoration.context("Resource without decorations")?;
+                    let set = decoration.set.unwrap_or(0);
+                    let binding = decoration
+                        .binding
+                        .with_context(|| format!("Resource in set {} without a binding", set))?;
+                    let (descriptor_type, count) =
+                        module.descriptor(pointee, storage_class).with_context(|| {
+                            format!("Unsupported resource at set {} binding {}", set, binding)
+                        })?;
+                    reflection.bindings.push(ReflectedBinding {
+                        set,
+                        binding,
+                        descriptor_type,
+                        count,
+                        stages: stage,
+                    });
+                }
+                STORAGE_PUSH_CONSTANT => {
+                    reflection.push_constant_size = Some(module.size(pointee, None)?);
+                }
+                STORAGE_INPUT if stage == vk::ShaderStageFlags::VERTEX => {
+                    if decoration.is_some_and(|d| d.built_in) || module.is_built_in(pointee) {
+                        continue;
+                    }
+                    let location = decoration
+                        .and_then(|d| d.location)
+                        .context("Vertex input without a location")?;
+                    for (i, format) in module.input_formats(pointee)?.into_iter().enumerate() {
+                        reflection.inputs.push(ReflectedInput {
+                            location: location + i as u32,
+                            format,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        reflection.bindings.sort_by_key(|b| (b.set, b.binding));
+        reflection.inputs.sort_by_key(|i| i.location);
+        Ok(reflection)
+    }
+}
+
+struct Module {
+    types: HashMap<u32, Type>,
+    decorations: HashMap<u32, Decorations>,
+    member_decorations: HashMap<(u32, u32), MemberDecorations>,
+}
+
+impl Module {
+    fn get(&self, id: u32) -> Result<&Type> {
+        self.types
+            .get(&id)
+            .with_context(|| format!("Unknown SPIR-V type {}", id))
+    }
+
+    fn descriptor(&self, id: u32, storage_class: u32) -> Result<(vk::DescriptorType, u32)> {
+        Ok(match self.get(id)? {
+            Type::Array { element, length } => {
+                let (descriptor_type, count) = self.descriptor(*element, storage_class)?;
+                (descriptor_type, count * length)
+            }
+            Type::RuntimeArray => bail!("Runtime sized descriptor arrays are not supported"),
+            Type::SampledImage => (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1),
+            Type::Sampler => (vk::DescriptorType::SAMPLER, 1),
+            Type::Image { dim, sampled } => match (*dim, *sampled) {
+                (DIM_SUBPASS_DATA, _) => (vk::DescriptorType::INPUT_ATTACHMENT, 1),
+                (DIM_BUFFER, 2) => (vk::DescriptorType::STORAGE_TEXEL_BUFFER, 1),
+                (DIM_BUFFER, _) => (vk::DescriptorType::UNIFORM_TEXEL_BUFFER, 1),
+                (_, 2) => (vk::DescriptorType::STORAGE_IMAGE, 1),
+                _ => (vk::DescriptorType::SAMPLED_IMAGE, 1),
+            },
+            Type::Struct { .. } => {
+                let decoration = self.decorations.get(&id);
+                if storage_class == STORAGE_STORAGE_BUFFER
+                    || decoration.is_some_and(|d| d.buffer_block)
+                {
+                    (vk::DescriptorType::STORAGE_BUFFER, 1)
+                } else if decoration.is_some_and(|d| d.block) {
+                    (vk::DescriptorType::UNIFORM_BUFFER, 1)
+                } else {
+                    bail!("Struct resource is neither a Block nor a BufferBlock")
+                }
+            }
+            _ => bail!("Unsupported resource type"),
+        })
+    }
+
+    /// Size in bytes as laid out in a block. Matrices need the stride of the
+    /// member holding them.
+    fn size(&self, id: u32, matrix_stride: Option<u32>) -> Result<u32> {
+        Ok(match self.get(id)? {
+            Type::Bool => 4,
+            Type::Int { width, .. } | Type::Float { width } => width / 8,
+            Type::Vector { component, count } => count * self.size(*component, None)?,
+            Type::Matrix { column, count } => match matrix_stride {
+                Some(stride) => count * stride,
+                None => count * self.size(*column, None)?,
+            },
+            Type::Array { element, length } => {
+                let stride = match self.decorations.get(&id).and_then(|d| d.array_stride) {
+                    Some(stride) => stride,
+                    None => self.size(*element, matrix_stride)?,
+                };
+                length * stride
+            }
+            Type::RuntimeArray => 0,
+            Type::Struct { members } => {
+                let mut size = 0;
+                for (i, member) in members.iter().enumerate() {
+                    let decoration = self.member_decorations.get(&(id, i as u32));
+                    let offset = decoration.map_or(0, |d| d.offset);
+                    let stride = decoration.and_then(|d| d.matrix_stride);
+                    size = size.max(offset + self.size(*member, stride)?);
+                }
+                size
+            }
+            _ => bail!("Opaque type {} has no size", id),
+        })
+    }
+
+    fn is_built_in(&self, id: u32) -> bool {
+        match self.types.get(&id) {
+            Some(Type::Struct { members }) => (0..members.len() as u32).any(|i| {
+                self.member_decorations
+                    .get(&(id, i))
+                    .is_some_and(|d| d.built_in)
+            }),
+            _ => false,
+        }
+    }
+
+    /// One format per location the input occupies.
+    fn input_formats(&self, id: u32) -> Result<Vec<vk::Format>> {
+        Ok(match self.get(id)? {
+            Type::Matrix { column, count } => {
+                vec![self.input_formats(*column)?[0]; *count as usize]
+            }
+            Type::Array { element, length } => {
+                let element = self.input_formats(*element)?;
+                let mut formats = vec![];
+                for _ in 0..*length {
+                    formats.extend_from_slice(&element);
+                }
+                formats
+            }
+            Type::Vector { component, count } => vec![self.scalar_format(*component, *count)?],
+            _ => vec![self.scalar_format(id, 1)?],
+        })
+    }
+
+    fn scalar_format(&self, id: u32, count: u32) -> Result<vk::Format> {
+        use vk::Format as F;
+        Ok(match (self.get(id)?, count) {
+            (Type::Float { width: 32 }, 1) => F::R32_SFLOAT,
+            (Type::Float { width: 32 }, 2) => F::R32G32_SFLOAT,
+            (Type::Float { width: 32 }, 3) => F::R32G32B32_SFLOAT,
+            (Type::Float { width: 32 }, 4) => F::R32G32B32A32_SFLOAT,
+            (
+                Type::Int {
+                    width: 32,
+                    signed: true,
+                },
+                1,
+            ) => F::R32_SINT,
+            (
+                Type::Int {
+                    width: 32,
+                    signed: true,
+                },
+                2,
+            ) => F::R32G32_SINT,
+            (
+                Type::Int {
+                    width: 32,
+                    signed: true,
+                },
+                3,
+            ) => F::R32G32B32_SINT,
+            (
+                Type::Int {
+                    width: 32,
+                    signed: true,
+                },
+                4,
+            ) => F::R32G32B32A32_SINT,
+            (
+                Type::Int {
+                    width: 32,
+                    signed: false,
+                },
+                1,
+            ) => F::R32_UINT,
+            (
+                Type::Int {
+                    width: 32,
+                    signed: false,
+                },
+                2,
+            ) => F::R32G32_UINT,
+            (
+                Type::Int {
+                    width: 32,
+                    signed: false,
+                },
+                3,
+            ) => F::R32G32B32_UINT,
+            (
+                Type::Int {
+                    width: 32,
+                    signed: false,
+                },
+                4,
+            ) => F::R32G32B32A32_UINT,
+            _ => bail!("Unsupported vertex input type"),
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum NumericType {
+    Float,
+    Sint,
+    Uint,
+}
+
+fn numeric_type(format: vk::Format) -> Option<NumericType> {
+    use vk::Format as F;
+    match format {
+        F::R32_SFLOAT
+        | F::R32G32_SFLOAT
+        | F::R32G32B32_SFLOAT
+        | F::R32G32B32A32_SFLOAT
+        | F::R16_SFLOAT
+        | F::R16G16_SFLOAT
+        | F::R16G16B16A16_SFLOAT
+        | F::R8G8B8A8_UNORM
+        | F::R8G8B8A8_SNORM
+        | F::R16G16_UNORM
+        | F::R16G16_SNORM
+        | F::R16G16B16A16_UNORM
+        | F::R16G16B16A16_SNORM
+        | F::A2B10G10R10_UNORM_PACK32 => Some(NumericType::Float),
+        F::R32_SINT | F::R32G32_SINT | F::R32G32B32_SINT | F::R32G32B32A32_SINT => {
+            Some(NumericType::Sint)
+        }
+        F::R32_UINT | F::R32G32_UINT | F::R32G32B32_UINT | F::R32G32B32A32_UINT => {
+            Some(NumericType::Uint)
+        }
+        _ => None,
+    }
+}
+
+/// Interface of all stages of a pipeline, merged.
+#[derive(Clone, Debug)]
+pub struct PipelineInterface {
+    pub bindings: Vec<ReflectedBinding>,
+    /// Stages declaring push constants and the largest block among them.
+    pub push_constants: Option<(vk::ShaderStageFlags, u32)>,
+    pub vertex_inputs: Vec<ReflectedInput>,
+}
+
+impl PipelineInterface {
+    pub fn reflect(stages: &[&[u32]]) -> Result<PipelineInterface> {
+        let mut interface = PipelineInterface {
+            bindings: vec![],
+            push_constants: None,
+            vertex_inputs: vec![],
+        };
+        for spirv in stages {
+            let shader = ShaderReflection::reflect(spirv)?;
+            for binding in shader.bindings {
+                match interface
+                    .bindings
+                    .iter_mut()
+                    .find(|b| b.set == binding.set && b.binding == binding.binding)
+                {
+                    Some(known) => {
+                        if known.descriptor_type != binding.descriptor_type
+                            || known.count != binding.count
+                        {
+                            bail!(
+                                "Set {} binding {} is {} x {:?} in {:?} but {} x {:?} in {:?}",
+                                binding.set,
+                                binding.binding,
+                                known.count,
+                                known.descriptor_type,
+                                known.stages,
+                                binding.count,
+                                binding.descriptor_type,
+                                binding.stages
+                            );
+                        }
+                        known.stages |= binding.stages;
+                    }
+                    None => interface.bindings.push(binding),
+                }
+            }
+            if let Some(size) = shader.push_constant_size {
+                interface.push_constants = Some(match interface.push_constants {
+                    Some((stages, known)) => (stages | shader.stage, known.max(size)),
+                    None => (shader.stage, size),
+                });
+            }
+            if shader.stage == vk::ShaderStageFlags::VERTEX {
+                interface.vertex_inputs = shader.inputs;
+            }
+        }
+        interface.bindings.sort_by_key(|b| (b.set, b.binding));
+        Ok(interface)
+    }
+
+    /// Checks that set `set` holds exactly one descriptor of each of `types`,
+    /// at bindings `0..types.len()`.
+    pub fn check_bindings(&self, set: u32, types: &[vk::DescriptorType]) -> Result<()> {
+        let declared: Vec<&ReflectedBinding> =
+            self.bindings.iter().filter(|b| b.set == set).collect();
+        let matches = declared.len() == types.len()
+            && declared.iter().zip(types).enumerate().all(|(i, (b, ty))| {
+                b.binding == i as u32 && b.descriptor_type == *ty && b.count == 1
+            });
+        if !matches {
+            let declared: Vec<(u32, vk::DescriptorType, u32)> = declared
+                .iter()
+                .map(|b| (b.binding, b.descriptor_type, b.count))
+                .collect();
+            bail!(
+                "Pipeline binds {:?} to set {} but the shaders declare (binding, type, count) {:?}",
+                types,
+                set,
+                declared
+            );
+        }
+        Ok(())
+    }
+
+    /// Checks that every vertex input is fed by an attribute of the same
+    /// numeric type.
+    pub fn check_vertex_input(
+        &self,
+        attributes: &[vk::VertexInputAttributeDescription],
+    ) -> Result<()> {
+        for input in &self.vertex_inputs {
+            let attribute = attributes
+                .iter()
+                .find(|a| a.location == input.location)
+                .with_context(|| {
+                    format!(
+                        "Vertex shader reads location {} as {:?} but the pipeline has no attribute there",
+                        input.location, input.format
+                    )
+                })?;
+            if let (Some(expected), Some(provided)) =
+                (numeric_type(input.format), numeric_type(attribute.format))
+            {
+                if expected != provided {
+                    bail!(
+                        "Vertex shader reads location {} as {:?} but the attribute is {:?}",
+                        input.location,
+                        input.format,
+                        attribute.format
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates one layout per set up to the highest declared one, and at least
+    /// `min_sets`, plus the pipeline layout. `push_constant_size` is the size of
+    /// what the renderer pushes and has to cover the shaders' block.
+    pub fn create_layouts(
+        &self,
+        logical_device: &ash::Device,
+        min_sets: u32,
+        push_constant_size: u32,
+    ) -> Result<(Vec<vk::DescriptorSetLayout>, vk::PipelineLayout)> {
+        let push_constant_ranges = match self.push_constants {
+            Some((stages, size)) => {
+                if push_constant_size < size {
+                    bail!(
+                        "Shaders read {} bytes of push constants but the pipeline pushes {}",
+                        size,
+                        push_constant_size
+                    );
+                }
+                vec![vk::PushConstantRange {
+                    stage_flags: stages,
+                    offset: 0,
+                    size: push_constant_size,
+                }]
+            }
+            None if push_constant_size > 0 => bail!(
+                "Pipeline pushes {} bytes of constants but no shader declares a push constant block",
+                push_constant_size
+            ),
+            None => vec![],
+        };
+        let set_count = self
+            .bindings
+            .iter()
+            .map(|b| b.set + 1)
+            .max()
+            .unwrap_or(0)
+            .max(min_sets);
+        let mut desclayouts = Vec::with_capacity(set_count as usize);
+        for set in 0..set_count {
+            let descriptorset_layout_binding_descs: Vec<vk::DescriptorSetLayoutBinding> = self
+                .bindings
+                .iter()
+                .filter(|b| b.set == set)
+                .map(|b| {
+                    vk::DescriptorSetLayoutBinding::builder()
+                        .binding(b.binding)
+                        .descriptor_type(b.descriptor_type)
+                        .descriptor_count(b.count)
+                        .stage_flags(b.stages)
+                        .build()
+                })
+                .collect();
+            let descriptorset_layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+                .bindings(&descriptorset_layout_binding_descs);
+            desclayouts.push(unsafe {
+                logical_device.create_descriptor_set_layout(&descriptorset_layout_info, None)
+            }?);
+        }
+        let pipelinelayout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&desclayouts)
+            .push_constant_ranges(&push_constant_ranges);
+        let pipelinelayout =
+            unsafe { logical_device.create_pipeline_layout(&pipelinelayout_info, None) }?;
+        Ok((desclayouts, pipelinelayout))
+    }
+}
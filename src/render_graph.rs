@@ -0,0 +1,664 @@
+use crate::render_target::DEPTH_FORMAT;
+use ash::{version::DeviceV1_0, vk};
+use eyre::*;
+
+/// How a pass touches a resource. Each access implies the pipeline stage, the
+/// memory access and, for images, the layout the graph has to provide.
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Access {
+    TransferRead,
+    TransferWrite,
+    ColourAttachmentWrite,
+    DepthAttachmentWrite,
+    FragmentSampled,
+    ComputeSampled,
+    /// Sampled by fragment or compute shaders, for images whose readers vary,
+    /// e.g. as their last use in the previous frame.
+    Sampled,
+    ComputeStorageRead,
+    ComputeStorageWrite,
+    VertexBuffer,
+    /// Storage buffer read by vertex shaders, e.g. particles.
+    VertexStorageRead,
+    IndexBuffer,
+    IndirectBuffer,
+    UniformRead,
+    HostRead,
+    /// Owned by the presentation engine. As a source it waits for everything
+    /// submitted before.
+    Present,
+}
+
+impl Access {
+    fn stage(self) -> vk::PipelineStageFlags {
+        match self {
+            Access::TransferRead | Access::TransferWrite => vk::PipelineStageFlags::TRANSFER,
+            Access::ColourAttachmentWrite => vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            Access::DepthAttachmentWrite => {
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+            }
+            Access::FragmentSampled => vk::PipelineStageFlags::FRAGMENT_SHADER,
+            Access::Sampled => {
+                vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER
+            }
+            Access::ComputeSampled | Access::ComputeStorageRead | Access::ComputeStorageWrite => {
+                vk::PipelineStageFlags::COMPUTE_SHADER
+            }
+            Access::VertexBuffer | Access::IndexBuffer => vk::PipelineStageFlags::VERTEX_INPUT,
+            Access::VertexStorageRead => vk::PipelineStageFlags::VERTEX_SHADER,
+            Access::IndirectBuffer => vk::PipelineStageFlags::DRAW_INDIRECT,
+            Access::UniformRead => {
+                vk::PipelineStageFlags::VERTEX_SHADER
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags::COMPUTE_SHADER
+            }
+            Access::HostRead => vk::PipelineStageFlags::HOST,
+            Access::Present => vk::PipelineStageFlags::ALL_COMMANDS,
+        }
+    }
+
+    fn access(self) -> vk::AccessFlags {
+        match self {
+            Access::TransferRead => vk::AccessFlags::TRANSFER_READ,
+            Access::TransferWrite => vk::AccessFlags::TRANSFER_WRITE,
+            Access::ColourAttachmentWrite => {
+                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+            }
+            Access::DepthAttachmentWrite => {
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+            }
+            Access::FragmentSampled
+            | Access::ComputeSampled
+            | Access::Sampled
+            | Access::ComputeStorageRead
+            | Access::VertexStorageRead => vk::AccessFlags::SHADER_READ,
+            // Storage writes are mostly read-modify-write.
+            Access::ComputeStorageWrite => {
+                vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE
+            }
+            Access::VertexBuffer => vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+            Access::IndexBuffer => vk::AccessFlags::INDEX_READ,
+            Access::IndirectBuffer => vk::AccessFlags::INDIRECT_COMMAND_READ,
+            Access::UniformRead => vk::AccessFlags::UNIFORM_READ,
+            Access::HostRead => vk::AccessFlags::HOST_READ,
+            Access::Present => vk::AccessFlags::MEMORY_READ,
+        }
+    }
+
+    fn layout(self) -> vk::ImageLayout {
+        match self {
+            Access::TransferRead => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            Access::TransferWrite => vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            Access::ColourAttachmentWrite => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            Access::DepthAttachmentWrite => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            Access::FragmentSampled | Access::ComputeSampled | Access::Sampled => {
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+            }
+            Access::Present => vk::ImageLayout::PRESENT_SRC_KHR,
+            _ => vk::ImageLayout::GENERAL,
+        }
+    }
+
+    pub fn writes(self) -> bool {
+        matches!(
+            self,
+            Access::TransferWrite
+                | Access::ColourAttachmentWrite
+                | Access::DepthAttachmentWrite
+                | Access::ComputeStorageWrite
+        )
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ImageHandle(usize);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BufferHandle(usize);
+
+/// Image owned by the graph for the duration of one execution. Transient
+/// images with equal descriptions whose lifetimes do not overlap share one image.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TransientImageDesc {
+    pub extent: vk::Extent2D,
+    pub format: vk::Format,
+    pub usage: vk::ImageUsageFlags,
+}
+
+/// What the graph knows about a resource between passes.
+#[derive(Copy, Clone, Debug)]
+struct ResourceState {
+    layout: vk::ImageLayout,
+    write_stages: vk::PipelineStageFlags,
+    write_access: vk::AccessFlags,
+    /// Stages that read since the last write; a later write has to wait for them.
+    read_stages: vk::PipelineStageFlags,
+    /// Stages the last write has already been made visible to.
+    visible_stages: vk::PipelineStageFlags,
+}
+
+impl ResourceState {
+    fn new(layout: vk::ImageLayout, previous: Option<Access>) -> ResourceState {
+        let mut state = ResourceState {
+            layout,
+            write_stages: vk::PipelineStageFlags::empty(),
+            write_access: vk::AccessFlags::empty(),
+            read_stages: vk::PipelineStageFlags::empty(),
+            visible_stages: vk::PipelineStageFlags::empty(),
+        };
+        match previous {
+            Some(access) if access.writes() => {
+                state.write_stages = access.stage();
+                state.write_access = access.access();
+            }
+            Some(access) => state.read_stages = access.stage(),
+            None => {}
+        }
+        state
+    }
+
+    /// Moves the resource to `access`, returning the source stages and access of
+    /// the barrier required first, if any.
+    fn transition(
+        &mut self,
+        access: Access,
+        layout: vk::ImageLayout,
+    ) -> Option<(vk::PipelineStageFlags, vk::AccessFlags)> {
+        let stage = access.stage();
+        let barrier = if access.writes() || layout != self.layout {
+            Some((self.write_stages | self.read_stages, self.write_access))
+        } else if !self.write_stages.is_empty() && !self.visible_stages.contains(stage) {
+            Some((self.write_stages, self.write_access))
+        } else {
+            None
+        };
+        if access.writes() {
+            self.write_stages = stage;
+            self.write_access = access.access();
+            self.read_stages = vk::PipelineStageFlags::empty();
+            self.visible_stages = vk::PipelineStageFlags::empty();
+        } else if layout != self.layout {
+            // The layout transition itself is a write that later readers in
+            // other stages have to wait for.
+            self.write_stages = stage;
+            self.write_access = vk::AccessFlags::empty();
+            self.read_stages = stage;
+            self.visible_stages = stage;
+        } else {
+            self.read_stages |= stage;
+            self.visible_stages |= stage;
+        }
+        self.layout = layout;
+        barrier
+    }
+}
+
+enum ImageSource {
+    Imported(vk::Image),
+    Transient(TransientImageDesc),
+}
+
+struct ImageResource {
+    source: ImageSource,
+    aspect: vk::ImageAspectFlags,
+    initial: Option<Access>,
+    /// Whether the contents from before the graph can be dropped, so that the
+    /// layout `initial` implies does not have to hold.
+    discard: bool,
+    final_access: Option<Access>,
+    /// Index into the physical images once the graph is compiled.
+    physical: usize,
+}
+
+impl ImageResource {
+    fn initial_state(&self) -> ResourceState {
+        let layout = match self.initial {
+            Some(access) if !self.discard => access.layout(),
+            _ => vk::ImageLayout::UNDEFINED,
+        };
+        ResourceState::new(layout, self.initial)
+    }
+}
+
+struct BufferResource {
+    buffer: vk::Buffer,
+    initial: Option<Access>,
+    final_access: Option<Access>,
+}
+
+#[derive(Copy, Clone)]
+enum Usage {
+    Image(ImageHandle, Access),
+    Buffer(BufferHandle, Access),
+}
+
+type RecordFn<'a> = Box<dyn FnOnce(&ash::Device, vk::CommandBuffer, &GraphResources) + 'a>;
+
+struct Pass<'a> {
+    name: String,
+    usages: Vec<Usage>,
+    record: RecordFn<'a>,
+}
+
+/// Physical handles a pass resolves its declared resources through.
+pub struct GraphResources {
+    images: Vec<vk::Image>,
+    buffers: Vec<vk::Buffer>,
+}
+
+impl GraphResources {
+    pub fn image(&self, handle: ImageHandle) -> vk::Image {
+        self.images[handle.0]
+    }
+    #[allow(dead_code)]
+    pub fn buffer(&self, handle: BufferHandle) -> vk::Buffer {
+        self.buffers[handle.0]
+    }
+}
+
+/// Declares how a pass uses resources, see [`RenderGraph::add_pass`].
+pub struct PassBuilder<'g, 'a> {
+    graph: &'g mut RenderGraph<'a>,
+    name: String,
+    usages: Vec<Usage>,
+}
+
+#[allow(dead_code)]
+impl<'g, 'a> PassBuilder<'g, 'a> {
+    pub fn read(mut self, image: ImageHandle, access: Access) -> Self {
+        debug_assert!(!access.writes(), "{:?} is not a read", access);
+        self.usages.push(Usage::Image(image, access));
+        self
+    }
+    pub fn write(mut self, image: ImageHandle, access: Access) -> Self {
+        debug_assert!(access.writes(), "{:?} is not a write", access);
+        self.usages.push(Usage::Image(image, access));
+        self
+    }
+    pub fn read_buffer(mut self, buffer: BufferHandle, access: Access) -> Self {
+        debug_assert!(!access.writes(), "{:?} is not a read", access);
+        self.usages.push(Usage::Buffer(buffer, access));
+        self
+    }
+    pub fn write_buffer(mut self, buffer: BufferHandle, access: Access) -> Self {
+        debug_assert!(access.writes(), "{:?} is not a write", access);
+        self.usages.push(Usage::Buffer(buffer, access));
+        self
+    }
+    /// Finishes the declaration. `record` runs when the graph is executed, after
+    /// every barrier the declared usages need.
+    pub fn record<F>(self, record: F)
+    where
+        F: FnOnce(&ash::Device, vk::CommandBuffer, &GraphResources) + 'a,
+    {
+        self.graph.passes.push(Pass {
+            name: self.name,
+            usages: self.usages,
+            record: Box::new(record),
+        });
+    }
+}
+
+/// Passes in submission order together with the resources they touch. The graph
+/// inserts the pipeline barriers and layout transitions between them and owns
+/// the transient images.
+///
+/// Passes that use render passes with their own subpass dependencies should only
+/// declare the state the render pass leaves behind.
+pub struct RenderGraph<'a> {
+    images: Vec<ImageResource>,
+    buffers: Vec<BufferResource>,
+    passes: Vec<Pass<'a>>,
+    transients: Vec<(vk::Image, vk_mem::Allocation)>,
+}
+
+#[allow(dead_code)]
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> RenderGraph<'a> {
+        RenderGraph {
+            images: vec![],
+            buffers: vec![],
+            passes: vec![],
+            transients: vec![],
+        }
+    }
+
+    /// Brings an image owned elsewhere into the graph. `initial` is how it was
+    /// last used, `None` if its contents can be discarded. With a `final_access`
+    /// the graph leaves it ready for that use.
+    pub fn import_image(
+        &mut self,
+        image: vk::Image,
+        aspect: vk::ImageAspectFlags,
+        initial: Option<Access>,
+        final_access: Option<Access>,
+    ) -> ImageHandle {
+        self.images.push(ImageResource {
+            source: ImageSource::Imported(image),
+            aspect,
+            initial,
+            discard: false,
+            final_access,
+            physical: 0,
+        });
+        ImageHandle(self.images.len() - 1)
+    }
+
+    /// Like `import_image`, for an image that is cleared or overwritten before
+    /// it is read, so it can be in any layout, e.g. before its first frame.
+    /// Passes still wait for `previous`, its last use, e.g. in the frame before.
+    pub fn import_discarded(
+        &mut self,
+        image: vk::Image,
+        aspect: vk::ImageAspectFlags,
+        previous: Access,
+        final_access: Option<Access>,
+    ) -> ImageHandle {
+        let handle = self.import_image(image, aspect, Some(previous), final_access);
+        self.images[handle.0].discard = true;
+        handle
+    }
+
+    pub fn create_image(&mut self, desc: TransientImageDesc) -> ImageHandle {
+        let aspect = if desc.format == DEPTH_FORMAT {
+            vk::ImageAspectFlags::DEPTH
+        } else {
+            vk::ImageAspectFlags::COLOR
+        };
+        self.images.push(ImageResource {
+            source: ImageSource::Transient(desc),
+            aspect,
+            initial: None,
+            discard: true,
+            final_access: None,
+            physical: 0,
+        });
+        ImageHandle(self.images.len() - 1)
+    }
+
+    /// Buffers are always imported; `initial` and `final_access` work as for
+    /// images.
+    pub fn import_buffer(
+        &mut self,
+        buffer: vk::Buffer,
+        initial: Option<Access>,
+        final_access: Option<Access>,
+    ) -> BufferHandle {
+        self.buffers.push(BufferResource {
+            buffer,
+            initial,
+            final_access,
+        });
+        BufferHandle(self.buffers.len() - 1)
+    }
+
+    pub fn add_pass<'g>(&'g mut self, name: &str) -> PassBuilder<'g, 'a> {
+        PassBuilder {
+            graph: self,
+            name: name.to_owned(),
+            usages: vec![],
+        }
+    }
+
+    /// Assigns every image a physical image, creating transient ones where no
+    /// earlier transient with the same description is free any more.
+    fn allocate(&mut self, allocator: &vk_mem::Allocator) -> Result<Vec<vk::Image>> {
+        let mut lifetimes = vec![None; self.images.len()];
+        for (index, pass) in self.passes.iter().enumerate() {
+            for usage in &pass.usages {
+                if let Usage::Image(handle, _) = usage {
+                    let lifetime: &mut Option<(usize, usize)> = &mut lifetimes[handle.0];
+                    *lifetime = Some(lifetime.map_or((index, index), |(first, _)| (first, index)));
+                }
+            }
+        }
+        let mut physical = vec![];
+        // Description, last pass and physical index of every transient allocation.
+        let mut pool: Vec<(TransientImageDesc, usize, usize)> = vec![];
+        let mut order: Vec<usize> = (0..self.images.len()).collect();
+        order.sort_by_key(|i| lifetimes[*i].map_or(usize::MAX, |(first, _)| first));
+        for i in order {
+            self.images[i].physical = match self.images[i].source {
+                ImageSource::Imported(image) => {
+                    physical.push(image);
+                    physical.len() - 1
+                }
+                ImageSource::Transient(desc) => {
+                    let (first, last) = match lifetimes[i] {
+                        Some(lifetime) => lifetime,
+                        None => continue,
+                    };
+                    match pool
+                        .iter_mut()
+                        .find(|(pooled, pooled_last, _)| *pooled == desc && *pooled_last < first)
+                    {
+                        Some((_, pooled_last, index)) => {
+                            *pooled_last = last;
+                            *index
+                        }
+                        None => {
+                            physical.push(self.create_transient(allocator, desc)?);
+                            pool.push((desc, last, physical.len() - 1));
+                            physical.len() - 1
+                        }
+                    }
+                }
+            };
+        }
+        Ok(physical)
+    }
+
+    fn create_transient(
+        &mut self,
+        allocator: &vk_mem::Allocator,
+        desc: TransientImageDesc,
+    ) -> Result<vk::Image> {
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(desc.format)
+            .extent(vk::Extent3D {
+                width: desc.extent.width,
+                height: desc.extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(desc.usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        let allocation_info = vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::GpuOnly,
+            ..Default::default()
+        };
+        let (image, allocation, _) = allocator.create_image(&image_info, &allocation_info)?;
+        self.transients.push((image, allocation));
+        Ok(image)
+    }
+
+    /// Allocates transient images and records every pass with the barriers
+    /// between them into `commandbuffer`. The graph has to be kept until the
+    /// command buffer has finished executing, then cleaned up.
+    pub fn execute(
+        &mut self,
+        logical_device: &ash::Device,
+        allocator: &vk_mem::Allocator,
+        commandbuffer: vk::CommandBuffer,
+    ) -> Result<()> {
+        let physical_images = self.allocate(allocator)?;
+        let resources = GraphResources {
+            images: self
+                .images
+                .iter()
+                .map(|image| physical_images[image.physical])
+                .collect(),
+            buffers: self.buffers.iter().map(|buffer| buffer.buffer).collect(),
+        };
+
+        let mut image_states: Vec<Option<ResourceState>> = vec![None; physical_images.len()];
+        let mut first_use = vec![true; self.images.len()];
+        let mut buffer_states: Vec<ResourceState> = self
+            .buffers
+            .iter()
+            .map(|buffer| ResourceState::new(vk::ImageLayout::UNDEFINED, buffer.initial))
+            .collect();
+
+        let passes: Vec<Pass> = self.passes.drain(..).collect();
+        for pass in passes {
+            let mut barriers = Barriers::default();
+            for usage in &pass.usages {
+                match *usage {
+                    Usage::Image(handle, access) => {
+                        let image = &self.images[handle.0];
+                        let state = image_states[image.physical]
+                            .get_or_insert_with(|| image.initial_state());
+                        // Transients start with undefined contents, even when
+                        // they reuse an image an earlier transient left behind.
+                        if first_use[handle.0] && image.discard {
+                            state.layout = vk::ImageLayout::UNDEFINED;
+                        }
+                        first_use[handle.0] = false;
+                        barriers.image(
+                            state,
+                            resources.images[handle.0],
+                            image.aspect,
+                            access,
+                            access.layout(),
+                        );
+                    }
+                    Usage::Buffer(handle, access) => {
+                        barriers.buffer(
+                            &mut buffer_states[handle.0],
+                            resources.buffers[handle.0],
+                            access,
+                        );
+                    }
+                }
+            }
+            barriers.record(logical_device, commandbuffer);
+            log::trace!("Render graph pass {}", pass.name);
+            (pass.record)(logical_device, commandbuffer, &resources);
+        }
+
+        let mut barriers = Barriers::default();
+        for (i, image) in self.images.iter().enumerate() {
+            if let Some(access) = image.final_access {
+                // Images no pass used still get their final layout.
+                let state =
+                    image_states[image.physical].get_or_insert_with(|| image.initial_state());
+                barriers.image(
+                    state,
+                    resources.images[i],
+                    image.aspect,
+                    access,
+                    access.layout(),
+                );
+            }
+        }
+        for (i, buffer) in self.buffers.iter().enumerate() {
+            if let Some(access) = buffer.final_access {
+                barriers.buffer(&mut buffer_states[i], buffer.buffer, access);
+            }
+        }
+        barriers.record(logical_device, commandbuffer);
+        Ok(())
+    }
+
+    /// Frees the transient images. Only call once the work recorded by
+    /// [`RenderGraph::execute`] has completed.
+    pub fn cleanup(&mut self, allocator: &vk_mem::Allocator) -> Result<()> {
+        for (image, allocation) in self.transients.drain(..) {
+            allocator.destroy_image(image, &allocation)?;
+        }
+        Ok(())
+    }
+}
+
+/// Barriers collected for one pass and issued in a single call.
+#[derive(Default)]
+struct Barriers {
+    src_stages: vk::PipelineStageFlags,
+    dst_stages: vk::PipelineStageFlags,
+    images: Vec<vk::ImageMemoryBarrier>,
+    buffers: Vec<vk::BufferMemoryBarrier>,
+}
+
+impl Barriers {
+    fn image(
+        &mut self,
+        state: &mut ResourceState,
+        image: vk::Image,
+        aspect: vk::ImageAspectFlags,
+        access: Access,
+        layout: vk::ImageLayout,
+    ) {
+        let old_layout = state.layout;
+        if let Some((src_stages, src_access)) = state.transition(access, layout) {
+            self.src_stages |= src_stages;
+            self.dst_stages |= access.stage();
+            self.images.push(
+                vk::ImageMemoryBarrier::builder()
+                    .image(image)
+                    .src_access_mask(src_access)
+                    .dst_access_mask(access.access())
+                    .old_layout(old_layout)
+                    .new_layout(layout)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .subresource_range(vk::ImageSubresourceRange {
+                        aspect_mask: aspect,
+                        base_mip_level: 0,
+                        level_count: vk::REMAINING_MIP_LEVELS,
+                        base_array_layer: 0,
+                        layer_count: vk::REMAINING_ARRAY_LAYERS,
+                    })
+                    .build(),
+            );
+        }
+    }
+
+    fn buffer(&mut self, state: &mut ResourceState, buffer: vk::Buffer, access: Access) {
+        if let Some((src_stages, src_access)) = state.transition(access, vk::ImageLayout::UNDEFINED)
+        {
+            self.src_stages |= src_stages;
+            self.dst_stages |= access.stage();
+            self.buffers.push(
+                vk::BufferMemoryBarrier::builder()
+                    .buffer(buffer)
+                    .src_access_mask(src_access)
+                    .dst_access_mask(access.access())
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .offset(0)
+                    .size(vk::WHOLE_SIZE)
+                    .build(),
+            );
+        }
+    }
+
+    fn record(self, logical_device: &ash::Device, commandbuffer: vk::CommandBuffer) {
+        if self.images.is_empty() && self.buffers.is_empty() {
+            return;
+        }
+        let src_stages = if self.src_stages.is_empty() {
+            vk::PipelineStageFlags::TOP_OF_PIPE
+        } else {
+            self.src_stages
+        };
+        unsafe {
+            logical_device.cmd_pipeline_barrier(
+                commandbuffer,
+                src_stages,
+                self.dst_stages,
+                vk::DependencyFlags::empty(),
+                &[],
+                &self.buffers,
+                &self.images,
+            )
+        };
+    }
+}
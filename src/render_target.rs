@@ -0,0 +1,90 @@
+use ash::{version::DeviceV1_0, vk};
+use eyre::*;
+
+/// Scene colour is rendered in linear light and only mapped to the display range
+/// by the tonemapping pass.
+pub const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+pub const DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+/// Model index plus one, zero for the background, and instance index.
+pub const ID_FORMAT: vk::Format = vk::Format::R32G32_UINT;
+
+/// Offscreen image with a single view, sized to whatever it is attached to.
+pub struct RenderTarget {
+    pub image: vk::Image,
+    allocation: vk_mem::Allocation,
+    pub imageview: vk::ImageView,
+}
+
+impl RenderTarget {
+    pub fn new(
+        logical_device: &ash::Device,
+        allocator: &vk_mem::Allocator,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+    ) -> Result<RenderTarget> {
+        let aspect_mask = if format == DEPTH_FORMAT {
+            vk::ImageAspectFlags::DEPTH
+        } else {
+            vk::ImageAspectFlags::COLOR
+        };
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        let allocation_info = vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::GpuOnly,
+            ..Default::default()
+        };
+        let (image, allocation, _) = allocator.create_image(&image_info, &allocation_info)?;
+        let subresource_range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(aspect_mask)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1);
+        let imageview_create_info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .subresource_range(*subresource_range);
+        let imageview = unsafe { logical_device.create_image_view(&imageview_create_info, None) }?;
+        Ok(RenderTarget {
+            image,
+            allocation,
+            imageview,
+        })
+    }
+
+    pub fn cleanup(&self, logical_device: &ash::Device, allocator: &vk_mem::Allocator) {
+        unsafe {
+            logical_device.destroy_image_view(self.imageview, None);
+        }
+        allocator
+            .destroy_image(self.image, &self.allocation)
+            .expect("Failed destroy render target image");
+    }
+}
+
+pub fn create_sampler(logical_device: &ash::Device) -> Result<vk::Sampler, vk::Result> {
+    let sampler_info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .max_lod(0.0);
+    unsafe { logical_device.create_sampler(&sampler_info, None) }
+}
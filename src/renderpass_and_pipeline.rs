@@ -1,29 +1,30 @@
-use crate::include_spirv_from_outdir;
-use crate::swapchain::SwapchainDongXi;
+use crate::reflection::PipelineInterface;
+use crate::render_target::{DEPTH_FORMAT, HDR_FORMAT, ID_FORMAT};
+use crate::shaders;
 use ash::{version::DeviceV1_0, vk};
+use eyre::*;
 
-pub fn init_renderpass(
-    logical_device: &ash::Device,
-    format: vk::Format,
-) -> Result<vk::RenderPass, vk::Result> {
+/// Scene pass: renders into the HDR and depth targets. They stay in attachment
+/// layouts, the render graph provides those and the barriers around the pass.
+pub fn init_renderpass(logical_device: &ash::Device) -> Result<vk::RenderPass, vk::Result> {
     let attachments = [
         vk::AttachmentDescription::builder()
-            .format(format)
+            .format(HDR_FORMAT)
             .load_op(vk::AttachmentLoadOp::CLEAR)
             .store_op(vk::AttachmentStoreOp::STORE)
             .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
             .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .initial_layout(vk::ImageLayout::UNDEFINED)
-            .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .initial_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
             .samples(vk::SampleCountFlags::TYPE_1)
             .build(),
         vk::AttachmentDescription::builder()
-            .format(vk::Format::D32_SFLOAT)
+            .format(DEPTH_FORMAT)
             .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)
             .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
             .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .initial_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
             .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
             .samples(vk::SampleCountFlags::TYPE_1)
             .build(),
@@ -41,6 +42,36 @@ pub fn init_renderpass(
         .depth_stencil_attachment(&depth_attachment_reference)
         .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
         .build()];
+    let renderpass_info = vk::RenderPassCreateInfo::builder()
+        .attachments(&attachments)
+        .subpasses(&subpasses);
+    let renderpass = unsafe { logical_device.create_render_pass(&renderpass_info, None)? };
+    Ok(renderpass)
+}
+
+/// Final pass: a single full-screen draw into the swapchain image.
+pub fn init_present_renderpass(
+    logical_device: &ash::Device,
+    format: vk::Format,
+) -> Result<vk::RenderPass, vk::Result> {
+    let attachments = [vk::AttachmentDescription::builder()
+        .format(format)
+        .load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .build()];
+    let color_attachment_references = [vk::AttachmentReference {
+        attachment: 0,
+        layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+    }];
+    let subpasses = [vk::SubpassDescription::builder()
+        .color_attachments(&color_attachment_references)
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .build()];
     let subpass_dependencies = [vk::SubpassDependency::builder()
         .src_subpass(vk::SUBPASS_EXTERNAL)
         .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
@@ -58,6 +89,306 @@ pub fn init_renderpass(
     Ok(renderpass)
 }
 
+/// Single colour attachment for full-screen passes that render into an image
+/// which is sampled afterwards. With `load` the previous contents are kept, so
+/// the pass can blend on top of them. Like the scene pass it leaves layouts and
+/// barriers to the render graph.
+pub fn init_offscreen_renderpass(
+    logical_device: &ash::Device,
+    format: vk::Format,
+    load: bool,
+) -> Result<vk::RenderPass, vk::Result> {
+    let attachments = [vk::AttachmentDescription::builder()
+        .format(format)
+        .load_op(if load {
+            vk::AttachmentLoadOp::LOAD
+        } else {
+            vk::AttachmentLoadOp::DONT_CARE
+        })
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .build()];
+    let color_attachment_references = [vk::AttachmentReference {
+        attachment: 0,
+        layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+    }];
+    let subpasses = [vk::SubpassDescription::builder()
+        .color_attachments(&color_attachment_references)
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .build()];
+    let renderpass_info = vk::RenderPassCreateInfo::builder()
+        .attachments(&attachments)
+        .subpasses(&subpasses);
+    let renderpass = unsafe { logical_device.create_render_pass(&renderpass_info, None)? };
+    Ok(renderpass)
+}
+
+/// ID pass: model and instance of each pixel, left ready to be copied out.
+pub fn init_id_renderpass(logical_device: &ash::Device) -> Result<vk::RenderPass, vk::Result> {
+    let attachments = [
+        vk::AttachmentDescription::builder()
+            .format(ID_FORMAT)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .build(),
+        vk::AttachmentDescription::builder()
+            .format(DEPTH_FORMAT)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .build(),
+    ];
+    let color_attachment_references = [vk::AttachmentReference {
+        attachment: 0,
+        layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+    }];
+    let depth_attachment_reference = vk::AttachmentReference {
+        attachment: 1,
+        layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+    };
+    let subpasses = [vk::SubpassDescription::builder()
+        .color_attachments(&color_attachment_references)
+        .depth_stencil_attachment(&depth_attachment_reference)
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .build()];
+    // Like the scene pass the targets are shared between frames in flight; the
+    // IDs are read by copies rather than shaders.
+    let subpass_dependencies = [
+        vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .src_stage_mask(
+                vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            )
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_subpass(0)
+            .dst_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            )
+            .dst_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .build(),
+        vk::SubpassDependency::builder()
+            .src_subpass(0)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_stage_mask(vk::PipelineStageFlags::TRANSFER)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .build(),
+    ];
+    let renderpass_info = vk::RenderPassCreateInfo::builder()
+        .attachments(&attachments)
+        .subpasses(&subpasses)
+        .dependencies(&subpass_dependencies);
+    let renderpass = unsafe { logical_device.create_render_pass(&renderpass_info, None)? };
+    Ok(renderpass)
+}
+
+/// Sets viewport and scissor to cover `extent`, for pipelines with dynamic
+/// viewport state.
+pub fn set_viewport(
+    logical_device: &ash::Device,
+    commandbuffer: vk::CommandBuffer,
+    extent: vk::Extent2D,
+) {
+    let viewports = [vk::Viewport {
+        x: 0.,
+        y: 0.,
+        width: extent.width as f32,
+        height: extent.height as f32,
+        min_depth: 0.,
+        max_depth: 1.,
+    }];
+    let scissors = [vk::Rect2D {
+        offset: vk::Offset2D { x: 0, y: 0 },
+        extent,
+    }];
+    unsafe {
+        logical_device.cmd_set_viewport(commandbuffer, 0, &viewports);
+        logical_device.cmd_set_scissor(commandbuffer, 0, &scissors);
+    }
+}
+
+/// Which end of the 0..1 depth range is near. Reversed depth spreads the
+/// float precision of the depth buffer much more evenly over distance.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DepthConvention {
+    /// Near at 0, far at 1.
+    Standard,
+    /// Near at 1, far at 0.
+    Reversed,
+}
+
+impl DepthConvention {
+    /// Passes fragments at least as near as what is already there.
+    pub fn compare_op(self) -> vk::CompareOp {
+        match self {
+            DepthConvention::Standard => vk::CompareOp::LESS_OR_EQUAL,
+            DepthConvention::Reversed => vk::CompareOp::GREATER_OR_EQUAL,
+        }
+    }
+
+    /// Depth of the far plane, which the depth buffer is cleared to.
+    pub fn far(self) -> f32 {
+        match self {
+            DepthConvention::Standard => 1.0,
+            DepthConvention::Reversed => 0.0,
+        }
+    }
+}
+
+/// Vertex layout of `Model<VertexData, InstanceData>`: per-vertex position and
+/// normal, per-instance matrices and material.
+fn scene_vertex_input() -> (
+    [vk::VertexInputAttributeDescription; 13],
+    [vk::VertexInputBindingDescription; 2],
+) {
+    let attributes = [
+        vk::VertexInputAttributeDescription {
+            binding: 0,
+            location: 0,
+            offset: 0,
+            format: vk::Format::R32G32B32_SFLOAT,
+        },
+        vk::VertexInputAttributeDescription {
+            binding: 0,
+            location: 1,
+            offset: 12,
+            format: vk::Format::R32G32B32_SFLOAT,
+        },
+        vk::VertexInputAttributeDescription {
+            binding: 1,
+            location: 2,
+            offset: 0,
+            format: vk::Format::R32G32B32A32_SFLOAT,
+        },
+        vk::VertexInputAttributeDescription {
+            binding: 1,
+            location: 3,
+            offset: 16,
+            format: vk::Format::R32G32B32A32_SFLOAT,
+        },
+        vk::VertexInputAttributeDescription {
+            binding: 1,
+            location: 4,
+            offset: 32,
+            format: vk::Format::R32G32B32A32_SFLOAT,
+        },
+        vk::VertexInputAttributeDescription {
+            binding: 1,
+            location: 5,
+            offset: 48,
+            format: vk::Format::R32G32B32A32_SFLOAT,
+        },
+        vk::VertexInputAttributeDescription {
+            binding: 1,
+            location: 6,
+            offset: 64,
+            format: vk::Format::R32G32B32A32_SFLOAT,
+        },
+        vk::VertexInputAttributeDescription {
+            binding: 1,
+            location: 7,
+            offset: 80,
+            format: vk::Format::R32G32B32A32_SFLOAT,
+        },
+        vk::VertexInputAttributeDescription {
+            binding: 1,
+            location: 8,
+            offset: 96,
+            format: vk::Format::R32G32B32A32_SFLOAT,
+        },
+        vk::VertexInputAttributeDescription {
+            binding: 1,
+            location: 9,
+            offset: 112,
+            format: vk::Format::R32G32B32A32_SFLOAT,
+        },
+        vk::VertexInputAttributeDescription {
+            binding: 1,
+            location: 10,
+            offset: 128,
+            format: vk::Format::R32G32B32_SFLOAT,
+        },
+        vk::VertexInputAttributeDescription {
+            binding: 1,
+            location: 11,
+            offset: 140,
+            format: vk::Format::R32_SFLOAT,
+        },
+        vk::VertexInputAttributeDescription {
+            binding: 1,
+            location: 12,
+            offset: 144,
+            format: vk::Format::R32_SFLOAT,
+        },
+    ];
+    let bindings = [
+        vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: 24,
+            input_rate: vk::VertexInputRate::VERTEX,
+        },
+        vk::VertexInputBindingDescription {
+            binding: 1,
+            stride: 148,
+            input_rate: vk::VertexInputRate::INSTANCE,
+        },
+    ];
+    (attributes, bindings)
+}
+
+/// Shader modules that only live while a pipeline is built. Dropping destroys
+/// them, so an early return on a bad shader doesn't leak them.
+struct ShaderModules<'a> {
+    logical_device: &'a ash::Device,
+    modules: Vec<vk::ShaderModule>,
+}
+
+impl<'a> ShaderModules<'a> {
+    fn new(logical_device: &'a ash::Device) -> Self {
+        ShaderModules {
+            logical_device,
+            modules: Vec::new(),
+        }
+    }
+
+    fn create(&mut self, code: &[u32]) -> Result<vk::ShaderModule> {
+        let createinfo = vk::ShaderModuleCreateInfo::builder().code(code);
+        let module = unsafe {
+            self.logical_device
+                .create_shader_module(&createinfo, None)?
+        };
+        self.modules.push(module);
+        Ok(module)
+    }
+}
+
+impl Drop for ShaderModules<'_> {
+    fn drop(&mut self) {
+        for module in &self.modules {
+            unsafe { self.logical_device.destroy_shader_module(*module, None) };
+        }
+    }
+}
+
 pub struct Pipeline {
     pub pipeline: vk::Pipeline,
     pub layout: vk::PipelineLayout,
@@ -75,20 +406,27 @@ impl Pipeline {
         }
     }
 
+    /// Scene pipeline, with the fragment shader compiled for `permutation`.
     pub fn init(
         logical_device: &ash::Device,
-        swapchain: &SwapchainDongXi,
+        pipeline_cache: vk::PipelineCache,
         renderpass: &vk::RenderPass,
-    ) -> Result<Pipeline, vk::Result> {
-        let vs_src = include_spirv_from_outdir!("/shaders/shader.vert.spv");
-        let vertexshader_createinfo = vk::ShaderModuleCreateInfo::builder().code(&vs_src);
-        let vertexshader_module =
-            unsafe { logical_device.create_shader_module(&vertexshader_createinfo, None)? };
-
-        let fs_src = include_spirv_from_outdir!("/shaders/shader.frag.spv");
-        let fragmentshader_createinfo = vk::ShaderModuleCreateInfo::builder().code(&fs_src);
-        let fragmentshader_module =
-            unsafe { logical_device.create_shader_module(&fragmentshader_createinfo, None)? };
+        depth: DepthConvention,
+        permutation: &str,
+    ) -> Result<Pipeline> {
+        let vs_src = shaders::spirv("shader.vert", "");
+        let fs_src = shaders::spirv("shader.frag", permutation);
+        let interface = PipelineInterface::reflect(&[&vs_src, &fs_src])?;
+        interface
+            .check_bindings(0, &[vk::DescriptorType::UNIFORM_BUFFER])
+            .wrap_err("Scene shaders: set 0 must be the camera uniform buffer")?;
+        interface
+            .check_bindings(1, &[vk::DescriptorType::STORAGE_BUFFER])
+            .wrap_err("Scene shaders: set 1 must be the light storage buffer")?;
+        let mut modules = ShaderModules::new(logical_device);
+        let vertexshader_module = modules.create(&vs_src)?;
+
+        let fragmentshader_module = modules.create(&fs_src)?;
         let mainfunctionname = std::ffi::CString::new("main").unwrap();
         let vertexshader_stage = vk::PipelineShaderStageCreateInfo::builder()
             .stage(vk::ShaderStageFlags::VERTEX)
@@ -100,120 +438,19 @@ impl Pipeline {
             .name(&mainfunctionname);
         let shader_stages = vec![vertexshader_stage.build(), fragmentshader_stage.build()];
 
-        let vertex_attrib_descs = [
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 0,
-                offset: 0,
-                format: vk::Format::R32G32B32_SFLOAT,
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 1,
-                offset: 12,
-                format: vk::Format::R32G32B32_SFLOAT,
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 1,
-                location: 2,
-                offset: 0,
-                format: vk::Format::R32G32B32A32_SFLOAT,
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 1,
-                location: 3,
-                offset: 16,
-                format: vk::Format::R32G32B32A32_SFLOAT,
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 1,
-                location: 4,
-                offset: 32,
-                format: vk::Format::R32G32B32A32_SFLOAT,
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 1,
-                location: 5,
-                offset: 48,
-                format: vk::Format::R32G32B32A32_SFLOAT,
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 1,
-                location: 6,
-                offset: 64,
-                format: vk::Format::R32G32B32A32_SFLOAT,
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 1,
-                location: 7,
-                offset: 80,
-                format: vk::Format::R32G32B32A32_SFLOAT,
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 1,
-                location: 8,
-                offset: 96,
-                format: vk::Format::R32G32B32A32_SFLOAT,
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 1,
-                location: 9,
-                offset: 112,
-                format: vk::Format::R32G32B32A32_SFLOAT,
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 1,
-                location: 10,
-                offset: 128,
-                format: vk::Format::R32G32B32_SFLOAT,
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 1,
-                location: 11,
-                offset: 140,
-                format: vk::Format::R32_SFLOAT,
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 1,
-                location: 12,
-                offset: 144,
-                format: vk::Format::R32_SFLOAT,
-            },
-        ];
-        let vertex_binding_descs = [
-            vk::VertexInputBindingDescription {
-                binding: 0,
-                stride: 24,
-                input_rate: vk::VertexInputRate::VERTEX,
-            },
-            vk::VertexInputBindingDescription {
-                binding: 1,
-                stride: 148,
-                input_rate: vk::VertexInputRate::INSTANCE,
-            },
-        ];
+        let (vertex_attrib_descs, vertex_binding_descs) = scene_vertex_input();
 
         let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::builder()
             .vertex_attribute_descriptions(&vertex_attrib_descs)
             .vertex_binding_descriptions(&vertex_binding_descs);
         let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
             .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
-        let viewports = [vk::Viewport {
-            x: 0.,
-            y: 0.,
-            width: swapchain.extent.width as f32,
-            height: swapchain.extent.height as f32,
-            min_depth: 0.,
-            max_depth: 1.,
-        }];
-        let scissors = [vk::Rect2D {
-            offset: vk::Offset2D { x: 0, y: 0 },
-            extent: swapchain.extent,
-        }];
-
         let viewport_info = vk::PipelineViewportStateCreateInfo::builder()
-            .viewports(&viewports)
-            .scissors(&scissors);
+            .viewport_count(1)
+            .scissor_count(1);
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state_info =
+            vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
         let rasterizer_info = vk::PipelineRasterizationStateCreateInfo::builder()
             .line_width(1.0)
             .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
@@ -224,7 +461,7 @@ impl Pipeline {
         let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
             .depth_test_enable(true)
             .depth_write_enable(true)
-            .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL);
+            .depth_compare_op(depth.compare_op());
         let colorblend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
             .blend_enable(true)
             .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
@@ -243,58 +480,295 @@ impl Pipeline {
         let colorblend_info =
             vk::PipelineColorBlendStateCreateInfo::builder().attachments(&colorblend_attachments);
 
-        let descriptorset_layout_binding_descs0 = [vk::DescriptorSetLayoutBinding::builder()
-            .binding(0)
-            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-            .descriptor_count(1)
-            .stage_flags(vk::ShaderStageFlags::VERTEX)
+        interface.check_vertex_input(&vertex_attrib_descs)?;
+        let (desclayouts, pipelinelayout) = interface.create_layouts(logical_device, 0, 0)?;
+        let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&shader_stages)
+            .vertex_input_state(&vertex_input_info)
+            .input_assembly_state(&input_assembly_info)
+            .viewport_state(&viewport_info)
+            .dynamic_state(&dynamic_state_info)
+            .rasterization_state(&rasterizer_info)
+            .multisample_state(&multisampler_info)
+            .depth_stencil_state(&depth_stencil_info)
+            .color_blend_state(&colorblend_info)
+            .layout(pipelinelayout)
+            .render_pass(*renderpass)
+            .subpass(0);
+        let graphicspipeline = unsafe {
+            logical_device
+                .create_graphics_pipelines(pipeline_cache, &[pipeline_info.build()], None)
+                .expect("A problem with the pipeline creation")
+        }[0];
+        Ok(Pipeline {
+            pipeline: graphicspipeline,
+            layout: pipelinelayout,
+            descriptor_set_layouts: desclayouts,
+        })
+    }
+
+    /// Writes the model index pushed before each draw, plus one, and the
+    /// instance index into the ID pass.
+    pub fn init_id(
+        logical_device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
+        renderpass: &vk::RenderPass,
+        depth: DepthConvention,
+    ) -> Result<Pipeline> {
+        let vs_src = shaders::spirv("id.vert", "");
+        let fs_src = shaders::spirv("id.frag", "");
+        let interface = PipelineInterface::reflect(&[&vs_src, &fs_src])?;
+        let mut modules = ShaderModules::new(logical_device);
+        let vertexshader_module = modules.create(&vs_src)?;
+        let fragmentshader_module = modules.create(&fs_src)?;
+        let mainfunctionname = std::ffi::CString::new("main").unwrap();
+        let vertexshader_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::VERTEX)
+            .module(vertexshader_module)
+            .name(&mainfunctionname);
+        let fragmentshader_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .module(fragmentshader_module)
+            .name(&mainfunctionname);
+        let shader_stages = vec![vertexshader_stage.build(), fragmentshader_stage.build()];
+
+        let (vertex_attrib_descs, vertex_binding_descs) = scene_vertex_input();
+        let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_attribute_descriptions(&vertex_attrib_descs)
+            .vertex_binding_descriptions(&vertex_binding_descs);
+        let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+        let viewport_info = vk::PipelineViewportStateCreateInfo::builder()
+            .viewport_count(1)
+            .scissor_count(1);
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state_info =
+            vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
+        let rasterizer_info = vk::PipelineRasterizationStateCreateInfo::builder()
+            .line_width(1.0)
+            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+            .cull_mode(vk::CullModeFlags::BACK)
+            .polygon_mode(vk::PolygonMode::FILL);
+        let multisampler_info = vk::PipelineMultisampleStateCreateInfo::builder()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+        let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
+            .depth_test_enable(true)
+            .depth_write_enable(true)
+            .depth_compare_op(depth.compare_op());
+        // Integer attachments cannot blend.
+        let colourblend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
+            .blend_enable(false)
+            .color_write_mask(vk::ColorComponentFlags::R | vk::ColorComponentFlags::G)
             .build()];
-        let descriptorset_layout_info0 = vk::DescriptorSetLayoutCreateInfo::builder()
-            .bindings(&descriptorset_layout_binding_descs0);
-        let descriptorsetlayout0 = unsafe {
-            logical_device.create_descriptor_set_layout(&descriptorset_layout_info0, None)
-        }?;
-        let descriptorset_layout_binding_descs1 = [vk::DescriptorSetLayoutBinding::builder()
-            .binding(0)
-            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-            .descriptor_count(1)
-            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        let colourblend_info =
+            vk::PipelineColorBlendStateCreateInfo::builder().attachments(&colourblend_attachments);
+
+        interface.check_vertex_input(&vertex_attrib_descs)?;
+        let (desclayouts, pipelinelayout) =
+            interface.create_layouts(logical_device, 0, std::mem::size_of::<u32>() as u32)?;
+        let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&shader_stages)
+            .vertex_input_state(&vertex_input_info)
+            .input_assembly_state(&input_assembly_info)
+            .viewport_state(&viewport_info)
+            .dynamic_state(&dynamic_state_info)
+            .rasterization_state(&rasterizer_info)
+            .multisample_state(&multisampler_info)
+            .depth_stencil_state(&depth_stencil_info)
+            .color_blend_state(&colourblend_info)
+            .layout(pipelinelayout)
+            .render_pass(*renderpass)
+            .subpass(0);
+        let graphicspipeline = unsafe {
+            logical_device
+                .create_graphics_pipelines(pipeline_cache, &[pipeline_info.build()], None)
+                .expect("A problem with the pipeline creation")
+        }[0];
+        Ok(Pipeline {
+            pipeline: graphicspipeline,
+            layout: pipelinelayout,
+            descriptor_set_layouts: desclayouts,
+        })
+    }
+
+    pub fn init_skybox(
+        logical_device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
+        renderpass: &vk::RenderPass,
+        depth: DepthConvention,
+    ) -> Result<Pipeline> {
+        let vs_src = shaders::spirv("skybox.vert", "");
+        let fs_src = shaders::spirv("skybox.frag", "");
+        let interface = PipelineInterface::reflect(&[&vs_src, &fs_src])?;
+        let mut modules = ShaderModules::new(logical_device);
+        let vertexshader_module = modules.create(&vs_src)?;
+
+        let fragmentshader_module = modules.create(&fs_src)?;
+        let mainfunctionname = std::ffi::CString::new("main").unwrap();
+        let vertexshader_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::VERTEX)
+            .module(vertexshader_module)
+            .name(&mainfunctionname);
+        let fragmentshader_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .module(fragmentshader_module)
+            .name(&mainfunctionname);
+        let shader_stages = vec![vertexshader_stage.build(), fragmentshader_stage.build()];
+
+        // The full-screen triangle is generated from gl_VertexIndex.
+        let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::builder();
+        let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+        let viewport_info = vk::PipelineViewportStateCreateInfo::builder()
+            .viewport_count(1)
+            .scissor_count(1);
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state_info =
+            vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
+        let rasterizer_info = vk::PipelineRasterizationStateCreateInfo::builder()
+            .line_width(1.0)
+            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .polygon_mode(vk::PolygonMode::FILL);
+        let multisampler_info = vk::PipelineMultisampleStateCreateInfo::builder()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+        // Drawn at the far plane after the scene, so only uncovered pixels pass.
+        let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
+            .depth_test_enable(true)
+            .depth_write_enable(false)
+            .depth_compare_op(depth.compare_op());
+        let colourblend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
+            .blend_enable(false)
+            .color_write_mask(
+                vk::ColorComponentFlags::R
+                    | vk::ColorComponentFlags::G
+                    | vk::ColorComponentFlags::B
+                    | vk::ColorComponentFlags::A,
+            )
             .build()];
-        let descriptorset_layout_info1 = vk::DescriptorSetLayoutCreateInfo::builder()
-            .bindings(&descriptorset_layout_binding_descs1);
-        let descriptorsetlayout1 = unsafe {
-            logical_device.create_descriptor_set_layout(&descriptorset_layout_info1, None)
-        }?;
-        let desclayouts = vec![descriptorsetlayout0, descriptorsetlayout1];
-
-        let pipelinelayout_info = vk::PipelineLayoutCreateInfo::builder().set_layouts(&desclayouts);
-        let pipelinelayout =
-            unsafe { logical_device.create_pipeline_layout(&pipelinelayout_info, None) }?;
+        let colourblend_info =
+            vk::PipelineColorBlendStateCreateInfo::builder().attachments(&colourblend_attachments);
+
+        let (desclayouts, pipelinelayout) = interface.create_layouts(
+            logical_device,
+            0,
+            std::mem::size_of::<crate::skybox::SkyboxParameters>() as u32,
+        )?;
         let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
             .stages(&shader_stages)
             .vertex_input_state(&vertex_input_info)
             .input_assembly_state(&input_assembly_info)
             .viewport_state(&viewport_info)
+            .dynamic_state(&dynamic_state_info)
             .rasterization_state(&rasterizer_info)
             .multisample_state(&multisampler_info)
             .depth_stencil_state(&depth_stencil_info)
-            .color_blend_state(&colorblend_info)
+            .color_blend_state(&colourblend_info)
             .layout(pipelinelayout)
             .render_pass(*renderpass)
             .subpass(0);
         let graphicspipeline = unsafe {
             logical_device
-                .create_graphics_pipelines(
-                    vk::PipelineCache::null(),
-                    &[pipeline_info.build()],
-                    None,
-                )
+                .create_graphics_pipelines(pipeline_cache, &[pipeline_info.build()], None)
+                .expect("A problem with the pipeline creation")
+        }[0];
+        Ok(Pipeline {
+            pipeline: graphicspipeline,
+            layout: pipelinelayout,
+            descriptor_set_layouts: desclayouts,
+        })
+    }
+
+    /// Full-screen triangle with the given fragment shader. Set 0 holds
+    /// `sampled_images` combined image samplers at bindings `0..sampled_images`.
+    /// Viewport and scissor are dynamic, see [`set_viewport`].
+    pub fn init_fullscreen(
+        logical_device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
+        renderpass: &vk::RenderPass,
+        fragment_shader: &[u32],
+        sampled_images: u32,
+        push_constant_size: u32,
+        additive_blend: bool,
+    ) -> Result<Pipeline> {
+        let vs_src = shaders::spirv("fullscreen.vert", "");
+        let interface = PipelineInterface::reflect(&[&vs_src, fragment_shader])?;
+        interface.check_bindings(
+            0,
+            &vec![vk::DescriptorType::COMBINED_IMAGE_SAMPLER; sampled_images as usize],
+        )?;
+        let mut modules = ShaderModules::new(logical_device);
+        let vertexshader_module = modules.create(&vs_src)?;
+
+        let fragmentshader_module = modules.create(fragment_shader)?;
+        let mainfunctionname = std::ffi::CString::new("main").unwrap();
+        let vertexshader_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::VERTEX)
+            .module(vertexshader_module)
+            .name(&mainfunctionname);
+        let fragmentshader_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .module(fragmentshader_module)
+            .name(&mainfunctionname);
+        let shader_stages = vec![vertexshader_stage.build(), fragmentshader_stage.build()];
+
+        let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::builder();
+        let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+        let viewport_info = vk::PipelineViewportStateCreateInfo::builder()
+            .viewport_count(1)
+            .scissor_count(1);
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state_info =
+            vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
+        let rasterizer_info = vk::PipelineRasterizationStateCreateInfo::builder()
+            .line_width(1.0)
+            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .polygon_mode(vk::PolygonMode::FILL);
+        let multisampler_info = vk::PipelineMultisampleStateCreateInfo::builder()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+        let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
+            .depth_test_enable(false)
+            .depth_write_enable(false);
+        let colourblend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
+            .blend_enable(additive_blend)
+            .src_color_blend_factor(vk::BlendFactor::ONE)
+            .dst_color_blend_factor(vk::BlendFactor::ONE)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+            .alpha_blend_op(vk::BlendOp::ADD)
+            .color_write_mask(
+                vk::ColorComponentFlags::R
+                    | vk::ColorComponentFlags::G
+                    | vk::ColorComponentFlags::B
+                    | vk::ColorComponentFlags::A,
+            )
+            .build()];
+        let colourblend_info =
+            vk::PipelineColorBlendStateCreateInfo::builder().attachments(&colourblend_attachments);
+
+        let (desclayouts, pipelinelayout) =
+            interface.create_layouts(logical_device, 1, push_constant_size)?;
+        let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&shader_stages)
+            .vertex_input_state(&vertex_input_info)
+            .input_assembly_state(&input_assembly_info)
+            .viewport_state(&viewport_info)
+            .rasterization_state(&rasterizer_info)
+            .multisample_state(&multisampler_info)
+            .depth_stencil_state(&depth_stencil_info)
+            .color_blend_state(&colourblend_info)
+            .dynamic_state(&dynamic_state_info)
+            .layout(pipelinelayout)
+            .render_pass(*renderpass)
+            .subpass(0);
+        let graphicspipeline = unsafe {
+            logical_device
+                .create_graphics_pipelines(pipeline_cache, &[pipeline_info.build()], None)
                 .expect("A problem with the pipeline creation")
         }[0];
-        unsafe {
-            logical_device.destroy_shader_module(fragmentshader_module, None);
-            logical_device.destroy_shader_module(vertexshader_module, None);
-        }
         Ok(Pipeline {
             pipeline: graphicspipeline,
             layout: pipelinelayout,
@@ -302,20 +776,152 @@ impl Pipeline {
         })
     }
 
+    /// Camera-facing quads expanded from a particle storage buffer. Set 0 is the
+    /// camera, set 1 the particles. `spheres` draws opaque impostors that write
+    /// depth, otherwise the sprites are blended additively.
+    pub fn init_particles(
+        logical_device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
+        renderpass: &vk::RenderPass,
+        depth: DepthConvention,
+        spheres: bool,
+    ) -> Result<Pipeline> {
+        let vs_src = shaders::spirv("particles.vert", "");
+        let fs_src = shaders::spirv("particles.frag", "");
+        let interface = PipelineInterface::reflect(&[&vs_src, &fs_src])?;
+        let mut modules = ShaderModules::new(logical_device);
+        let vertexshader_module = modules.create(&vs_src)?;
+
+        let fragmentshader_module = modules.create(&fs_src)?;
+        let mainfunctionname = std::ffi::CString::new("main").unwrap();
+        let vertexshader_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::VERTEX)
+            .module(vertexshader_module)
+            .name(&mainfunctionname);
+        let fragmentshader_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .module(fragmentshader_module)
+            .name(&mainfunctionname);
+        let shader_stages = vec![vertexshader_stage.build(), fragmentshader_stage.build()];
+
+        // Quads are generated from gl_VertexIndex, one instance per particle.
+        let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::builder();
+        let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+        let viewport_info = vk::PipelineViewportStateCreateInfo::builder()
+            .viewport_count(1)
+            .scissor_count(1);
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state_info =
+            vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
+        let rasterizer_info = vk::PipelineRasterizationStateCreateInfo::builder()
+            .line_width(1.0)
+            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .polygon_mode(vk::PolygonMode::FILL);
+        let multisampler_info = vk::PipelineMultisampleStateCreateInfo::builder()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+        let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
+            .depth_test_enable(true)
+            .depth_write_enable(spheres)
+            .depth_compare_op(depth.compare_op());
+        let colourblend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
+            .blend_enable(!spheres)
+            .src_color_blend_factor(vk::BlendFactor::ONE)
+            .dst_color_blend_factor(vk::BlendFactor::ONE)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+            .alpha_blend_op(vk::BlendOp::ADD)
+            .color_write_mask(
+                vk::ColorComponentFlags::R
+                    | vk::ColorComponentFlags::G
+                    | vk::ColorComponentFlags::B
+                    | vk::ColorComponentFlags::A,
+            )
+            .build()];
+        let colourblend_info =
+            vk::PipelineColorBlendStateCreateInfo::builder().attachments(&colourblend_attachments);
+
+        let (desclayouts, pipelinelayout) = interface.create_layouts(
+            logical_device,
+            0,
+            std::mem::size_of::<crate::particles::DrawParameters>() as u32,
+        )?;
+        let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&shader_stages)
+            .vertex_input_state(&vertex_input_info)
+            .input_assembly_state(&input_assembly_info)
+            .viewport_state(&viewport_info)
+            .rasterization_state(&rasterizer_info)
+            .multisample_state(&multisampler_info)
+            .depth_stencil_state(&depth_stencil_info)
+            .color_blend_state(&colourblend_info)
+            .dynamic_state(&dynamic_state_info)
+            .layout(pipelinelayout)
+            .render_pass(*renderpass)
+            .subpass(0);
+        let graphicspipeline = unsafe {
+            logical_device
+                .create_graphics_pipelines(pipeline_cache, &[pipeline_info.build()], None)
+                .expect("A problem with the pipeline creation")
+        }[0];
+        Ok(Pipeline {
+            pipeline: graphicspipeline,
+            layout: pipelinelayout,
+            descriptor_set_layouts: desclayouts,
+        })
+    }
+
+    /// Compute pipeline whose set 0 has one descriptor of each of `bindings`,
+    /// at bindings `0..bindings.len()`.
+    pub fn init_compute(
+        logical_device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
+        compute_shader: &[u32],
+        bindings: &[vk::DescriptorType],
+        push_constant_size: u32,
+    ) -> Result<Pipeline> {
+        let interface = PipelineInterface::reflect(&[compute_shader])?;
+        interface.check_bindings(0, bindings)?;
+        let mut modules = ShaderModules::new(logical_device);
+        let computeshader_module = modules.create(compute_shader)?;
+        let mainfunctionname = std::ffi::CString::new("main").unwrap();
+        let computeshader_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(computeshader_module)
+            .name(&mainfunctionname);
+
+        let (desclayouts, pipelinelayout) =
+            interface.create_layouts(logical_device, 1, push_constant_size)?;
+        let pipeline_info = vk::ComputePipelineCreateInfo::builder()
+            .stage(computeshader_stage.build())
+            .layout(pipelinelayout);
+        let computepipeline = unsafe {
+            logical_device
+                .create_compute_pipelines(pipeline_cache, &[pipeline_info.build()], None)
+                .expect("A problem with the pipeline creation")
+        }[0];
+        Ok(Pipeline {
+            pipeline: computepipeline,
+            layout: pipelinelayout,
+            descriptor_set_layouts: desclayouts,
+        })
+    }
+
     pub fn init_textured(
         logical_device: &ash::Device,
-        swapchain: &SwapchainDongXi,
+        pipeline_cache: vk::PipelineCache,
         renderpass: &vk::RenderPass,
-    ) -> Result<Pipeline, vk::Result> {
-        let vs_src = include_spirv_from_outdir!("/shaders/shader_textured.vert.spv");
-        let vertexshader_createinfo = vk::ShaderModuleCreateInfo::builder().code(&vs_src);
-        let vertexshader_module =
-            unsafe { logical_device.create_shader_module(&vertexshader_createinfo, None)? };
-
-        let fs_src = include_spirv_from_outdir!("/shaders/shader_textured.frag.spv");
-        let fragmentshader_createinfo = vk::ShaderModuleCreateInfo::builder().code(&fs_src);
-        let fragmentshader_module =
-            unsafe { logical_device.create_shader_module(&fragmentshader_createinfo, None)? };
+        depth: DepthConvention,
+    ) -> Result<Pipeline> {
+        let vs_src = shaders::spirv("shader_textured.vert", "");
+        let fs_src = shaders::spirv("shader_textured.frag", "");
+        let interface = PipelineInterface::reflect(&[&vs_src, &fs_src])?;
+        let mut modules = ShaderModules::new(logical_device);
+        let vertexshader_module = modules.create(&vs_src)?;
+
+        let fragmentshader_module = modules.create(&fs_src)?;
         let mainfunctionname = std::ffi::CString::new("main").unwrap();
         let vertexshader_stage = vk::PipelineShaderStageCreateInfo::builder()
             .stage(vk::ShaderStageFlags::VERTEX)
@@ -401,22 +1007,12 @@ impl Pipeline {
             .vertex_binding_descriptions(&vertex_binding_descs);
         let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
             .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
-        let viewports = [vk::Viewport {
-            x: 0.,
-            y: 0.,
-            width: swapchain.extent.width as f32,
-            height: swapchain.extent.height as f32,
-            min_depth: 0.,
-            max_depth: 1.,
-        }];
-        let scissors = [vk::Rect2D {
-            offset: vk::Offset2D { x: 0, y: 0 },
-            extent: swapchain.extent,
-        }];
-
         let viewport_info = vk::PipelineViewportStateCreateInfo::builder()
-            .viewports(&viewports)
-            .scissors(&scissors);
+            .viewport_count(1)
+            .scissor_count(1);
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state_info =
+            vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
         let rasterizer_info = vk::PipelineRasterizationStateCreateInfo::builder()
             .line_width(1.0)
             .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
@@ -427,7 +1023,7 @@ impl Pipeline {
         let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
             .depth_test_enable(true)
             .depth_write_enable(true)
-            .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL);
+            .depth_compare_op(depth.compare_op());
         let colourblend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
             .blend_enable(true)
             .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
@@ -445,27 +1041,14 @@ impl Pipeline {
             .build()];
         let colourblend_info =
             vk::PipelineColorBlendStateCreateInfo::builder().attachments(&colourblend_attachments);
-        let descriptorset_layout_binding_descs0 = [vk::DescriptorSetLayoutBinding::builder()
-            .binding(0)
-            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-            .descriptor_count(1)
-            .stage_flags(vk::ShaderStageFlags::VERTEX)
-            .build()];
-        let descriptorset_layout_info0 = vk::DescriptorSetLayoutCreateInfo::builder()
-            .bindings(&descriptorset_layout_binding_descs0);
-        let descriptorsetlayout0 = unsafe {
-            logical_device.create_descriptor_set_layout(&descriptorset_layout_info0, None)
-        }?;
-
-        let desclayouts = vec![descriptorsetlayout0];
-        let pipelinelayout_info = vk::PipelineLayoutCreateInfo::builder().set_layouts(&desclayouts);
-        let pipelinelayout =
-            unsafe { logical_device.create_pipeline_layout(&pipelinelayout_info, None) }?;
+        interface.check_vertex_input(&vertex_attrib_descs)?;
+        let (desclayouts, pipelinelayout) = interface.create_layouts(logical_device, 0, 0)?;
         let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
             .stages(&shader_stages)
             .vertex_input_state(&vertex_input_info)
             .input_assembly_state(&input_assembly_info)
             .viewport_state(&viewport_info)
+            .dynamic_state(&dynamic_state_info)
             .rasterization_state(&rasterizer_info)
             .multisample_state(&multisampler_info)
             .depth_stencil_state(&depth_stencil_info)
@@ -475,17 +1058,9 @@ impl Pipeline {
             .subpass(0);
         let graphicspipeline = unsafe {
             logical_device
-                .create_graphics_pipelines(
-                    vk::PipelineCache::null(),
-                    &[pipeline_info.build()],
-                    None,
-                )
+                .create_graphics_pipelines(pipeline_cache, &[pipeline_info.build()], None)
                 .expect("A problem with the pipeline creation")
         }[0];
-        unsafe {
-            logical_device.destroy_shader_module(fragmentshader_module, None);
-            logical_device.destroy_shader_module(vertexshader_module, None);
-        }
         Ok(Pipeline {
             pipeline: graphicspipeline,
             layout: pipelinelayout,
@@ -0,0 +1,367 @@
+use crate::{
+    camera::{Camera, CameraHandle},
+    clock::Interpolate,
+    light::LightManager,
+    model::Model,
+    picking::InstanceTransform,
+};
+use eyre::*;
+use nalgebra as na;
+
+/// Node of a [`SceneGraph`]. Handles stay valid until their node is removed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NodeHandle(pub(crate) usize);
+
+/// Placement relative to the parent node: scaled, then rotated, then
+/// translated.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: na::Vector3<f32>,
+    pub rotation: na::UnitQuaternion<f32>,
+    pub scale: na::Vector3<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform {
+            translation: na::Vector3::zeros(),
+            rotation: na::UnitQuaternion::identity(),
+            scale: na::Vector3::repeat(1.0),
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl Transform {
+    pub fn from_translation(translation: na::Vector3<f32>) -> Transform {
+        Transform {
+            translation,
+            ..Default::default()
+        }
+    }
+
+    pub fn from_scale(scale: f32) -> Transform {
+        Transform {
+            scale: na::Vector3::repeat(scale),
+            ..Default::default()
+        }
+    }
+
+    /// Splits `matrix` into translation, rotation and scale, assuming it has
+    /// no shear. A mirroring matrix gets a negative x scale.
+    pub fn from_matrix(matrix: &na::Matrix4<f32>) -> Transform {
+        let linear = matrix.fixed_slice::<na::U3, na::U3>(0, 0).into_owned();
+        let mut scale = na::Vector3::new(
+            linear.column(0).norm(),
+            linear.column(1).norm(),
+            linear.column(2).norm(),
+        );
+        if linear.determinant() < 0.0 {
+            scale.x = -scale.x;
+        }
+        let rotation = if scale.iter().all(|s| s.abs() > 1e-12) {
+            let columns = linear * na::Matrix3::from_diagonal(&scale.map(|s| 1.0 / s));
+            na::UnitQuaternion::from_rotation_matrix(&na::Rotation3::from_matrix_unchecked(columns))
+        } else {
+            na::UnitQuaternion::identity()
+        };
+        Transform {
+            translation: matrix.fixed_slice::<na::U3, na::U1>(0, 3).into_owned(),
+            rotation,
+            scale,
+        }
+    }
+
+    pub fn matrix(&self) -> na::Matrix4<f32> {
+        na::Matrix4::new_translation(&self.translation)
+            * self.rotation.to_homogeneous()
+            * na::Matrix4::new_nonuniform_scaling(&self.scale)
+    }
+}
+
+impl Interpolate for Transform {
+    fn interpolate(&self, other: &Transform, t: f32) -> Transform {
+        Transform {
+            translation: self.translation.interpolate(&other.translation, t),
+            rotation: self.rotation.interpolate(&other.rotation, t),
+            scale: self.scale.interpolate(&other.scale, t),
+        }
+    }
+}
+
+/// What a node places. Lights and cameras take the node's origin as their
+/// position and its z axis as the direction they point in, a camera's down
+/// direction is its y axis.
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Attachment {
+    /// Instance `handle` of `Aetna::models[model]`, whose model matrix
+    /// becomes the node's world matrix.
+    Instance {
+        model: usize,
+        handle: usize,
+    },
+    /// Index of a point light in the `LightManager`.
+    PointLight(usize),
+    /// Index of a directional light in the `LightManager`.
+    DirectionalLight(usize),
+    Camera(CameraHandle),
+}
+
+struct Node {
+    name: String,
+    local: Transform,
+    parent: Option<NodeHandle>,
+    children: Vec<NodeHandle>,
+    attachment: Option<Attachment>,
+    world: na::Matrix4<f32>,
+    /// Whether the world matrix is out of date with `local`, or the
+    /// attachment has not been placed yet.
+    dirty: bool,
+}
+
+/// Tree of nodes with transforms relative to their parents, which place model
+/// instances, lights and cameras. Changes are collected until `update`, which
+/// recomputes the world matrices of the changed nodes and their subtrees and
+/// writes them to the attachments.
+///
+/// Methods taking a [`NodeHandle`] panic if its node was removed.
+#[derive(Default)]
+pub struct SceneGraph {
+    nodes: Vec<Option<Node>>,
+    roots: Vec<NodeHandle>,
+}
+
+#[allow(dead_code)]
+impl SceneGraph {
+    pub fn new() -> SceneGraph {
+        SceneGraph::default()
+    }
+
+    fn node(&self, handle: NodeHandle) -> &Node {
+        self.nodes[handle.0].as_ref().expect("Node was removed")
+    }
+
+    fn node_mut(&mut self, handle: NodeHandle) -> &mut Node {
+        self.nodes[handle.0].as_mut().expect("Node was removed")
+    }
+
+    /// Adds a node under `parent`, or as a root without one.
+    pub fn add(&mut self, name: &str, local: Transform, parent: Option<NodeHandle>) -> NodeHandle {
+        let handle = NodeHandle(self.nodes.len());
+        self.nodes.push(Some(Node {
+            name: name.to_string(),
+            local,
+            parent,
+            children: vec![],
+            attachment: None,
+            world: na::Matrix4::identity(),
+            dirty: true,
+        }));
+        match parent {
+            Some(parent) => self.node_mut(parent).children.push(handle),
+            None => self.roots.push(handle),
+        }
+        handle
+    }
+
+    /// Like `add`, placing `attachment`.
+    pub fn add_attached(
+        &mut self,
+        name: &str,
+        local: Transform,
+        parent: Option<NodeHandle>,
+        attachment: Attachment,
+    ) -> NodeHandle {
+        let handle = self.add(name, local, parent);
+        self.attach(handle, Some(attachment));
+        handle
+    }
+
+    /// Removes `handle` and everything below it. Their attachments stay where
+    /// they were last placed.
+    pub fn remove(&mut self, handle: NodeHandle) {
+        self.unlink(handle);
+        let mut stack = vec![handle];
+        while let Some(handle) = stack.pop() {
+            if let Some(node) = self.nodes[handle.0].take() {
+                stack.extend(node.children);
+            }
+        }
+    }
+
+    fn unlink(&mut self, handle: NodeHandle) {
+        let siblings = match self.node(handle).parent {
+            Some(parent) => &mut self.node_mut(parent).children,
+            None => &mut self.roots,
+        };
+        siblings.retain(|&sibling| sibling != handle);
+    }
+
+    /// Moves `handle` with its subtree under `parent`, keeping the local
+    /// transform. Fails if `parent` is in the subtree.
+    pub fn set_parent(&mut self, handle: NodeHandle, parent: Option<NodeHandle>) -> Result<()> {
+        let mut ancestor = parent;
+        while let Some(node) = ancestor {
+            if node == handle {
+                bail!("{} cannot be moved below itself", self.node(handle).name);
+            }
+            ancestor = self.node(node).parent;
+        }
+        self.unlink(handle);
+        match parent {
+            Some(parent) => self.node_mut(parent).children.push(handle),
+            None => self.roots.push(handle),
+        }
+        let node = self.node_mut(handle);
+        node.parent = parent;
+        node.dirty = true;
+        Ok(())
+    }
+
+    pub fn parent(&self, handle: NodeHandle) -> Option<NodeHandle> {
+        self.node(handle).parent
+    }
+
+    pub fn children(&self, handle: NodeHandle) -> &[NodeHandle] {
+        &self.node(handle).children
+    }
+
+    pub fn roots(&self) -> &[NodeHandle] {
+        &self.roots
+    }
+
+    /// Every node, parents before their children.
+    pub fn nodes(&self) -> Vec<NodeHandle> {
+        let mut nodes = vec![];
+        let mut stack: Vec<NodeHandle> = self.roots.iter().rev().copied().collect();
+        while let Some(handle) = stack.pop() {
+            nodes.push(handle);
+            stack.extend(self.children(handle).iter().rev());
+        }
+        nodes
+    }
+
+    /// First node called `name`.
+    pub fn find(&self, name: &str) -> Option<NodeHandle> {
+        self.nodes
+            .iter()
+            .position(|node| node.as_ref().is_some_and(|node| node.name == name))
+            .map(NodeHandle)
+    }
+
+    pub fn name(&self, handle: NodeHandle) -> &str {
+        &self.node(handle).name
+    }
+
+    pub fn local(&self, handle: NodeHandle) -> &Transform {
+        &self.node(handle).local
+    }
+
+    pub fn set_local(&mut self, handle: NodeHandle, local: Transform) {
+        let node = self.node_mut(handle);
+        node.local = local;
+        node.dirty = true;
+    }
+
+    pub fn attachment(&self, handle: NodeHandle) -> Option<Attachment> {
+        self.node(handle).attachment
+    }
+
+    /// Replaces what `handle` places. A detached attachment stays where it
+    /// was last placed.
+    pub fn attach(&mut self, handle: NodeHandle, attachment: Option<Attachment>) {
+        let node = self.node_mut(handle);
+        node.attachment = attachment;
+        node.dirty = true;
+    }
+
+    /// Transform from the node's space to world space as of the last
+    /// `update`.
+    pub fn world_matrix(&self, handle: NodeHandle) -> na::Matrix4<f32> {
+        self.node(handle).world
+    }
+
+    /// Recomputes the world matrices of nodes whose transform or parent
+    /// changed, along with their subtrees, and places their attachments.
+    /// Returns whether a light was placed, after which the light buffer needs
+    /// `LightManager::refill_buffer`.
+    pub fn update<V, I: InstanceTransform>(
+        &mut self,
+        models: &mut [Model<V, I>],
+        cameras: &mut [Camera],
+        lights: &mut LightManager,
+    ) -> Result<bool> {
+        let mut lights_changed = false;
+        let mut stack: Vec<(NodeHandle, na::Matrix4<f32>, bool)> = self
+            .roots
+            .iter()
+            .map(|&root| (root, na::Matrix4::identity(), false))
+            .collect();
+        while let Some((handle, parent_world, parent_changed)) = stack.pop() {
+            let node = self.node_mut(handle);
+            let changed = node.dirty || parent_changed;
+            if changed {
+                node.world = parent_world * node.local.matrix();
+                node.dirty = false;
+                if let Some(attachment) = node.attachment {
+                    lights_changed |= place(attachment, &node.world, models, cameras, lights)
+                        .with_context(|| format!("Failed to place {}", node.name))?;
+                }
+            }
+            let world = node.world;
+            stack.extend(node.children.iter().map(|&child| (child, world, changed)));
+        }
+        Ok(lights_changed)
+    }
+}
+
+/// Moves `attachment` to `world`, returning whether it is a light.
+fn place<V, I: InstanceTransform>(
+    attachment: Attachment,
+    world: &na::Matrix4<f32>,
+    models: &mut [Model<V, I>],
+    cameras: &mut [Camera],
+    lights: &mut LightManager,
+) -> Result<bool> {
+    let origin = world.transform_point(&na::Point3::origin());
+    let axis = |axis: na::Vector3<f32>| {
+        na::Unit::try_new(world.transform_vector(&axis), 1e-6)
+            .with_context(|| format!("Degenerate transform {}", world))
+    };
+    match attachment {
+        Attachment::Instance { model, handle } => {
+            models
+                .get_mut(model)
+                .and_then(|model| model.get_mut(handle))
+                .with_context(|| format!("No instance {} of model {}", handle, model))?
+                .set_modelmatrix(*world);
+            Ok(false)
+        }
+        Attachment::PointLight(index) => {
+            lights
+                .point_light_mut(index)
+                .with_context(|| format!("No point light {}", index))?
+                .position = origin;
+            Ok(true)
+        }
+        Attachment::DirectionalLight(index) => {
+            lights
+                .directional_light_mut(index)
+                .with_context(|| format!("No directional light {}", index))?
+                .direction = axis(na::Vector3::z())?.into_inner();
+            Ok(true)
+        }
+        Attachment::Camera(camera) => {
+            cameras
+                .get_mut(camera.0)
+                .with_context(|| format!("No camera {}", camera.0))?
+                .set_pose(
+                    origin.coords,
+                    axis(na::Vector3::z())?,
+                    axis(na::Vector3::y())?,
+                );
+            Ok(false)
+        }
+    }
+}
@@ -0,0 +1,890 @@
+use crate::{
+    aetna::Aetna,
+    camera::{Camera, CameraHandle, Projection},
+    light::{DirectionalLight, LightManager, PointLight},
+    lod::LodHandle,
+    model::{InstanceData, Model, VertexData},
+    picking::InstanceTransform,
+    scene::Transform,
+    skybox::Background,
+};
+use eyre::*;
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::Path, path::PathBuf};
+
+/// Scene used when there is no scene file, see `scene.toml` for the format.
+pub const DEFAULT_SCENE: &str = include_str!("../scene.toml");
+
+/// Adding zero turns -0 into 0, which would be written as such.
+fn triple(vector: &na::Vector3<f32>) -> [f32; 3] {
+    [vector.x + 0.0, vector.y + 0.0, vector.z + 0.0]
+}
+
+/// `radians` in degrees, rounded so that conversion noise is not written.
+fn degrees(radians: f32) -> f32 {
+    (radians.to_degrees() * 1e4).round() / 1e4 + 0.0
+}
+
+/// Where the vertices of a mesh come from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MeshSource {
+    /// Icosahedron refined this many times and projected onto the unit
+    /// sphere.
+    Sphere(u32),
+    Icosahedron,
+    /// Wavefront OBJ file, see `Model::load_obj`.
+    Obj(PathBuf),
+}
+
+impl MeshSource {
+    fn model(&self) -> Result<Model<VertexData, InstanceData>> {
+        match self {
+            MeshSource::Sphere(refinements) => {
+                Ok(Model::<VertexData, InstanceData>::sphere(*refinements))
+            }
+            MeshSource::Icosahedron => Ok(Model::<VertexData, InstanceData>::icosahedron()),
+            MeshSource::Obj(path) => Model::load_obj(path),
+        }
+    }
+}
+
+/// Model of `mesh` with its vertex and index buffers uploaded.
+fn upload(
+    mesh: &MeshDescription,
+    allocator: &vk_mem::Allocator,
+) -> Result<Model<VertexData, InstanceData>> {
+    let mut model = mesh
+        .source
+        .model()
+        .with_context(|| format!("Failed to build mesh {}", mesh.name))?;
+    model.update_vertexbuffer(allocator)?;
+    model.update_indexbuffer(allocator)?;
+    Ok(model)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeshDescription {
+    pub name: String,
+    pub source: MeshSource,
+}
+
+/// Meshes drawn as the levels of detail of one object, see `LodModel`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LodDescription {
+    pub name: String,
+    /// Names of meshes, most detailed first.
+    pub levels: Vec<String>,
+    #[serde(default)]
+    pub thresholds: Vec<f32>,
+    #[serde(default)]
+    pub hysteresis: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InstanceDescription {
+    /// Optional, for finding the instance after loading.
+    pub name: Option<String>,
+    /// Name of a mesh or level of detail object.
+    pub mesh: String,
+    pub transform: Transform,
+    pub colour: [f32; 3],
+    pub metallic: f32,
+    pub roughness: f32,
+}
+
+/// Light of a scene file.
+#[derive(Clone, Debug)]
+pub struct LightDescription<L> {
+    /// Optional, for finding the light after loading.
+    pub name: Option<String>,
+    pub light: L,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CameraDescription {
+    pub position: na::Vector3<f32>,
+    pub view_direction: na::Vector3<f32>,
+    pub down_direction: na::Vector3<f32>,
+    pub projection: Projection,
+}
+
+impl CameraDescription {
+    fn of(camera: &Camera) -> CameraDescription {
+        CameraDescription {
+            position: camera.position(),
+            view_direction: camera.view_direction().into_inner(),
+            down_direction: camera.down_direction().into_inner(),
+            projection: camera.projection(),
+        }
+    }
+
+    fn camera(&self) -> Camera {
+        Camera::builder()
+            .position(self.position)
+            .view_direction(self.view_direction)
+            .down_direction(self.down_direction)
+            .projection(self.projection)
+            .build()
+    }
+}
+
+/// Contents of a scene file: meshes, the instances drawn with them, lights,
+/// the camera and the background.
+///
+/// The file is TOML with the tables `[background]` and `[camera]` and the
+/// arrays of tables `[[mesh]]`, `[[lod]]`, `[[instance]]`, `[[point_light]]`
+/// and `[[directional_light]]`. Angles are in degrees, point lights give
+/// their luminous flux in lm and directional lights their illuminance in lx.
+/// OBJ files are found relative to the scene file. See `scene.toml` for an
+/// example of every section.
+#[derive(Clone, Debug, Default)]
+pub struct SceneFile {
+    /// Left as it is if `None`.
+    pub background: Option<Background>,
+    /// The default camera if `None`.
+    pub camera: Option<CameraDescription>,
+    pub meshes: Vec<MeshDescription>,
+    pub lods: Vec<LodDescription>,
+    pub instances: Vec<InstanceDescription>,
+    pub point_lights: Vec<LightDescription<PointLight>>,
+    pub directional_lights: Vec<LightDescription<DirectionalLight>>,
+}
+
+/// `[background]` of a scene file, told apart by its `type` key.
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum BackgroundSection {
+    Colour {
+        colour: [f32; 3],
+    },
+    Gradient {
+        zenith: [f32; 3],
+        horizon: [f32; 3],
+        ground: [f32; 3],
+    },
+    Sky {
+        direction_to_sun: [f32; 3],
+        sun_intensity: f32,
+    },
+    Equirectangular {
+        path: PathBuf,
+    },
+    Cubemap {
+        faces: [PathBuf; 6],
+    },
+}
+
+impl BackgroundSection {
+    fn of(background: &Background) -> BackgroundSection {
+        match background.clone() {
+            Background::Colour(colour) => BackgroundSection::Colour { colour },
+            Background::Gradient {
+                zenith,
+                horizon,
+                ground,
+            } => BackgroundSection::Gradient {
+                zenith,
+                horizon,
+                ground,
+            },
+            Background::Sky {
+                direction_to_sun,
+                sun_intensity,
+            } => BackgroundSection::Sky {
+                direction_to_sun: triple(&direction_to_sun),
+                sun_intensity,
+            },
+            Background::Equirectangular(path) => BackgroundSection::Equirectangular { path },
+            Background::Cubemap(faces) => BackgroundSection::Cubemap { faces },
+        }
+    }
+
+    fn background(self) -> Background {
+        match self {
+            BackgroundSection::Colour { colour } => Background::Colour(colour),
+            BackgroundSection::Gradient {
+                zenith,
+                horizon,
+                ground,
+            } => Background::Gradient {
+                zenith,
+                horizon,
+                ground,
+            },
+            BackgroundSection::Sky {
+                direction_to_sun,
+                sun_intensity,
+            } => Background::Sky {
+                direction_to_sun: direction_to_sun.into(),
+                sun_intensity,
+            },
+            BackgroundSection::Equirectangular { path } => Background::Equirectangular(path),
+            BackgroundSection::Cubemap { faces } => Background::Cubemap(faces),
+        }
+    }
+}
+
+/// `[camera]` of a scene file. Missing keys take the values of the default
+/// camera, or those of a perspective projection with a 60° field of view.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct CameraSection {
+    position: Option<[f32; 3]>,
+    view_direction: Option<[f32; 3]>,
+    down_direction: Option<[f32; 3]>,
+    projection: Option<String>,
+    fovy: Option<f32>,
+    near: Option<f32>,
+    far: Option<f32>,
+    height: Option<f32>,
+}
+
+impl CameraSection {
+    fn of(camera: &CameraDescription) -> CameraSection {
+        let (projection, fovy, near, far, height) = match camera.projection {
+            Projection::Perspective { fovy, near, far } => {
+                ("perspective", Some(fovy), near, Some(far), None)
+            }
+            Projection::InfinitePerspective { fovy, near } => {
+                ("infinite_perspective", Some(fovy), near, None, None)
+            }
+            Projection::ReverseZ { fovy, near, far } => ("reverse_z", Some(fovy), near, far, None),
+            Projection::Orthographic { height, near, far } => {
+                ("orthographic", None, near, Some(far), Some(height))
+            }
+        };
+        CameraSection {
+            position: Some(triple(&camera.position)),
+            view_direction: Some(triple(&camera.view_direction)),
+            down_direction: Some(triple(&camera.down_direction)),
+            projection: Some(projection.to_string()),
+            fovy: fovy.map(degrees),
+            near: Some(near),
+            far,
+            height,
+        }
+    }
+
+    fn description(self) -> Result<CameraDescription> {
+        let kind = self.projection.as_deref().unwrap_or("perspective");
+        let fovy = self
+            .fovy
+            .map_or(std::f32::consts::FRAC_PI_3, f32::to_radians);
+        let near = self.near.unwrap_or(0.1);
+        let projection = match kind {
+            "perspective" => Projection::Perspective {
+                fovy,
+                near,
+                far: self.far.unwrap_or(100.0),
+            },
+            "infinite_perspective" => Projection::InfinitePerspective { fovy, near },
+            "reverse_z" => Projection::ReverseZ {
+                fovy,
+                near,
+                far: self.far,
+            },
+            "orthographic" => Projection::Orthographic {
+                height: self
+                    .height
+                    .context("An orthographic camera needs a height")?,
+                near: self.near.unwrap_or(0.0),
+                far: self.far.unwrap_or(100.0),
+            },
+            _ => bail!("Unknown projection {}", kind),
+        };
+        let default = CameraDescription::of(&Camera::builder().build());
+        Ok(CameraDescription {
+            position: self.position.map_or(default.position, Into::into),
+            view_direction: self
+                .view_direction
+                .map_or(default.view_direction, Into::into),
+            down_direction: self
+                .down_direction
+                .map_or(default.down_direction, Into::into),
+            projection,
+        })
+    }
+}
+
+/// `[[mesh]]` of a scene file, with either a `shape` or a `file`.
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct MeshSection {
+    name: String,
+    shape: Option<String>,
+    refinements: Option<u32>,
+    file: Option<PathBuf>,
+}
+
+impl MeshSection {
+    fn of(mesh: &MeshDescription) -> MeshSection {
+        let (shape, refinements, file) = match &mesh.source {
+            MeshSource::Sphere(refinements) => (Some("sphere"), Some(*refinements), None),
+            MeshSource::Icosahedron => (Some("icosahedron"), None, None),
+            MeshSource::Obj(path) => (None, None, Some(path.clone())),
+        };
+        MeshSection {
+            name: mesh.name.clone(),
+            shape: shape.map(str::to_string),
+            refinements,
+            file,
+        }
+    }
+
+    fn description(self) -> Result<MeshDescription> {
+        let source = match (self.shape.as_deref(), self.file) {
+            (Some("sphere"), None) => MeshSource::Sphere(self.refinements.unwrap_or(0)),
+            (Some("icosahedron"), None) => MeshSource::Icosahedron,
+            (Some(shape), None) => bail!("Mesh {} has unknown shape {}", self.name, shape),
+            (None, Some(file)) => MeshSource::Obj(file),
+            _ => bail!("Mesh {} needs either a shape or a file", self.name),
+        };
+        Ok(MeshDescription {
+            name: self.name,
+            source,
+        })
+    }
+}
+
+/// Scale of an instance, the same along every axis or one per axis.
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum Scale {
+    Uniform(f32),
+    PerAxis([f32; 3]),
+}
+
+/// Row of copies of an instance: `count` of them, each moved by
+/// `translation` in world space and made more metallic and rough by the
+/// given steps than the one before.
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct Repeat {
+    count: u32,
+    #[serde(default)]
+    translation: [f32; 3],
+    #[serde(default)]
+    metallic: f32,
+    #[serde(default)]
+    roughness: f32,
+}
+
+/// `[[instance]]` of a scene file, with its rotation as Euler angles. With
+/// `repeat`, it stands for the copies along every row in turn, so that two
+/// rows give a grid. Written scenes list the copies one by one.
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct InstanceSection {
+    name: Option<String>,
+    mesh: String,
+    translation: Option<[f32; 3]>,
+    rotation: Option<[f32; 3]>,
+    scale: Option<Scale>,
+    colour: Option<[f32; 3]>,
+    metallic: Option<f32>,
+    roughness: Option<f32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    repeat: Vec<Repeat>,
+}
+
+impl InstanceSection {
+    fn of(instance: &InstanceDescription) -> InstanceSection {
+        let transform = &instance.transform;
+        let (x, y, z) = transform.rotation.euler_angles();
+        let scale = transform.scale;
+        let scale = if scale.x == scale.y && scale.x == scale.z {
+            Scale::Uniform(scale.x)
+        } else {
+            Scale::PerAxis(triple(&scale))
+        };
+        InstanceSection {
+            name: instance.name.clone(),
+            mesh: instance.mesh.clone(),
+            translation: Some(triple(&transform.translation)),
+            rotation: Some([degrees(x), degrees(y), degrees(z)]),
+            scale: Some(scale),
+            colour: Some(instance.colour),
+            metallic: Some(instance.metallic),
+            roughness: Some(instance.roughness),
+            repeat: vec![],
+        }
+    }
+
+    fn descriptions(mut self) -> Result<Vec<InstanceDescription>> {
+        if self.name.is_some() && !self.repeat.is_empty() {
+            bail!("Repeated instances of {} cannot have a name", self.mesh);
+        }
+        let mut instances = vec![];
+        let repeat = std::mem::take(&mut self.repeat);
+        instances.push(self.description());
+        for row in &repeat {
+            instances = instances
+                .iter()
+                .flat_map(|instance| {
+                    (0..row.count).map(move |index| {
+                        let index = index as f32;
+                        let mut copy = instance.clone();
+                        copy.transform.translation += na::Vector3::from(row.translation) * index;
+                        copy.metallic += row.metallic * index;
+                        copy.roughness += row.roughness * index;
+                        copy
+                    })
+                })
+                .collect();
+        }
+        Ok(instances)
+    }
+
+    fn description(self) -> InstanceDescription {
+        let rotation = self.rotation.unwrap_or([0.0; 3]);
+        let scale = match self.scale {
+            Some(Scale::Uniform(scale)) => na::Vector3::repeat(scale),
+            Some(Scale::PerAxis(scale)) => scale.into(),
+            None => na::Vector3::repeat(1.0),
+        };
+        InstanceDescription {
+            name: self.name,
+            mesh: self.mesh,
+            transform: Transform {
+                translation: self.translation.map_or_else(na::Vector3::zeros, Into::into),
+                rotation: na::UnitQuaternion::from_euler_angles(
+                    rotation[0].to_radians(),
+                    rotation[1].to_radians(),
+                    rotation[2].to_radians(),
+                ),
+                scale,
+            },
+            colour: self.colour.unwrap_or([1.0; 3]),
+            metallic: self.metallic.unwrap_or(1.0),
+            roughness: self.roughness.unwrap_or(0.5),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct PointLightSection {
+    name: Option<String>,
+    position: [f32; 3],
+    luminous_flux: [f32; 3],
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct DirectionalLightSection {
+    name: Option<String>,
+    direction: [f32; 3],
+    illuminance: [f32; 3],
+}
+
+/// Everything in a scene file, as it is written.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct Sections {
+    background: Option<BackgroundSection>,
+    camera: Option<CameraSection>,
+    #[serde(default, rename = "mesh", skip_serializing_if = "Vec::is_empty")]
+    meshes: Vec<MeshSection>,
+    #[serde(default, rename = "lod", skip_serializing_if = "Vec::is_empty")]
+    lods: Vec<LodDescription>,
+    #[serde(default, rename = "instance", skip_serializing_if = "Vec::is_empty")]
+    instances: Vec<InstanceSection>,
+    #[serde(default, rename = "point_light", skip_serializing_if = "Vec::is_empty")]
+    point_lights: Vec<PointLightSection>,
+    #[serde(
+        default,
+        rename = "directional_light",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    directional_lights: Vec<DirectionalLightSection>,
+}
+
+#[allow(dead_code)]
+impl SceneFile {
+    pub fn parse(text: &str) -> Result<SceneFile> {
+        let sections: Sections = toml::from_str(text)?;
+        let mut instances = vec![];
+        for section in sections.instances {
+            instances.extend(section.descriptions()?);
+        }
+        let scene = SceneFile {
+            background: sections.background.map(BackgroundSection::background),
+            camera: sections
+                .camera
+                .map(CameraSection::description)
+                .transpose()?,
+            meshes: sections
+                .meshes
+                .into_iter()
+                .map(MeshSection::description)
+                .collect::<Result<_>>()?,
+            lods: sections.lods,
+            instances,
+            point_lights: sections
+                .point_lights
+                .into_iter()
+                .map(|light| LightDescription {
+                    name: light.name,
+                    light: PointLight {
+                        position: light.position.into(),
+                        luminous_flux: light.luminous_flux,
+                    },
+                })
+                .collect(),
+            directional_lights: sections
+                .directional_lights
+                .into_iter()
+                .map(|light| LightDescription {
+                    name: light.name,
+                    light: DirectionalLight {
+                        direction: light.direction.into(),
+                        illuminance: light.illuminance,
+                    },
+                })
+                .collect(),
+        };
+        scene.check()?;
+        Ok(scene)
+    }
+
+    /// Fails on names that are given twice or refer to nothing.
+    fn check(&self) -> Result<()> {
+        let mut names: Vec<&str> = vec![];
+        for name in self
+            .meshes
+            .iter()
+            .map(|mesh| &mesh.name)
+            .chain(self.lods.iter().map(|lod| &lod.name))
+        {
+            if names.contains(&name.as_str()) {
+                bail!("Two meshes or levels of detail are called {}", name);
+            }
+            names.push(name);
+        }
+        for lod in &self.lods {
+            if let Some(level) = lod.levels.iter().find(|level| self.mesh(level).is_none()) {
+                bail!("Level of detail {} uses unknown mesh {}", lod.name, level);
+            }
+        }
+        if let Some(instance) = self
+            .instances
+            .iter()
+            .find(|instance| !names.contains(&instance.mesh.as_str()))
+        {
+            bail!("Instance of unknown mesh {}", instance.mesh);
+        }
+        Ok(())
+    }
+
+    fn mesh(&self, name: &str) -> Option<&MeshDescription> {
+        self.meshes.iter().find(|mesh| mesh.name == name)
+    }
+
+    pub fn to_text(&self) -> String {
+        let sections = Sections {
+            background: self.background.as_ref().map(BackgroundSection::of),
+            camera: self.camera.as_ref().map(CameraSection::of),
+            meshes: self.meshes.iter().map(MeshSection::of).collect(),
+            lods: self.lods.clone(),
+            instances: self.instances.iter().map(InstanceSection::of).collect(),
+            point_lights: self
+                .point_lights
+                .iter()
+                .map(|description| PointLightSection {
+                    name: description.name.clone(),
+                    position: triple(&description.light.position.coords),
+                    luminous_flux: description.light.luminous_flux,
+                })
+                .collect(),
+            directional_lights: self
+                .directional_lights
+                .iter()
+                .map(|description| DirectionalLightSection {
+                    name: description.name.clone(),
+                    direction: triple(&description.light.direction),
+                    illuminance: description.light.illuminance,
+                })
+                .collect(),
+        };
+        // Only tables of numbers, strings and arrays, which TOML can hold.
+        toml::to_string(&sections).expect("Failed to write the scene as TOML")
+    }
+
+    /// The scene at `path`, or the default one if there is no such file.
+    /// Relative OBJ paths in the file are taken from its directory.
+    pub fn load(path: impl AsRef<Path>) -> Result<SceneFile> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(SceneFile::default_scene());
+        }
+        let mut scene = SceneFile::parse(&std::fs::read_to_string(path)?)
+            .with_context(|| format!("Failed to load {}", path.display()))?;
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        for file in scene.obj_paths_mut() {
+            *file = directory.join(&file);
+        }
+        Ok(scene)
+    }
+
+    pub fn default_scene() -> SceneFile {
+        SceneFile::parse(DEFAULT_SCENE).expect("Default scene is valid")
+    }
+
+    /// Writes the scene to `path`, with OBJ paths relative to its directory
+    /// where they can be.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        let mut scene = self.clone();
+        for file in scene.obj_paths_mut() {
+            *file = match file.strip_prefix(directory) {
+                Ok(relative) => relative.to_path_buf(),
+                Err(_) if file.is_absolute() => continue,
+                Err(_) => std::env::current_dir()?.join(&file),
+            };
+        }
+        std::fs::write(path, scene.to_text())?;
+        Ok(())
+    }
+
+    fn obj_paths_mut(&mut self) -> impl Iterator<Item = &mut PathBuf> {
+        self.meshes
+            .iter_mut()
+            .filter_map(|mesh| match &mut mesh.source {
+                MeshSource::Obj(file) => Some(file),
+                _ => None,
+            })
+    }
+
+    /// Adds the meshes, instances, lights and camera to `aetna` and sets its
+    /// background. Meshes become models, where those that are only levels of
+    /// detail appear once per level of detail object using them.
+    pub fn build(&self, aetna: &mut Aetna<VertexData, InstanceData>) -> Result<Scene> {
+        self.check()?;
+        let mut models = HashMap::new();
+        for mesh in &self.meshes {
+            if self
+                .instances
+                .iter()
+                .any(|instance| instance.mesh == mesh.name)
+            {
+                let model = upload(mesh, &aetna.allocator)?;
+                models.insert(mesh.name.as_str(), aetna.models.len());
+                aetna.models.push(model);
+            }
+        }
+        let mut lods = vec![];
+        for lod in &self.lods {
+            let levels = lod
+                .levels
+                .iter()
+                .map(|level| upload(self.mesh(level).expect("Checked mesh"), &aetna.allocator))
+                .collect::<Result<Vec<_>>>()?;
+            let handle = aetna
+                .add_lod_model(levels, lod.thresholds.clone())
+                .with_context(|| format!("Failed to build level of detail {}", lod.name))?;
+            aetna.lod_model_mut(handle).hysteresis = lod.hysteresis;
+            lods.push((lod.name.clone(), handle));
+        }
+        let mut instances = vec![];
+        for description in &self.instances {
+            let mut instance = InstanceData::from_matrix_colour_metallic_and_roughness(
+                na::Matrix4::identity(),
+                description.colour,
+                description.metallic,
+                description.roughness,
+            );
+            instance.set_modelmatrix(description.transform.matrix());
+            instances.push(match models.get(description.mesh.as_str()) {
+                Some(&model) => SceneInstance::Model {
+                    model,
+                    handle: aetna.models[model].insert_visibly(instance),
+                },
+                None => {
+                    let lod = lods
+                        .iter()
+                        .find(|(name, _)| *name == description.mesh)
+                        .map(|&(_, handle)| handle)
+                        .expect("Checked mesh");
+                    SceneInstance::Lod {
+                        lod,
+                        handle: aetna.insert_lod_instance(lod, instance),
+                    }
+                }
+            });
+        }
+        let mut lights = LightManager::default();
+        for description in &self.directional_lights {
+            lights.add_light(description.light);
+        }
+        for description in &self.point_lights {
+            lights.add_light(description.light);
+        }
+        lights.update_buffer(
+            &aetna.device,
+            &aetna.allocator,
+            &mut aetna.lightbuffer,
+            &mut aetna.descriptor_sets_light,
+        )?;
+        let camera = aetna.register_camera(
+            self.camera
+                .map_or_else(|| Camera::builder().build(), |camera| camera.camera()),
+        );
+        if let Some(background) = &self.background {
+            aetna.set_background(background.clone())?;
+        }
+        Ok(Scene {
+            file: self.clone(),
+            instances,
+            lods,
+            camera,
+            lights,
+        })
+    }
+}
+
+/// Where `SceneFile::build` put an instance.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SceneInstance {
+    /// Instance `handle` of `Aetna::models[model]`.
+    Model { model: usize, handle: usize },
+    /// Instance `handle` of a level of detail model.
+    Lod { lod: LodHandle, handle: usize },
+}
+
+/// Scene built from a [`SceneFile`], which `capture` turns back into one.
+pub struct Scene {
+    file: SceneFile,
+    /// In the order of `file.instances`.
+    instances: Vec<SceneInstance>,
+    lods: Vec<(String, LodHandle)>,
+    pub camera: CameraHandle,
+    /// Lights of the file, whose buffer is filled.
+    pub lights: LightManager,
+}
+
+#[allow(dead_code)]
+impl Scene {
+    /// Instance given `name` in the file.
+    pub fn instance(&self, name: &str) -> Option<SceneInstance> {
+        self.file
+            .instances
+            .iter()
+            .position(|instance| instance.name.as_deref() == Some(name))
+            .map(|index| self.instances[index])
+    }
+
+    /// Index of the point light given `name` in the file, as counted by
+    /// `LightManager::point_lights` and `Attachment::PointLight`.
+    pub fn point_light(&self, name: &str) -> Option<usize> {
+        find_light(&self.file.point_lights, name)
+    }
+
+    /// Index of the directional light given `name` in the file, as counted by
+    /// `LightManager::directional_lights` and `Attachment::DirectionalLight`.
+    pub fn directional_light(&self, name: &str) -> Option<usize> {
+        find_light(&self.file.directional_lights, name)
+    }
+
+    /// Level of detail object called `name` in the file.
+    pub fn lod(&self, name: &str) -> Option<LodHandle> {
+        self.lods
+            .iter()
+            .find(|(lod, _)| lod == name)
+            .map(|&(_, handle)| handle)
+    }
+
+    /// The file as the scene is now: where the instances, lights and camera
+    /// went, what the instances look like and the current background. Removed
+    /// instances are left out, and instances added other than by the file are
+    /// not included.
+    pub fn capture(&self, aetna: &Aetna<VertexData, InstanceData>) -> SceneFile {
+        let instances = self
+            .file
+            .instances
+            .iter()
+            .zip(&self.instances)
+            .filter_map(|(description, &instance)| {
+                let data = match instance {
+                    SceneInstance::Model { model, handle } => aetna.models[model].get(handle),
+                    SceneInstance::Lod { lod, handle } => {
+                        aetna.lod_model(lod).get(&aetna.models, handle)
+                    }
+                }?;
+                Some(InstanceDescription {
+                    transform: Transform::from_matrix(&data.modelmatrix()),
+                    colour: data.colour,
+                    metallic: data.metallic,
+                    roughness: data.roughness,
+                    ..description.clone()
+                })
+            })
+            .collect();
+        SceneFile {
+            background: Some(aetna.background().clone()),
+            camera: Some(CameraDescription::of(aetna.camera(self.camera))),
+            instances,
+            point_lights: named(&self.file.point_lights, self.lights.point_lights()),
+            directional_lights: named(
+                &self.file.directional_lights,
+                self.lights.directional_lights(),
+            ),
+            ..self.file.clone()
+        }
+    }
+}
+
+fn find_light<L>(lights: &[LightDescription<L>], name: &str) -> Option<usize> {
+    lights
+        .iter()
+        .position(|light| light.name.as_deref() == Some(name))
+}
+
+/// `lights` as they are now, with the names of those that came from the file
+/// in `described`.
+fn named<L: Copy>(described: &[LightDescription<L>], lights: &[L]) -> Vec<LightDescription<L>> {
+    lights
+        .iter()
+        .enumerate()
+        .map(|(index, &light)| LightDescription {
+            name: described.get(index).and_then(|light| light.name.clone()),
+            light,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn written_scene_reads_back() {
+        let file = SceneFile::default_scene();
+        let text = file.to_text();
+        assert_eq!(SceneFile::parse(&text).unwrap().to_text(), text);
+    }
+
+    #[test]
+    fn repeat_makes_a_grid() {
+        let text = "[[mesh]]\nname = 'sphere'\nshape = 'sphere'\n\
+                    [[instance]]\nmesh = 'sphere'\nmetallic = 0\nroughness = 0\n\
+                    repeat = [{ count = 3, translation = [1, 0, 0], metallic = 0.5 }, \
+                    { count = 2, translation = [0, 2, 0], roughness = 1 }]\n";
+        let instances = SceneFile::parse(text).unwrap().instances;
+        assert_eq!(instances.len(), 6);
+        let last = &instances[5];
+        assert_eq!(last.transform.translation, na::Vector3::new(2.0, 2.0, 0.0));
+        assert_eq!((last.metallic, last.roughness), (1.0, 1.0));
+    }
+
+    #[test]
+    fn reads_toml_the_writer_does_not_produce() {
+        let text = "[[mesh]] # comment\nname = 'literal'\nshape = \"sphere\"\n\n\
+                    [[lod]]\nname = \"\"\"multi\nline\"\"\"\nlevels = [\n  'literal',\n]\n";
+        let file = SceneFile::parse(text).unwrap();
+        assert_eq!(file.lods[0].name, "multi\nline");
+        assert_eq!(file.lods[0].levels, ["literal"]);
+    }
+}
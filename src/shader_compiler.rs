@@ -0,0 +1,130 @@
+// Shared by build.rs and the runtime hot reload, so it only uses std, eyre and
+// shaderc.
+use eyre::*;
+use std::path::{Path, PathBuf};
+
+/// Lists the permutations, relative to the shader directory.
+pub const MANIFEST: &str = "permutations.manifest";
+
+/// Variant of a shader compiled with extra macros, besides its base variant.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Permutation {
+    /// Path relative to the shader directory, e.g. `shader.frag`.
+    pub shader: String,
+    pub name: String,
+    pub defines: Vec<(String, Option<String>)>,
+}
+
+/// Parses lines of `<shader> <permutation> [MACRO[=value] ...]`. `#` starts a
+/// comment.
+pub fn parse_manifest(text: &str) -> Result<Vec<Permutation>> {
+    let mut permutations: Vec<Permutation> = vec![];
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut words = line.split_whitespace();
+        let shader = match words.next() {
+            Some(shader) => shader.to_string(),
+            None => continue,
+        };
+        let name = words
+            .next()
+            .with_context(|| format!("{}:{}: permutation without a name", MANIFEST, number + 1))?
+            .to_string();
+        if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            bail!(
+                "{}:{}: permutation names may only use letters, digits and '_'",
+                MANIFEST,
+                number + 1
+            );
+        }
+        if permutations
+            .iter()
+            .any(|p| p.shader == shader && p.name == name)
+        {
+            bail!(
+                "{}:{}: {} has two permutations called {}",
+                MANIFEST,
+                number + 1,
+                shader,
+                name
+            );
+        }
+        let defines = words
+            .map(|define| match define.find('=') {
+                Some(i) => (define[..i].to_string(), Some(define[i + 1..].to_string())),
+                None => (define.to_string(), None),
+            })
+            .collect();
+        permutations.push(Permutation {
+            shader,
+            name,
+            defines,
+        });
+    }
+    Ok(permutations)
+}
+
+/// Permutations declared in `directory`, none if it has no manifest.
+pub fn read_manifest(directory: &Path) -> Result<Vec<Permutation>> {
+    let path = directory.join(MANIFEST);
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    parse_manifest(&std::fs::read_to_string(path)?)
+}
+
+pub fn shader_kind(path: &Path) -> Option<shaderc::ShaderKind> {
+    match path.extension()?.to_str()? {
+        "vert" => Some(shaderc::ShaderKind::Vertex),
+        "frag" => Some(shaderc::ShaderKind::Fragment),
+        "comp" => Some(shaderc::ShaderKind::Compute),
+        _ => None,
+    }
+}
+
+/// Compiles the shader at `path` with `defines`. `#include "file"` is looked up
+/// next to the including file first and then in `directory`, `#include <file>`
+/// only in `directory`.
+pub fn compile(
+    compiler: &mut shaderc::Compiler,
+    directory: &Path,
+    path: &Path,
+    defines: &[(String, Option<String>)],
+    optimise: bool,
+) -> Result<shaderc::CompilationArtifact> {
+    let kind = shader_kind(path).context("Unsupported shader")?;
+    let src = std::fs::read_to_string(path)?;
+    let mut options = shaderc::CompileOptions::new().context("Unable to create compile options")?;
+    let directory = directory.to_path_buf();
+    options.set_include_callback(move |requested, include_type, requesting, _depth| {
+        let mut candidates: Vec<PathBuf> = vec![];
+        if include_type == shaderc::IncludeType::Relative {
+            if let Some(parent) = Path::new(requesting).parent() {
+                candidates.push(parent.join(requested));
+            }
+        }
+        candidates.push(directory.join(requested));
+        let resolved = candidates
+            .into_iter()
+            .find(|candidate| candidate.is_file())
+            .ok_or_else(|| format!("Cannot find {} included from {}", requested, requesting))?;
+        let content = std::fs::read_to_string(&resolved)
+            .map_err(|e| format!("Cannot read {}: {}", resolved.display(), e))?;
+        Ok(shaderc::ResolvedInclude {
+            resolved_name: resolved.to_string_lossy().into_owned(),
+            content,
+        })
+    });
+    for (name, value) in defines {
+        options.add_macro_definition(name, value.as_deref());
+    }
+    if optimise {
+        options.set_optimization_level(shaderc::OptimizationLevel::Performance);
+    } else {
+        options.set_optimization_level(shaderc::OptimizationLevel::Zero);
+        options.set_generate_debug_info();
+    }
+    let artifact =
+        compiler.compile_into_spirv(&src, kind, &path.to_string_lossy(), "main", Some(&options))?;
+    Ok(artifact)
+}
@@ -0,0 +1,37 @@
+use std::borrow::Cow;
+
+/// SPIR-V compiled by build.rs. Shaders are named by their path relative to
+/// `shaders/`, permutations as in `shaders/permutations.manifest`, with `""`
+/// for the base variant.
+pub struct ShaderEntry {
+    pub shader: &'static str,
+    pub permutation: &'static str,
+    spirv: &'static [u8],
+}
+
+include!(concat!(env!("OUT_DIR"), "/shader_table.rs"));
+
+/// SPIR-V of `shader` compiled for `permutation`. Panics if build.rs did not
+/// compile that combination.
+pub fn spirv(shader: &str, permutation: &str) -> Cow<'static, [u32]> {
+    #[cfg(feature = "hot-reload")]
+    {
+        if let Some(spirv) = crate::hot_reload::reloaded(shader, permutation) {
+            return Cow::Owned(spirv);
+        }
+    }
+    let entry = SHADER_TABLE
+        .iter()
+        .find(|entry| entry.shader == shader && entry.permutation == permutation)
+        .unwrap_or_else(|| panic!("No shader {} with permutation {:?}", shader, permutation));
+    crate::utils::make_spirv(entry.spirv)
+}
+
+/// Names of the permutations of `shader`, the base variant first.
+#[allow(dead_code)]
+pub fn permutations(shader: &str) -> impl Iterator<Item = &'static str> + '_ {
+    SHADER_TABLE
+        .iter()
+        .filter(move |entry| entry.shader == shader)
+        .map(|entry| entry.permutation)
+}
@@ -0,0 +1,290 @@
+use crate::{
+    pool_and_commandbuffer::Pools,
+    renderpass_and_pipeline::{DepthConvention, Pipeline},
+    texture::Texture,
+};
+use ash::{version::DeviceV1_0, vk};
+use eyre::*;
+use nalgebra as na;
+use std::path::PathBuf;
+
+/// What is drawn behind the scene. World "up" is `-y`, like the camera's default
+/// down direction.
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub enum Background {
+    Colour([f32; 3]),
+    Gradient {
+        zenith: [f32; 3],
+        horizon: [f32; 3],
+        ground: [f32; 3],
+    },
+    Sky {
+        direction_to_sun: na::Vector3<f32>,
+        sun_intensity: f32,
+    },
+    Equirectangular(PathBuf),
+    /// Faces in the Vulkan order: +x, -x, +y, -y, +z, -z.
+    Cubemap([PathBuf; 6]),
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Colour([0.0, 0.0, 0.08])
+    }
+}
+
+impl Background {
+    pub fn clear_colour(&self) -> [f32; 4] {
+        match self {
+            Background::Colour([r, g, b]) => [*r, *g, *b, 1.0],
+            _ => [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+
+    fn parameters(&self) -> SkyboxParameters {
+        let mut parameters = SkyboxParameters::default();
+        match self {
+            Background::Colour(colour) => {
+                parameters.mode = 0;
+                parameters.colours[0] = [colour[0], colour[1], colour[2], 1.0];
+            }
+            Background::Gradient {
+                zenith,
+                horizon,
+                ground,
+            } => {
+                parameters.mode = 1;
+                parameters.colours[0] = [zenith[0], zenith[1], zenith[2], 1.0];
+                parameters.colours[1] = [horizon[0], horizon[1], horizon[2], 1.0];
+                parameters.colours[2] = [ground[0], ground[1], ground[2], 1.0];
+            }
+            Background::Sky {
+                direction_to_sun,
+                sun_intensity,
+            } => {
+                parameters.mode = 2;
+                let sun = direction_to_sun.normalize();
+                parameters.sun = [sun.x, sun.y, sun.z, *sun_intensity];
+            }
+            Background::Equirectangular(_) => {
+                parameters.mode = 3;
+            }
+            Background::Cubemap(_) => {
+                parameters.mode = 4;
+            }
+        }
+        parameters
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+pub struct SkyboxParameters {
+    colours: [[f32; 4]; 3],
+    sun: [f32; 4],
+    mode: u32,
+    far_depth: f32,
+    _padding: [u32; 2],
+}
+
+unsafe impl bytemuck::Zeroable for SkyboxParameters {}
+unsafe impl bytemuck::Pod for SkyboxParameters {}
+
+pub struct Skybox {
+    background: Background,
+    parameters: SkyboxParameters,
+    depth: DepthConvention,
+    pipeline: Pipeline,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    equirectangular: Texture,
+    cubemap: Texture,
+}
+
+impl Skybox {
+    pub fn init(
+        logical_device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
+        allocator: &vk_mem::Allocator,
+        pools: &Pools,
+        queue: vk::Queue,
+        renderpass: &vk::RenderPass,
+        depth: DepthConvention,
+    ) -> Result<Skybox> {
+        let pipeline = Pipeline::init_skybox(logical_device, pipeline_cache, renderpass, depth)?;
+        let equirectangular = Texture::placeholder(logical_device, allocator, pools, queue, false)?;
+        let cubemap = Texture::placeholder(logical_device, allocator, pools, queue, true)?;
+
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 2,
+        }];
+        let descriptor_pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(1)
+            .pool_sizes(&pool_sizes);
+        let descriptor_pool =
+            unsafe { logical_device.create_descriptor_pool(&descriptor_pool_info, None) }?;
+        let desc_layouts = [pipeline.descriptor_set_layouts[1]];
+        let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(&desc_layouts);
+        let descriptor_set =
+            unsafe { logical_device.allocate_descriptor_sets(&descriptor_set_allocate_info) }?[0];
+
+        let background = Background::default();
+        let skybox = Skybox {
+            parameters: background.parameters(),
+            depth,
+            background,
+            pipeline,
+            descriptor_pool,
+            descriptor_set,
+            equirectangular,
+            cubemap,
+        };
+        skybox.update_descriptor_set(logical_device);
+        Ok(skybox)
+    }
+
+    pub fn background(&self) -> &Background {
+        &self.background
+    }
+
+    /// Loads the textures the background needs. The device must be idle, since
+    /// the old textures may still be referenced by recorded command buffers.
+    pub fn set_background(
+        &mut self,
+        logical_device: &ash::Device,
+        allocator: &vk_mem::Allocator,
+        pools: &Pools,
+        queue: vk::Queue,
+        background: Background,
+    ) -> Result<()> {
+        match &background {
+            Background::Equirectangular(path) => {
+                let texture = Texture::from_file(logical_device, allocator, pools, queue, path)?;
+                self.equirectangular.cleanup(logical_device, allocator);
+                self.equirectangular = texture;
+            }
+            Background::Cubemap(paths) => {
+                let texture =
+                    Texture::cubemap_from_files(logical_device, allocator, pools, queue, paths)?;
+                self.cubemap.cleanup(logical_device, allocator);
+                self.cubemap = texture;
+            }
+            _ => {}
+        }
+        self.parameters = background.parameters();
+        self.background = background;
+        self.update_descriptor_set(logical_device);
+        Ok(())
+    }
+
+    fn update_descriptor_set(&self, logical_device: &ash::Device) {
+        let equirectangular_infos = [vk::DescriptorImageInfo {
+            sampler: self.equirectangular.sampler,
+            image_view: self.equirectangular.imageview,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }];
+        let cubemap_infos = [vk::DescriptorImageInfo {
+            sampler: self.cubemap.sampler,
+            image_view: self.cubemap.imageview,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }];
+        let desc_sets_write = [
+            vk::WriteDescriptorSet::builder()
+                .dst_set(self.descriptor_set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&equirectangular_infos)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(self.descriptor_set)
+                .dst_binding(1)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&cubemap_infos)
+                .build(),
+        ];
+        unsafe { logical_device.update_descriptor_sets(&desc_sets_write, &[]) };
+    }
+
+    /// Rebuilds the pipeline from the current shader code, keeping the old one
+    /// if that fails. The device must be idle.
+    pub fn recreate_pipeline(
+        &mut self,
+        logical_device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
+        renderpass: &vk::RenderPass,
+        depth: DepthConvention,
+    ) -> Result<()> {
+        let pipeline = Pipeline::init_skybox(logical_device, pipeline_cache, renderpass, depth)?;
+        self.pipeline.cleanup(logical_device);
+        self.pipeline = pipeline;
+        self.depth = depth;
+        // Sets must not be updated once their layout is gone, so reallocate the
+        // texture set from the new layout.
+        unsafe {
+            logical_device
+                .reset_descriptor_pool(self.descriptor_pool, vk::DescriptorPoolResetFlags::empty())
+        }?;
+        let desc_layouts = [self.pipeline.descriptor_set_layouts[1]];
+        let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.descriptor_pool)
+            .set_layouts(&desc_layouts);
+        self.descriptor_set =
+            unsafe { logical_device.allocate_descriptor_sets(&descriptor_set_allocate_info) }?[0];
+        self.update_descriptor_set(logical_device);
+        Ok(())
+    }
+
+    /// Records the background draw. Has to come after the scene so that the depth
+    /// test rejects every covered pixel.
+    pub fn draw(
+        &self,
+        logical_device: &ash::Device,
+        commandbuffer: vk::CommandBuffer,
+        descriptor_set_camera: vk::DescriptorSet,
+    ) {
+        if let Background::Colour(_) = self.background {
+            return;
+        }
+        unsafe {
+            logical_device.cmd_bind_pipeline(
+                commandbuffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.pipeline,
+            );
+            logical_device.cmd_bind_descriptor_sets(
+                commandbuffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.layout,
+                0,
+                &[descriptor_set_camera, self.descriptor_set],
+                &[],
+            );
+            // The vertex shader places the triangle at the far plane.
+            let parameters = SkyboxParameters {
+                far_depth: self.depth.far(),
+                ..self.parameters
+            };
+            logical_device.cmd_push_constants(
+                commandbuffer,
+                self.pipeline.layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                bytemuck::bytes_of(&parameters),
+            );
+            logical_device.cmd_draw(commandbuffer, 3, 1, 0, 0);
+        }
+    }
+
+    pub fn cleanup(&self, logical_device: &ash::Device, allocator: &vk_mem::Allocator) {
+        unsafe {
+            logical_device.destroy_descriptor_pool(self.descriptor_pool, None);
+        }
+        self.equirectangular.cleanup(logical_device, allocator);
+        self.cubemap.cleanup(logical_device, allocator);
+        self.pipeline.cleanup(logical_device);
+    }
+}
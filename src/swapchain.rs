@@ -1,18 +1,126 @@
-use crate::{instance_device_queues::QueueFamilies, surface::SurfaceDongXi};
+use crate::{
+    instance_device_queues::QueueFamilies,
+    render_target::{RenderTarget, DEPTH_FORMAT, HDR_FORMAT},
+    surface::SurfaceDongXi,
+};
 use ash::{version::DeviceV1_0, vk};
 use eyre::*;
 
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColourEncoding {
+    /// The presentation engine applies the sRGB transfer function.
+    Srgb,
+    /// The tonemapping pass encodes sRGB itself.
+    Unorm,
+}
+
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PresentPreference {
+    Vsync,
+    Mailbox,
+    Immediate,
+}
+
+/// What the application would like the swapchain to be. Every choice is
+/// negotiated against the surface; see the fields of [`SwapchainDongXi`] for what
+/// was actually picked.
+#[derive(Clone, Debug)]
+pub struct SwapchainConfig {
+    pub encoding: ColourEncoding,
+    pub present: PresentPreference,
+    pub image_count: u32,
+    /// Used when the surface lets the application choose the extent, which is
+    /// signalled by a `current_extent` of `u32::MAX`. Normally the window size.
+    pub fallback_extent: vk::Extent2D,
+}
+
+impl Default for SwapchainConfig {
+    fn default() -> Self {
+        SwapchainConfig {
+            encoding: ColourEncoding::Srgb,
+            present: PresentPreference::Vsync,
+            image_count: 3,
+            fallback_extent: vk::Extent2D {
+                width: 800,
+                height: 600,
+            },
+        }
+    }
+}
+
+impl SwapchainConfig {
+    fn choose_format(&self, available: &[vk::SurfaceFormatKHR]) -> Option<vk::SurfaceFormatKHR> {
+        let preferred: &[vk::Format] = match self.encoding {
+            ColourEncoding::Srgb => &[vk::Format::B8G8R8A8_SRGB, vk::Format::R8G8B8A8_SRGB],
+            ColourEncoding::Unorm => &[vk::Format::B8G8R8A8_UNORM, vk::Format::R8G8B8A8_UNORM],
+        };
+        preferred
+            .iter()
+            .find_map(|format| {
+                available.iter().copied().find(|surface_format| {
+                    surface_format.format == *format
+                        && surface_format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+                })
+            })
+            .or_else(|| available.first().copied())
+    }
+
+    fn choose_present_mode(&self, available: &[vk::PresentModeKHR]) -> vk::PresentModeKHR {
+        let preferred: &[vk::PresentModeKHR] = match self.present {
+            PresentPreference::Vsync => &[vk::PresentModeKHR::FIFO],
+            PresentPreference::Mailbox => &[vk::PresentModeKHR::MAILBOX],
+            PresentPreference::Immediate => {
+                &[vk::PresentModeKHR::IMMEDIATE, vk::PresentModeKHR::MAILBOX]
+            }
+        };
+        // FIFO is the only mode every implementation has to support.
+        preferred
+            .iter()
+            .copied()
+            .find(|mode| available.contains(mode))
+            .unwrap_or(vk::PresentModeKHR::FIFO)
+    }
+
+    fn choose_image_count(&self, capabilities: &vk::SurfaceCapabilitiesKHR) -> u32 {
+        let count = self.image_count.max(capabilities.min_image_count);
+        // A maximum of zero means there is no limit.
+        if capabilities.max_image_count > 0 {
+            count.min(capabilities.max_image_count)
+        } else {
+            count
+        }
+    }
+
+    fn choose_extent(&self, capabilities: &vk::SurfaceCapabilitiesKHR) -> vk::Extent2D {
+        if capabilities.current_extent.width != u32::MAX {
+            return capabilities.current_extent;
+        }
+        vk::Extent2D {
+            width: self.fallback_extent.width.clamp(
+                capabilities.min_image_extent.width,
+                capabilities.max_image_extent.width,
+            ),
+            height: self.fallback_extent.height.clamp(
+                capabilities.min_image_extent.height,
+                capabilities.max_image_extent.height,
+            ),
+        }
+    }
+}
+
 pub struct SwapchainDongXi {
     pub swapchain_loader: ash::extensions::khr::Swapchain,
     pub swapchain: vk::SwapchainKHR,
     pub images: Vec<vk::Image>,
     pub imageviews: Vec<vk::ImageView>,
-    pub depth_image: vk::Image,
-    depth_image_allocation: vk_mem::Allocation,
-    _depth_image_allocation_info: vk_mem::AllocationInfo,
-    pub depth_imageview: vk::ImageView,
+    pub hdr: RenderTarget,
+    pub depth: RenderTarget,
+    pub scene_framebuffer: vk::Framebuffer,
     pub framebuffers: Vec<vk::Framebuffer>,
     pub surface_format: vk::SurfaceFormatKHR,
+    pub present_mode: vk::PresentModeKHR,
     pub extent: vk::Extent2D,
     pub image_available: Vec<vk::Semaphore>,
     pub rendering_finished: Vec<vk::Semaphore>,
@@ -22,6 +130,9 @@ pub struct SwapchainDongXi {
 }
 
 impl SwapchainDongXi {
+    /// `old_swapchain` is retired in favour of the new one, but still has to be
+    /// destroyed by the caller.
+    #[allow(clippy::too_many_arguments)]
     pub fn init(
         instance: &ash::Instance,
         physical_device: vk::PhysicalDevice,
@@ -29,18 +140,20 @@ impl SwapchainDongXi {
         surfaces: &SurfaceDongXi,
         queue_families: &QueueFamilies,
         allocator: &vk_mem::Allocator,
+        config: &SwapchainConfig,
+        old_swapchain: vk::SwapchainKHR,
     ) -> Result<SwapchainDongXi> {
         let surface_capabilities = surfaces.get_capabilities(physical_device)?;
-        let extent = surface_capabilities.current_extent;
-        let _surface_present_modes = surfaces.get_present_modes(physical_device)?;
-        let surface_format = *surfaces.get_formats(physical_device)?.first().unwrap();
+        let extent = config.choose_extent(&surface_capabilities);
+        let surface_present_modes = surfaces.get_present_modes(physical_device)?;
+        let present_mode = config.choose_present_mode(&surface_present_modes);
+        let surface_format = config
+            .choose_format(&surfaces.get_formats(physical_device)?)
+            .context("Surface reports no formats")?;
         let queuefamilies = [queue_families.graphics_q_index.unwrap()];
         let swapchain_create_info = vk::SwapchainCreateInfoKHR::builder()
             .surface(surfaces.surface)
-            .min_image_count(
-                3.max(surface_capabilities.min_image_count)
-                    .min(surface_capabilities.max_image_count),
-            )
+            .min_image_count(config.choose_image_count(&surface_capabilities))
             .image_format(surface_format.format)
             .image_color_space(surface_format.color_space)
             .image_extent(extent)
@@ -50,7 +163,9 @@ impl SwapchainDongXi {
             .queue_family_indices(&queuefamilies)
             .pre_transform(surface_capabilities.current_transform)
             .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
-            .present_mode(vk::PresentModeKHR::FIFO);
+            .present_mode(present_mode)
+            .clipped(true)
+            .old_swapchain(old_swapchain);
         let swapchain_loader = ash::extensions::khr::Swapchain::new(instance, logical_device);
         let swapchain = unsafe { swapchain_loader.create_swapchain(&swapchain_create_info, None)? };
         let swapchain_images = unsafe { swapchain_loader.get_swapchain_images(swapchain)? };
@@ -66,47 +181,26 @@ impl SwapchainDongXi {
             let imageview_create_info = vk::ImageViewCreateInfo::builder()
                 .image(*image)
                 .view_type(vk::ImageViewType::TYPE_2D)
-                .format(vk::Format::B8G8R8A8_UNORM)
+                .format(surface_format.format)
                 .subresource_range(*subresource_range);
             let imageview =
                 unsafe { logical_device.create_image_view(&imageview_create_info, None) }?;
             swapchain_imageviews.push(imageview);
         }
-        let extent3d = vk::Extent3D {
-            width: extent.width,
-            height: extent.height,
-            depth: 1,
-        };
-        let depth_image_info = vk::ImageCreateInfo::builder()
-            .image_type(vk::ImageType::TYPE_2D)
-            .format(vk::Format::D32_SFLOAT)
-            .extent(extent3d)
-            .mip_levels(1)
-            .array_layers(1)
-            .samples(vk::SampleCountFlags::TYPE_1)
-            .tiling(vk::ImageTiling::OPTIMAL)
-            .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
-            .sharing_mode(vk::SharingMode::EXCLUSIVE)
-            .queue_family_indices(&queuefamilies);
-        let allocation_info = vk_mem::AllocationCreateInfo {
-            usage: vk_mem::MemoryUsage::GpuOnly,
-            ..Default::default()
-        };
-        let (depth_image, depth_image_allocation, depth_image_allocation_info) =
-            allocator.create_image(&depth_image_info, &allocation_info)?;
-        let subresource_range = vk::ImageSubresourceRange::builder()
-            .aspect_mask(vk::ImageAspectFlags::DEPTH)
-            .base_mip_level(0)
-            .level_count(1)
-            .base_array_layer(0)
-            .layer_count(1);
-        let imageview_create_info = vk::ImageViewCreateInfo::builder()
-            .image(depth_image)
-            .view_type(vk::ImageViewType::TYPE_2D)
-            .format(vk::Format::D32_SFLOAT)
-            .subresource_range(*subresource_range);
-        let depth_imageview =
-            unsafe { logical_device.create_image_view(&imageview_create_info, None) }?;
+        let hdr = RenderTarget::new(
+            logical_device,
+            allocator,
+            extent,
+            HDR_FORMAT,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        )?;
+        let depth = RenderTarget::new(
+            logical_device,
+            allocator,
+            extent,
+            DEPTH_FORMAT,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        )?;
 
         let mut image_available = vec![];
         let mut rendering_finished = vec![];
@@ -128,12 +222,12 @@ impl SwapchainDongXi {
             swapchain,
             images: swapchain_images,
             imageviews: swapchain_imageviews,
-            depth_image,
-            depth_image_allocation,
-            _depth_image_allocation_info: depth_image_allocation_info,
-            depth_imageview,
+            hdr,
+            depth,
+            scene_framebuffer: vk::Framebuffer::null(),
             framebuffers: vec![],
             surface_format,
+            present_mode,
             extent,
             amount_of_images,
             current_image: 0,
@@ -145,12 +239,22 @@ impl SwapchainDongXi {
     pub fn create_framebuffers(
         &mut self,
         logical_device: &ash::Device,
-        renderpass: vk::RenderPass,
+        scene_renderpass: vk::RenderPass,
+        present_renderpass: vk::RenderPass,
     ) -> Result<(), vk::Result> {
+        let iview = [self.hdr.imageview, self.depth.imageview];
+        let framebuffer_info = vk::FramebufferCreateInfo::builder()
+            .render_pass(scene_renderpass)
+            .attachments(&iview)
+            .width(self.extent.width)
+            .height(self.extent.height)
+            .layers(1);
+        self.scene_framebuffer =
+            unsafe { logical_device.create_framebuffer(&framebuffer_info, None) }?;
         for iv in &self.imageviews {
-            let iview = [*iv, self.depth_imageview];
+            let iview = [*iv];
             let framebuffer_info = vk::FramebufferCreateInfo::builder()
-                .render_pass(renderpass)
+                .render_pass(present_renderpass)
                 .attachments(&iview)
                 .width(self.extent.width)
                 .height(self.extent.height)
@@ -161,10 +265,8 @@ impl SwapchainDongXi {
         Ok(())
     }
     pub unsafe fn cleanup(&mut self, logical_device: &ash::Device, allocator: &vk_mem::Allocator) {
-        logical_device.destroy_image_view(self.depth_imageview, None);
-        allocator
-            .destroy_image(self.depth_image, &self.depth_image_allocation)
-            .expect("Failed destroy depth image");
+        self.hdr.cleanup(logical_device, allocator);
+        self.depth.cleanup(logical_device, allocator);
         for fence in &self.may_begin_drawing {
             logical_device.destroy_fence(*fence, None);
         }
@@ -174,6 +276,7 @@ impl SwapchainDongXi {
         for semaphore in &self.rendering_finished {
             logical_device.destroy_semaphore(*semaphore, None);
         }
+        logical_device.destroy_framebuffer(self.scene_framebuffer, None);
         for fb in &self.framebuffers {
             logical_device.destroy_framebuffer(*fb, None);
         }
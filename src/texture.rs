@@ -0,0 +1,261 @@
+use crate::{
+    buffers::Buffer,
+    pool_and_commandbuffer::{one_time_submit, Pools},
+};
+use ash::{version::DeviceV1_0, vk};
+use eyre::*;
+use std::path::Path;
+
+pub struct Texture {
+    pub image: vk::Image,
+    allocation: vk_mem::Allocation,
+    pub imageview: vk::ImageView,
+    pub sampler: vk::Sampler,
+}
+
+impl Texture {
+    pub fn from_file<P: AsRef<Path>>(
+        logical_device: &ash::Device,
+        allocator: &vk_mem::Allocator,
+        pools: &Pools,
+        queue: vk::Queue,
+        path: P,
+    ) -> Result<Texture> {
+        let image = image::open(path.as_ref())
+            .wrap_err_with(|| format!("Failed to open texture {}", path.as_ref().display()))?
+            .to_rgba8();
+        let (width, height) = image.dimensions();
+        Texture::from_rgba8(
+            logical_device,
+            allocator,
+            pools,
+            queue,
+            width,
+            height,
+            &[&image.into_raw()],
+            false,
+        )
+    }
+
+    /// Faces are expected in the Vulkan order: +x, -x, +y, -y, +z, -z.
+    pub fn cubemap_from_files<P: AsRef<Path>>(
+        logical_device: &ash::Device,
+        allocator: &vk_mem::Allocator,
+        pools: &Pools,
+        queue: vk::Queue,
+        paths: &[P; 6],
+    ) -> Result<Texture> {
+        let mut faces = Vec::with_capacity(6);
+        for path in paths {
+            let face = image::open(path.as_ref())
+                .wrap_err_with(|| format!("Failed to open texture {}", path.as_ref().display()))?
+                .to_rgba8();
+            faces.push(face);
+        }
+        let (width, height) = faces[0].dimensions();
+        if faces
+            .iter()
+            .any(|face| face.dimensions() != (width, height))
+        {
+            bail!("All cubemap faces must have the same size");
+        }
+        let layers: Vec<&[u8]> = faces.iter().map(|face| face.as_raw().as_slice()).collect();
+        Texture::from_rgba8(
+            logical_device,
+            allocator,
+            pools,
+            queue,
+            width,
+            height,
+            &layers,
+            true,
+        )
+    }
+
+    /// 1x1 white texture for descriptor slots that are not in use.
+    pub fn placeholder(
+        logical_device: &ash::Device,
+        allocator: &vk_mem::Allocator,
+        pools: &Pools,
+        queue: vk::Queue,
+        cube: bool,
+    ) -> Result<Texture> {
+        let white = [255u8; 4];
+        let layers = if cube {
+            vec![&white[..]; 6]
+        } else {
+            vec![&white[..]]
+        };
+        Texture::from_rgba8(logical_device, allocator, pools, queue, 1, 1, &layers, cube)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_rgba8(
+        logical_device: &ash::Device,
+        allocator: &vk_mem::Allocator,
+        pools: &Pools,
+        queue: vk::Queue,
+        width: u32,
+        height: u32,
+        layers: &[&[u8]],
+        cube: bool,
+    ) -> Result<Texture> {
+        let layer_size = (4 * width * height) as usize;
+        if layers.iter().any(|layer| layer.len() != layer_size) {
+            bail!(
+                "Texture layer size does not match {}x{} RGBA8",
+                width,
+                height
+            );
+        }
+        let data: Vec<u8> = layers.concat();
+        let mut staging = Buffer::new(
+            allocator,
+            data.len() as u64,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk_mem::MemoryUsage::CpuToGpu,
+        )?;
+        staging.fill(allocator, &data)?;
+
+        let layer_count = layers.len() as u32;
+        let image_info = vk::ImageCreateInfo::builder()
+            .flags(if cube {
+                vk::ImageCreateFlags::CUBE_COMPATIBLE
+            } else {
+                vk::ImageCreateFlags::empty()
+            })
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(vk::Format::R8G8B8A8_SRGB)
+            .extent(vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(layer_count)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        let allocation_info = vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::GpuOnly,
+            ..Default::default()
+        };
+        let (image, allocation, _) = allocator.create_image(&image_info, &allocation_info)?;
+
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count,
+        };
+        one_time_submit(logical_device, pools, queue, |commandbuffer| {
+            let barrier = vk::ImageMemoryBarrier::builder()
+                .image(image)
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .subresource_range(subresource_range)
+                .build();
+            unsafe {
+                logical_device.cmd_pipeline_barrier(
+                    commandbuffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[barrier],
+                )
+            };
+            let regions: Vec<vk::BufferImageCopy> = (0..layer_count)
+                .map(|layer| {
+                    vk::BufferImageCopy::builder()
+                        .buffer_offset(layer as u64 * layer_size as u64)
+                        .image_subresource(vk::ImageSubresourceLayers {
+                            aspect_mask: vk::ImageAspectFlags::COLOR,
+                            mip_level: 0,
+                            base_array_layer: layer,
+                            layer_count: 1,
+                        })
+                        .image_extent(vk::Extent3D {
+                            width,
+                            height,
+                            depth: 1,
+                        })
+                        .build()
+                })
+                .collect();
+            unsafe {
+                logical_device.cmd_copy_buffer_to_image(
+                    commandbuffer,
+                    staging.buffer,
+                    image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &regions,
+                )
+            };
+            let barrier = vk::ImageMemoryBarrier::builder()
+                .image(image)
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .subresource_range(subresource_range)
+                .build();
+            unsafe {
+                logical_device.cmd_pipeline_barrier(
+                    commandbuffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[barrier],
+                )
+            };
+        })?;
+        allocator.destroy_buffer(staging.buffer, &staging.allocation)?;
+
+        let imageview_create_info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(if cube {
+                vk::ImageViewType::CUBE
+            } else {
+                vk::ImageViewType::TYPE_2D
+            })
+            .format(vk::Format::R8G8B8A8_SRGB)
+            .subresource_range(subresource_range);
+        let imageview = unsafe { logical_device.create_image_view(&imageview_create_info, None) }?;
+
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::REPEAT)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .max_lod(0.0);
+        let sampler = unsafe { logical_device.create_sampler(&sampler_info, None) }?;
+
+        Ok(Texture {
+            image,
+            allocation,
+            imageview,
+            sampler,
+        })
+    }
+
+    pub fn cleanup(&self, logical_device: &ash::Device, allocator: &vk_mem::Allocator) {
+        unsafe {
+            logical_device.destroy_sampler(self.sampler, None);
+            logical_device.destroy_image_view(self.imageview, None);
+        }
+        allocator
+            .destroy_image(self.image, &self.allocation)
+            .expect("Failed destroy texture image");
+    }
+}
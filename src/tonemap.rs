@@ -0,0 +1,229 @@
+use crate::{
+    bloom::Bloom,
+    render_target::create_sampler,
+    renderpass_and_pipeline::{set_viewport, Pipeline},
+    shaders,
+    swapchain::SwapchainDongXi,
+};
+use ash::{version::DeviceV1_0, vk};
+use eyre::*;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TonemapOperator {
+    Reinhard,
+    Aces,
+    AgX,
+}
+
+impl TonemapOperator {
+    pub fn next(self) -> TonemapOperator {
+        match self {
+            TonemapOperator::Reinhard => TonemapOperator::Aces,
+            TonemapOperator::Aces => TonemapOperator::AgX,
+            TonemapOperator::AgX => TonemapOperator::Reinhard,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+struct TonemapParameters {
+    exposure: f32,
+    operator: u32,
+    encode_srgb: u32,
+    bloom_intensity: f32,
+    bloom_scale: f32,
+}
+
+unsafe impl bytemuck::Zeroable for TonemapParameters {}
+unsafe impl bytemuck::Pod for TonemapParameters {}
+
+/// Composites bloom onto the HDR scene colour and maps the result into the
+/// swapchain image.
+pub struct Tonemapping {
+    pub operator: TonemapOperator,
+    /// Linear scale applied to the scene colour before the operator.
+    pub exposure: f32,
+    pipeline: Pipeline,
+    sampler: vk::Sampler,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    encode_srgb: bool,
+    extent: vk::Extent2D,
+}
+
+impl Tonemapping {
+    pub fn init(
+        logical_device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
+        swapchain: &SwapchainDongXi,
+        renderpass: &vk::RenderPass,
+        scene: vk::ImageView,
+        bloom: &Bloom,
+    ) -> Result<Tonemapping> {
+        let sampler = create_sampler(logical_device)?;
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 2,
+        }];
+        let descriptor_pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(1)
+            .pool_sizes(&pool_sizes);
+        let descriptor_pool =
+            unsafe { logical_device.create_descriptor_pool(&descriptor_pool_info, None) }?;
+        let mut tonemapping = Tonemapping {
+            operator: TonemapOperator::Reinhard,
+            exposure: 1.0,
+            pipeline: Tonemapping::create_pipeline(logical_device, pipeline_cache, renderpass)?,
+            sampler,
+            descriptor_pool,
+            descriptor_set: vk::DescriptorSet::null(),
+            encode_srgb: false,
+            extent: swapchain.extent,
+        };
+        tonemapping.set_inputs(logical_device, swapchain, scene, bloom)?;
+        Ok(tonemapping)
+    }
+
+    fn create_pipeline(
+        logical_device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
+        renderpass: &vk::RenderPass,
+    ) -> Result<Pipeline> {
+        let fs_src = shaders::spirv("tonemap.frag", "");
+        let pipeline = Pipeline::init_fullscreen(
+            logical_device,
+            pipeline_cache,
+            renderpass,
+            &fs_src,
+            2,
+            std::mem::size_of::<TonemapParameters>() as u32,
+            false,
+        )?;
+        Ok(pipeline)
+    }
+
+    /// Rebuilds everything that depends on the swapchain extent, format or scene
+    /// colour. The device must be idle.
+    pub fn recreate(
+        &mut self,
+        logical_device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
+        swapchain: &SwapchainDongXi,
+        renderpass: &vk::RenderPass,
+        scene: vk::ImageView,
+        bloom: &Bloom,
+    ) -> Result<()> {
+        let pipeline = Tonemapping::create_pipeline(logical_device, pipeline_cache, renderpass)?;
+        self.pipeline.cleanup(logical_device);
+        self.pipeline = pipeline;
+        self.extent = swapchain.extent;
+        self.set_inputs(logical_device, swapchain, scene, bloom)
+    }
+
+    /// Rebinds the scene colour and bloom images. The device must be idle.
+    pub fn set_inputs(
+        &mut self,
+        logical_device: &ash::Device,
+        swapchain: &SwapchainDongXi,
+        scene: vk::ImageView,
+        bloom: &Bloom,
+    ) -> Result<()> {
+        unsafe {
+            logical_device
+                .reset_descriptor_pool(self.descriptor_pool, vk::DescriptorPoolResetFlags::empty())
+        }?;
+        let desc_layouts = [self.pipeline.descriptor_set_layouts[0]];
+        let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.descriptor_pool)
+            .set_layouts(&desc_layouts);
+        self.descriptor_set =
+            unsafe { logical_device.allocate_descriptor_sets(&descriptor_set_allocate_info) }?[0];
+        let hdr_infos = [vk::DescriptorImageInfo {
+            sampler: self.sampler,
+            image_view: scene,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }];
+        let bloom_infos = [vk::DescriptorImageInfo {
+            sampler: self.sampler,
+            image_view: bloom.output(),
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }];
+        let desc_sets_write = [
+            vk::WriteDescriptorSet::builder()
+                .dst_set(self.descriptor_set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&hdr_infos)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(self.descriptor_set)
+                .dst_binding(1)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&bloom_infos)
+                .build(),
+        ];
+        unsafe { logical_device.update_descriptor_sets(&desc_sets_write, &[]) };
+
+        // sRGB swapchain formats encode in hardware, UNORM ones need it in the shader.
+        self.encode_srgb = !crate::any!(
+            swapchain.surface_format.format,
+            vk::Format::B8G8R8A8_SRGB,
+            vk::Format::R8G8B8A8_SRGB,
+            vk::Format::A8B8G8R8_SRGB_PACK32,
+        );
+        Ok(())
+    }
+
+    pub fn draw(
+        &self,
+        logical_device: &ash::Device,
+        commandbuffer: vk::CommandBuffer,
+        bloom: &Bloom,
+    ) {
+        let (bloom_intensity, bloom_scale) = bloom.composite_weights();
+        let parameters = TonemapParameters {
+            exposure: self.exposure,
+            operator: match self.operator {
+                TonemapOperator::Reinhard => 0,
+                TonemapOperator::Aces => 1,
+                TonemapOperator::AgX => 2,
+            },
+            encode_srgb: self.encode_srgb as u32,
+            bloom_intensity,
+            bloom_scale,
+        };
+        set_viewport(logical_device, commandbuffer, self.extent);
+        unsafe {
+            logical_device.cmd_bind_pipeline(
+                commandbuffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.pipeline,
+            );
+            logical_device.cmd_bind_descriptor_sets(
+                commandbuffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            logical_device.cmd_push_constants(
+                commandbuffer,
+                self.pipeline.layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                bytemuck::bytes_of(&parameters),
+            );
+            logical_device.cmd_draw(commandbuffer, 3, 1, 0, 0);
+        }
+    }
+
+    pub fn cleanup(&self, logical_device: &ash::Device) {
+        unsafe {
+            logical_device.destroy_descriptor_pool(self.descriptor_pool, None);
+            logical_device.destroy_sampler(self.sampler, None);
+        }
+        self.pipeline.cleanup(logical_device);
+    }
+}
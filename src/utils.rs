@@ -55,13 +55,6 @@ macro_rules! include_bytes_from_outdir {
     };
 }
 
-#[macro_export]
-macro_rules! include_spirv_from_outdir {
-    ($t: literal) => {
-        crate::utils::make_spirv(crate::include_bytes_from_outdir!($t))
-    };
-}
-
 #[macro_export]
 macro_rules! tuple_as {
     ($e:expr, ( $T0:ty, $T1:ty, $T2:ty, $T3:ty, $T4:ty, $T5:ty ) ) => {